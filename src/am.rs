@@ -0,0 +1,276 @@
+//! Mailboxes of patches, as `format-patch` writes them, and the state
+//! `got am` keeps while applying one.
+//!
+//! Each mail's `From:` and `Date:` give the author of the commit made from
+//! it, its `Subject:` (less any `[PATCH n/m]` prefix) the first line of the
+//! message and its body up to the `---` line the rest; the patch follows.
+//!
+//! Applying a series can stop at a patch that doesn't apply, so the series
+//! is kept in `.got/rebase-apply` until it is done: each mail in a numbered
+//! file (`0001`, `0002`, ...), `next` and `last` numbering the mail to
+//! apply next and the final one, and `orig-head` the commit HEAD was at
+//! before the first, for `--abort` to go back to.
+
+use crate::object::Signature;
+use crate::oid::Oid;
+use anyhow::Context;
+use chrono::DateTime;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Splits an mbox into its mails. Each mail starts with a `From ` line at
+/// the top of the file or after a blank line.
+pub fn split_mbox(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut mails: Vec<Vec<u8>> = vec![];
+    let mut previous_blank = true;
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        if previous_blank && line.starts_with(b"From ") || mails.is_empty() {
+            mails.push(vec![]);
+        }
+        previous_blank = line == b"\n";
+        mails.last_mut().expect("a mail was started").extend(line);
+    }
+    mails
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    pub author: Signature,
+    pub subject: String,
+    /// The explanation between the headers and the `---` line.
+    pub body: String,
+    /// Everything after the explanation: the diffstat and the diff.
+    pub patch: Vec<u8>,
+}
+
+impl Mail {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Mail> {
+        let text = String::from_utf8_lossy(data);
+        let (headers, rest) = text.split_once("\n\n").unwrap_or((&text, ""));
+        let mut from = None;
+        let mut date = None;
+        let mut subject = String::new();
+        // Folded headers continue on lines starting with whitespace.
+        let mut unfolded: Vec<String> = vec![];
+        for line in headers.lines() {
+            match unfolded.last_mut() {
+                Some(last) if line.starts_with([' ', '\t']) => last.push_str(line),
+                _ => unfolded.push(line.to_string()),
+            }
+        }
+        for header in &unfolded {
+            let Some((name, value)) = header.split_once(':') else {
+                continue;
+            };
+            let value = decode_header(value.trim());
+            match name.to_ascii_lowercase().as_str() {
+                "from" => from = Some(value),
+                "date" => date = Some(value),
+                "subject" => subject = clean_subject(&value),
+                _ => {}
+            }
+        }
+        let from = from.context("patch mail has no From: header")?;
+        let (name, email) = match (from.find('<'), from.rfind('>')) {
+            (Some(open), Some(close)) if open < close => {
+                (from[..open].trim().trim_matches('"').to_string(), from[open + 1..close].to_string())
+            }
+            _ => (String::new(), from.trim().to_string()),
+        };
+        let date = date.context("patch mail has no Date: header")?;
+        let time = DateTime::parse_from_rfc2822(&date).with_context(|| format!("invalid date '{date}'"))?;
+
+        let split = rest
+            .match_indices('\n')
+            .map(|(at, _)| at + 1)
+            .chain([0])
+            .filter(|&at| {
+                let line = &rest[at..];
+                line.starts_with("---\n") || line.starts_with("diff --git ")
+            })
+            .min()
+            .unwrap_or(rest.len());
+        let (body, patch) = rest.split_at(split);
+        Ok(Mail {
+            author: Signature { name, email, time },
+            subject,
+            body: body.trim().to_string(),
+            patch: patch.as_bytes().to_vec(),
+        })
+    }
+
+    /// The commit message: the subject, and the body after a blank line.
+    pub fn message(&self) -> String {
+        if self.body.is_empty() {
+            format!("{}\n", self.subject)
+        } else {
+            format!("{}\n\n{}\n", self.subject, self.body)
+        }
+    }
+}
+
+/// The subject without `Re:` and leading `[...]` tags such as `[PATCH 2/3]`.
+fn clean_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        if let Some(rest) = subject.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+            subject = rest.1.trim_start();
+        } else if subject.get(..3).is_some_and(|re| re.eq_ignore_ascii_case("re:")) {
+            subject = subject[3..].trim_start();
+        } else {
+            return subject.to_string();
+        }
+    }
+}
+
+/// Decodes the RFC 2047 encoded words (`=?UTF-8?q?...?=`) of a header.
+fn decode_header(value: &str) -> String {
+    let mut out = vec![];
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let word = rest[start + 2..].splitn(3, '?').collect::<Vec<_>>();
+        let decoded = match word[..] {
+            [_, encoding, text] => text.split_once("?=").and_then(|(text, _)| {
+                let bytes = match encoding {
+                    "q" | "Q" => decode_q(text),
+                    "b" | "B" => decode_base64(text),
+                    _ => None,
+                }?;
+                // =?charset?encoding?text?=
+                let len = word[0].len() + encoding.len() + text.len() + 6;
+                Some((bytes, len))
+            }),
+            _ => None,
+        };
+        let Some((bytes, len)) = decoded else {
+            out.extend(&rest.as_bytes()[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+        // Whitespace between two encoded words is dropped.
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            out.extend(between.as_bytes());
+        }
+        out.extend(bytes);
+        rest = &rest[start + len..];
+        after_word = true;
+    }
+    out.extend(rest.as_bytes());
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'_' => out.push(b' '),
+            b'=' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => out.push(b),
+        }
+    }
+    Some(out)
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = vec![];
+    let (mut bits, mut count) = (0u32, 0);
+    for b in text.bytes().filter(|&b| b != b'=') {
+        bits = bits << 6 | ALPHABET.iter().position(|&a| a == b)? as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// A series being applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    dir: PathBuf,
+    /// The number of the mail to apply next, from 1.
+    pub next: usize,
+    pub last: usize,
+    pub orig_head: Oid,
+}
+
+impl State {
+    fn dir(git_dir: &Path) -> PathBuf {
+        git_dir.join("rebase-apply")
+    }
+
+    /// Starts applying `mails` on top of `orig_head`.
+    pub fn start(git_dir: &Path, mails: &[Vec<u8>], orig_head: &Oid) -> anyhow::Result<State> {
+        let dir = State::dir(git_dir);
+        fs::create_dir_all(&dir).context("create .got/rebase-apply")?;
+        for (n, mail) in mails.iter().enumerate() {
+            fs::write(dir.join(format!("{:04}", n + 1)), mail).context("write patch to .got/rebase-apply")?;
+        }
+        fs::write(dir.join("orig-head"), format!("{orig_head}\n")).context("write .got/rebase-apply/orig-head")?;
+        let state = State {
+            dir,
+            next: 1,
+            last: mails.len(),
+            orig_head: *orig_head,
+        };
+        state.save()?;
+        Ok(state)
+    }
+
+    /// The series in progress, if there is one.
+    pub fn load(git_dir: &Path) -> anyhow::Result<Option<State>> {
+        let dir = State::dir(git_dir);
+        let read = |name: &str| -> anyhow::Result<String> {
+            let text = fs::read_to_string(dir.join(name)).with_context(|| format!("read .got/rebase-apply/{name}"))?;
+            Ok(text.trim().to_string())
+        };
+        match fs::metadata(dir.join("next")) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            result => result.context("read .got/rebase-apply/next")?,
+        };
+        Ok(Some(State {
+            next: read("next")?.parse().context("corrupt .got/rebase-apply/next")?,
+            last: read("last")?.parse().context("corrupt .got/rebase-apply/last")?,
+            orig_head: read("orig-head")?.parse().context("corrupt .got/rebase-apply/orig-head")?,
+            dir,
+        }))
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        fs::write(self.dir.join("next"), format!("{}\n", self.next)).context("write .got/rebase-apply/next")?;
+        fs::write(self.dir.join("last"), format!("{}\n", self.last)).context("write .got/rebase-apply/last")
+    }
+
+    /// Whether every mail has been applied or skipped.
+    pub fn is_done(&self) -> bool {
+        self.next > self.last
+    }
+
+    /// The mail to apply next.
+    pub fn current(&self) -> anyhow::Result<Mail> {
+        let path = self.dir.join(format!("{:04}", self.next));
+        let data = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+        Mail::parse(&data).with_context(|| format!("parse patch {:04}", self.next))
+    }
+
+    /// Moves on to the next mail.
+    pub fn advance(&mut self) -> anyhow::Result<()> {
+        self.next += 1;
+        self.save()
+    }
+
+    /// Forgets the series.
+    pub fn remove(self) -> anyhow::Result<()> {
+        fs::remove_dir_all(&self.dir).context("remove .got/rebase-apply")
+    }
+}
@@ -0,0 +1,299 @@
+//! Applying patches in git's unified diff format, as `got diff` and
+//! `format-patch` write them, to the index and worktree.
+//!
+//! Each hunk must match the file exactly, context and all, though it may
+//! have moved: it is looked for at the line it names first and then at
+//! increasing distances either side. A patch applies as a whole or not at
+//! all; nothing is written until every file in it has been patched in
+//! memory.
+
+use crate::diff;
+use crate::index::{self, Entry, Index, Stat};
+use crate::merge;
+use crate::object::{Kind, Object};
+use crate::path;
+use crate::quote;
+use crate::tree::Mode;
+use crate::worktree;
+use anyhow::Context;
+use std::fs;
+
+/// The changes a patch makes to one file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilePatch {
+    /// `None` for a file the patch creates.
+    pub old_path: Option<Vec<u8>>,
+    /// `None` for a file the patch deletes.
+    pub new_path: Option<Vec<u8>>,
+    pub old_mode: Option<Mode>,
+    pub new_mode: Option<Mode>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    /// The path the patch is reported under.
+    pub fn path(&self) -> &[u8] {
+        self.new_path.as_deref().or(self.old_path.as_deref()).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hunk {
+    /// The 1-based line the hunk starts at in the old file.
+    pub old_start: usize,
+    /// The lines the hunk replaces and what replaces them, each with its
+    /// line ending (if it has one).
+    pub old: Vec<Vec<u8>>,
+    pub new: Vec<Vec<u8>>,
+}
+
+/// Parses the `diff --git` sections of `text`, ignoring anything around
+/// them such as a commit message.
+pub fn parse_patch(text: &[u8]) -> anyhow::Result<Vec<FilePatch>> {
+    let lines = diff::split_lines(text);
+    let mut patches: Vec<FilePatch> = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        let Some(names) = line.strip_prefix(b"diff --git ") else {
+            continue;
+        };
+        let names = names.strip_suffix(b"\n").unwrap_or(names);
+        let (old, new) = split_names(names)
+            .with_context(|| format!("malformed diff header 'diff --git {}'", String::from_utf8_lossy(names)))?;
+        let mut patch = FilePatch {
+            old_path: Some(old),
+            new_path: Some(new),
+            ..FilePatch::default()
+        };
+        while i < lines.len() && !lines[i].starts_with(b"diff --git ") {
+            let raw = lines[i].strip_suffix(b"\n").unwrap_or(lines[i]);
+            let line = String::from_utf8_lossy(raw);
+            i += 1;
+            if let Some(mode) = line.strip_prefix("new file mode ") {
+                patch.old_path = None;
+                patch.new_mode = Some(Mode::parse(mode)?);
+            } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                patch.new_path = None;
+                patch.old_mode = Some(Mode::parse(mode)?);
+            } else if let Some(mode) = line.strip_prefix("old mode ") {
+                patch.old_mode = Some(Mode::parse(mode)?);
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                patch.new_mode = Some(Mode::parse(mode)?);
+            } else if let Some(index) = line.strip_prefix("index ") {
+                if let Some((_, mode)) = index.split_once(' ') {
+                    let mode = Mode::parse(mode)?;
+                    patch.old_mode = Some(mode);
+                    patch.new_mode = Some(mode);
+                }
+            } else if let Some(name) = raw.strip_prefix(b"rename from ").or(raw.strip_prefix(b"copy from ")) {
+                patch.old_path = Some(unquote_name(name).context("malformed rename source")?);
+            } else if let Some(name) = raw.strip_prefix(b"rename to ").or(raw.strip_prefix(b"copy to ")) {
+                patch.new_path = Some(unquote_name(name).context("malformed rename destination")?);
+            } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+                anyhow::bail!("cannot apply binary patch to '{}'", path::display(patch.path()));
+            } else if line.starts_with("@@ ") {
+                let hunk;
+                (hunk, i) = parse_hunk(&line, &lines, i)
+                    .with_context(|| format!("corrupt patch for '{}'", path::display(patch.path())))?;
+                patch.hunks.push(hunk);
+            } else if !line.starts_with("--- ") && !line.starts_with("+++ ") && !line.starts_with("similarity index ") {
+                break;
+            }
+        }
+        if patch.old_path.is_none() {
+            patch.old_mode = None;
+        }
+        if patch.new_path.is_none() {
+            patch.new_mode = None;
+        }
+        patches.push(patch);
+    }
+    Ok(patches)
+}
+
+/// The two paths of a `diff --git a/<old> b/<new>` header. A name in
+/// quotes is read up to its closing quote. When neither is quoted and the
+/// two are the same, as they are unless the file was renamed, the split
+/// point is found from that; otherwise at the first ` b/`.
+fn split_names(names: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    if names.starts_with(b"\"") {
+        let (old, rest) = quote::unquote(names)?;
+        let new = unquote_name(rest.strip_prefix(b" ")?)?;
+        return Some((old.strip_prefix(b"a/")?.to_vec(), new.strip_prefix(b"b/")?.to_vec()));
+    }
+    let rest = names.strip_prefix(b"a/")?;
+    if let Some(at) = rest.windows(4).position(|window| window == b" \"b/") {
+        let (new, after) = quote::unquote(&rest[at + 1..])?;
+        let new = new.strip_prefix(b"b/").filter(|_| after.is_empty())?;
+        return Some((rest[..at].to_vec(), new.to_vec()));
+    }
+    let half = rest.len().checked_sub(3)? / 2;
+    if rest.len() % 2 == 1 && rest[half..].starts_with(b" b/") && rest[..half] == rest[half + 3..] {
+        return Some((rest[..half].to_vec(), rest[..half].to_vec()));
+    }
+    let at = rest.windows(3).position(|window| window == b" b/")?;
+    Some((rest[..at].to_vec(), rest[at + 3..].to_vec()))
+}
+
+/// A name standing alone on a line, unquoted if it is in quotes.
+fn unquote_name(name: &[u8]) -> Option<Vec<u8>> {
+    if !name.starts_with(b"\"") {
+        return Some(name.to_vec());
+    }
+    quote::unquote(name).and_then(|(name, rest)| rest.is_empty().then_some(name))
+}
+
+/// Parses the hunk whose header is `header` and whose lines start at
+/// `lines[i]`, returning it and the index of the line after it.
+fn parse_hunk(header: &str, lines: &[&[u8]], mut i: usize) -> anyhow::Result<(Hunk, usize)> {
+    let ranges = header
+        .strip_prefix("@@ -")
+        .and_then(|rest| rest.split_once(" @@"))
+        .map(|(ranges, _)| ranges)
+        .with_context(|| format!("malformed hunk header '{header}'"))?;
+    let (old, new) = ranges.split_once(" +").with_context(|| format!("malformed hunk header '{header}'"))?;
+    let range = |range: &str| -> anyhow::Result<(usize, usize)> {
+        let (start, len) = range.split_once(',').unwrap_or((range, "1"));
+        Ok((start.parse()?, len.parse()?))
+    };
+    let (old_start, mut old_left) = range(old)?;
+    let (_, mut new_left) = range(new)?;
+    let mut hunk = Hunk {
+        old_start,
+        ..Hunk::default()
+    };
+    // Which side the last line went to, for a following "\ No newline".
+    let mut last: (bool, bool) = (false, false);
+    while old_left > 0 || new_left > 0 || lines.get(i).is_some_and(|line| line.starts_with(b"\\")) {
+        let line = *lines.get(i).context("hunk is truncated")?;
+        i += 1;
+        // Mailers can strip the space of an empty context line.
+        let (kind, text) = match line {
+            b"\n" => (b' ', line),
+            _ => (line[0], &line[1..]),
+        };
+        match kind {
+            b' ' if old_left > 0 && new_left > 0 => {
+                hunk.old.push(text.to_vec());
+                hunk.new.push(text.to_vec());
+                (old_left, new_left) = (old_left - 1, new_left - 1);
+                last = (true, true);
+            }
+            b'-' if old_left > 0 => {
+                hunk.old.push(text.to_vec());
+                old_left -= 1;
+                last = (true, false);
+            }
+            b'+' if new_left > 0 => {
+                hunk.new.push(text.to_vec());
+                new_left -= 1;
+                last = (false, true);
+            }
+            b'\\' => {
+                for (side, lines) in [(last.0, &mut hunk.old), (last.1, &mut hunk.new)] {
+                    if side && let Some(line) = lines.last_mut() {
+                        line.pop_if(|b| *b == b'\n');
+                    }
+                }
+            }
+            _ => anyhow::bail!("unexpected line in hunk: {}", String::from_utf8_lossy(line).trim_end()),
+        }
+    }
+    Ok((hunk, i))
+}
+
+/// Applies `patch` to `old`, the contents of the file it changes (empty
+/// for a new file).
+pub fn apply_hunks(patch: &FilePatch, old: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let lines = diff::split_lines(old);
+    let mut out: Vec<u8> = vec![];
+    // Lines before `at` have been copied or replaced; hunks may not go back
+    // over them. `offset` is how far the previous hunk had moved.
+    let mut at = 0;
+    let mut offset: isize = 0;
+    for hunk in &patch.hunks {
+        // A hunk that only adds lines names the line it adds them after.
+        let named = if hunk.old.is_empty() { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+        let wanted = (named as isize + offset).max(0) as usize;
+        let matches = |start: usize| {
+            start >= at
+                && start + hunk.old.len() <= lines.len()
+                && hunk.old.iter().zip(&lines[start..]).all(|(a, b)| a == b)
+        };
+        let found = (0..=lines.len()).find_map(|distance| {
+            [wanted.checked_add(distance), wanted.checked_sub(distance)]
+                .into_iter()
+                .flatten()
+                .find(|&start| matches(start))
+        });
+        let start = found.with_context(|| format!("patch failed: {}:{}", path::display(patch.path()), hunk.old_start))?;
+        offset = start as isize - named as isize;
+        out.extend(lines[at..start].concat());
+        out.extend(hunk.new.concat());
+        at = start + hunk.old.len();
+    }
+    out.extend(lines[at..].concat());
+    Ok(out)
+}
+
+/// Applies `patches` to the index and the worktree, which must match the
+/// index for every file they touch.
+pub fn apply_to_index(index: &mut Index, patches: &[FilePatch]) -> anyhow::Result<()> {
+    // The result for each path: its new mode and blob, or None to remove it.
+    let mut results: Vec<(Vec<u8>, merge::Side)> = vec![];
+    for patch in patches {
+        let old = match &patch.old_path {
+            Some(path) => {
+                let entry = index.get(path).with_context(|| format!("{}: does not exist in index", path::display(path)))?;
+                anyhow::ensure!(!worktree::is_blocked(index, path, true), "{}: does not match index", path::display(path));
+                Some(entry.clone())
+            }
+            None => None,
+        };
+        if let Some(path) = &patch.new_path
+            && patch.old_path.as_ref() != Some(path)
+        {
+            anyhow::ensure!(index.get(path).is_none(), "{}: already exists in index", path::display(path));
+            anyhow::ensure!(
+                fs::symlink_metadata(path::to_path(path)).is_err(),
+                "{}: already exists in working directory",
+                path::display(path)
+            );
+        }
+        let contents = match &old {
+            Some(entry) => Object::read(&entry.hash)?.contents,
+            None => vec![],
+        };
+        let contents = apply_hunks(patch, &contents)
+            .with_context(|| format!("{}: patch does not apply", path::display(patch.path())))?;
+        if let Some(old_path) = &patch.old_path
+            && patch.new_path.as_ref() != Some(old_path)
+        {
+            results.push((old_path.clone(), None));
+        }
+        match &patch.new_path {
+            Some(path) => {
+                let mode = patch.new_mode.or(old.map(|entry| entry.mode)).unwrap_or(Mode::File);
+                results.push((path.clone(), Some((mode, Object::store(Kind::Blob, &contents)?))));
+            }
+            None => anyhow::ensure!(contents.is_empty(), "{}: removal patch leaves file contents", path::display(patch.path())),
+        }
+    }
+
+    for (path, result) in results {
+        match result {
+            None => {
+                worktree::remove(&path)?;
+                index.remove(&path);
+            }
+            Some((mode, hash)) => {
+                index::write_worktree_file(&path, mode, &Object::read(&hash)?.contents)?;
+                let meta = fs::symlink_metadata(path::to_path(&path)).with_context(|| format!("stat {}", path::display(&path)))?;
+                index.insert(Entry::new(&path, mode, hash, Stat::from_metadata(&meta)));
+            }
+        }
+    }
+    Ok(())
+}
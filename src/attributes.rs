@@ -0,0 +1,121 @@
+//! Path attributes, which say how got should treat particular files.
+//!
+//! Attributes are read from `.gotattributes` at the top of the worktree and
+//! then from the repository's private `.got/info/attributes`, so that the
+//! latter wins. Each line is a pattern followed by attributes: `name` sets
+//! one, `-name` unsets it, `name=value` gives it a value and `!name` leaves
+//! it unspecified, undoing an earlier line. The built-in `binary` macro
+//! stands for `-diff -merge -text`. Patterns match as in `.gotignore`,
+//! except that there is no negation and directory-only patterns are
+//! skipped.
+
+use crate::ignore;
+use crate::path;
+use anyhow::Context;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State {
+    Set,
+    Unset,
+    Value(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    /// Whether the pattern is matched against the whole path.
+    anchored: bool,
+    /// `None` for `!name`.
+    attributes: Vec<(String, Option<State>)>,
+}
+
+impl Attributes {
+    /// The attributes for the repository at `git_dir` and its worktree,
+    /// which is the current directory.
+    pub fn load(git_dir: &Path) -> anyhow::Result<Attributes> {
+        let mut attributes = Attributes::default();
+        attributes.read_file(Path::new(".gotattributes"))?;
+        attributes.read_file(&git_dir.join("info/attributes"))?;
+        Ok(attributes)
+    }
+
+    /// Adds the lines of `path`, ignoring the file if it doesn't exist.
+    pub fn read_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.add_rules(&text);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+        }
+    }
+
+    /// Adds the lines in `text` after those already added.
+    pub fn add_rules(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut words = line.split_ascii_whitespace();
+            let Some(pattern) = words.next().filter(|pattern| !pattern.starts_with('#')) else {
+                continue;
+            };
+            if pattern.starts_with('!') || pattern.ends_with('/') {
+                continue;
+            }
+            let mut attributes = vec![];
+            for word in words {
+                let (name, state) = if let Some(name) = word.strip_prefix('-') {
+                    (name, Some(State::Unset))
+                } else if let Some(name) = word.strip_prefix('!') {
+                    (name, None)
+                } else if let Some((name, value)) = word.split_once('=') {
+                    (name, Some(State::Value(value.to_string())))
+                } else {
+                    (word, Some(State::Set))
+                };
+                if name == "binary" && state == Some(State::Set) {
+                    for name in ["diff", "merge", "text"] {
+                        attributes.push((name.to_string(), Some(State::Unset)));
+                    }
+                }
+                attributes.push((name.to_string(), state));
+            }
+            self.rules.push(Rule {
+                pattern: pattern.strip_prefix('/').unwrap_or(pattern).to_string(),
+                anchored: pattern.contains('/'),
+                attributes,
+            });
+        }
+    }
+
+    /// The state of attribute `name` for `path`, relative to the top of the
+    /// worktree, or `None` if it is unspecified.
+    pub fn get(&self, path: &[u8], name: &str) -> Option<&State> {
+        let base = path::file_name(path);
+        for rule in self.rules.iter().rev() {
+            let Some((_, state)) = rule.attributes.iter().rev().find(|(attribute, _)| attribute == name) else {
+                continue;
+            };
+            let text = if rule.anchored { path } else { base };
+            if ignore::wildmatch(rule.pattern.as_bytes(), text) {
+                return state.as_ref();
+            }
+        }
+        None
+    }
+
+    /// The value of attribute `name` for `path`, if it has one.
+    pub fn value(&self, path: &[u8], name: &str) -> Option<&str> {
+        match self.get(path, name) {
+            Some(State::Value(value)) => Some(value),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Backups: the whole of a repository's history in one archive, to keep
+//! somewhere no remote reaches.
+//!
+//! The archive is a tar file laid out as a bare repository: `HEAD`,
+//! `config`, `shallow` if there is one, every ref under `refs/`, and a
+//! single pack in `objects/pack` with every object the refs reach. The refs
+//! are read first and exactly what they reach is packed, so the archive is
+//! consistent even if the repository changes while it is written. Reflogs,
+//! the index and objects nothing reaches are left out.
+//!
+//! Restoring unpacks the archive as the metadata directory of a new
+//! repository; `tar -x` into an empty directory does the same by hand.
+
+use crate::config::Config;
+use crate::oid::Oid;
+use crate::refs;
+use crate::repack::{self, RepackOptions};
+use anyhow::Context;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path};
+use std::time::SystemTime;
+
+const BLOCK: usize = 512;
+
+/// Writes a backup of the repository in `git_dir` to `out`, showing
+/// progress while packing if `progress` is set. Returns how many refs it
+/// holds.
+pub fn create(git_dir: &Path, config: &Config, out: &mut impl Write, progress: bool) -> anyhow::Result<usize> {
+    let mtime = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let mut archive = TarWriter { out, mtime };
+    let head = refs::read(git_dir, "HEAD")?.context("HEAD is missing")?;
+    archive.file("HEAD", format!("{head}\n").as_bytes())?;
+    for name in ["config", "shallow"] {
+        match fs::read(git_dir.join(name)) {
+            Ok(data) => archive.file(name, &data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("read {name}")),
+        }
+    }
+
+    let all_refs = refs::list(git_dir)?;
+    let mut tips: Vec<Oid> = refs::resolve(git_dir, "HEAD")?.into_iter().collect();
+    for dir in ["refs", "refs/heads", "refs/tags"] {
+        archive.directory(dir)?;
+    }
+    for (name, value) in &all_refs {
+        archive.file(name, format!("{value}\n").as_bytes())?;
+        if !value.starts_with("ref: ") {
+            tips.push(value.parse().with_context(|| format!("ref {name} does not contain an object ID"))?);
+        }
+    }
+
+    archive.directory("objects")?;
+    archive.directory("objects/pack")?;
+    if !tips.is_empty() {
+        let temp = std::env::temp_dir().join(format!("got-backup-{}", std::process::id()));
+        let mut options = RepackOptions::from_config(config)?;
+        options.progress = progress;
+        let packed = repack::pack_reachable_from(git_dir, &tips, &[], &temp, &options).and_then(|(path, _)| {
+            for extension in ["pack", "idx"] {
+                let file = path.with_extension(extension);
+                let data = fs::read(&file).with_context(|| format!("read {}", file.display()))?;
+                let name = file.file_name().expect("packs have names").to_string_lossy().into_owned();
+                archive.file(&format!("objects/pack/{name}"), &data)?;
+            }
+            Ok(())
+        });
+        fs::remove_dir_all(&temp).ok();
+        packed?;
+    }
+    archive.finish()?;
+    Ok(all_refs.len())
+}
+
+/// Unpacks the backup `archive` as the metadata directory `git_dir`,
+/// which must not exist yet.
+pub fn restore(archive: &mut impl Read, git_dir: &Path) -> anyhow::Result<()> {
+    fs::create_dir(git_dir).with_context(|| format!("create {}", git_dir.display()))?;
+    let mut header = [0u8; BLOCK];
+    loop {
+        archive.read_exact(&mut header).context("archive is truncated")?;
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let name = entry_name(&header)?;
+        anyhow::ensure!(
+            Path::new(&name).components().all(|part| matches!(part, Component::Normal(_))),
+            "archive holds unsafe path '{name}'"
+        );
+        let size = octal(&header[124..136]).with_context(|| format!("bad size for '{name}'"))?;
+        let path = git_dir.join(&name);
+        match header[156] {
+            b'0' | 0 => {
+                let mut data = vec![0; size];
+                archive.read_exact(&mut data).with_context(|| format!("archive is truncated in '{name}'"))?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+                }
+                fs::write(&path, data).with_context(|| format!("write {}", path.display()))?;
+                let padding = size.next_multiple_of(BLOCK) - size;
+                io::copy(&mut archive.by_ref().take(padding as u64), &mut io::sink())?;
+            }
+            b'5' => fs::create_dir_all(&path).with_context(|| format!("create {}", path.display()))?,
+            kind => anyhow::bail!("archive entry '{name}' is of unsupported type '{}'", kind as char),
+        }
+    }
+    anyhow::ensure!(git_dir.join("HEAD").is_file(), "archive is not a got backup: it has no HEAD");
+    Ok(())
+}
+
+/// The path of the entry `header` starts, from its `prefix` and `name`
+/// fields.
+fn entry_name(header: &[u8; BLOCK]) -> anyhow::Result<String> {
+    let field = |bytes: &[u8]| -> anyhow::Result<String> {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        Ok(std::str::from_utf8(&bytes[..end]).context("archive entry name is not valid UTF-8")?.to_string())
+    };
+    let name = field(&header[0..100])?;
+    let prefix = field(&header[345..500])?;
+    let name = if prefix.is_empty() { name } else { format!("{prefix}/{name}") };
+    Ok(name.trim_end_matches('/').to_string())
+}
+
+fn octal(field: &[u8]) -> anyhow::Result<usize> {
+    let text = std::str::from_utf8(field)?.trim_matches(|c: char| c == '\0' || c == ' ');
+    Ok(usize::from_str_radix(text, 8)?)
+}
+
+/// Writes a ustar archive.
+struct TarWriter<'a, W: Write> {
+    out: &'a mut W,
+    mtime: u64,
+}
+
+impl<W: Write> TarWriter<'_, W> {
+    fn file(&mut self, name: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.header(name, 0o644, data.len(), b'0')?;
+        self.out.write_all(data)?;
+        let padding = data.len().next_multiple_of(BLOCK) - data.len();
+        self.out.write_all(&vec![0; padding])?;
+        Ok(())
+    }
+
+    fn directory(&mut self, name: &str) -> anyhow::Result<()> {
+        self.header(&format!("{name}/"), 0o755, 0, b'5')
+    }
+
+    fn header(&mut self, name: &str, mode: u32, size: usize, kind: u8) -> anyhow::Result<()> {
+        // Names too long for the name field are split at a slash, the
+        // start going in the prefix field.
+        let (prefix, name) = if name.len() <= 100 {
+            ("", name)
+        } else {
+            let at = name
+                .char_indices()
+                .filter(|&(i, c)| c == '/' && i <= 155 && name.len() - i - 1 <= 100)
+                .map(|(i, _)| i)
+                .next()
+                .with_context(|| format!("path '{name}' is too long to archive"))?;
+            (&name[..at], &name[at + 1..])
+        };
+        let mut header = [0u8; BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(format!("{mode:07o}\0").as_bytes());
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        header[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+        header[136..148].copy_from_slice(format!("{:011o}\0", self.mtime).as_bytes());
+        header[156] = kind;
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        // The checksum is taken with its own field as spaces.
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+        self.out.write_all(&header)?;
+        Ok(())
+    }
+
+    /// Ends the archive with the two empty blocks tar expects.
+    fn finish(self) -> anyhow::Result<()> {
+        self.out.write_all(&[0; 2 * BLOCK])?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+//! Blame: which commit last changed each line of a file.
+//!
+//! This follows git's approach. Every line starts out suspected on the
+//! starting commit. Suspects are taken newest commit first, and each one
+//! passes the lines it has in common with a parent on to that parent
+//! (following renames); whatever no parent accounts for was introduced by
+//! the suspect itself. Root commits and commits on the shallow boundary
+//! take the blame for all of their remaining lines.
+//!
+//! Commits never change, so the answer for a commit and path can be kept:
+//! `blame_cached` keeps each in `.got/blame-cache`, named by the SHA-1 of
+//! `<commit>\0<path>`, so that an editor asking again gets it at once
+//! (nothing is kept in a read-only repository). The only things that
+//! change what history looks like are the shallow boundary and replacement
+//! refs, so each cached answer is stamped with a hash of both and ignored
+//! once they change:
+//!
+//! ```text
+//! stamp <hex>
+//! <commit> <orig-start> <final-start> <len> <boundary 0|1> <path>
+//! previous <commit> <path>
+//! ```
+//!
+//! with a `previous` line after each entry that has one.
+
+use crate::config::Config;
+use crate::diff;
+use crate::object::{Commit, Kind, Object};
+use crate::oid::Oid;
+use crate::path;
+use crate::refs;
+use crate::repository;
+use crate::shallow;
+use crate::tree;
+use crate::tree_diff::{self, Status};
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A run of consecutive lines blamed on one commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub commit: Oid,
+    /// The file's path in `commit`.
+    pub path: Vec<u8>,
+    /// Where the lines start in `commit`'s version of the file (0-based).
+    pub orig_start: usize,
+    /// Where the lines start in the blamed version of the file (0-based).
+    pub final_start: usize,
+    pub len: usize,
+    /// The first parent of `commit` that has the file, and its path there.
+    pub previous: Option<(Oid, Vec<u8>)>,
+    /// Whether `commit` is a root or shallow commit, whose lines may be
+    /// older than it.
+    pub boundary: bool,
+}
+
+/// Lines of a suspect still waiting to be blamed.
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    orig_start: usize,
+    final_start: usize,
+    len: usize,
+}
+
+/// Blames each line of `path` as of commit `start`, calling `found` with
+/// each entry as soon as it is known: newest commits first, and in order of
+/// line within a commit. Entries for all the lines are found in the end.
+pub fn blame(
+    git_dir: &Path,
+    start: &Oid,
+    path: &[u8],
+    mut found: impl FnMut(&Entry) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let commit = read_commit(start)?;
+    let blob = file_hash(&commit.tree, path)?.with_context(|| format!("no such path '{}' in {start}", path::display(path)))?;
+    let lines = diff::split_lines(&Object::read(&blob)?.contents).len();
+    let shallow = shallow::read(git_dir)?;
+
+    let mut suspects = Suspects::default();
+    let all = vec![Range {
+        orig_start: 0,
+        final_start: 0,
+        len: lines,
+    }];
+    suspects.add(&commit, *start, path, all);
+
+    while let Some((hash, path, mut ranges)) = suspects.pop() {
+        let commit = read_commit(&hash)?;
+        let blob = file_hash(&commit.tree, &path)?.with_context(|| format!("{} vanished from {hash}", path::display(&path)))?;
+        let parents = if shallow.contains(&hash) { &[][..] } else { &commit.parents[..] };
+        let mut previous = None;
+        for parent_hash in parents {
+            if ranges.is_empty() {
+                break;
+            }
+            let parent = read_commit(parent_hash)?;
+            let Some(parent_path) = parent_path(&parent, &commit, &path)? else {
+                continue;
+            };
+            let parent_blob = file_hash(&parent.tree, &parent_path)?.expect("parent_path found it");
+            if parent_blob == blob {
+                suspects.add(&parent, *parent_hash, &parent_path, std::mem::take(&mut ranges));
+                break;
+            }
+            previous.get_or_insert((*parent_hash, parent_path.clone()));
+            let (passed, kept) = split_ranges(&ranges, &parent_blob, &blob)?;
+            suspects.add(&parent, *parent_hash, &parent_path, passed);
+            ranges = kept;
+        }
+        ranges.sort_by_key(|range| range.final_start);
+        for range in ranges {
+            found(&Entry {
+                commit: hash,
+                path: path.clone(),
+                orig_start: range.orig_start,
+                final_start: range.final_start,
+                len: range.len,
+                previous: previous.clone(),
+                boundary: parents.is_empty(),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Blames `path` as of `start` like `blame`, answering from the cache when
+/// the same question has been answered under the same history, and
+/// keeping the answer otherwise. Entries come in the order `blame` found
+/// them either way.
+pub fn blame_cached(
+    git_dir: &Path,
+    start: &Oid,
+    path: &[u8],
+    mut found: impl FnMut(&Entry) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let stamp = history_stamp(git_dir)?;
+    let file = cache_path(git_dir, start, path);
+    if let Some(entries) = read_cache(&file, &stamp) {
+        for entry in &entries {
+            found(entry)?;
+        }
+        return Ok(());
+    }
+    let mut entries = vec![];
+    blame(git_dir, start, path, |entry| {
+        entries.push(entry.clone());
+        found(entry)
+    })?;
+    // Failing to keep the answer only costs time next time.
+    if !repository::is_read_only(&Config::load()?)? {
+        write_cache(&file, &stamp, &entries).ok();
+    }
+    Ok(())
+}
+
+/// Where the answer for `path` as of `start` is cached.
+fn cache_path(git_dir: &Path, start: &Oid, path: &[u8]) -> PathBuf {
+    let key = Sha1::digest([format!("{start}\0").as_bytes(), path].concat());
+    let name: String = key.iter().map(|byte| format!("{byte:02x}")).collect();
+    git_dir.join("blame-cache").join(name)
+}
+
+/// A hash of what decides the shape of history besides the commits
+/// themselves: the shallow boundary and the replacement refs.
+fn history_stamp(git_dir: &Path) -> anyhow::Result<String> {
+    let mut hasher = Sha1::new();
+    for commit in shallow::read(git_dir)? {
+        hasher.update(format!("shallow {commit}\n"));
+    }
+    for (name, value) in refs::list(git_dir)? {
+        if name.starts_with("refs/replace/") {
+            hasher.update(format!("replace {name} {value}\n"));
+        }
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/// The cached entries in `file`, if it holds an answer stamped `stamp`.
+/// Anything unreadable counts as no answer.
+fn read_cache(file: &Path, stamp: &str) -> Option<Vec<Entry>> {
+    let data = fs::read(file).ok()?;
+    let mut lines = data.strip_suffix(b"\n")?.split(|&byte| byte == b'\n');
+    if lines.next()?.strip_prefix(b"stamp ")? != stamp.as_bytes() {
+        return None;
+    }
+    let field = |bytes: &[u8]| std::str::from_utf8(bytes).ok().map(str::to_string);
+    let mut entries: Vec<Entry> = vec![];
+    for line in lines {
+        if let Some(previous) = line.strip_prefix(b"previous ") {
+            let (commit, path) = previous.split_at(previous.iter().position(|&byte| byte == b' ')?);
+            entries.last_mut()?.previous = Some((field(commit)?.parse().ok()?, path[1..].to_vec()));
+            continue;
+        }
+        let fields: Vec<&[u8]> = line.splitn(6, |&byte| byte == b' ').collect();
+        let [commit, orig_start, final_start, len, boundary, path] = fields[..] else {
+            return None;
+        };
+        entries.push(Entry {
+            commit: field(commit)?.parse().ok()?,
+            path: path.to_vec(),
+            orig_start: field(orig_start)?.parse().ok()?,
+            final_start: field(final_start)?.parse().ok()?,
+            len: field(len)?.parse().ok()?,
+            previous: None,
+            boundary: boundary == b"1",
+        });
+    }
+    Some(entries)
+}
+
+/// Keeps `entries` in `file`, stamped `stamp`. Paths with a newline in
+/// them don't fit the format and aren't cached.
+fn write_cache(file: &Path, stamp: &str, entries: &[Entry]) -> anyhow::Result<()> {
+    let mut text = format!("stamp {stamp}\n").into_bytes();
+    for entry in entries {
+        let previous = entry.previous.as_ref();
+        anyhow::ensure!(
+            !entry.path.contains(&b'\n') && previous.is_none_or(|(_, path)| !path.contains(&b'\n')),
+            "path cannot be cached"
+        );
+        let boundary = u8::from(entry.boundary);
+        text.extend(format!("{} {} {} {} {boundary} ", entry.commit, entry.orig_start, entry.final_start, entry.len).bytes());
+        text.extend([&entry.path[..], b"\n"].concat());
+        if let Some((commit, path)) = previous {
+            text.extend(format!("previous {commit} ").bytes());
+            text.extend([&path[..], b"\n"].concat());
+        }
+    }
+    let dir = file.parent().expect("cache files are in a directory");
+    fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    // Write under a temporary name so a reader never sees half an answer.
+    let temp = file.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&temp, text).with_context(|| format!("write {}", temp.display()))?;
+    fs::rename(&temp, file).with_context(|| format!("write {}", file.display()))
+}
+
+/// The lines waiting to be blamed, by the commit and path suspected of
+/// them.
+#[derive(Default)]
+struct Suspects {
+    pending: HashMap<(Oid, Vec<u8>), Vec<Range>>,
+    /// Newest commit first; among equal times, first added first.
+    queue: BinaryHeap<(i64, Reverse<usize>, Oid, Vec<u8>)>,
+    added: usize,
+}
+
+impl Suspects {
+    fn add(&mut self, commit: &Commit, hash: Oid, path: &[u8], ranges: Vec<Range>) {
+        if ranges.is_empty() {
+            return;
+        }
+        let key = (hash, path.to_vec());
+        if !self.pending.contains_key(&key) {
+            self.queue
+                .push((commit.committer.time.timestamp(), Reverse(self.added), hash, path.to_vec()));
+            self.added += 1;
+        }
+        self.pending.entry(key).or_default().extend(ranges);
+    }
+
+    /// The next suspect to examine, with its lines in file order.
+    fn pop(&mut self) -> Option<(Oid, Vec<u8>, Vec<Range>)> {
+        let (_, _, hash, path) = self.queue.pop()?;
+        let mut ranges = self.pending.remove(&(hash, path.clone())).expect("queued suspects are pending");
+        ranges.sort_by_key(|range| range.orig_start);
+        Some((hash, path, ranges))
+    }
+}
+
+/// Splits `ranges` of the `new` blob into the parts unchanged from the
+/// `old` blob, renumbered to `old`'s lines, and the parts that changed.
+fn split_ranges(ranges: &[Range], old: &Oid, new: &Oid) -> anyhow::Result<(Vec<Range>, Vec<Range>)> {
+    let old = Object::read(old)?.contents;
+    let new = Object::read(new)?.contents;
+    let new_lines = diff::split_lines(&new);
+    let edits = diff::diff_lines(&diff::split_lines(&old), &new_lines);
+    // The stretches of `new` that `old` shares: (new start, old start, len).
+    let mut common = vec![];
+    let (mut old_at, mut new_at) = (0, 0);
+    for edit in &edits {
+        common.push((new_at, old_at, edit.new_start - new_at));
+        old_at = edit.old_start + edit.old_len;
+        new_at = edit.new_start + edit.new_len;
+    }
+    common.push((new_at, old_at, new_lines.len() - new_at));
+
+    let (mut passed, mut kept) = (vec![], vec![]);
+    for range in ranges {
+        let mut at = range.orig_start;
+        let end = range.orig_start + range.len;
+        for &(common_new, common_old, len) in &common {
+            let start = common_new.max(at);
+            let stop = (common_new + len).min(end);
+            if start >= stop {
+                continue;
+            }
+            if start > at {
+                kept.push(Range {
+                    orig_start: at,
+                    final_start: range.final_start + (at - range.orig_start),
+                    len: start - at,
+                });
+            }
+            passed.push(Range {
+                orig_start: common_old + (start - common_new),
+                final_start: range.final_start + (start - range.orig_start),
+                len: stop - start,
+            });
+            at = stop;
+        }
+        if at < end {
+            kept.push(Range {
+                orig_start: at,
+                final_start: range.final_start + (at - range.orig_start),
+                len: end - at,
+            });
+        }
+    }
+    Ok((passed, kept))
+}
+
+/// Where `path` of `commit` came from in `parent`: the same path, or the
+/// path it was renamed from.
+fn parent_path(parent: &Commit, commit: &Commit, path: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+    if file_hash(&parent.tree, path)?.is_some() {
+        return Ok(Some(path.to_vec()));
+    }
+    let changes = tree_diff::detect_renames(tree_diff::diff_trees(Some(&parent.tree), Some(&commit.tree))?)?;
+    Ok(changes
+        .into_iter()
+        .find(|change| matches!(change.status, Status::Renamed(_)) && change.path == path)
+        .map(|change| change.old_path))
+}
+
+/// The blob at `path` in `tree`, if there is a file there.
+fn file_hash(tree: &Oid, path: &[u8]) -> anyhow::Result<Option<Oid>> {
+    Ok(tree::lookup(tree, path)?
+        .filter(|entry| entry.mode.kind() == Kind::Blob)
+        .map(|entry| entry.hash))
+}
+
+fn read_commit(hash: &Oid) -> anyhow::Result<Commit> {
+    let object = Object::read(hash)?;
+    anyhow::ensure!(object.kind == Kind::Commit, "object {hash} is a {}, not a commit", object.kind);
+    Commit::parse(&object.contents).with_context(|| format!("parse commit {hash}"))
+}
+
+/// Merges entries sorted by final line where one carries straight on from
+/// the other in the same commit, as git does before printing anything but
+/// incremental output.
+pub fn coalesce(entries: Vec<Entry>) -> Vec<Entry> {
+    let mut merged: Vec<Entry> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        if let Some(last) = merged.last_mut()
+            && last.commit == entry.commit
+            && last.path == entry.path
+            && last.orig_start + last.len == entry.orig_start
+            && last.final_start + last.len == entry.final_start
+        {
+            last.len += entry.len;
+            continue;
+        }
+        merged.push(entry);
+    }
+    merged
+}
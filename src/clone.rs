@@ -0,0 +1,236 @@
+//! Cloning repositories.
+//!
+//! A repository on the local filesystem has its objects copied; one at an
+//! `http://` or `https://` URL is fetched from over smart HTTP, its pack
+//! stored as it arrives. A bare
+//! (`--bare`) or mirror (`--mirror`) clone copies the object store and the
+//! refs without a working tree. A mirror copies every ref verbatim and
+//! records a `+refs/*:refs/*` fetch refspec so later fetches keep it in sync.
+//! Any other clone gets a `.got` directory, the source's branches as
+//! `refs/remotes/origin/*` and a local branch for the one the source has
+//! checked out; filling in the worktree is left to the caller.
+
+use crate::config::{self, Config};
+use crate::object::Kind;
+use crate::oid::Oid;
+use crate::refs;
+use crate::refspec::Refspec;
+use crate::remote_helper::{PackLocks, RefValue};
+use crate::repository;
+use crate::revision;
+use crate::transport::SmartHttp;
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone)]
+pub struct CloneOptions {
+    pub bare: bool,
+    pub mirror: bool,
+}
+
+/// Clones `source` into `directory` (by default named after the source) and
+/// returns the path of the new repository.
+pub fn clone(source: &str, directory: Option<&str>, options: &CloneOptions) -> anyhow::Result<PathBuf> {
+    let bare = options.bare || options.mirror;
+    let remote = source.starts_with("http://") || source.starts_with("https://");
+    anyhow::ensure!(
+        remote || !source.contains("://"),
+        "cloning from '{source}' is not supported yet; only local repositories and http(s) remotes can be cloned"
+    );
+    let source_dir = match remote {
+        true => None,
+        false => Some(find_git_dir(Path::new(source))?),
+    };
+    let directory = match directory {
+        Some(directory) => PathBuf::from(directory),
+        None => default_directory(source, bare)?,
+    };
+    let existed = directory.exists();
+    if existed {
+        let empty = fs::read_dir(&directory).map(|mut d| d.next().is_none()).unwrap_or(false);
+        anyhow::ensure!(
+            empty,
+            "destination path '{}' already exists and is not an empty directory",
+            directory.display()
+        );
+    }
+    if bare {
+        println!("Cloning into bare repository '{}'...", directory.display());
+    } else {
+        println!("Cloning into '{}'...", directory.display());
+    }
+
+    let git_dir = if bare { directory.clone() } else { directory.join(".got") };
+    fs::create_dir_all(repository::objects_dir(&git_dir))?;
+    fs::create_dir_all(git_dir.join("refs/heads"))?;
+    fs::create_dir_all(git_dir.join("refs/tags"))?;
+
+    let refspecs: Vec<Refspec> = if options.mirror {
+        vec!["+refs/*:refs/*".parse()?]
+    } else if bare {
+        vec![
+            "+refs/heads/*:refs/heads/*".parse()?,
+            "+refs/tags/*:refs/tags/*".parse()?,
+        ]
+    } else {
+        vec![
+            "+refs/heads/*:refs/remotes/origin/*".parse()?,
+            "+refs/tags/*:refs/tags/*".parse()?,
+        ]
+    };
+    let (head, head_hash) = match &source_dir {
+        Some(source_dir) => {
+            copy_objects(&source_dir.join("objects"), &repository::objects_dir(&git_dir))?;
+            for (name, value) in refs::list(source_dir)? {
+                if let Some(local) = refspecs.iter().find_map(|spec| spec.map(&name)) {
+                    refs::write(&git_dir, &local, &value)?;
+                }
+            }
+            let head = refs::read(source_dir, "HEAD")?.context("source repository has no HEAD")?;
+            (head, refs::resolve(source_dir, "HEAD")?)
+        }
+        // A clone that fails while fetching leaves nothing behind but the
+        // empty directory it was asked to clone into.
+        None => fetch_remote(source, &git_dir, &refspecs).inspect_err(|_| {
+            let _ = match existed {
+                true if !bare => fs::remove_dir_all(&git_dir),
+                true => fs::remove_dir_all(&directory).and_then(|()| fs::create_dir(&directory)),
+                false => fs::remove_dir_all(&directory),
+            };
+        })?,
+    };
+    let branch = head.strip_prefix("ref: refs/heads/").map(str::to_string);
+    refs::write(&git_dir, "HEAD", &head)?;
+
+    let url = match remote {
+        true => source.to_string(),
+        false => fs::canonicalize(source)?.display().to_string(),
+    };
+    let config_path = git_dir.join("config");
+    config::set_value(&config_path, "core.bare", if bare { "true" } else { "false" })?;
+    config::set_value(&config_path, "remote.origin.url", &url)?;
+    if options.mirror {
+        config::set_value(&config_path, "remote.origin.fetch", &refspecs[0].to_string())?;
+        config::set_value(&config_path, "remote.origin.mirror", "true")?;
+    } else if !bare {
+        config::set_value(&config_path, "remote.origin.fetch", &refspecs[0].to_string())?;
+        if let Some(branch) = branch {
+            if let Some(hash) = head_hash {
+                refs::write(&git_dir, &format!("refs/heads/{branch}"), &hash.to_string())?;
+                refs::write(&git_dir, "refs/remotes/origin/HEAD", &format!("ref: refs/remotes/origin/{branch}"))?;
+            }
+            config::set_value(&config_path, &format!("branch.{branch}.remote"), "origin")?;
+            config::set_value(&config_path, &format!("branch.{branch}.merge"), &format!("refs/heads/{branch}"))?;
+        }
+    }
+    Ok(directory)
+}
+
+/// Fetches what `refspecs` map from the smart HTTP remote at `url` into the
+/// new repository at `git_dir` and points the refs they map to at it.
+/// Returns the remote's HEAD as it would be written to a ref file, either
+/// `ref: <branch>` or a hash, and the commit it resolves to; an empty
+/// remote gets a HEAD on `main` with nothing yet.
+fn fetch_remote(url: &str, git_dir: &Path, refspecs: &[Refspec]) -> anyhow::Result<(String, Option<Oid>)> {
+    let config = Config::load_global()?;
+    let mut http = SmartHttp::new(&config, url)?;
+    let listed = http.list(&config, false)?;
+    let hashes: HashMap<&str, Oid> = listed
+        .iter()
+        .filter_map(|r| match r.value {
+            RefValue::Hash(hash) => Some((r.name.as_str(), hash)),
+            _ => None,
+        })
+        .collect();
+    let mut updates = vec![];
+    let mut wants = vec![];
+    for r in &listed {
+        let (Some(&hash), Some(local)) = (hashes.get(r.name.as_str()), refspecs.iter().find_map(|spec| spec.map(&r.name))) else {
+            continue;
+        };
+        if !wants.contains(&hash) {
+            wants.push(hash);
+        }
+        updates.push((local, hash));
+    }
+    let _locks = match wants.is_empty() {
+        true => PackLocks::default(),
+        false => http.fetch(&config, git_dir, &wants, false, true)?,
+    };
+    for (local, hash) in updates {
+        refs::write(git_dir, &local, &hash.to_string())?;
+    }
+    let head = listed.iter().find(|r| r.name == "HEAD");
+    Ok(match head.map(|head| &head.value) {
+        Some(RefValue::Symref(target)) => (format!("ref: {target}"), hashes.get(target.as_str()).copied()),
+        Some(RefValue::Hash(hash)) => (hash.to_string(), Some(*hash)),
+        Some(RefValue::Unknown) | None => {
+            eprintln!("warning: You appear to have cloned an empty repository.");
+            ("ref: refs/heads/main".to_string(), None)
+        }
+    })
+}
+
+/// Copies the objects of the local repository at `source` into this one
+/// and returns the commit its ref `name` (`main`, `refs/heads/main`, a tag)
+/// points at.
+pub fn fetch_local(source: &str, name: &str) -> anyhow::Result<Oid> {
+    let source_dir = find_git_dir(Path::new(source))?;
+    let mut hash = None;
+    for candidate in [name.to_string(), format!("refs/heads/{name}"), format!("refs/tags/{name}")] {
+        hash = refs::resolve(&source_dir, &candidate)?;
+        if hash.is_some() {
+            break;
+        }
+    }
+    let hash = hash.with_context(|| format!("couldn't find remote ref {name} in {source}"))?;
+    copy_objects(&source_dir.join("objects"), &repository::objects_dir(repository::git_dir()))?;
+    revision::peel(&hash, Some(Kind::Commit))
+}
+
+/// The repository metadata directory of the repository at `path`, which is
+/// either `path/.got` or, for a bare repository, `path` itself.
+pub fn find_git_dir(path: &Path) -> anyhow::Result<PathBuf> {
+    let dotgot = path.join(".got");
+    if dotgot.is_dir() {
+        return Ok(dotgot);
+    }
+    if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    anyhow::bail!("repository '{}' does not exist", path.display())
+}
+
+/// `foo` for `/src/foo`, `/src/foo/.got` or `https://host/foo.git`, with
+/// `.got` appended for a bare clone as bare repositories are conventionally
+/// named.
+fn default_directory(source: &str, bare: bool) -> anyhow::Result<PathBuf> {
+    let source = source.trim_end_matches('/');
+    let source = source.strip_suffix("/.got").unwrap_or(source);
+    let name = Path::new(source)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .with_context(|| format!("cannot guess a directory name from '{source}'"))?;
+    let name = name.strip_suffix(".got").or_else(|| name.strip_suffix(".git")).unwrap_or(name);
+    Ok(PathBuf::from(if bare { format!("{name}.got") } else { name.to_string() }))
+}
+
+fn copy_objects(from: &Path, to: &Path) -> anyhow::Result<()> {
+    for entry in fs::read_dir(from).with_context(|| format!("read {}", from.display()))? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)?;
+            copy_objects(&entry.path(), &target)?;
+        } else if !target.exists() {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("copy {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+//! Listing names in columns across the terminal, as `column.ui` asks, the
+//! way git lays out its branch, tag and untracked file lists.
+//!
+//! The setting is a list of words separated by spaces or commas: `always`,
+//! `never` or `auto` (only when standard output is a terminal) say whether
+//! to use columns at all; `column` fills each column before the next,
+//! `row` each row before the next and `plain` lists one name a line; and
+//! `dense` lets each column be only as wide as its widest name rather than
+//! all of them as wide as the widest name anywhere (`nodense`). Naming a
+//! layout alone means `always`. `column.<command>` overrides `column.ui`
+//! for one command, and `--column` overrides both.
+
+use crate::config::Config;
+use std::io::IsTerminal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Enabled {
+    Always,
+    Never,
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    Column,
+    Row,
+    Plain,
+}
+
+/// How a command lays out its list.
+#[derive(Debug, Clone, Copy)]
+pub struct Columns {
+    enabled: Enabled,
+    layout: Layout,
+    dense: bool,
+}
+
+impl Default for Columns {
+    fn default() -> Columns {
+        Columns {
+            enabled: Enabled::Never,
+            layout: Layout::Column,
+            dense: false,
+        }
+    }
+}
+
+impl Columns {
+    /// The layout `column.ui` and `column.<command>` set.
+    pub fn from_config(config: &Config, command: &str) -> anyhow::Result<Columns> {
+        let mut columns = Columns::default();
+        for key in ["column.ui".to_string(), format!("column.{command}")] {
+            if let Some(value) = config.get(&key) {
+                columns.apply(value).map_err(|e| e.context(format!("invalid value for '{key}'")))?;
+            }
+        }
+        Ok(columns)
+    }
+
+    /// Changes the layout as the words in `spec` say.
+    pub fn apply(&mut self, spec: &str) -> anyhow::Result<()> {
+        let (mut enabled, mut layout) = (false, false);
+        for word in spec.split([' ', ',']).filter(|word| !word.is_empty()) {
+            match word {
+                "always" => self.enabled = Enabled::Always,
+                "never" => self.enabled = Enabled::Never,
+                "auto" => self.enabled = Enabled::Auto,
+                "column" => self.layout = Layout::Column,
+                "row" => self.layout = Layout::Row,
+                "plain" => self.layout = Layout::Plain,
+                "dense" => self.dense = true,
+                "nodense" => self.dense = false,
+                _ => anyhow::bail!("unsupported option '{word}'"),
+            }
+            enabled |= matches!(word, "always" | "never" | "auto");
+            layout |= matches!(word, "column" | "row" | "plain");
+        }
+        if layout && !enabled {
+            self.enabled = Enabled::Always;
+        }
+        Ok(())
+    }
+
+    /// Takes `--column[=<spec>]` and `--no-column` into account: a bare
+    /// `--column` means `always`.
+    pub fn apply_option(&mut self, column: Option<&str>, no_column: bool) -> anyhow::Result<()> {
+        match (column, no_column) {
+            (_, true) => self.enabled = Enabled::Never,
+            (Some(""), false) => self.enabled = Enabled::Always,
+            (Some(spec), false) => self.apply(spec)?,
+            (None, false) => {}
+        }
+        Ok(())
+    }
+
+    /// Whether names go in columns rather than one to a line.
+    pub fn is_active(&self) -> bool {
+        let enabled = match self.enabled {
+            Enabled::Always => true,
+            Enabled::Never => false,
+            Enabled::Auto => std::io::stdout().is_terminal(),
+        };
+        enabled && self.layout != Layout::Plain
+    }
+
+    /// Lays out `items` in lines no wider than the terminal, each starting
+    /// with `indent` and with at least `padding` spaces between columns.
+    pub fn format(&self, items: &[String], indent: &str, padding: usize) -> String {
+        let mut out = String::new();
+        if !self.is_active() {
+            for item in items {
+                out.push_str(&format!("{indent}{item}\n"));
+            }
+            return out;
+        }
+        if items.is_empty() {
+            return out;
+        }
+        let lens: Vec<usize> = items.iter().map(|item| item.chars().count()).collect();
+        let space = terminal_width().saturating_sub(1).saturating_sub(indent.len());
+        let cell = lens.iter().max().expect("there are items") + padding;
+        let mut cols = (space / cell).max(1);
+        let mut rows = items.len().div_ceil(cols);
+        let at = |cols: usize, rows: usize, x: usize, y: usize| match self.layout {
+            Layout::Row => y * cols + x,
+            _ => x * rows + y,
+        };
+        // The widest name in each column.
+        let widths = |cols: usize, rows: usize| -> Vec<usize> {
+            (0..cols).map(|x| (0..rows).map(|y| at(cols, rows, x, y)).filter(|&i| i < items.len()).map(|i| lens[i]).max().unwrap_or(0)).collect()
+        };
+        let mut widths = match self.dense {
+            true => {
+                // Fewer rows, so more columns, while they still fit.
+                while rows > 1 {
+                    let fewer = rows - 1;
+                    let more = items.len().div_ceil(fewer);
+                    if widths(more, fewer).iter().map(|width| width + padding).sum::<usize>() > space {
+                        break;
+                    }
+                    (rows, cols) = (fewer, more);
+                }
+                widths(cols, rows)
+            }
+            false => vec![cell - padding; cols],
+        };
+        widths.iter_mut().for_each(|width| *width += padding);
+        for y in 0..rows {
+            for (x, width) in widths.iter().enumerate() {
+                let i = at(cols, rows, x, y);
+                if i >= items.len() {
+                    continue;
+                }
+                if x == 0 {
+                    out.push_str(indent);
+                }
+                out.push_str(&items[i]);
+                let last = match self.layout {
+                    Layout::Row => x == cols - 1 || i == items.len() - 1,
+                    _ => i + rows >= items.len(),
+                };
+                match last {
+                    true => out.push('\n'),
+                    false => out.push_str(&" ".repeat(width - lens[i])),
+                }
+            }
+        }
+        out
+    }
+}
+
+/// How many characters wide the terminal is: `COLUMNS` if set, else 80.
+pub fn terminal_width() -> usize {
+    std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).filter(|&width| width > 0).unwrap_or(80)
+}
@@ -0,0 +1,335 @@
+//! The commit-graph file, `objects/info/commit-graph`, in git's format.
+//!
+//! It caches what history walks need of every commit (tree, parents and
+//! date) so they don't have to inflate and parse commit objects, and adds
+//! generation numbers: the corrected commit date, which is the commit date
+//! raised where needed to be later than every parent's. A commit's
+//! corrected date is always greater than its ancestors', so an ancestry
+//! search can stop at commits whose corrected date is below the target's.
+//!
+//! The file is a header, a table of chunks and a SHA-1 trailer. The chunks
+//! are the fanout of first bytes (`OIDF`), the sorted commit IDs (`OIDL`),
+//! per-commit data (`CDAT`: tree, first two parents as positions, the
+//! topological generation and the date), the corrected date offsets
+//! (`GDA2`, with `GDO2` for offsets too big for 31 bits) and the parent
+//! lists of octopus merges (`EDGE`). Commits not in the graph, made since
+//! it was written, have an infinite generation.
+
+use crate::config::Config;
+use crate::grafts;
+use crate::object::{Commit, Kind, Object};
+use crate::oid::Oid;
+use crate::refs;
+use crate::repository;
+use crate::shallow;
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const SIGNATURE: &[u8; 4] = b"CGPH";
+const CHUNK_FANOUT: u32 = u32::from_be_bytes(*b"OIDF");
+const CHUNK_OIDS: u32 = u32::from_be_bytes(*b"OIDL");
+const CHUNK_DATA: u32 = u32::from_be_bytes(*b"CDAT");
+const CHUNK_GENERATIONS: u32 = u32::from_be_bytes(*b"GDA2");
+const CHUNK_GENERATION_OVERFLOW: u32 = u32::from_be_bytes(*b"GDO2");
+const CHUNK_EDGES: u32 = u32::from_be_bytes(*b"EDGE");
+const PARENT_NONE: u32 = 0x7000_0000;
+const EDGE_FLAG: u32 = 0x8000_0000;
+const TOPO_GENERATION_MAX: u64 = 0x3fff_ffff;
+const OFFSET_MAX: u64 = 0x7fff_ffff;
+const DATA_SIZE: usize = 36;
+
+/// What the graph knows of one commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphCommit {
+    pub tree: Oid,
+    pub parents: Vec<Oid>,
+    /// Committer date, in seconds since the epoch.
+    pub time: i64,
+    /// The corrected commit date.
+    pub generation: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitGraph {
+    /// Sorted, with `commits` in the same order.
+    oids: Vec<Oid>,
+    commits: Vec<GraphCommit>,
+}
+
+impl CommitGraph {
+    /// Builds the graph of `tips` and all of their ancestors.
+    pub fn build(tips: &[Oid]) -> anyhow::Result<CommitGraph> {
+        let mut parsed: HashMap<Oid, Commit> = HashMap::new();
+        let mut pending = tips.to_vec();
+        while let Some(hash) = pending.pop() {
+            if parsed.contains_key(&hash) {
+                continue;
+            }
+            let object = Object::read_original(&hash)?;
+            anyhow::ensure!(object.kind == Kind::Commit, "object {hash} is a {}, not a commit", object.kind);
+            let commit = Commit::parse(&object.contents).with_context(|| format!("parse commit {hash}"))?;
+            pending.extend(&commit.parents);
+            parsed.insert(hash, commit);
+        }
+
+        // Generations need every parent's first, so go parents first.
+        let mut generations: HashMap<Oid, u64> = HashMap::new();
+        let mut stack: Vec<Oid> = parsed.keys().copied().collect();
+        while let Some(hash) = stack.last().copied() {
+            if generations.contains_key(&hash) {
+                stack.pop();
+                continue;
+            }
+            let commit = &parsed[&hash];
+            let missing: Vec<Oid> = commit.parents.iter().filter(|p| !generations.contains_key(*p)).copied().collect();
+            if !missing.is_empty() {
+                stack.extend(missing);
+                continue;
+            }
+            stack.pop();
+            let time = commit.committer.time.timestamp().max(0) as u64;
+            let after_parents = commit.parents.iter().map(|p| generations[p] + 1).max().unwrap_or(0);
+            generations.insert(hash, time.max(after_parents));
+        }
+
+        let mut oids: Vec<Oid> = parsed.keys().copied().collect();
+        oids.sort();
+        let commits = oids
+            .iter()
+            .map(|hash| {
+                let commit = &parsed[hash];
+                GraphCommit {
+                    tree: commit.tree,
+                    parents: commit.parents.clone(),
+                    time: commit.committer.time.timestamp(),
+                    generation: generations[hash],
+                }
+            })
+            .collect();
+        Ok(CommitGraph { oids, commits })
+    }
+
+    /// Reads the repository's commit-graph, if it has one that may be used:
+    /// not with `core.commitGraph` false, in a shallow repository or with
+    /// replace refs or grafts, which change what a commit's parents are.
+    pub fn read(git_dir: &Path) -> anyhow::Result<Option<CommitGraph>> {
+        if !Config::load()?.get_bool("core.commitGraph")?.unwrap_or(true)
+            || shallow::is_shallow(git_dir)?
+            || refs::list(git_dir)?.iter().any(|(name, _)| name.starts_with("refs/replace/"))
+            || !grafts::read(git_dir)?.is_empty()
+        {
+            return Ok(None);
+        }
+        match fs::read(path(git_dir)) {
+            Ok(data) => Ok(Some(CommitGraph::parse(&data).context("parse commit-graph")?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context("read commit-graph"),
+        }
+    }
+
+    /// Replaces the repository's commit-graph with this one.
+    pub fn write(&self, git_dir: &Path) -> anyhow::Result<()> {
+        let path = path(git_dir);
+        fs::create_dir_all(path.parent().expect("the graph is in a directory")).context("create objects/info")?;
+        let lock = path.with_extension("lock");
+        fs::write(&lock, self.serialize()).context("write commit-graph.lock")?;
+        fs::rename(&lock, &path).context("replace commit-graph")
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<CommitGraph> {
+        anyhow::ensure!(data.len() >= 8 + 20 && &data[..4] == SIGNATURE, "not a commit-graph file");
+        anyhow::ensure!(data[4] == 1, "unsupported commit-graph version {}", data[4]);
+        anyhow::ensure!(data[5] == 1, "unsupported commit-graph hash version {}", data[5]);
+        anyhow::ensure!(data[7] == 0, "split commit-graphs are not supported");
+        let (body, checksum) = data.split_at(data.len() - 20);
+        anyhow::ensure!(Sha1::digest(body).as_slice() == checksum, "commit-graph checksum mismatch");
+
+        let mut chunks: HashMap<u32, &[u8]> = HashMap::new();
+        let table = 8;
+        for i in 0..data[6] as usize {
+            let at = table + i * 12;
+            let id = read_u32(body, at)?;
+            let start = read_u64(body, at + 4)? as usize;
+            let end = read_u64(body, at + 16)? as usize;
+            let chunk = body.get(start..end).with_context(|| format!("chunk {i} is out of bounds"))?;
+            chunks.insert(id, chunk);
+        }
+        let chunk = |id: u32, name: &str| chunks.get(&id).copied().with_context(|| format!("missing {name} chunk"));
+        let fanout = chunk(CHUNK_FANOUT, "OIDF")?;
+        anyhow::ensure!(fanout.len() == 1024, "OIDF chunk has the wrong size");
+        let count = read_u32(fanout, 1020)? as usize;
+        let oid_list = chunk(CHUNK_OIDS, "OIDL")?;
+        let data_chunk = chunk(CHUNK_DATA, "CDAT")?;
+        anyhow::ensure!(oid_list.len() == count * 20, "OIDL chunk has the wrong size");
+        anyhow::ensure!(data_chunk.len() == count * DATA_SIZE, "CDAT chunk has the wrong size");
+        let offsets = chunks.get(&CHUNK_GENERATIONS).copied();
+        let overflow = chunks.get(&CHUNK_GENERATION_OVERFLOW).copied().unwrap_or(&[]);
+        let edges = chunks.get(&CHUNK_EDGES).copied().unwrap_or(&[]);
+
+        let oids: Vec<Oid> = oid_list.chunks(20).map(Oid::from_bytes).collect::<anyhow::Result<_>>()?;
+        anyhow::ensure!(oids.windows(2).all(|pair| pair[0] < pair[1]), "commit-graph IDs are not sorted");
+        let position = |n: u32| -> anyhow::Result<Oid> {
+            oids.get(n as usize).copied().with_context(|| format!("parent position {n} is out of range"))
+        };
+        let mut commits = Vec::with_capacity(count);
+        for i in 0..count {
+            let entry = &data_chunk[i * DATA_SIZE..(i + 1) * DATA_SIZE];
+            let mut parents = vec![];
+            let first = read_u32(entry, 20)?;
+            if first != PARENT_NONE {
+                parents.push(position(first)?);
+            }
+            let second = read_u32(entry, 24)?;
+            if second & EDGE_FLAG != 0 {
+                let mut at = (second & !EDGE_FLAG) as usize;
+                loop {
+                    let edge = read_u32(edges, at * 4).context("EDGE chunk is too short")?;
+                    parents.push(position(edge & !EDGE_FLAG)?);
+                    if edge & EDGE_FLAG != 0 {
+                        break;
+                    }
+                    at += 1;
+                }
+            } else if second != PARENT_NONE {
+                parents.push(position(second)?);
+            }
+            let time = ((read_u32(entry, 28)? as u64 & 0x3) << 32 | read_u32(entry, 32)? as u64) as i64;
+            let generation = match offsets {
+                Some(offsets) => {
+                    let offset = read_u32(offsets, i * 4).context("GDA2 chunk is too short")?;
+                    let offset = if offset & EDGE_FLAG != 0 {
+                        read_u64(overflow, (offset & !EDGE_FLAG) as usize * 8).context("GDO2 chunk is too short")?
+                    } else {
+                        offset as u64
+                    };
+                    time as u64 + offset
+                }
+                // Without corrected dates, the topological generation still
+                // orders commits after their ancestors.
+                None => (read_u32(entry, 28)? >> 2) as u64,
+            };
+            commits.push(GraphCommit {
+                tree: Oid::from_bytes(&entry[..20])?,
+                parents,
+                time,
+                generation,
+            });
+        }
+        Ok(CommitGraph { oids, commits })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let position = |hash: &Oid| self.oids.binary_search(hash).expect("parents are in the graph") as u32;
+
+        let mut fanout = Vec::with_capacity(1024);
+        for byte in 0..=255u8 {
+            let count = self.oids.partition_point(|oid| oid.as_bytes()[0] <= byte);
+            fanout.extend((count as u32).to_be_bytes());
+        }
+        let oid_list: Vec<u8> = self.oids.iter().flat_map(|oid| *oid.as_bytes()).collect();
+
+        // Topological generations, for readers that predate corrected dates.
+        let mut topo: Vec<u64> = vec![0; self.commits.len()];
+        let mut order: Vec<usize> = (0..self.commits.len()).collect();
+        order.sort_by_key(|&i| self.commits[i].generation);
+        for i in order {
+            let parents = self.commits[i].parents.iter().map(|p| topo[position(p) as usize]);
+            topo[i] = (parents.max().unwrap_or(0) + 1).min(TOPO_GENERATION_MAX);
+        }
+
+        let mut data = Vec::with_capacity(self.commits.len() * DATA_SIZE);
+        let mut edges: Vec<u8> = vec![];
+        let mut offsets = Vec::with_capacity(self.commits.len() * 4);
+        let mut overflow: Vec<u8> = vec![];
+        for (i, commit) in self.commits.iter().enumerate() {
+            data.extend(commit.tree.as_bytes());
+            let first = commit.parents.first().map_or(PARENT_NONE, position);
+            let second = match &commit.parents[..] {
+                [] | [_] => PARENT_NONE,
+                [_, second] => position(second),
+                [_, rest @ ..] => {
+                    let at = edges.len() as u32 / 4;
+                    for (n, parent) in rest.iter().enumerate() {
+                        let last = if n + 1 == rest.len() { EDGE_FLAG } else { 0 };
+                        edges.extend((position(parent) | last).to_be_bytes());
+                    }
+                    at | EDGE_FLAG
+                }
+            };
+            data.extend(first.to_be_bytes());
+            data.extend(second.to_be_bytes());
+            let time = commit.time.max(0) as u64;
+            data.extend((((topo[i] << 2) | (time >> 32 & 0x3)) as u32).to_be_bytes());
+            data.extend((time as u32).to_be_bytes());
+            let offset = commit.generation - time;
+            if offset > OFFSET_MAX {
+                offsets.extend(((overflow.len() as u32 / 8) | EDGE_FLAG).to_be_bytes());
+                overflow.extend(offset.to_be_bytes());
+            } else {
+                offsets.extend((offset as u32).to_be_bytes());
+            }
+        }
+
+        let mut chunks: Vec<(u32, Vec<u8>)> = vec![
+            (CHUNK_FANOUT, fanout),
+            (CHUNK_OIDS, oid_list),
+            (CHUNK_DATA, data),
+            (CHUNK_GENERATIONS, offsets),
+        ];
+        if !overflow.is_empty() {
+            chunks.push((CHUNK_GENERATION_OVERFLOW, overflow));
+        }
+        if !edges.is_empty() {
+            chunks.push((CHUNK_EDGES, edges));
+        }
+        let mut out = SIGNATURE.to_vec();
+        out.extend([1, 1, chunks.len() as u8, 0]);
+        let mut offset = (out.len() + (chunks.len() + 1) * 12) as u64;
+        for (id, chunk) in &chunks {
+            out.extend(id.to_be_bytes());
+            out.extend(offset.to_be_bytes());
+            offset += chunk.len() as u64;
+        }
+        out.extend(0u32.to_be_bytes());
+        out.extend(offset.to_be_bytes());
+        for (_, chunk) in chunks {
+            out.extend(chunk);
+        }
+        let checksum = Sha1::digest(&out);
+        out.extend(checksum);
+        out
+    }
+
+    /// The commits in the graph, in ID order.
+    pub fn oids(&self) -> &[Oid] {
+        &self.oids
+    }
+
+    pub fn get(&self, hash: &Oid) -> Option<&GraphCommit> {
+        self.oids.binary_search(hash).ok().map(|at| &self.commits[at])
+    }
+
+    /// The corrected commit date of `hash`, infinite for commits not in
+    /// the graph.
+    pub fn generation(&self, hash: &Oid) -> u64 {
+        self.get(hash).map_or(u64::MAX, |commit| commit.generation)
+    }
+}
+
+fn path(git_dir: &Path) -> PathBuf {
+    repository::objects_dir(git_dir).join("info/commit-graph")
+}
+
+fn read_u32(data: &[u8], at: usize) -> anyhow::Result<u32> {
+    let bytes = data.get(at..at + 4).context("commit-graph is truncated")?;
+    Ok(u32::from_be_bytes(bytes.try_into().expect("slice is 4 bytes")))
+}
+
+fn read_u64(data: &[u8], at: usize) -> anyhow::Result<u64> {
+    let bytes = data.get(at..at + 8).context("commit-graph is truncated")?;
+    Ok(u64::from_be_bytes(bytes.try_into().expect("slice is 8 bytes")))
+}
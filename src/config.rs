@@ -0,0 +1,385 @@
+//! Git-style configuration files.
+//!
+//! Settings are read from the user's `~/.gotconfig` and then the repository's
+//! `.got/config` (just `config` in a bare repository), so a value set in the
+//! repository overrides the global one.
+//! Keys are addressed as `section.name` or `section.subsection.name`; section
+//! and variable names are case-insensitive, subsections are not.
+
+use anyhow::Context;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    entries: Vec<(String, String)>,
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        let mut config = Config::load_global()?;
+        config.read_file(&crate::repository::git_dir().join("config"))?;
+        Ok(config)
+    }
+
+    /// The user's settings alone, for work outside any repository.
+    pub fn load_global() -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        if let Some(path) = global_path() {
+            config.read_file(&path)?;
+        }
+        Ok(config)
+    }
+
+    /// Appends the settings in `path`, ignoring the file if it doesn't exist.
+    pub fn read_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("read config file {}", path.display()));
+            }
+        };
+        self.parse(&text)
+            .with_context(|| format!("parse config file {}", path.display()))
+    }
+
+    pub fn parse(&mut self, text: &str) -> anyhow::Result<()> {
+        let mut section: Option<String> = None;
+        let mut lines = text.lines().enumerate();
+        while let Some((n, line)) = lines.next() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix('[') {
+                let Some((header, _)) = rest.split_once(']') else {
+                    anyhow::bail!("line {}: unterminated section header", n + 1);
+                };
+                section = Some(parse_section(header).with_context(|| format!("line {}", n + 1))?);
+                continue;
+            }
+            let Some(section) = &section else {
+                anyhow::bail!("line {}: variable outside of a section", n + 1);
+            };
+            let (name, raw) = match line.split_once('=') {
+                Some((name, raw)) => (name.trim(), Some(raw)),
+                None => (line.trim(), None),
+            };
+            anyhow::ensure!(
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'),
+                "line {}: invalid variable name '{name}'",
+                n + 1
+            );
+            let value = match raw {
+                // A bare variable name is shorthand for `name = true`.
+                None => "true".to_string(),
+                Some(raw) => parse_value(raw, &mut lines).with_context(|| format!("line {}", n + 1))?,
+            };
+            self.entries
+                .push((format!("{section}.{}", name.to_ascii_lowercase()), value));
+        }
+        Ok(())
+    }
+
+    /// The last value set for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value set for a multi-valued `key`, in the order they were read.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// The distinct subsection names of `section`, in the order first read:
+    /// `origin` for `[remote "origin"]`.
+    pub fn subsections(&self, section: &str) -> Vec<&str> {
+        let prefix = format!("{}.", section.to_ascii_lowercase());
+        let mut names: Vec<&str> = vec![];
+        for (key, _) in &self.entries {
+            let Some((subsection, _)) = key.strip_prefix(&prefix).and_then(|rest| rest.rsplit_once('.')) else {
+                continue;
+            };
+            if !names.contains(&subsection) {
+                names.push(subsection);
+            }
+        }
+        names
+    }
+
+    pub fn get_bool(&self, key: &str) -> anyhow::Result<Option<bool>> {
+        self.get(key)
+            .map(|v| parse_bool(v).with_context(|| format!("bad boolean config value for '{key}'")))
+            .transpose()
+    }
+
+    pub fn get_int(&self, key: &str) -> anyhow::Result<Option<i64>> {
+        self.get(key)
+            .map(|v| parse_int(v).with_context(|| format!("bad numeric config value for '{key}'")))
+            .transpose()
+    }
+
+    /// Looks up `section.<url>.name`, where the subsection that is the longest
+    /// prefix of `url` wins, falling back to the plain `section.name`. This is
+    /// how settings such as `http.<url>.proxy` are scoped to a single remote.
+    pub fn get_for_url(&self, section: &str, url: &str, name: &str) -> Option<&str> {
+        let section = section.to_ascii_lowercase();
+        let name = name.to_ascii_lowercase();
+        let mut best: Option<(usize, &str)> = None;
+        for (key, value) in &self.entries {
+            let Some(rest) = key.strip_prefix(&section).and_then(|k| k.strip_prefix('.')) else {
+                continue;
+            };
+            let Some(subsection) = rest.strip_suffix(&name).and_then(|k| k.strip_suffix('.')) else {
+                continue;
+            };
+            if !url.starts_with(subsection.trim_end_matches('/')) {
+                continue;
+            }
+            if best.is_none_or(|(len, _)| subsection.len() >= len) {
+                best = Some((subsection.len(), value));
+            }
+        }
+        best.map(|(_, v)| v)
+            .or_else(|| self.get(&format!("{section}.{name}")))
+    }
+}
+
+fn global_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".gotconfig"))
+}
+
+fn normalize_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) if first != last => format!(
+            "{}{}{}",
+            key[..first].to_ascii_lowercase(),
+            &key[first..last],
+            key[last..].to_ascii_lowercase()
+        ),
+        _ => key.to_ascii_lowercase(),
+    }
+}
+
+fn parse_section(header: &str) -> anyhow::Result<String> {
+    let header = header.trim();
+    let (name, subsection) = match header.split_once(char::is_whitespace) {
+        Some((name, rest)) => {
+            let rest = rest.trim();
+            let Some(quoted) = rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')) else {
+                anyhow::bail!("subsection name must be quoted: '{rest}'");
+            };
+            (name, Some(quoted.replace("\\\"", "\"").replace("\\\\", "\\")))
+        }
+        // The deprecated `[section.subsection]` form, where the subsection is
+        // case-insensitive.
+        None => match header.split_once('.') {
+            Some((name, sub)) => (name, Some(sub.to_ascii_lowercase())),
+            None => (header, None),
+        },
+    };
+    anyhow::ensure!(
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.'),
+        "invalid section name '{name}'"
+    );
+    let name = name.to_ascii_lowercase();
+    Ok(match subsection {
+        Some(subsection) => format!("{name}.{subsection}"),
+        None => name,
+    })
+}
+
+fn parse_value<'a>(
+    raw: &'a str,
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> anyhow::Result<String> {
+    let mut value = String::new();
+    // Length of `value` up to its last quoted or escaped character, so
+    // trailing unquoted whitespace can be trimmed without touching it.
+    let mut keep = 0;
+    let mut quoted = false;
+    let mut line = raw.trim_start();
+    loop {
+        let mut chars = line.chars();
+        let mut continued = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '#' | ';' if !quoted => break,
+                '\\' => match chars.next() {
+                    None => {
+                        continued = true;
+                        break;
+                    }
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    Some(c) => anyhow::bail!("invalid escape sequence '\\{c}'"),
+                },
+                c => {
+                    value.push(c);
+                    if !quoted && c.is_whitespace() {
+                        continue;
+                    }
+                }
+            }
+            keep = value.len();
+        }
+        if !continued {
+            break;
+        }
+        match lines.next() {
+            Some((_, next)) => line = next,
+            None => break,
+        }
+    }
+    anyhow::ensure!(!quoted, "unterminated quoted value");
+    value.truncate(keep);
+    Ok(value)
+}
+
+pub fn parse_bool(value: &str) -> anyhow::Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        _ => anyhow::bail!("'{value}' is not a boolean"),
+    }
+}
+
+pub fn parse_int(value: &str) -> anyhow::Result<i64> {
+    let value = value.trim();
+    let (digits, scale) = match value.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('k') => (&value[..value.len() - 1], 1 << 10),
+        Some('m') => (&value[..value.len() - 1], 1 << 20),
+        Some('g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    let n = digits
+        .parse::<i64>()
+        .with_context(|| format!("'{value}' is not a number"))?;
+    n.checked_mul(scale)
+        .with_context(|| format!("'{value}' is out of range"))
+}
+
+/// Parses an expiry age such as `2.weeks.ago` or `3 days ago`; `now` is
+/// no age at all and `never` gives `None`.
+pub fn parse_age(value: &str) -> anyhow::Result<Option<Duration>> {
+    match value {
+        "never" => return Ok(None),
+        "now" => return Ok(Some(Duration::ZERO)),
+        _ => {}
+    }
+    let words: Vec<&str> = value.split(['.', ' ']).filter(|word| !word.is_empty()).collect();
+    let [count, unit, "ago"] = words[..] else {
+        anyhow::bail!("expected '<count>.<unit>.ago'");
+    };
+    let count = u64::try_from(parse_int(count)?).context("negative age")?;
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => anyhow::bail!("unknown unit '{unit}'"),
+    };
+    Ok(Some(Duration::from_secs(count * seconds)))
+}
+
+/// Sets `key` to `value` in the config file at `path`, replacing any values
+/// it already has there.
+pub fn set_value(path: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+    edit_file(path, key, value, true)
+}
+
+/// Adds another value for a multi-valued `key` to the config file at `path`.
+pub fn add_value(path: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+    edit_file(path, key, value, false)
+}
+
+fn edit_file(path: &Path, key: &str, value: &str, replace: bool) -> anyhow::Result<()> {
+    let (Some(first), Some(last)) = (key.find('.'), key.rfind('.')) else {
+        anyhow::bail!("key does not contain a section: '{key}'");
+    };
+    let section = key[..first].to_ascii_lowercase();
+    let subsection = (first != last).then(|| &key[first + 1..last]);
+    let name = key[last + 1..].to_ascii_lowercase();
+    let wanted = match subsection {
+        Some(subsection) => format!("{section}.{subsection}"),
+        None => section.clone(),
+    };
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("read config file {}", path.display())),
+    };
+    let mut lines: Vec<String> = vec![];
+    let mut in_section = false;
+    let mut insert_at = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(rest) = trimmed.strip_prefix('[') {
+            in_section = rest
+                .split_once(']')
+                .and_then(|(header, _)| parse_section(header).ok())
+                .is_some_and(|s| s == wanted);
+        } else if in_section && replace {
+            let var = trimmed.split(['=', ' ', '\t']).next().unwrap_or("");
+            if var.eq_ignore_ascii_case(&name) {
+                continue;
+            }
+        }
+        lines.push(line.to_string());
+        if in_section {
+            insert_at = Some(lines.len());
+        }
+    }
+    let entry = format!("\t{name} = {}", quote_value(value));
+    match insert_at {
+        Some(i) => lines.insert(i, entry),
+        None => {
+            lines.push(match subsection {
+                Some(subsection) => format!(
+                    "[{section} \"{}\"]",
+                    subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                ),
+                None => format!("[{section}]"),
+            });
+            lines.push(entry);
+        }
+    }
+    let mut text = lines.join("\n");
+    text.push('\n');
+    fs::write(path, text).with_context(|| format!("write config file {}", path.display()))
+}
+
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if value != value.trim() || value.contains(['#', ';']) {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
@@ -0,0 +1,117 @@
+//! Converting file contents as attributes ask.
+//!
+//! The `filter` attribute names a driver whose `filter.<driver>.smudge`
+//! command turns a blob into what is checked out; it reads the blob on
+//! standard input and writes the file, with `%f` in the command standing
+//! for the path. A failing filter leaves the contents as they are unless
+//! `filter.<driver>.required` is set.
+//!
+//! The `diff` attribute names a driver whose `diff.<driver>.textconv`
+//! command turns a blob into text for showing in diffs, say the words of a
+//! word processor document rather than that it is binary. The command is
+//! given a temporary file holding the blob as it would be checked out and
+//! writes the text.
+
+use crate::attributes::Attributes;
+use crate::config::Config;
+use crate::path;
+use crate::temp::TempDir;
+use anyhow::Context;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+pub struct Converter {
+    config: Config,
+    attributes: Attributes,
+}
+
+impl Converter {
+    pub fn new(config: Config, attributes: Attributes) -> Converter {
+        Converter { config, attributes }
+    }
+
+    /// The converter for the repository at `git_dir` and its worktree.
+    pub fn load(git_dir: &Path) -> anyhow::Result<Converter> {
+        Ok(Converter::new(Config::load()?, Attributes::load(git_dir)?))
+    }
+
+    /// `contents` as they are to be checked out at `path`.
+    pub fn smudge(&self, path: &[u8], contents: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let Some(driver) = self.attributes.value(path, "filter") else {
+            return Ok(contents);
+        };
+        let path = path::display(path);
+        let required = self.config.get_bool(&format!("filter.{driver}.required"))?.unwrap_or(false);
+        let Some(command) = self.config.get(&format!("filter.{driver}.smudge")) else {
+            anyhow::ensure!(!required, "{path}: smudge filter {driver} failed");
+            return Ok(contents);
+        };
+        let command = command.replace("%f", &shell_quote(&path));
+        match run_filter(&command, &contents) {
+            Ok(filtered) => Ok(filtered),
+            Err(e) if required => Err(e).with_context(|| format!("{path}: smudge filter {driver} failed")),
+            Err(e) => {
+                eprintln!("error: {e:#}");
+                Ok(contents)
+            }
+        }
+    }
+
+    /// `contents` of `path` converted to text by its textconv command, or
+    /// `None` if it has none.
+    pub fn textconv(&self, path: &[u8], contents: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(driver) = self.attributes.value(path, "diff") else {
+            return Ok(None);
+        };
+        let Some(command) = self.config.get(&format!("diff.{driver}.textconv")) else {
+            return Ok(None);
+        };
+        let temp = TempDir::new("textconv")?;
+        // Keep the file name, so the command can tell the file type.
+        let file = temp.path().join(path::to_path(path::file_name(path)));
+        fs::write(&file, self.smudge(path, contents.to_vec())?).with_context(|| format!("write {}", file.display()))?;
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{command} \"$@\""))
+            .arg(command)
+            .arg(&file)
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("run textconv '{command}'"))?;
+        anyhow::ensure!(
+            output.status.success(),
+            "unable to read files to diff: textconv '{command}' failed for {}",
+            path::display(path)
+        );
+        Ok(Some(output.stdout))
+    }
+}
+
+/// Runs filter `command` with `input` on its standard input, returning
+/// what it writes.
+fn run_filter(command: &str, input: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("cannot fork to run external filter '{command}'"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Feed the input while reading the output, so neither pipe fills up.
+    let output = thread::scope(|scope| {
+        scope.spawn(move || stdin.write_all(input));
+        child.wait_with_output()
+    })
+    .with_context(|| format!("read from external filter '{command}' failed"))?;
+    anyhow::ensure!(output.status.success(), "external filter '{command}' failed");
+    Ok(output.stdout)
+}
+
+/// `text` quoted for the shell.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
@@ -0,0 +1,177 @@
+//! The `cache` credential helper, which keeps credentials in the memory of
+//! a daemon for a while (15 minutes unless `--timeout` says otherwise)
+//! rather than on disk.
+//!
+//! The first `store` starts the daemon, which listens on a Unix socket in
+//! `$XDG_CACHE_HOME/got/credential/` (or one given with `--socket`) and
+//! exits once every credential it holds has expired. Each request is a
+//! connection carrying `action=<action>` and `timeout=<seconds>` lines and
+//! the credential; the answer to a `get` is the username and password.
+
+use crate::credential::Credential;
+use anyhow::Context;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TIMEOUT: u64 = 900;
+
+/// Where the daemon listens unless told otherwise.
+pub fn default_socket() -> anyhow::Result<PathBuf> {
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(cache) => PathBuf::from(cache),
+        None => PathBuf::from(std::env::var_os("HOME").context("HOME is not set")?).join(".cache"),
+    };
+    Ok(cache.join("got/credential/socket"))
+}
+
+/// Runs the `cache` helper's `action` on the credential read from `input`,
+/// writing what `get` finds to `out`.
+pub fn cache_helper(socket: &Path, timeout: u64, action: &str, input: &mut impl BufRead, out: &mut impl Write) -> anyhow::Result<()> {
+    let credential = Credential::read(input)?;
+    let mut request = format!("action={action}\ntimeout={timeout}\n").into_bytes();
+    credential.write(&mut request)?;
+    request.push(b'\n');
+    match action {
+        "get" | "erase" | "exit" => {
+            // Without a daemon there is nothing cached.
+            if let Ok(answer) = send(socket, &request) {
+                out.write_all(&answer)?;
+            }
+        }
+        "store" if send(socket, &request).is_err() => {
+            spawn_daemon(socket)?;
+            send(socket, &request).context("unable to connect to cache daemon")?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn send(socket: &Path, request: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(request)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut answer = vec![];
+    stream.read_to_end(&mut answer)?;
+    Ok(answer)
+}
+
+/// Starts the daemon in the background and waits until it is listening.
+fn spawn_daemon(socket: &Path) -> anyhow::Result<()> {
+    let got = std::env::current_exe().context("find got executable")?;
+    let mut child = Command::new(got)
+        .arg("credential-cache--daemon")
+        .arg(socket)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        // The daemon outlives the command that started it, and shouldn't
+        // get the terminal's signals or hold on to its output.
+        .process_group(0)
+        .spawn()
+        .context("start cache daemon")?;
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().expect("stdout is piped")).read_line(&mut line)?;
+    anyhow::ensure!(line == "ok\n", "cache daemon did not start");
+    Ok(())
+}
+
+/// Serves requests on `socket` until no credentials are left in the cache.
+pub fn daemon(socket: &Path) -> anyhow::Result<()> {
+    if let Some(dir) = socket.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("create {}", dir.display()))?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    // A socket left by a daemon that died would keep us from listening.
+    if UnixStream::connect(socket).is_err() {
+        fs::remove_file(socket).ok();
+    }
+    let listener = UnixListener::bind(socket).with_context(|| format!("unable to bind to '{}'", socket.display()))?;
+    println!("ok");
+    io::stdout().flush()?;
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if sender.send(stream).is_err() {
+                break;
+            }
+        }
+    });
+    let mut cache: Vec<(Credential, Instant)> = vec![];
+    loop {
+        let now = Instant::now();
+        cache.retain(|(_, expiry)| *expiry > now);
+        let wait = match cache.iter().map(|(_, expiry)| *expiry).min() {
+            Some(expiry) => expiry - now,
+            // Give the first store a moment to arrive.
+            None => Duration::from_secs(30),
+        };
+        let stream = match receiver.recv_timeout(wait) {
+            Ok(stream) => stream,
+            Err(mpsc::RecvTimeoutError::Timeout) if cache.is_empty() => break,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        // A client that goes away mid-request just loses its answer.
+        if let Ok(true) = serve(stream, &mut cache) {
+            break;
+        }
+        if cache.is_empty() {
+            break;
+        }
+    }
+    fs::remove_file(socket).ok();
+    Ok(())
+}
+
+/// Answers one request, returning whether it asked the daemon to exit.
+fn serve(stream: UnixStream, cache: &mut Vec<(Credential, Instant)>) -> anyhow::Result<bool> {
+    let mut request = String::new();
+    (&stream).read_to_string(&mut request)?;
+    let (mut action, mut timeout) = ("", DEFAULT_TIMEOUT);
+    let mut rest = String::new();
+    for line in request.lines() {
+        if let Some(value) = line.strip_prefix("action=") {
+            action = value;
+        } else if let Some(value) = line.strip_prefix("timeout=") {
+            timeout = value.parse().context("invalid timeout")?;
+        } else {
+            rest.push_str(line);
+            rest.push('\n');
+        }
+    }
+    let credential = Credential::read(&mut rest.as_bytes())?;
+    let mut out = &stream;
+    match action {
+        "get" => {
+            if let Some((found, _)) = cache.iter().find(|(cached, _)| credential.matches(cached, false)) {
+                writeln!(out, "username={}", found.username.as_deref().unwrap_or_default())?;
+                writeln!(out, "password={}", found.password.as_deref().unwrap_or_default())?;
+            }
+        }
+        "store" => {
+            let pattern = Credential { password: None, ..credential.clone() };
+            cache.retain(|(cached, _)| !pattern.matches(cached, false));
+            if credential.is_complete() {
+                cache.push((credential, Instant::now() + Duration::from_secs(timeout)));
+            }
+        }
+        "erase" => cache.retain(|(cached, _)| !credential.matches(cached, true)),
+        "exit" => return Ok(true),
+        _ => {}
+    }
+    Ok(false)
+}
@@ -0,0 +1,1185 @@
+//! Line diffs of file contents and unified patches.
+//!
+//! The line matching follows git's xdiff closely so that patches come out
+//! the same as `git diff` prints them: common leading and trailing lines are
+//! trimmed, lines with no counterpart on the other side are set aside before
+//! running Myers' algorithm on the rest, and each run of changed lines is
+//! then slid to its canonical position.
+
+use crate::convert::Converter;
+use crate::index;
+use crate::object::Object;
+use crate::oid::Oid;
+use crate::path;
+use crate::quote::quote_path;
+use crate::tree::MODE_SUBMODULE;
+use crate::tree_diff::{Change, Status};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs;
+
+/// Lines of context around each change in a hunk.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// How many leading bytes are checked for NULs to decide a file is binary.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Longest function name shown after a hunk header.
+const FUNC_NAME_LEN: usize = 80;
+
+/// Splits `data` into lines, each keeping its `\n`; only the last line may
+/// lack one.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Whether `data` looks binary, which git decides by a NUL in the first
+/// 8000 bytes.
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// A run of changed lines: `old_len` lines at `old_start` replaced by
+/// `new_len` lines at `new_start` (0-based).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edit {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+/// The changes turning `old` into `new`, in order.
+pub fn diff_lines<'a>(old: &[&'a [u8]], new: &[&'a [u8]]) -> Vec<Edit> {
+    let mut ids: HashMap<&'a [u8], usize> = HashMap::new();
+    let mut intern = |lines: &[&'a [u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|line| {
+                let next = ids.len();
+                *ids.entry(line).or_insert(next)
+            })
+            .collect()
+    };
+    let a = intern(old);
+    let b = intern(new);
+    let (mut changed_a, mut changed_b) = mark_changes(&a, &b);
+    compact(old, &a, &mut changed_a, &changed_b);
+    compact(new, &b, &mut changed_b, &changed_a);
+
+    let mut edits = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if changed_a[i + 1] || changed_b[j + 1] {
+            let (old_start, new_start) = (i, j);
+            while changed_a[i + 1] {
+                i += 1;
+            }
+            while changed_b[j + 1] {
+                j += 1;
+            }
+            edits.push(Edit {
+                old_start,
+                old_len: i - old_start,
+                new_start,
+                new_len: j - new_start,
+            });
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+    edits
+}
+
+/// Flags the changed lines of each side. The flags are offset by one, with
+/// a `false` sentinel at each end, so that runs can be scanned without
+/// bounds checks.
+fn mark_changes(a: &[usize], b: &[usize]) -> (Vec<bool>, Vec<bool>) {
+    let mut changed_a = vec![false; a.len() + 2];
+    let mut changed_b = vec![false; b.len() + 2];
+
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_end, b_end) = (a.len() - suffix, b.len() - suffix);
+
+    // Lines that can't match anything are changed regardless, and leaving
+    // them out keeps Myers' algorithm from pairing up lines across them.
+    let mut counts: HashMap<usize, (usize, usize)> = HashMap::new();
+    for &line in a {
+        counts.entry(line).or_default().0 += 1;
+    }
+    for &line in b {
+        counts.entry(line).or_default().1 += 1;
+    }
+    let keep_a = keep_lines(&a[prefix..a_end], |line| counts[&line].1, a.len());
+    let keep_b = keep_lines(&b[prefix..b_end], |line| counts[&line].0, b.len());
+    let mut index_a = vec![];
+    for (i, keep) in keep_a.into_iter().enumerate() {
+        if keep {
+            index_a.push(prefix + i);
+        } else {
+            changed_a[prefix + i + 1] = true;
+        }
+    }
+    let mut index_b = vec![];
+    for (i, keep) in keep_b.into_iter().enumerate() {
+        if keep {
+            index_b.push(prefix + i);
+        } else {
+            changed_b[prefix + i + 1] = true;
+        }
+    }
+
+    let lines_a: Vec<usize> = index_a.iter().map(|&i| a[i]).collect();
+    let lines_b: Vec<usize> = index_b.iter().map(|&i| b[i]).collect();
+    let mut myers = Myers::new(&lines_a, &lines_b);
+    myers.compare(0, lines_a.len(), 0, lines_b.len());
+    for (i, changed) in myers.changed_a.into_iter().enumerate() {
+        if changed {
+            changed_a[index_a[i] + 1] = true;
+        }
+    }
+    for (j, changed) in myers.changed_b.into_iter().enumerate() {
+        if changed {
+            changed_b[index_b[j] + 1] = true;
+        }
+    }
+    (changed_a, changed_b)
+}
+
+/// Decides which lines of one side take part in the Myers comparison, given
+/// how often each appears on the other side. Lines with no match never do;
+/// lines matching very often only do when they aren't in the middle of a
+/// stretch of unmatched lines.
+fn keep_lines(lines: &[usize], matches: impl Fn(usize) -> usize, total: usize) -> Vec<bool> {
+    const SCAN_WINDOW: usize = 100;
+    const KEEP_RUN: usize = 4;
+    let limit = isqrt_approx(total).min(1024);
+    // 0: no match, 1: ordinary, 2: matches many times.
+    let class: Vec<u8> = lines
+        .iter()
+        .map(|&line| match matches(line) {
+            0 => 0,
+            n if n >= limit => 2,
+            _ => 1,
+        })
+        .collect();
+    let discard_multimatch = |i: usize| -> bool {
+        let start = i.saturating_sub(SCAN_WINDOW);
+        let end = (i + SCAN_WINDOW).min(class.len() - 1);
+        let (mut unmatched_before, mut multi_before) = (0, 1);
+        for r in (start..i).rev() {
+            match class[r] {
+                0 => unmatched_before += 1,
+                2 => multi_before += 1,
+                _ => break,
+            }
+        }
+        if unmatched_before == 0 {
+            return false;
+        }
+        let (mut unmatched_after, mut multi_after) = (0, 1);
+        for &c in &class[i + 1..=end] {
+            match c {
+                0 => unmatched_after += 1,
+                2 => multi_after += 1,
+                _ => break,
+            }
+        }
+        if unmatched_after == 0 {
+            return false;
+        }
+        let unmatched = unmatched_before + unmatched_after;
+        let multi = multi_before + multi_after;
+        multi * KEEP_RUN < multi + unmatched
+    };
+    (0..lines.len())
+        .map(|i| class[i] == 1 || (class[i] == 2 && !discard_multimatch(i)))
+        .collect()
+}
+
+/// The rough square root xdiff uses to decide when a line matches "many"
+/// times.
+fn isqrt_approx(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// Myers' algorithm in linear space, splitting each box at the middle of
+/// an optimal path and recursing on both halves.
+struct Myers<'a> {
+    a: &'a [usize],
+    b: &'a [usize],
+    changed_a: Vec<bool>,
+    changed_b: Vec<bool>,
+    /// Furthest x reached on each diagonal `x - y`, forwards and backwards,
+    /// indexed from `offset`.
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+}
+
+impl<'a> Myers<'a> {
+    fn new(a: &'a [usize], b: &'a [usize]) -> Myers<'a> {
+        let size = a.len() + b.len() + 3;
+        Myers {
+            a,
+            b,
+            changed_a: vec![false; a.len()],
+            changed_b: vec![false; b.len()],
+            forward: vec![0; size],
+            backward: vec![0; size],
+            offset: b.len() as isize + 1,
+        }
+    }
+
+    fn compare(&mut self, mut a_lo: usize, mut a_hi: usize, mut b_lo: usize, mut b_hi: usize) {
+        while a_lo < a_hi && b_lo < b_hi && self.a[a_lo] == self.b[b_lo] {
+            a_lo += 1;
+            b_lo += 1;
+        }
+        while a_lo < a_hi && b_lo < b_hi && self.a[a_hi - 1] == self.b[b_hi - 1] {
+            a_hi -= 1;
+            b_hi -= 1;
+        }
+        if a_lo == a_hi {
+            self.changed_b[b_lo..b_hi].fill(true);
+        } else if b_lo == b_hi {
+            self.changed_a[a_lo..a_hi].fill(true);
+        } else {
+            let (x, y) = self.split(a_lo as isize, a_hi as isize, b_lo as isize, b_hi as isize);
+            self.compare(a_lo, x, b_lo, y);
+            self.compare(x, a_hi, y, b_hi);
+        }
+    }
+
+    /// A point on an optimal path through the box, found where the forward
+    /// and backward searches meet.
+    fn split(&mut self, a_lo: isize, a_hi: isize, b_lo: isize, b_hi: isize) -> (usize, usize) {
+        let (a, b, off) = (self.a, self.b, self.offset);
+        let (d_min, d_max) = (a_lo - b_hi, a_hi - b_lo);
+        let (f_mid, b_mid) = (a_lo - b_lo, a_hi - b_hi);
+        let odd = (f_mid - b_mid) & 1 != 0;
+        let (mut f_min, mut f_max) = (f_mid, f_mid);
+        let (mut bk_min, mut bk_max) = (b_mid, b_mid);
+        self.forward[(off + f_mid) as usize] = a_lo;
+        self.backward[(off + b_mid) as usize] = a_hi;
+        loop {
+            if f_min > d_min {
+                f_min -= 1;
+                self.forward[(off + f_min - 1) as usize] = -1;
+            } else {
+                f_min += 1;
+            }
+            if f_max < d_max {
+                f_max += 1;
+                self.forward[(off + f_max + 1) as usize] = -1;
+            } else {
+                f_max -= 1;
+            }
+            let mut d = f_max;
+            while d >= f_min {
+                let (below, above) = (self.forward[(off + d - 1) as usize], self.forward[(off + d + 1) as usize]);
+                let mut x = if below >= above { below + 1 } else { above };
+                let mut y = x - d;
+                while x < a_hi && y < b_hi && a[x as usize] == b[y as usize] {
+                    x += 1;
+                    y += 1;
+                }
+                self.forward[(off + d) as usize] = x;
+                if odd && bk_min <= d && d <= bk_max && self.backward[(off + d) as usize] <= x {
+                    return (x as usize, y as usize);
+                }
+                d -= 2;
+            }
+
+            if bk_min > d_min {
+                bk_min -= 1;
+                self.backward[(off + bk_min - 1) as usize] = isize::MAX;
+            } else {
+                bk_min += 1;
+            }
+            if bk_max < d_max {
+                bk_max += 1;
+                self.backward[(off + bk_max + 1) as usize] = isize::MAX;
+            } else {
+                bk_max -= 1;
+            }
+            let mut d = bk_max;
+            while d >= bk_min {
+                let (below, above) = (self.backward[(off + d - 1) as usize], self.backward[(off + d + 1) as usize]);
+                let mut x = if below < above { below } else { above - 1 };
+                let mut y = x - d;
+                while x > a_lo && y > b_lo && a[x as usize - 1] == b[y as usize - 1] {
+                    x -= 1;
+                    y -= 1;
+                }
+                self.backward[(off + d) as usize] = x;
+                if !odd && f_min <= d && d <= f_max && x <= self.forward[(off + d) as usize] {
+                    return (x as usize, y as usize);
+                }
+                d -= 2;
+            }
+        }
+    }
+}
+
+/// Slides each run of changed lines to its canonical position: lined up
+/// with a change on the other side if it can be, or else wherever the
+/// indentation around it reads best. Runs that meet along the way merge.
+/// `text` holds the lines of one side and `lines` their interned IDs;
+/// `changed` and `other` are offset by one as in `mark_changes`.
+fn compact(text: &[&[u8]], lines: &[usize], changed: &mut [bool], other: &[bool]) {
+    let n = lines.len();
+    let mut g = Group::first(changed);
+    let mut go = Group::first(other);
+    loop {
+        if g.end != g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let size = g.end - g.start;
+                end_matching_other = None;
+                while g.slide_up(lines, changed) {
+                    go.previous(other);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = Some(g.end);
+                }
+                while g.slide_down(lines, changed) {
+                    go.next(other, other.len() - 2);
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                }
+                if size == g.end - g.start {
+                    break;
+                }
+            }
+            if g.end == earliest_end {
+                // The run can't move.
+            } else if end_matching_other.is_some() {
+                while go.end == go.start {
+                    g.slide_up(lines, changed);
+                    go.previous(other);
+                }
+            } else {
+                let size = g.end - g.start;
+                let first = earliest_end.max(g.start.saturating_sub(1)).max(g.end.saturating_sub(MAX_SLIDING));
+                let mut best: Option<(usize, SplitScore)> = None;
+                for shift in first..=g.end {
+                    let mut score = SplitScore::default();
+                    score.add(&measure_split(text, shift));
+                    score.add(&measure_split(text, shift - size));
+                    if best.as_ref().is_none_or(|(_, best)| score.cmp(best) <= 0) {
+                        best = Some((shift, score));
+                    }
+                }
+                let best = best.map_or(g.end, |(shift, _)| shift);
+                while g.end > best {
+                    g.slide_up(lines, changed);
+                    go.previous(other);
+                }
+            }
+        }
+        if !g.next(changed, n) {
+            break;
+        }
+        go.next(other, other.len() - 2);
+    }
+}
+
+/// How far back the indent heuristic looks for a better position.
+const MAX_SLIDING: usize = 100;
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+
+/// What surrounds the split between line `split - 1` and line `split`.
+/// Indents are -1 for blank lines or when there is no such line.
+struct Split {
+    end_of_file: bool,
+    /// The indent of the line just after the split.
+    indent: i32,
+    /// Blank lines just above the split.
+    pre_blank: i32,
+    /// The indent of the nearest non-blank line above the split.
+    pre_indent: i32,
+    /// Blank lines after the line just after the split.
+    post_blank: i32,
+    /// The indent of the nearest non-blank line after that.
+    post_indent: i32,
+}
+
+/// A line's indent with tabs to multiples of 8, or -1 if it's blank.
+fn indent(line: &[u8]) -> i32 {
+    let mut indent = 0;
+    for &b in line {
+        match b {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            b'\n' | b'\r' | 0x0b | 0x0c => {}
+            _ => return indent,
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+fn measure_split(text: &[&[u8]], split: usize) -> Split {
+    let (end_of_file, at) = match text.get(split) {
+        Some(line) => (false, indent(line)),
+        None => (true, -1),
+    };
+    let (mut pre_blank, mut pre_indent) = (0, -1);
+    for line in text[..split.min(text.len())].iter().rev() {
+        pre_indent = indent(line);
+        if pre_indent != -1 {
+            break;
+        }
+        pre_blank += 1;
+        if pre_blank == MAX_BLANKS {
+            pre_indent = 0;
+            break;
+        }
+    }
+    let (mut post_blank, mut post_indent) = (0, -1);
+    for line in text.iter().skip(split + 1) {
+        post_indent = indent(line);
+        if post_indent != -1 {
+            break;
+        }
+        post_blank += 1;
+        if post_blank == MAX_BLANKS {
+            post_indent = 0;
+            break;
+        }
+    }
+    Split {
+        end_of_file,
+        indent: at,
+        pre_blank,
+        pre_indent,
+        post_blank,
+        post_indent,
+    }
+}
+
+/// How bad a position for a run of changes is, from the two splits at its
+/// ends; lower is better. The weights are xdiff's.
+#[derive(Default)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    fn add(&mut self, m: &Split) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += 1;
+        }
+        if m.end_of_file {
+            self.penalty += 21;
+        }
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += -30 * total_blank + 6 * post_blank;
+        let indent = if m.indent != -1 { m.indent } else { m.post_indent };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            return;
+        }
+        self.penalty += if indent > m.pre_indent {
+            if any_blanks { 10 } else { -4 }
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            if any_blanks { 17 } else { 24 }
+        } else if any_blanks {
+            17
+        } else {
+            23
+        };
+    }
+
+    fn cmp(&self, other: &SplitScore) -> i32 {
+        let indents = (self.effective_indent > other.effective_indent) as i32
+            - (self.effective_indent < other.effective_indent) as i32;
+        60 * indents + (self.penalty - other.penalty)
+    }
+}
+
+/// A run of changed lines `start..end` (possibly empty) in one side's
+/// offset flags, as xdiff's group helpers track it.
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Group {
+        let mut end = 0;
+        while changed[end + 1] {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    fn next(&mut self, changed: &[bool], n: usize) -> bool {
+        if self.end == n {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while changed[self.end + 1] {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while changed[self.start] {
+            self.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, lines: &[usize], changed: &mut [bool]) -> bool {
+        if self.end < lines.len() && lines[self.start] == lines[self.end] {
+            changed[self.start + 1] = false;
+            changed[self.end + 1] = true;
+            self.start += 1;
+            self.end += 1;
+            while changed[self.end + 1] {
+                self.end += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, lines: &[usize], changed: &mut [bool]) -> bool {
+        if self.start > 0 && lines[self.start - 1] == lines[self.end - 1] {
+            self.start -= 1;
+            self.end -= 1;
+            changed[self.start + 1] = true;
+            changed[self.end + 1] = false;
+            while changed[self.start] {
+                self.start -= 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A group of changes close enough to share one hunk, with the line ranges
+/// the hunk covers including its context (0-based, end exclusive).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_end: usize,
+    pub new_start: usize,
+    pub new_end: usize,
+    pub edits: Vec<Edit>,
+}
+
+/// Groups the changes turning `old` into `new` into hunks with `context`
+/// lines around each change.
+pub fn hunks(old: &[&[u8]], new: &[&[u8]], context: usize) -> Vec<Hunk> {
+    let edits = diff_lines(old, new);
+    let mut hunks = vec![];
+    let mut rest = &edits[..];
+    while let Some(first) = rest.first() {
+        // Changes closer than twice the context share a hunk.
+        let mut count = 1;
+        while count < rest.len() {
+            let previous = rest[count - 1];
+            if rest[count].old_start - (previous.old_start + previous.old_len) > 2 * context {
+                break;
+            }
+            count += 1;
+        }
+        let (group, tail) = rest.split_at(count);
+        rest = tail;
+        let last = group[count - 1];
+        hunks.push(Hunk {
+            old_start: first.old_start.saturating_sub(context),
+            new_start: first.new_start.saturating_sub(context),
+            old_end: (last.old_start + last.old_len + context).min(old.len()),
+            new_end: (last.new_start + last.new_len + context).min(new.len()),
+            edits: group.to_vec(),
+        });
+    }
+    hunks
+}
+
+/// Writes the hunks of a unified diff between `old` and `new`, each headed
+/// by `@@ -a,b +c,d @@` and the nearest preceding "function" line of `old`
+/// (one starting with a letter, `_` or `$`).
+pub fn write_hunks(out: &mut Vec<u8>, old: &[u8], new: &[u8], context: usize) {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    for hunk in hunks(&old_lines, &new_lines, context) {
+        write_hunk(out, &hunk, &old_lines, &new_lines);
+    }
+}
+
+/// Writes one hunk of the diff between the lines `old` and `new`.
+pub fn write_hunk(out: &mut Vec<u8>, hunk: &Hunk, old: &[&[u8]], new: &[&[u8]]) {
+    let range = |start: usize, len: usize| match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    };
+    out.extend(
+        format!(
+            "@@ -{} +{} @@",
+            range(hunk.old_start, hunk.old_end - hunk.old_start),
+            range(hunk.new_start, hunk.new_end - hunk.new_start)
+        )
+        .as_bytes(),
+    );
+    if let Some(func) = function_line(&old[..hunk.old_start]) {
+        out.push(b' ');
+        out.extend(func);
+    }
+    out.push(b'\n');
+
+    let mut i = hunk.old_start;
+    for edit in &hunk.edits {
+        for line in &old[i..edit.old_start] {
+            write_line(out, b' ', line);
+        }
+        for line in &old[edit.old_start..edit.old_start + edit.old_len] {
+            write_line(out, b'-', line);
+        }
+        for line in &new[edit.new_start..edit.new_start + edit.new_len] {
+            write_line(out, b'+', line);
+        }
+        i = edit.old_start + edit.old_len;
+    }
+    for line in &old[i..hunk.old_end] {
+        write_line(out, b' ', line);
+    }
+}
+
+/// `old` with the changes of the hunks for which `selected` is true
+/// applied, and the rest left out.
+pub fn apply_hunks(old: &[&[u8]], new: &[&[u8]], hunks: &[Hunk], selected: &[bool]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+    for (hunk, _) in hunks.iter().zip(selected).filter(|(_, selected)| **selected) {
+        for edit in &hunk.edits {
+            out.extend(old[i..edit.old_start].concat());
+            out.extend(new[edit.new_start..edit.new_start + edit.new_len].concat());
+            i = edit.old_start + edit.old_len;
+        }
+    }
+    out.extend(old[i..].concat());
+    out
+}
+
+fn write_line(out: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    out.push(prefix);
+    out.extend(line);
+    if !line.ends_with(b"\n") {
+        out.extend(b"\n\\ No newline at end of file\n");
+    }
+}
+
+fn function_line<'a>(lines: &[&'a [u8]]) -> Option<&'a [u8]> {
+    let line = lines
+        .iter()
+        .rev()
+        .find(|line| line.first().is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$'))?;
+    let line = &line[..line.len().min(FUNC_NAME_LEN)];
+    let len = line.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |i| i + 1);
+    Some(&line[..len])
+}
+
+/// Writes the `diff --git` patch for one change between two trees, with
+/// object names abbreviated to `abbrev` digits. With `textconv`, files
+/// that have a textconv command are compared as the text it gives.
+pub fn write_patch(out: &mut Vec<u8>, change: &Change, abbrev: usize, textconv: Option<&Converter>) -> anyhow::Result<()> {
+    write_patch_from(out, change, abbrev, textconv, false)
+}
+
+/// Writes the patch for one change from the index to the worktree, as
+/// [`write_patch`] does, reading the new side from the worktree file since
+/// its blob needn't be stored.
+pub fn write_worktree_patch(out: &mut Vec<u8>, change: &Change, abbrev: usize, textconv: Option<&Converter>) -> anyhow::Result<()> {
+    write_patch_from(out, change, abbrev, textconv, true)
+}
+
+fn write_patch_from(out: &mut Vec<u8>, change: &Change, abbrev: usize, textconv: Option<&Converter>, worktree: bool) -> anyhow::Result<()> {
+    // A change of type is shown as a deletion followed by an addition.
+    if change.status == Status::TypeChanged {
+        let deleted = Change {
+            status: Status::Deleted,
+            new_mode: 0,
+            new_hash: Oid::ZERO,
+            ..change.clone()
+        };
+        let added = Change {
+            status: Status::Added,
+            old_mode: 0,
+            old_hash: Oid::ZERO,
+            ..change.clone()
+        };
+        write_patch_from(out, &deleted, abbrev, textconv, worktree)?;
+        return write_patch_from(out, &added, abbrev, textconv, worktree);
+    }
+
+    let read = |path: &[u8], hash: &Oid, mode: u32| -> anyhow::Result<Vec<u8>> {
+        if change.old_hash == change.new_hash {
+            return Ok(vec![]);
+        }
+        let contents = side_contents(hash, mode)?;
+        match textconv {
+            Some(converter) if !hash.is_zero() && mode & 0o170000 != MODE_SUBMODULE => {
+                Ok(converter.textconv(path, &contents)?.unwrap_or(contents))
+            }
+            _ => Ok(contents),
+        }
+    };
+    let old = read(&change.old_path, &change.old_hash, change.old_mode)?;
+    let new = match worktree && change.old_hash != change.new_hash && !change.new_hash.is_zero() && change.new_mode & 0o170000 != MODE_SUBMODULE {
+        true => {
+            let path = path::to_path(&change.path);
+            let meta = fs::symlink_metadata(path).with_context(|| format!("stat {}", path::display(&change.path)))?;
+            let contents = index::read_worktree_file(path, &meta)?;
+            match textconv {
+                Some(converter) => converter.textconv(&change.path, &contents)?.unwrap_or(contents),
+                None => contents,
+            }
+        }
+        false => read(&change.path, &change.new_hash, change.new_mode)?,
+    };
+    if write_file_header(out, change, &old, &new, abbrev) {
+        write_hunks(out, &old, &new, DEFAULT_CONTEXT);
+    }
+    Ok(())
+}
+
+/// Writes everything of `change`'s patch before the hunks, given the
+/// contents `old` and `new` of its two sides. Returns whether hunks should
+/// follow, which they don't for binary files or when only the mode or name
+/// changed. Type changes must already be split into a deletion and an
+/// addition.
+pub fn write_file_header(out: &mut Vec<u8>, change: &Change, old: &[u8], new: &[u8], abbrev: usize) -> bool {
+    let (old_path, new_path) = (quote_path(&change.old_path), quote_path(&change.path));
+    let (old_name, new_name) = (quote_path(&[b"a/", &change.old_path[..]].concat()).into_owned(), quote_path(&[b"b/", &change.path[..]].concat()).into_owned());
+    out.extend(format!("diff --git {old_name} {new_name}\n").as_bytes());
+    match change.status {
+        Status::Added => out.extend(format!("new file mode {:06o}\n", change.new_mode).as_bytes()),
+        Status::Deleted => out.extend(format!("deleted file mode {:06o}\n", change.old_mode).as_bytes()),
+        _ => {
+            if change.old_mode != change.new_mode {
+                out.extend(format!("old mode {:06o}\nnew mode {:06o}\n", change.old_mode, change.new_mode).as_bytes());
+            }
+            match change.status {
+                Status::Renamed(score) => out.extend(
+                    format!("similarity index {score}%\nrename from {old_path}\nrename to {new_path}\n").as_bytes(),
+                ),
+                Status::Copied(score) => out.extend(
+                    format!("similarity index {score}%\ncopy from {old_path}\ncopy to {new_path}\n").as_bytes(),
+                ),
+                _ => {}
+            }
+        }
+    }
+    if change.old_hash == change.new_hash {
+        return false;
+    }
+    out.extend(format!("index {}..{}", change.old_hash.abbrev(abbrev), change.new_hash.abbrev(abbrev)).as_bytes());
+    if change.old_mode == change.new_mode {
+        out.extend(format!(" {:06o}", change.new_mode).as_bytes());
+    }
+    out.push(b'\n');
+
+    let old_name = match change.status {
+        Status::Added => "/dev/null".to_string(),
+        _ => old_name,
+    };
+    let new_name = match change.status {
+        Status::Deleted => "/dev/null".to_string(),
+        _ => new_name,
+    };
+    if is_binary(old) || is_binary(new) {
+        out.extend(format!("Binary files {old_name} and {new_name} differ\n").as_bytes());
+        return false;
+    }
+    if old.is_empty() && new.is_empty() {
+        return false;
+    }
+    out.extend(format!("--- {old_name}\n+++ {new_name}\n").as_bytes());
+    true
+}
+
+/// One file's line of a diffstat.
+struct StatLine {
+    name: String,
+    binary: bool,
+    /// Lines added and deleted, or new and old sizes in bytes for binary
+    /// files.
+    added: usize,
+    deleted: usize,
+}
+
+/// The diffstat line of each of `changes`.
+fn stat_lines(changes: &[Change]) -> anyhow::Result<Vec<StatLine>> {
+    let mut lines = vec![];
+    for change in changes {
+        let (old, new) = if change.old_hash == change.new_hash {
+            (vec![], vec![])
+        } else {
+            (side_contents(&change.old_hash, change.old_mode)?, side_contents(&change.new_hash, change.new_mode)?)
+        };
+        let name = match change.status {
+            Status::Renamed(_) | Status::Copied(_) => rename_name(&change.old_path, &change.path),
+            _ => quote_path(&change.path).into_owned(),
+        };
+        let line = if is_binary(&old) || is_binary(&new) {
+            StatLine { name, binary: true, added: new.len(), deleted: old.len() }
+        } else {
+            let edits = diff_lines(&split_lines(&old), &split_lines(&new));
+            StatLine {
+                name,
+                binary: false,
+                added: edits.iter().map(|e| e.new_len).sum(),
+                deleted: edits.iter().map(|e| e.old_len).sum(),
+            }
+        };
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// Writes a `--stat` summary of `changes` fitted to `width` columns: one
+/// `name | count +++--` line per file followed by the totals, laid out the
+/// way git lays it out.
+pub fn write_stat(out: &mut Vec<u8>, changes: &[Change], width: usize) -> anyhow::Result<()> {
+    let lines = stat_lines(changes)?;
+    let max_len = lines.iter().map(|l| l.name.chars().count()).max().unwrap_or(0);
+    let mut number_width = 0;
+    let mut bin_width = 0;
+    let mut max_change = 0;
+    for line in &lines {
+        if line.binary {
+            // "Bin <old> -> <new> bytes"
+            bin_width = bin_width.max(14 + decimal_width(line.added) + decimal_width(line.deleted));
+            number_width = 3;
+        } else {
+            max_change = max_change.max(line.added + line.deleted);
+        }
+    }
+    number_width = number_width.max(decimal_width(max_change));
+    let width = width.max(16 + 6 + number_width);
+    let mut graph_width = if max_change + 4 > bin_width { max_change } else { bin_width - 4 };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        let limit = (width * 3 / 8).saturating_sub(number_width + 6);
+        if graph_width > limit {
+            graph_width = limit.max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    for line in &lines {
+        let mut name: &str = &line.name;
+        let mut prefix = "";
+        let mut len = name_width;
+        if name_width < name.chars().count() {
+            // Keep the end of the name, from a slash if there is one.
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let skip = name.chars().count() - len;
+            name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let padding = len.saturating_sub(name.chars().count());
+        out.extend(format!(" {prefix}{name}{:padding$} | ", "").as_bytes());
+        if line.binary {
+            out.extend(format!("{:>number_width$}", "Bin").as_bytes());
+            if line.added != 0 || line.deleted != 0 {
+                out.extend(format!(" {} -> {} bytes", line.deleted, line.added).as_bytes());
+            }
+            out.push(b'\n');
+            continue;
+        }
+        let (mut add, mut del) = (line.added, line.deleted);
+        if graph_width <= max_change {
+            let mut total = scale_linear(add + del, graph_width, max_change);
+            if total < 2 && add != 0 && del != 0 {
+                total = 2;
+            }
+            if add < del {
+                add = scale_linear(add, graph_width, max_change);
+                del = total - add;
+            } else {
+                del = scale_linear(del, graph_width, max_change);
+                add = total - del;
+            }
+        }
+        let total = line.added + line.deleted;
+        out.extend(format!("{total:>number_width$}").as_bytes());
+        if total != 0 {
+            out.push(b' ');
+        }
+        out.extend("+".repeat(add).as_bytes());
+        out.extend("-".repeat(del).as_bytes());
+        out.push(b'\n');
+    }
+    write_totals(out, &lines);
+    Ok(())
+}
+
+/// Writes just the totals line of `changes`' diffstat, as `--shortstat`
+/// does.
+pub fn write_shortstat(out: &mut Vec<u8>, changes: &[Change]) -> anyhow::Result<()> {
+    write_totals(out, &stat_lines(changes)?);
+    Ok(())
+}
+
+/// Writes the totals line of a diffstat: files changed, and lines inserted
+/// and deleted outside binary files.
+fn write_totals(out: &mut Vec<u8>, lines: &[StatLine]) {
+    let (insertions, deletions) = lines
+        .iter()
+        .filter(|line| !line.binary)
+        .fold((0, 0), |(insertions, deletions), line| (insertions + line.added, deletions + line.deleted));
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let files = lines.len();
+    let mut summary = format!(" {files} file{} changed", plural(files));
+    if files == 0 {
+        summary = " 0 files changed".to_string();
+    } else {
+        if insertions != 0 || deletions == 0 {
+            summary.push_str(&format!(", {insertions} insertion{}(+)", plural(insertions)));
+        }
+        if deletions != 0 || insertions == 0 {
+            summary.push_str(&format!(", {deletions} deletion{}(-)", plural(deletions)));
+        }
+    }
+    out.extend(summary.as_bytes());
+    out.push(b'\n');
+}
+
+/// Writes the `--summary` lines for `changes`: files created and deleted,
+/// mode changes, renames and copies. The mode change of a renamed or
+/// copied file follows without repeating the name.
+pub fn write_summary(out: &mut Vec<u8>, changes: &[Change]) {
+    for change in changes {
+        let mut line = match change.status {
+            Status::Added => format!(" create mode {:06o} {}\n", change.new_mode, quote_path(&change.path)),
+            Status::Deleted => format!(" delete mode {:06o} {}\n", change.old_mode, quote_path(&change.path)),
+            Status::Renamed(score) => format!(" rename {} ({score}%)\n", rename_name(&change.old_path, &change.path)),
+            Status::Copied(score) => format!(" copy {} ({score}%)\n", rename_name(&change.old_path, &change.path)),
+            _ => String::new(),
+        };
+        if change.old_mode != 0 && change.new_mode != 0 && change.old_mode != change.new_mode {
+            line.push_str(&format!(" mode change {:06o} => {:06o}", change.old_mode, change.new_mode));
+            match change.status {
+                Status::Renamed(_) | Status::Copied(_) => line.push('\n'),
+                _ => line.push_str(&format!(" {}\n", quote_path(&change.path))),
+            }
+        }
+        out.extend(line.into_bytes());
+    }
+}
+
+/// The contents one side of a change is diffed as: nothing if the side is
+/// missing, and a `Subproject commit` line for a submodule.
+fn side_contents(hash: &Oid, mode: u32) -> anyhow::Result<Vec<u8>> {
+    if hash.is_zero() {
+        Ok(vec![])
+    } else if mode & 0o170000 == MODE_SUBMODULE {
+        Ok(format!("Subproject commit {hash}\n").into_bytes())
+    } else {
+        Ok(Object::read(hash)?.contents)
+    }
+}
+
+fn decimal_width(n: usize) -> usize {
+    n.to_string().len()
+}
+
+/// Scales `n` changes to a graph `width` wide, where `max` changes fill it,
+/// keeping at least one column for any change at all.
+fn scale_linear(n: usize, width: usize, max: usize) -> usize {
+    if n == 0 { 0 } else { 1 + n * (width - 1) / max }
+}
+
+/// `old => new`, with any leading and trailing directories the two have in
+/// common pulled out: `dir/{old => new}/file`. Names that need quoting
+/// are quoted whole instead.
+fn rename_name(old: &[u8], new: &[u8]) -> String {
+    let (quoted_old, quoted_new) = (quote_path(old), quote_path(new));
+    if quoted_old.as_bytes() != old || quoted_new.as_bytes() != new {
+        return format!("{quoted_old} => {quoted_new}");
+    }
+    let (old, new) = (&*quoted_old, &*quoted_new);
+    let (a, b) = (old.as_bytes(), new.as_bytes());
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+    // Compare from the end, allowing the scan to reach the slash that ends
+    // the common prefix.
+    let floor = prefix.saturating_sub(1);
+    let mut suffix = 0;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > floor && j > floor && a[i - 1] == b[j - 1] {
+        i -= 1;
+        j -= 1;
+        if a[i] == b'/' {
+            suffix = a.len() - i;
+        }
+    }
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let mid = |s: &str, len: usize| s[prefix..prefix + len].to_string();
+    if prefix + suffix == 0 {
+        format!("{} => {}", mid(old, a_mid), mid(new, b_mid))
+    } else {
+        format!(
+            "{}{{{} => {}}}{}",
+            &old[..prefix],
+            mid(old, a_mid),
+            mid(new, b_mid),
+            &old[old.len() - suffix..]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hunks as `git diff --no-index` wrote them, headers left off.
+
+    /// `seq 1 20` with 3, 9 and 18 spelled out.
+    const SPELLED_OUT: &str = "@@ -1,12 +1,12 @@
+ 1
+ 2
+-3
++three
+ 4
+ 5
+ 6
+ 7
+ 8
+-9
++nine
+ 10
+ 11
+ 12
+@@ -15,6 +15,6 @@
+ 15
+ 16
+ 17
+-18
++eighteen
+ 19
+ 20
+";
+
+    const FUNCTION: (&str, &str, &str) = (
+        "int main(void)\n{\n\ta();\n\tb();\n\tc();\n\td();\n\te();\n}\n",
+        "int main(void)\n{\n\ta();\n\tb();\n\tc();\n\tD();\n\te();\n}\n",
+        "@@ -3,6 +3,6 @@ int main(void)
+ \ta();
+ \tb();
+ \tc();
+-\td();
++\tD();
+ \te();
+ }
+",
+    );
+
+    const NEWLINE_ADDED: (&str, &str, &str) = (
+        "one\ntwo\nthree",
+        "one\ntwo\nthree\nfour\n",
+        "@@ -1,3 +1,4 @@
+ one
+ two
+-three
+\\ No newline at end of file
++three
++four
+",
+    );
+
+    const NEWLINE_REMOVED: (&str, &str, &str) = (
+        "one\ntwo\n",
+        "one\ntwo",
+        "@@ -1,2 +1,2 @@
+ one
+-two
++two
+\\ No newline at end of file
+",
+    );
+
+    fn seq(replace: &[(u32, &str)]) -> String {
+        (1..=20)
+            .map(|i| replace.iter().find(|(at, _)| *at == i).map_or(i.to_string(), |(_, line)| line.to_string()) + "\n")
+            .collect()
+    }
+
+    fn diff(old: &str, new: &str) -> String {
+        let mut out = vec![];
+        write_hunks(&mut out, old.as_bytes(), new.as_bytes(), DEFAULT_CONTEXT);
+        String::from_utf8(out).unwrap()
+    }
+
+    fn headers(old: &str, new: &str) -> Vec<String> {
+        diff(old, new).lines().filter(|line| line.starts_with("@@")).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn hunks_come_out_as_git_writes_them() {
+        assert_eq!(diff(&seq(&[]), &seq(&[(3, "three"), (9, "nine"), (18, "eighteen")])), SPELLED_OUT);
+        for (old, new, hunks) in [FUNCTION, NEWLINE_ADDED, NEWLINE_REMOVED] {
+            assert_eq!(diff(old, new), hunks);
+        }
+    }
+
+    #[test]
+    fn changes_share_a_hunk_when_their_context_would_touch() {
+        // Six unchanged lines between is twice the context: still one hunk.
+        assert_eq!(headers(&seq(&[]), &seq(&[(3, "three"), (10, "ten")])), ["@@ -1,13 +1,13 @@"]);
+        assert_eq!(headers(&seq(&[]), &seq(&[(3, "three"), (11, "eleven")])), ["@@ -1,6 +1,6 @@", "@@ -8,7 +8,7 @@"]);
+    }
+}
@@ -0,0 +1,107 @@
+//! Handing diffs to other programs: the `diff.external` command that
+//! replaces the built-in patch output, and the visual tools `got difftool`
+//! launches.
+//!
+//! Both sides of a change are written out to temporary files first, since
+//! the programs only understand paths. An external diff is called the way
+//! git calls `GIT_EXTERNAL_DIFF`, with seven arguments
+//! (`path old-file old-hex old-mode new-file new-hex new-mode`, plus the
+//! new path and a description for renames). A difftool command is a shell
+//! snippet that finds the two sides in `$LOCAL` and `$REMOTE`.
+
+use crate::config::Config;
+use crate::object::Object;
+use crate::oid::Oid;
+use crate::path;
+use crate::temp::TempDir;
+use crate::tree::{MODE_EXECUTABLE, MODE_SUBMODULE};
+use crate::tree_diff::{Change, Status};
+use anyhow::Context;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// Writes blob `hash` to `path` below `dir` and returns where it went.
+/// Symlinks become files holding their target and submodules a
+/// `Subproject commit` line, as in a patch.
+pub fn materialize(dir: &Path, path: &[u8], mode: u32, hash: &Oid) -> anyhow::Result<PathBuf> {
+    let file = dir.join(path::to_path(path));
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create directory for {}", file.display()))?;
+    }
+    let contents = if mode & 0o170000 == MODE_SUBMODULE {
+        format!("Subproject commit {hash}\n").into_bytes()
+    } else {
+        Object::read(hash)?.contents
+    };
+    fs::write(&file, contents).with_context(|| format!("write {}", file.display()))?;
+    if mode == MODE_EXECUTABLE {
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(file)
+}
+
+/// Runs `command`, the `diff.external` setting, for one change.
+pub fn external_diff(command: &str, change: &Change) -> anyhow::Result<()> {
+    let temp = TempDir::new("diff")?;
+    let side = |name: &str, path: &[u8], mode: u32, hash: &Oid| -> anyhow::Result<[OsString; 3]> {
+        if hash.is_zero() {
+            return Ok(["/dev/null".into(), ".".into(), ".".into()]);
+        }
+        let file = materialize(&temp.path().join(name), path, mode, hash)?;
+        Ok([file.into_os_string(), hash.to_string().into(), format!("{mode:06o}").into()])
+    };
+    let mut args = vec![OsString::from_vec(change.old_path.clone())];
+    args.extend(side("a", &change.old_path, change.old_mode, &change.old_hash)?);
+    args.extend(side("b", &change.path, change.new_mode, &change.new_hash)?);
+    let (score, kind) = match change.status {
+        Status::Renamed(score) => (score, "rename"),
+        Status::Copied(score) => (score, "copy"),
+        _ => (0, ""),
+    };
+    if !kind.is_empty() {
+        args.push(OsString::from_vec(change.path.clone()));
+        let from = [format!("similarity index {score}%\n{kind} from ").as_bytes(), &change.old_path].concat();
+        args.push(OsString::from_vec([from, format!("\n{kind} to ").into_bytes(), change.path.clone(), b"\n".to_vec()].concat()));
+    }
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} \"$@\""))
+        .arg(command)
+        .args(&args)
+        .status()
+        .with_context(|| format!("run external diff '{command}'"))?;
+    anyhow::ensure!(status.success(), "external diff died, stopping at {}", path::display(&change.path));
+    Ok(())
+}
+
+/// The shell command that runs difftool `tool`: `difftool.<tool>.cmd` if it
+/// is set, otherwise the tool's usual invocation.
+pub fn tool_command(config: &Config, tool: &str) -> String {
+    if let Some(command) = config.get(&format!("difftool.{tool}.cmd")) {
+        return command.to_string();
+    }
+    match tool {
+        "vimdiff" => "vim -R -f -d \"$LOCAL\" \"$REMOTE\"".to_string(),
+        "nvimdiff" => "nvim -R -f -d \"$LOCAL\" \"$REMOTE\"".to_string(),
+        "vscode" => "code --wait --diff \"$LOCAL\" \"$REMOTE\"".to_string(),
+        _ => format!("{tool} \"$LOCAL\" \"$REMOTE\""),
+    }
+}
+
+/// Runs a difftool `command` on `local` and `remote`, the two versions of
+/// `merged`.
+pub fn launch(command: &str, local: &Path, remote: &Path, merged: &Path) -> anyhow::Result<ExitStatus> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("LOCAL", local)
+        .env("REMOTE", remote)
+        .env("MERGED", merged)
+        .env("BASE", merged)
+        .status()
+        .with_context(|| format!("run difftool '{command}'"))
+}
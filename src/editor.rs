@@ -0,0 +1,87 @@
+//! Asking the user for text in their editor, the way commit messages are
+//! written when `-m` or `-F` don't give one.
+//!
+//! The editor is `GOT_EDITOR`, else `core.editor`, else `VISUAL` (unless
+//! the terminal is dumb), else `EDITOR`, else `vi`. It is a shell snippet
+//! run with the file to edit as its argument; `:` edits nothing, which
+//! scripts use to take the text as it is.
+
+use crate::config::Config;
+use anyhow::Context;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// The command that edits text.
+pub fn editor(config: &Config) -> anyhow::Result<String> {
+    let dumb = std::env::var("TERM").map_or(true, |term| term == "dumb");
+    let editor = std::env::var("GOT_EDITOR")
+        .ok()
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| std::env::var("VISUAL").ok().filter(|_| !dumb))
+        .or_else(|| std::env::var("EDITOR").ok());
+    match editor {
+        Some(editor) => Ok(editor),
+        None if dumb => anyhow::bail!("terminal is dumb, but EDITOR unset; use -m or -F to give the message"),
+        None => Ok("vi".to_string()),
+    }
+}
+
+/// Writes `text` to `path`, has `editor` edit it and returns what it then
+/// holds.
+pub fn edit(editor: &str, path: &Path, text: &str) -> anyhow::Result<String> {
+    fs::write(path, text).with_context(|| format!("write {}", path.display()))?;
+    if editor != ":" {
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{editor} \"$@\""))
+            .arg(editor)
+            .arg(path)
+            .status()
+            .with_context(|| format!("run editor '{editor}'"))?;
+        anyhow::ensure!(status.success(), "there was a problem with the editor '{editor}'");
+    }
+    fs::read_to_string(path).with_context(|| format!("read {}", path.display()))
+}
+
+/// Tidies a message the way git's `stripspace` does: trailing whitespace
+/// goes from every line, runs of blank lines become one and blank lines at
+/// either end go, leaving a single newline at the end of anything left.
+/// With `strip_comments`, lines starting with `#` go too.
+pub fn cleanup(text: &str, strip_comments: bool) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in text.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Turns every line of `text` into a comment, as hints in a message to
+/// be edited are written: `# ` goes before each line, or just `#` before
+/// one that is empty or starts with a tab.
+pub fn comment_lines(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.lines() {
+        out.push('#');
+        if !line.is_empty() && !line.starts_with('\t') {
+            out.push(' ');
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
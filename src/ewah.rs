@@ -0,0 +1,59 @@
+//! EWAH-compressed bitmaps, as git's index extensions store them.
+//!
+//! A bitmap is its size in bits, a count of 64-bit words, the words and
+//! the position of the last marker word, all big-endian. Each marker word
+//! holds a bit repeated for a run of words (bit 0, with the run length in
+//! bits 1 to 32) and how many literal words follow it (bits 33 to 63);
+//! literal words hold their bits least significant first.
+
+use anyhow::Context;
+
+/// Reads a bitmap from the start of `data`, returning its bits and how
+/// many bytes it took up.
+pub fn read(data: &[u8]) -> anyhow::Result<(Vec<bool>, usize)> {
+    let u32_at = |at: usize| -> anyhow::Result<u32> {
+        let bytes = data.get(at..at + 4).context("truncated bitmap")?;
+        Ok(u32::from_be_bytes(bytes.try_into().expect("slice is 4 bytes")))
+    };
+    let bits = u32_at(0)? as usize;
+    let count = u32_at(4)? as usize;
+    let words: Vec<u64> = data
+        .get(8..8 + count * 8)
+        .context("truncated bitmap")?
+        .chunks(8)
+        .map(|word| u64::from_be_bytes(word.try_into().expect("chunk is 8 bytes")))
+        .collect();
+    u32_at(8 + count * 8)?;
+    let mut out = Vec::with_capacity(bits);
+    let mut i = 0;
+    while i < words.len() {
+        let marker = words[i];
+        let run_bit = marker & 1 != 0;
+        let run = (marker >> 1) & 0xffff_ffff;
+        let literals = (marker >> 33) as usize;
+        out.extend(std::iter::repeat_n(run_bit, run as usize * 64));
+        for &word in words.get(i + 1..i + 1 + literals).context("corrupt bitmap")? {
+            out.extend((0..64).map(|bit| word >> bit & 1 != 0));
+        }
+        i += 1 + literals;
+    }
+    anyhow::ensure!(out.len() >= bits, "bitmap is shorter than its size");
+    out.truncate(bits);
+    Ok((out, 12 + count * 8))
+}
+
+/// Writes `bits` as a bitmap of one marker word followed by every word as
+/// a literal, which is valid if not as small as it could be.
+pub fn write(out: &mut Vec<u8>, bits: &[bool]) {
+    let words: Vec<u64> = bits
+        .chunks(64)
+        .map(|chunk| chunk.iter().enumerate().fold(0, |word, (i, &bit)| word | (u64::from(bit) << i)))
+        .collect();
+    out.extend((bits.len() as u32).to_be_bytes());
+    out.extend((words.len() as u32 + 1).to_be_bytes());
+    out.extend(((words.len() as u64) << 33).to_be_bytes());
+    for word in words {
+        out.extend(word.to_be_bytes());
+    }
+    out.extend(0u32.to_be_bytes());
+}
@@ -0,0 +1,105 @@
+//! Housekeeping: packing every reachable object into one pack and removing
+//! the objects nothing refers to any more.
+//!
+//! Unreachable objects aren't removed at once, as a command running
+//! meanwhile may be about to refer to them. Those in the old packs are
+//! written out loose, keeping the age of their pack, and loose objects are
+//! only removed once older than `gc.pruneExpire` (`2.weeks.ago` unless set;
+//! `now` removes them all and `never` none).
+//!
+//! Aggressive collection searches much harder for deltas, trying each
+//! object against `gc.aggressiveWindow` (250 unless set) others and
+//! allowing chains `gc.aggressiveDepth` (50) long, without reusing any of
+//! the deltas already found: slow, but worth it now and then for a
+//! repository that has grown for years.
+
+use crate::config::{self, Config};
+use crate::object;
+use crate::oid::Oid;
+use crate::repack::{self, RepackOptions};
+use crate::repository;
+use anyhow::Context;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+const DEFAULT_AGGRESSIVE_WINDOW: usize = 250;
+const DEFAULT_AGGRESSIVE_DEPTH: usize = 50;
+
+#[derive(Debug, Clone, Default)]
+pub struct GcOptions {
+    pub aggressive: bool,
+    /// How old unreachable objects must be to go, overriding
+    /// `gc.pruneExpire`.
+    pub prune_expire: Option<String>,
+    /// Show progress meters on standard error.
+    pub progress: bool,
+}
+
+pub fn gc(git_dir: &Path, config: &Config, options: &GcOptions) -> anyhow::Result<()> {
+    let value = options
+        .prune_expire
+        .as_deref()
+        .or_else(|| config.get("gc.pruneExpire"))
+        .unwrap_or(DEFAULT_PRUNE_EXPIRE);
+    let expire = config::parse_age(value).with_context(|| format!("invalid prune expiry '{value}'"))?;
+    // Objects modified at or before the cutoff may go.
+    let cutoff = expire.map(|age| SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH));
+
+    let mut repack_options = RepackOptions::from_config(config)?;
+    repack_options.all = true;
+    repack_options.delete = true;
+    repack_options.progress = options.progress;
+    // Unreachable objects that may yet be kept are written out loose.
+    repack_options.unpack_unreachable = match expire {
+        Some(age) if age.is_zero() => None,
+        _ => Some(cutoff.unwrap_or(SystemTime::UNIX_EPOCH)),
+    };
+    if options.aggressive {
+        let count = |key: &str, default: usize| -> anyhow::Result<usize> {
+            match config.get_int(key)? {
+                None => Ok(default),
+                Some(value) => usize::try_from(value).with_context(|| format!("{key} must not be negative")),
+            }
+        };
+        repack_options.window = count("gc.aggressiveWindow", DEFAULT_AGGRESSIVE_WINDOW)?;
+        repack_options.depth = count("gc.aggressiveDepth", DEFAULT_AGGRESSIVE_DEPTH)?;
+        repack_options.reuse_deltas = false;
+    }
+    repack::repack(git_dir, &repack_options)?;
+    object::remove_stale_temp_objects()?;
+
+    if let Some(cutoff) = cutoff {
+        prune(git_dir, cutoff)?;
+    }
+    Ok(())
+}
+
+/// Removes the loose objects nothing reaches that were last modified at or
+/// before `cutoff`, and the directories that leaves empty.
+fn prune(git_dir: &Path, cutoff: SystemTime) -> anyhow::Result<()> {
+    let reachable = repack::reachable_objects(git_dir)?;
+    let objects_dir = repository::objects_dir(git_dir);
+    for first in 0..=255u8 {
+        let dir = objects_dir.join(format!("{first:02x}"));
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let entry = entry.with_context(|| format!("list {}", dir.display()))?;
+            let name = entry.file_name();
+            let Some(hash) = name.to_str().and_then(|rest| format!("{first:02x}{rest}").parse::<Oid>().ok()) else {
+                continue;
+            };
+            let modified = entry.metadata().and_then(|meta| meta.modified());
+            if reachable.contains(&hash) || !modified.is_ok_and(|modified| modified <= cutoff) {
+                continue;
+            }
+            fs::remove_file(entry.path()).with_context(|| format!("remove {}", entry.path().display()))?;
+        }
+        // Only succeeds if nothing is left.
+        let _ = fs::remove_dir(&dir);
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Grafts, which give commits other parents than the ones they were made
+//! with, so that history imported from elsewhere can be stitched onto the
+//! history that came before it without rewriting any objects.
+//!
+//! `.got/info/grafts` has a line for each grafted commit: its hash and then
+//! the hashes of the parents it should be seen to have, if any, separated by
+//! spaces. Blank lines and lines starting with `#` are ignored. A grafted
+//! commit read with `Object::read` has those parents in place of its own, so
+//! every history walk sees them, and the commit-graph, which records the
+//! original parents, goes unused while there are grafts.
+//!
+//! Grafts stay in the repository they are made in. `got replace --graft`
+//! and `got replace --convert-graft-file` make the same change with
+//! replacement commits instead, which can be pushed and fetched.
+
+use crate::oid::Oid;
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub fn path(git_dir: &Path) -> PathBuf {
+    git_dir.join("info/grafts")
+}
+
+/// The parents each grafted commit is given; empty without a grafts file.
+/// Lines that aren't a list of hashes are reported and skipped.
+pub fn read(git_dir: &Path) -> anyhow::Result<HashMap<Oid, Vec<Oid>>> {
+    let text = match fs::read_to_string(path(git_dir)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e).context("read .got/info/grafts"),
+    };
+    let mut grafts = HashMap::new();
+    for line in lines(&text) {
+        match parse_line(line) {
+            Some((commit, parents)) => {
+                grafts.insert(commit, parents);
+            }
+            None => eprintln!("error: bad graft data: {line}"),
+        }
+    }
+    Ok(grafts)
+}
+
+/// The lines of a grafts file that aren't blank or comments.
+pub fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim_end).filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+/// The commit a grafts file line grafts and the parents it gives it.
+pub fn parse_line(line: &str) -> Option<(Oid, Vec<Oid>)> {
+    let mut hashes = line.split(' ').map(|hash| hash.parse::<Oid>().ok());
+    let commit = hashes.next()??;
+    let parents = hashes.collect::<Option<Vec<_>>>()?;
+    Some((commit, parents))
+}
@@ -0,0 +1,160 @@
+//! HTTP client settings for talking to remotes.
+//!
+//! The relevant config keys are `http.proxy`, `http.sslCAInfo` and
+//! `http.sslVerify`, each of which may also be scoped to a remote with the
+//! `http.<url>.<key>` form. The `GOT_SSL_CAINFO` and `GOT_SSL_NO_VERIFY`
+//! environment variables take precedence over config, and when no proxy is
+//! configured the usual `https_proxy`/`http_proxy`/`all_proxy`/`no_proxy`
+//! variables are honored.
+//!
+//! Idempotent requests can be wrapped in [`HttpOptions::retry`], which retries
+//! connection resets, timeouts and 5xx responses with exponential backoff.
+//! `http.maxRetries` (default 5) bounds the number of retries,
+//! `http.retryDelay` sets the first backoff in milliseconds (default 500),
+//! and `http.timeout` limits in seconds how long connecting and waiting for
+//! a response may take.
+
+use crate::config::{self, Config};
+use anyhow::Context;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use ureq::tls::{Certificate, PemItem, RootCerts, TlsConfig};
+use ureq::{Agent, Proxy};
+
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// `None` defers to the proxy environment variables, while an empty
+    /// string explicitly disables proxying.
+    pub proxy: Option<String>,
+    pub ssl_ca_info: Option<PathBuf>,
+    pub ssl_verify: bool,
+    pub max_retries: u32,
+    pub retry_delay: Duration,
+    pub timeout: Option<Duration>,
+}
+
+/// The longest we'll wait between two attempts, however many have failed.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+impl HttpOptions {
+    pub fn from_config(config: &Config, url: &str) -> anyhow::Result<HttpOptions> {
+        let proxy = config.get_for_url("http", url, "proxy").map(str::to_string);
+        let ssl_ca_info = match std::env::var_os("GOT_SSL_CAINFO") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => config
+                .get_for_url("http", url, "sslCAInfo")
+                .map(PathBuf::from),
+        };
+        let ssl_verify = match std::env::var("GOT_SSL_NO_VERIFY") {
+            Ok(v) => !config::parse_bool(&v).unwrap_or(true),
+            Err(_) => match config.get_for_url("http", url, "sslVerify") {
+                Some(v) => config::parse_bool(v).context("bad boolean config value for 'http.sslVerify'")?,
+                None => true,
+            },
+        };
+        let max_retries = config.get_int("http.maxRetries")?.unwrap_or(5);
+        anyhow::ensure!(max_retries >= 0, "http.maxRetries must not be negative");
+        let retry_delay = config.get_int("http.retryDelay")?.unwrap_or(500);
+        anyhow::ensure!(retry_delay >= 0, "http.retryDelay must not be negative");
+        let timeout = config.get_int("http.timeout")?.filter(|&t| t > 0);
+        Ok(HttpOptions {
+            proxy,
+            ssl_ca_info,
+            ssl_verify,
+            max_retries: max_retries.try_into().unwrap_or(u32::MAX),
+            retry_delay: Duration::from_millis(retry_delay as u64),
+            timeout: timeout.map(|t| Duration::from_secs(t as u64)),
+        })
+    }
+
+    /// Builds an HTTP agent honoring these options.
+    pub fn agent(&self) -> anyhow::Result<Agent> {
+        let proxy = match self.proxy.as_deref() {
+            Some("") => None,
+            Some(proxy) => Some(Proxy::new(proxy).with_context(|| format!("invalid http.proxy '{proxy}'"))?),
+            None => Proxy::try_from_env(),
+        };
+        let mut tls = TlsConfig::builder().disable_verification(!self.ssl_verify);
+        if let Some(path) = &self.ssl_ca_info {
+            tls = tls.root_certs(load_ca_file(path)?);
+        }
+        let config = Agent::config_builder()
+            .proxy(proxy)
+            .tls_config(tls.build())
+            .timeout_connect(self.timeout)
+            .timeout_recv_response(self.timeout)
+            .user_agent(concat!("got/", env!("CARGO_PKG_VERSION")))
+            .build();
+        Ok(config.into())
+    }
+
+    /// Runs `attempt` until it succeeds, fails with an error that isn't
+    /// transient, or runs out of retries. Only wrap requests that are safe to
+    /// repeat: the whole request, including reading the body, is redone.
+    pub fn retry<T>(&self, mut attempt: impl FnMut() -> Result<T, ureq::Error>) -> anyhow::Result<T> {
+        let mut delay = self.retry_delay;
+        let mut retries = 0;
+        loop {
+            match attempt() {
+                Ok(value) => return Ok(value),
+                Err(e) if retries < self.max_retries && is_transient(&e) => {
+                    retries += 1;
+                    let wait = jitter(delay);
+                    eprintln!(
+                        "warning: {e}; retrying in {:.1}s ({retries}/{})",
+                        wait.as_secs_f64(),
+                        self.max_retries
+                    );
+                    thread::sleep(wait);
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Whether `err` is worth retrying: the connection dropped or timed out, or
+/// the server reported a temporary failure.
+pub fn is_transient(err: &ureq::Error) -> bool {
+    match err {
+        ureq::Error::StatusCode(code) => *code >= 500 || *code == 429,
+        ureq::Error::Timeout(_) | ureq::Error::ConnectionFailed => true,
+        ureq::Error::Io(e) => matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::Interrupted
+        ),
+        _ => false,
+    }
+}
+
+/// Spreads `delay` over [delay/2, delay) so clients that failed together
+/// don't all come back at the same moment.
+fn jitter(delay: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    delay / 2 + delay.mul_f64(f64::from(nanos % 1000) / 2000.0)
+}
+
+fn load_ca_file(path: &Path) -> anyhow::Result<RootCerts> {
+    let pem = fs::read(path).with_context(|| format!("read http.sslCAInfo file {}", path.display()))?;
+    let mut certs: Vec<Certificate<'static>> = vec![];
+    for item in ureq::tls::parse_pem(&pem) {
+        if let PemItem::Certificate(cert) = item.with_context(|| format!("parse {}", path.display()))? {
+            certs.push(cert);
+        }
+    }
+    anyhow::ensure!(!certs.is_empty(), "no certificates found in {}", path.display());
+    Ok(RootCerts::new_with_certs(&certs))
+}
@@ -0,0 +1,198 @@
+//! Ignore rules: which untracked files got leaves out.
+//!
+//! Rules are read from three places, from lowest to highest precedence:
+//! the user's excludes file (`core.excludesFile`, by default
+//! `$XDG_CONFIG_HOME/got/ignore`), the repository's private
+//! `.got/info/exclude`, and the `.gotignore` at the top of the worktree.
+//! The last rule that matches a path decides, so a `!pattern` in
+//! `.gotignore` can bring back a file the user's own rules ignore.
+//!
+//! Patterns are those of `.gitignore`: blank lines and lines starting with
+//! `#` are skipped, a leading `!` negates, a trailing `/` matches only
+//! directories, and a pattern with a `/` anywhere else is matched against
+//! the whole path from the top of the worktree rather than against the
+//! file name. `*`, `?` and `[...]` don't match `/`; `**` matches across
+//! directories.
+
+use crate::config::Config;
+use crate::path;
+use anyhow::Context;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    /// Whether the pattern is matched against the whole path.
+    anchored: bool,
+}
+
+impl Ignore {
+    /// The rules for the repository at `git_dir` and its worktree, which is
+    /// the current directory.
+    pub fn load(config: &Config, git_dir: &Path) -> anyhow::Result<Ignore> {
+        let mut ignore = Ignore::default();
+        if let Some(path) = excludes_file(config) {
+            ignore.read_file(&path)?;
+        }
+        ignore.read_file(&git_dir.join("info/exclude"))?;
+        ignore.read_file(Path::new(".gotignore"))?;
+        Ok(ignore)
+    }
+
+    /// Only the rules in the worktree's `.gotignore`, which go wherever the
+    /// repository does, without the user's or the repository's private
+    /// ones.
+    pub fn load_shared() -> anyhow::Result<Ignore> {
+        let mut ignore = Ignore::default();
+        ignore.read_file(Path::new(".gotignore"))?;
+        Ok(ignore)
+    }
+
+    /// Adds the rules in `path`, ignoring the file if it doesn't exist.
+    pub fn read_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.add_rules(&text);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("read {}", path.display())),
+        }
+    }
+
+    /// Adds the rules in `text`, one per line, after those already added.
+    pub fn add_rules(&mut self, text: &str) {
+        for line in text.lines() {
+            // Trailing spaces are dropped unless escaped.
+            let mut line = line.trim_end_matches('\r');
+            while line.ends_with(' ') && !line.ends_with("\\ ") {
+                line = &line[..line.len() - 1];
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').filter(|rest| rest.starts_with(['!', '#'])).unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            if line.is_empty() {
+                continue;
+            }
+            self.rules.push(Rule {
+                pattern: line.strip_prefix('/').unwrap_or(line).to_string(),
+                negated,
+                dir_only,
+                anchored: line.contains('/'),
+            });
+        }
+    }
+
+    /// Whether `path`, relative to the top of the worktree, is ignored.
+    /// Files in an ignored directory aren't looked at by callers, which
+    /// skip the directory.
+    pub fn is_ignored(&self, path: &[u8], is_dir: bool) -> bool {
+        let name = path::file_name(path);
+        for rule in self.rules.iter().rev() {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let matched = match rule.anchored {
+                true => wildmatch(rule.pattern.as_bytes(), path),
+                false => wildmatch(rule.pattern.as_bytes(), name),
+            };
+            if matched {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+}
+
+/// The user's excludes file: `core.excludesFile`, with a leading `~/`
+/// meaning the home directory, or `$XDG_CONFIG_HOME/got/ignore`.
+pub fn excludes_file(config: &Config) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    if let Some(path) = config.get("core.excludesFile") {
+        return match path.strip_prefix("~/") {
+            Some(rest) => home.map(|home| home.join(rest)),
+            None => Some(PathBuf::from(path)),
+        };
+    }
+    match std::env::var_os("XDG_CONFIG_HOME").filter(|xdg| !xdg.is_empty()) {
+        Some(xdg) => Some(PathBuf::from(xdg).join("got/ignore")),
+        None => home.map(|home| home.join(".config/got/ignore")),
+    }
+}
+
+/// Matches `text` against glob `pattern`, where only `**` can match a `/`.
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // `**/` also matches no directories at all.
+            if let Some(after) = rest.strip_prefix(b"/")
+                && wildmatch(after, text)
+            {
+                return true;
+            }
+            (0..=text.len()).any(|skip| wildmatch(rest, &text[skip..]))
+        }
+        [b'*', rest @ ..] => {
+            let run = text.iter().position(|&b| b == b'/').unwrap_or(text.len());
+            (0..=run).any(|skip| wildmatch(rest, &text[skip..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && wildmatch(rest, tail)),
+        [b'[', rest @ ..] => {
+            let Some((&c, tail)) = text.split_first().filter(|(c, _)| **c != b'/') else {
+                return false;
+            };
+            match match_class(rest, c) {
+                Some((true, after)) => wildmatch(after, tail),
+                Some((false, _)) => false,
+                // No closing `]`: the `[` is literal.
+                None => c == b'[' && wildmatch(rest, tail),
+            }
+        }
+        [b'\\', p, rest @ ..] => matches!(text, [c, tail @ ..] if c == p && wildmatch(rest, tail)),
+        [p, rest @ ..] => matches!(text, [c, tail @ ..] if c == p && wildmatch(rest, tail)),
+    }
+}
+
+/// Matches `c` against the bracket expression starting after a `[`,
+/// returning whether it matched and the pattern after the `]`.
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut rest) = match pattern {
+        [b'!' | b'^', rest @ ..] => (true, rest),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [] => return None,
+            [b']', after @ ..] if !first => return Some((matched != negated, after)),
+            [lo, b'-', hi, after @ ..] if *hi != b']' => {
+                matched |= (*lo..=*hi).contains(&c);
+                rest = after;
+            }
+            [b, after @ ..] => {
+                matched |= *b == c;
+                rest = after;
+            }
+        }
+        first = false;
+    }
+}
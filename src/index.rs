@@ -0,0 +1,811 @@
+//! The staging index, `.got/index`, in git's on-disk format.
+//!
+//! The file is a `DIRC` header (signature, version, entry count), the
+//! entries sorted by path and stage, optional extensions, and a trailing
+//! SHA-1 of everything before it. Each entry caches the file's stat data
+//! alongside its mode and blob hash so unchanged files needn't be rehashed.
+//! Versions 2 and 3 are read and written; version 3 is only needed for the
+//! extended flags (`intent-to-add`, `skip-worktree`). The untracked cache
+//! (see [`crate::untracked_cache`]) and the link to a shared index (see
+//! [`crate::split_index`]) are the extensions kept; others are skipped when
+//! read and not written back, which git tolerates for all of the optional
+//! ones.
+
+use crate::config::Config;
+use crate::object::{Kind, Object};
+use crate::oid::Oid;
+use crate::path;
+use crate::progress::ProgressReader;
+use crate::split_index::{self, Link};
+use crate::tree::{Mode, Tree};
+use crate::untracked_cache::{self, UntrackedCache};
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+
+const DEFAULT_BIG_FILE_THRESHOLD: u64 = 512 << 20;
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+const EXTENDED_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_INTENT_TO_ADD: u16 = 0x2000;
+
+/// The stat data cached for an entry. Fields are truncated to 32 bits, as
+/// git stores them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stat {
+    pub ctime: u32,
+    pub ctime_nsec: u32,
+    pub mtime: u32,
+    pub mtime_nsec: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+}
+
+impl Stat {
+    pub fn from_metadata(meta: &fs::Metadata) -> Stat {
+        Stat {
+            ctime: meta.ctime() as u32,
+            ctime_nsec: meta.ctime_nsec() as u32,
+            mtime: meta.mtime() as u32,
+            mtime_nsec: meta.mtime_nsec() as u32,
+            dev: meta.dev() as u32,
+            ino: meta.ino() as u32,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.size() as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub stat: Stat,
+    pub mode: Mode,
+    pub hash: Oid,
+    /// Path from the top of the worktree, `/`-separated.
+    pub path: Vec<u8>,
+    /// 0 normally; 1 to 3 for the base, ours and theirs of a conflict.
+    pub stage: u8,
+    pub assume_valid: bool,
+    pub skip_worktree: bool,
+    /// Recorded by `add -N`: the path is tracked but its contents aren't
+    /// staged yet.
+    pub intent_to_add: bool,
+}
+
+impl Entry {
+    pub fn new(path: &[u8], mode: Mode, hash: Oid, stat: Stat) -> Entry {
+        Entry {
+            stat,
+            mode,
+            hash,
+            path: path.to_vec(),
+            stage: 0,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+        }
+    }
+
+    /// Whether the file's current stat data matches what was cached, in
+    /// which case its contents can be assumed unchanged.
+    pub fn stat_matches(&self, meta: &fs::Metadata) -> bool {
+        let stat = Stat::from_metadata(meta);
+        let cached = &self.stat;
+        stat.mtime == cached.mtime
+            && stat.mtime_nsec == cached.mtime_nsec
+            && stat.ctime == cached.ctime
+            && stat.ctime_nsec == cached.ctime_nsec
+            && stat.ino == cached.ino
+            && stat.size == cached.size
+            && worktree_mode(meta) == Some(self.mode)
+    }
+
+    /// Whether the worktree file is to be left out of consideration, as
+    /// `update-index --assume-unchanged` and `--skip-worktree` ask: changes
+    /// to it are neither shown nor staged.
+    pub fn ignores_worktree(&self) -> bool {
+        self.assume_valid || self.skip_worktree
+    }
+
+    fn has_extended_flags(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Index {
+    entries: Vec<Entry>,
+    pub untracked_cache: Option<UntrackedCache>,
+    /// The shared index, when the index is split.
+    shared: Option<Shared>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Shared {
+    /// Zero until a shared index has been written.
+    hash: Oid,
+    entries: Vec<Entry>,
+}
+
+impl Index {
+    pub fn new() -> Index {
+        Index::default()
+    }
+
+    /// Reads the index of `git_dir`; a repository without one has an empty
+    /// index.
+    pub fn read(git_dir: &Path) -> anyhow::Result<Index> {
+        let data = match fs::read(git_dir.join("index")) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Index::new()),
+            Err(e) => return Err(e).context("read .got/index"),
+        };
+        let (mut index, link) = Index::decode(&data).context("parse .got/index")?;
+        if let Some(link) = link {
+            index.merge_shared(git_dir, link).context("parse .got/index")?;
+        }
+        Ok(index)
+    }
+
+    /// Parses an index that isn't split, as a split one can't be read
+    /// without its shared index.
+    pub fn parse(data: &[u8]) -> anyhow::Result<Index> {
+        let (index, link) = Index::decode(data)?;
+        anyhow::ensure!(link.is_none(), "index is split and needs its shared index");
+        Ok(index)
+    }
+
+    /// Parses an index file, leaving the entries of a split index as they
+    /// are stored and returning its link to the shared index.
+    fn decode(data: &[u8]) -> anyhow::Result<(Index, Option<Link>)> {
+        anyhow::ensure!(data.len() >= 12 + 20, "index file is too short");
+        let (body, checksum) = data.split_at(data.len() - 20);
+        anyhow::ensure!(Sha1::digest(body)[..] == *checksum, "index checksum mismatch");
+        anyhow::ensure!(&body[..4] == SIGNATURE, "index has a bad signature");
+        let version = read_u32(body, 4);
+        anyhow::ensure!(
+            version == 2 || version == 3,
+            "index version {version} is not supported"
+        );
+        let count = read_u32(body, 8) as usize;
+        let mut entries: Vec<Entry> = Vec::with_capacity(count);
+        let mut at = 12;
+        for _ in 0..count {
+            let start = at;
+            anyhow::ensure!(body.len() >= at + 62, "truncated index entry");
+            let field = |n: usize| read_u32(body, start + 4 * n);
+            let stat = Stat {
+                ctime: field(0),
+                ctime_nsec: field(1),
+                mtime: field(2),
+                mtime_nsec: field(3),
+                dev: field(4),
+                ino: field(5),
+                uid: field(7),
+                gid: field(8),
+                size: field(9),
+            };
+            let mode = Mode::from_bits(field(6))?;
+            let hash = Oid::from_bytes(&body[start + 40..start + 60])?;
+            let flags = read_u16(body, start + 60);
+            at = start + 62;
+            let mut extended = 0;
+            if flags & FLAG_EXTENDED != 0 {
+                anyhow::ensure!(version >= 3, "extended index entry in a version {version} index");
+                anyhow::ensure!(body.len() >= at + 2, "truncated index entry");
+                extended = read_u16(body, at);
+                at += 2;
+            }
+            let nul = body[at..]
+                .iter()
+                .position(|&b| b == 0)
+                .context("truncated index entry")?;
+            let path = body[at..at + nul].to_vec();
+            // Entries are padded with one to eight NULs to a multiple of
+            // eight bytes.
+            at = start + (at + nul - start + 8) / 8 * 8;
+            anyhow::ensure!(at <= body.len(), "truncated index entry");
+            let entry = Entry {
+                stat,
+                mode,
+                hash,
+                path,
+                stage: ((flags >> FLAG_STAGE_SHIFT) & 3) as u8,
+                assume_valid: flags & FLAG_ASSUME_VALID != 0,
+                skip_worktree: extended & EXTENDED_SKIP_WORKTREE != 0,
+                intent_to_add: extended & EXTENDED_INTENT_TO_ADD != 0,
+            };
+            // Replacements in a split index are nameless, so its entries
+            // are checked once merged.
+            if let Some(last) = entries.last().filter(|last| !last.path.is_empty() && !entry.path.is_empty()) {
+                anyhow::ensure!(
+                    entry_order(last, &entry).is_lt(),
+                    "index entry '{}' is out of order",
+                    path::display(&entry.path)
+                );
+            }
+            entries.push(entry);
+        }
+        let mut untracked_cache = None;
+        let mut link = None;
+        while at < body.len() {
+            anyhow::ensure!(body.len() >= at + 8, "truncated index extension");
+            let signature = &body[at..at + 4];
+            let size = read_u32(body, at + 4) as usize;
+            anyhow::ensure!(
+                signature[0].is_ascii_uppercase() || signature == split_index::SIGNATURE,
+                "index uses the required extension '{}', which is not supported",
+                String::from_utf8_lossy(signature)
+            );
+            anyhow::ensure!(body.len() >= at + 8 + size, "truncated index extension");
+            if signature == untracked_cache::SIGNATURE {
+                untracked_cache = Some(UntrackedCache::parse(&body[at + 8..at + 8 + size])?);
+            } else if signature == split_index::SIGNATURE {
+                link = Some(Link::parse(&body[at + 8..at + 8 + size])?);
+            }
+            at += 8 + size;
+        }
+        anyhow::ensure!(at == body.len(), "truncated index extension");
+        if link.is_none() && entries.iter().any(|entry| entry.path.is_empty()) {
+            anyhow::bail!("index entry has an empty path");
+        }
+        let index = Index {
+            entries,
+            untracked_cache,
+            shared: None,
+        };
+        Ok((index, link))
+    }
+
+    /// Applies the entries read from a split index to those of its shared
+    /// index: deleting, replacing, then adding.
+    fn merge_shared(&mut self, git_dir: &Path, link: Link) -> anyhow::Result<()> {
+        let own = std::mem::take(&mut self.entries);
+        let base = match link.base.is_zero() {
+            true => vec![],
+            false => {
+                let path = split_index::shared_path(git_dir, &link.base);
+                let data = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+                anyhow::ensure!(
+                    data.ends_with(link.base.as_bytes()),
+                    "broken index, expect {} in {}",
+                    link.base,
+                    path.display()
+                );
+                Index::parse(&data).with_context(|| format!("parse {}", path.display()))?.entries
+            }
+        };
+        let mut entries = base.clone();
+        let mut own = own.into_iter();
+        for (i, _) in link.replace.iter().enumerate().filter(|(_, replaced)| **replaced) {
+            anyhow::ensure!(i < base.len(), "position for replacement {i} exceeds base index size {}", base.len());
+            anyhow::ensure!(
+                link.delete.get(i) != Some(&true),
+                "entry {i} is marked as both replaced and deleted"
+            );
+            let mut replacement = own.next().context("too many replacements in link extension")?;
+            anyhow::ensure!(
+                replacement.path.is_empty(),
+                "corrupt link extension, entry {i} should have a zero length name"
+            );
+            replacement.path = base[i].path.clone();
+            entries[i] = replacement;
+        }
+        let mut i = 0;
+        entries.retain(|_| {
+            i += 1;
+            link.delete.get(i - 1) != Some(&true)
+        });
+        self.entries = entries;
+        for entry in own {
+            anyhow::ensure!(!entry.path.is_empty(), "index entry has an empty path");
+            match self.position(&entry.path, entry.stage) {
+                Ok(i) => self.entries[i] = entry,
+                Err(i) => self.entries.insert(i, entry),
+            }
+        }
+        self.shared = Some(Shared {
+            hash: link.base,
+            entries: base,
+        });
+        Ok(())
+    }
+
+    /// The index as a single file, not split.
+    pub fn serialize(&self) -> Vec<u8> {
+        let entries: Vec<&Entry> = self.entries.iter().collect();
+        encode(&entries, None, self.untracked_cache.as_ref())
+    }
+
+    /// Replaces the index of `git_dir`, writing `index.lock` first and
+    /// renaming it into place so readers never see a partial index. The
+    /// index is written split if it was read split or `update-index
+    /// --split-index` asked for it, unless `core.splitIndex` says
+    /// otherwise.
+    pub fn write(&self, git_dir: &Path) -> anyhow::Result<()> {
+        let config = Config::load()?;
+        let split = config.get_bool("core.splitIndex")?.unwrap_or(self.shared.is_some());
+        let data = match split {
+            true => self.serialize_split(&config, git_dir)?,
+            false => self.serialize(),
+        };
+        let lock = git_dir.join("index.lock");
+        fs::write(&lock, data).context("write .got/index.lock")?;
+        fs::rename(&lock, git_dir.join("index")).context("replace .got/index")
+    }
+
+    /// The index as the changes since its shared index, writing a new
+    /// shared index first if there isn't one or too much has changed.
+    fn serialize_split(&self, config: &Config, git_dir: &Path) -> anyhow::Result<Vec<u8>> {
+        let max_percent = split_index::max_percent_change(config)?;
+        let delta = match &self.shared {
+            Some(shared) if !shared.hash.is_zero() => {
+                let delta = Delta::between(&shared.entries, &self.entries);
+                let not_shared = delta.replaced.len() + delta.added.len();
+                (not_shared as i64 * 100 <= max_percent * self.entries.len() as i64).then_some((shared.hash, delta))
+            }
+            _ => None,
+        };
+        let (base, delta) = match delta {
+            Some((base, delta)) => {
+                split_index::freshen(git_dir, &base)?;
+                (base, delta)
+            }
+            None => {
+                let entries: Vec<&Entry> = self.entries.iter().collect();
+                let base = split_index::write_shared(config, git_dir, &encode(&entries, None, None))?;
+                (base, Delta::default())
+            }
+        };
+        // Replacements take their names from the entries they replace.
+        let replaced: Vec<Entry> = delta
+            .replaced
+            .iter()
+            .map(|entry| Entry {
+                path: vec![],
+                ..(*entry).clone()
+            })
+            .collect();
+        let entries: Vec<&Entry> = replaced.iter().chain(delta.added).collect();
+        let link = Link {
+            base,
+            delete: delta.delete,
+            replace: delta.replace,
+        };
+        Ok(encode(&entries, Some(&link), self.untracked_cache.as_ref()))
+    }
+
+    /// Whether the index is written split, with a shared index.
+    pub fn is_split(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    /// Splits the index, writing a shared index when it is next written,
+    /// or puts it back together.
+    pub fn set_split(&mut self, split: bool) {
+        match split {
+            true if self.shared.is_none() => self.shared = Some(Shared::default()),
+            true => {}
+            false => self.shared = None,
+        }
+    }
+
+    /// All entries, sorted by path and then stage.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn entries_mut(&mut self) -> impl Iterator<Item = &mut Entry> {
+        self.entries.iter_mut()
+    }
+
+    /// The stage 0 entry for `path`.
+    pub fn get(&self, path: &[u8]) -> Option<&Entry> {
+        self.position(path, 0).ok().map(|i| &self.entries[i])
+    }
+
+    /// Adds `entry`, replacing any entry with the same path and stage.
+    /// Staging a path at stage 0 resolves any conflict recorded for it.
+    pub fn insert(&mut self, entry: Entry) {
+        let range = self.range(&entry.path);
+        if range.is_empty()
+            && let Some(cache) = &mut self.untracked_cache
+        {
+            cache.invalidate(&entry.path);
+        }
+        if entry.stage == 0 {
+            self.entries.splice(range, [entry]);
+            return;
+        }
+        match self.position(&entry.path, entry.stage) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Removes every stage of `path`, returning whether there were any.
+    pub fn remove(&mut self, path: &[u8]) -> bool {
+        let range = self.range(path);
+        let found = !range.is_empty();
+        self.entries.drain(range);
+        if found && let Some(cache) = &mut self.untracked_cache {
+            cache.invalidate(path);
+        }
+        found
+    }
+
+    /// Stores what is staged as tree objects and returns the root tree.
+    /// Paths added with `add -N` are left out, having nothing staged yet.
+    pub fn write_tree(&self) -> anyhow::Result<Oid> {
+        let mut unmerged: Vec<Cow<str>> = self.entries.iter().filter(|e| e.stage != 0).map(|e| path::display(&e.path)).collect();
+        unmerged.dedup();
+        anyhow::ensure!(
+            unmerged.is_empty(),
+            "cannot write a tree with unmerged paths:\n\t{}",
+            unmerged.join("\n\t")
+        );
+        let entries: Vec<(&[u8], Mode, Oid)> = self
+            .entries
+            .iter()
+            .filter(|e| !e.intent_to_add)
+            .map(|e| (&e.path[..], e.mode, e.hash))
+            .collect();
+        write_subtree(&entries)
+    }
+
+    /// Where the entries for every stage of `path` are, which sit together.
+    fn range(&self, path: &[u8]) -> std::ops::Range<usize> {
+        let start = self.entries.partition_point(|e| &e.path[..] < path);
+        let len = self.entries[start..].iter().take_while(|e| e.path == path).count();
+        start..start + len
+    }
+
+    fn position(&self, path: &[u8], stage: u8) -> Result<usize, usize> {
+        self.entries.binary_search_by(|e| e.path[..].cmp(path).then(e.stage.cmp(&stage)))
+    }
+}
+
+/// Stores the tree holding `entries`, whose paths are relative to it and in
+/// index order, which keeps each subdirectory's entries together.
+fn write_subtree(entries: &[(&[u8], Mode, Oid)]) -> anyhow::Result<Oid> {
+    let mut tree = Tree::new();
+    let mut rest = entries;
+    while let Some(&(path, mode, hash)) = rest.first() {
+        let Some((dir, _)) = path::split_first(path) else {
+            tree.insert(mode, path, hash)?;
+            rest = &rest[1..];
+            continue;
+        };
+        let len = rest
+            .iter()
+            .take_while(|(path, _, _)| path::split_first(path).is_some_and(|(other, _)| other == dir))
+            .count();
+        let children: Vec<(&[u8], Mode, Oid)> = rest[..len]
+            .iter()
+            .map(|&(path, mode, hash)| (&path[dir.len() + 1..], mode, hash))
+            .collect();
+        tree.insert(Mode::Tree, dir, write_subtree(&children)?)?;
+        rest = &rest[len..];
+    }
+    Object::store(Kind::Tree, &tree.serialize())
+}
+
+/// Index order: bytewise by path, then by stage.
+/// How a split index's entries differ from its shared index.
+#[derive(Default)]
+struct Delta<'a> {
+    /// Shared entries that are gone or replaced, by position.
+    delete: Vec<bool>,
+    replace: Vec<bool>,
+    /// The replacements, in the order of the entries they replace.
+    replaced: Vec<&'a Entry>,
+    added: Vec<&'a Entry>,
+}
+
+impl<'a> Delta<'a> {
+    fn between(base: &[Entry], entries: &'a [Entry]) -> Delta<'a> {
+        let mut delta = Delta {
+            delete: vec![false; base.len()],
+            replace: vec![false; base.len()],
+            ..Delta::default()
+        };
+        let (mut i, mut j) = (0, 0);
+        loop {
+            let order = match (base.get(i), entries.get(j)) {
+                (None, None) => break,
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (Some(old), Some(new)) => entry_order(old, new),
+            };
+            match order {
+                std::cmp::Ordering::Less => {
+                    delta.delete[i] = true;
+                    i += 1;
+                }
+                std::cmp::Ordering::Greater => {
+                    delta.added.push(&entries[j]);
+                    j += 1;
+                }
+                std::cmp::Ordering::Equal => {
+                    if base[i] != entries[j] {
+                        delta.replace[i] = true;
+                        delta.replaced.push(&entries[j]);
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        delta
+    }
+}
+
+/// Encodes `entries` as an index file with the given extensions.
+fn encode(entries: &[&Entry], link: Option<&Link>, untracked_cache: Option<&UntrackedCache>) -> Vec<u8> {
+    let version: u32 = if entries.iter().any(|entry| entry.has_extended_flags()) { 3 } else { 2 };
+    let mut out = vec![];
+    out.extend(SIGNATURE);
+    out.extend(version.to_be_bytes());
+    out.extend((entries.len() as u32).to_be_bytes());
+    for entry in entries {
+        let start = out.len();
+        let stat = &entry.stat;
+        for field in [
+            stat.ctime,
+            stat.ctime_nsec,
+            stat.mtime,
+            stat.mtime_nsec,
+            stat.dev,
+            stat.ino,
+            entry.mode.bits(),
+            stat.uid,
+            stat.gid,
+            stat.size,
+        ] {
+            out.extend(field.to_be_bytes());
+        }
+        out.extend(entry.hash.as_bytes());
+        let mut flags = (entry.path.len().min(FLAG_NAME_MASK as usize) as u16)
+            | (u16::from(entry.stage) << FLAG_STAGE_SHIFT);
+        if entry.assume_valid {
+            flags |= FLAG_ASSUME_VALID;
+        }
+        if entry.has_extended_flags() {
+            flags |= FLAG_EXTENDED;
+        }
+        out.extend(flags.to_be_bytes());
+        if entry.has_extended_flags() {
+            let mut extended = 0;
+            if entry.skip_worktree {
+                extended |= EXTENDED_SKIP_WORKTREE;
+            }
+            if entry.intent_to_add {
+                extended |= EXTENDED_INTENT_TO_ADD;
+            }
+            out.extend(extended.to_be_bytes());
+        }
+        out.extend(&entry.path);
+        let len = out.len() - start;
+        out.resize(start + (len + 8) / 8 * 8, 0);
+    }
+    let extensions = [
+        link.map(|link| (split_index::SIGNATURE, link.serialize())),
+        untracked_cache.map(|cache| (untracked_cache::SIGNATURE, cache.serialize())),
+    ];
+    for (signature, data) in extensions.into_iter().flatten() {
+        out.extend(signature);
+        out.extend((data.len() as u32).to_be_bytes());
+        out.extend(data);
+    }
+    let checksum = Sha1::digest(&out);
+    out.extend(checksum);
+    out
+}
+
+fn entry_order(a: &Entry, b: &Entry) -> std::cmp::Ordering {
+    a.path.cmp(&b.path).then(a.stage.cmp(&b.stage))
+}
+
+/// The mode a worktree file would be staged with, or `None` for things
+/// that can't be staged (directories, sockets and so on).
+pub fn worktree_mode(meta: &fs::Metadata) -> Option<Mode> {
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        Some(Mode::Symlink)
+    } else if file_type.is_file() && meta.mode() & 0o100 != 0 {
+        Some(Mode::Executable)
+    } else if file_type.is_file() {
+        Some(Mode::File)
+    } else {
+        None
+    }
+}
+
+/// The blob contents of a worktree file: its bytes, or for a symlink the
+/// path it points to.
+pub fn read_worktree_file(path: &Path, meta: &fs::Metadata) -> anyhow::Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path).with_context(|| format!("read link {}", path.display()))?;
+        Ok(target.as_os_str().as_bytes().to_vec())
+    } else {
+        fs::read(path).with_context(|| format!("read {}", path.display()))
+    }
+}
+
+/// Replaces whatever is at `path` in the worktree with a file of `mode`
+/// holding `contents`; for a symlink, `contents` is the path it points to.
+pub fn write_worktree_file(path: &[u8], mode: Mode, contents: &[u8]) -> anyhow::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+    let (path, shown) = (path::to_path(path), path::display(path));
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("remove {shown}"));
+        }
+        _ => {}
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create directory for {shown}"))?;
+    }
+    match mode {
+        Mode::Symlink => {
+            let target = std::ffi::OsStr::from_bytes(contents);
+            std::os::unix::fs::symlink(target, path).with_context(|| format!("create link {shown}"))
+        }
+        Mode::File | Mode::Executable => {
+            fs::write(path, contents).with_context(|| format!("write {shown}"))?;
+            let bits = if mode == Mode::Executable { 0o755 } else { 0o644 };
+            fs::set_permissions(path, fs::Permissions::from_mode(bits)).with_context(|| format!("set mode of {shown}"))
+        }
+        Mode::Tree | Mode::Submodule => anyhow::bail!("cannot write a {} to the worktree", mode.kind()),
+    }
+}
+
+/// Stores the worktree file at `path` as a blob and returns its entry.
+pub fn stage_file(path: &[u8]) -> anyhow::Result<Entry> {
+    stage_file_with(path, DEFAULT_BIG_FILE_THRESHOLD, false)
+}
+
+/// Stages `path` as [`stage_file`] does, except that a file of `threshold`
+/// bytes or more is streamed into the object store rather than read whole,
+/// with a progress meter as it is hashed if `progress` is set.
+pub fn stage_file_with(path: &[u8], threshold: u64, progress: bool) -> anyhow::Result<Entry> {
+    let (file_path, shown) = (path::to_path(path), path::display(path));
+    let meta = fs::symlink_metadata(file_path).with_context(|| format!("stat {shown}"))?;
+    let mode = worktree_mode(&meta).with_context(|| format!("'{shown}' is not a file"))?;
+    let hash = if meta.is_file() && meta.len() >= threshold {
+        let file = fs::File::open(file_path).with_context(|| format!("open {shown}"))?;
+        let mut reader = ProgressReader::new(io::BufReader::new(file), &format!("Hashing {shown}"), meta.len(), progress);
+        let hash = Object::store_stream(Kind::Blob, &mut reader, meta.len()).with_context(|| format!("hash {shown}"))?;
+        reader.done();
+        hash
+    } else {
+        Object::store(Kind::Blob, &read_worktree_file(file_path, &meta)?)?
+    };
+    Ok(Entry::new(path, mode, hash, Stat::from_metadata(&meta)))
+}
+
+/// The size from which files are streamed into the object store:
+/// `core.bigFileThreshold`, 512 MiB unless set.
+pub fn big_file_threshold(config: &Config) -> anyhow::Result<u64> {
+    match config.get_int("core.bigFileThreshold")? {
+        None => Ok(DEFAULT_BIG_FILE_THRESHOLD),
+        Some(size) => u64::try_from(size).context("core.bigFileThreshold must not be negative"),
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().expect("slice is 4 bytes"))
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes(data[at..at + 2].try_into().expect("slice is 2 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempDir;
+
+    // Indexes written by git itself.
+
+    /// `a` staged, `b` staged and marked skip-worktree, `c` added with
+    /// `add -N`, and the untracked cache `git status` wrote with
+    /// `core.untrackedCache`.
+    const FLAGGED_INDEX: &str = concat!(
+        "4449524300000003000000036ad289e337f9ff136ad289e337f9ff130000fe000013c9d2000081a400000000000000000000000278981922613b2afb6025042f",
+        "f6bd878ac1994e85000161006ad289e337f9ff136ad289e337f9ff130000fe000013ca12000081a400000000000000000000000261780798228d17af2d34fce4",
+        "cfbdf35556832472400140006200000000000000000000000000000000000000000000000000000000000000000081a4000000000000000000000000e69de29b",
+        "b2d1d6434b8b29ae775ad8c2e48c5391400120006300000000000000554e5452000001161f4c6f636174696f6e202f746d702f69782c2073797374656d204c69",
+        "6e7578006ad289e33776b9f16ad289e33776b9f10000fe000013c9720000000000000000000000f0000000000000000000000000000000000000000000000000",
+        "00000000000000000000000000000006cc30ca8b9b10bb92f8e5c96ee94348c6c4ac93e600000000000000000000000000000000000000002e67697469676e6f",
+        "72650001010000756e747261636b6564000000000100000002000000020000000000000000000000010000000000000000000000010000000000000000000000",
+        "0000000000000000010000000000000000000000006ad289e337f9ff136ad289e337f9ff130000fe000013c73700000000000000000000100000ce1019d1a1d6",
+        "0e020856ce4657cd9ef225aa139f",
+    );
+
+    /// After `update-index --split-index` with `a`, `b` and `c` staged,
+    /// `a` changed, `d` added and `c` removed: the split index and the
+    /// shared index its link names.
+    const SPLIT_INDEX: &str = concat!(
+        "4449524300000002000000036ad289e7178186016ad289e7178186010000fe00001421c2000081a4000000000000000000000003c1827f07e114c20547dc6a72",
+        "96588870a4b5b62c000000006ad289e7174a50906ad289e7174a50900000fe00001421d2000081a400000000000000000000000261780798228d17af2d34fce4",
+        "cfbdf35556832472000000006ad289e7178186016ad289e7178186010000fe00001421f2000081a40000000000000000000000024bcfe98e640c828451131266",
+        "0fb8709b0afa888e000000006c696e6b0000004ce7f491cd9639d16324b435d9d955423f3ca741fe000000030000000200000002000000000000000000000004",
+        "0000000000000004000000020000000200000000000000000000000b00000000648a64dee301ef067dea1c6083f723e44a16ecf4",
+    );
+    const SHARED_INDEX: &str = concat!(
+        "4449524300000002000000046ad289e7178186016ad289e7178186010000fe00001421c2000081a4000000000000000000000003c1827f07e114c20547dc6a72",
+        "96588870a4b5b62c000161006ad289e7174a50906ad289e7174a50900000fe00001421d2000081a400000000000000000000000261780798228d17af2d34fce4",
+        "cfbdf35556832472000162006ad289e7174a50906ad289e7174a50900000fe00001421e2000081a4000000000000000000000002f2ad6c76f0115a6ba5b00456",
+        "a849810e7ec0af20000163006ad289e7178186016ad289e7178186010000fe00001421f2000081a40000000000000000000000024bcfe98e640c828451131266",
+        "0fb8709b0afa888e00016400e7f491cd9639d16324b435d9d955423f3ca741fe",
+    );
+    const SHARED_INDEX_HASH: &str = "e7f491cd9639d16324b435d9d955423f3ca741fe";
+
+    fn listing(index: &Index) -> Vec<(&[u8], String)> {
+        index.entries().iter().map(|entry| (&entry.path[..], entry.hash.to_string())).collect()
+    }
+
+    #[test]
+    fn extended_flags_and_extensions_round_trip() {
+        let data = hex::decode(FLAGGED_INDEX).unwrap();
+        let index = Index::parse(&data).unwrap();
+        let flags: Vec<_> =
+            index.entries().iter().map(|entry| (&entry.path[..], entry.skip_worktree, entry.intent_to_add)).collect();
+        assert_eq!(flags, [(&b"a"[..], false, false), (b"b", true, false), (b"c", false, true)]);
+        assert!(index.untracked_cache.is_some());
+        assert_eq!(index.serialize(), data);
+    }
+
+    #[test]
+    fn split_indexes_are_merged_with_their_shared_index() {
+        let data = hex::decode(SPLIT_INDEX).unwrap();
+        assert!(Index::parse(&data).is_err());
+        let (index, link) = Index::decode(&data).unwrap();
+        let entries: Vec<&Entry> = index.entries.iter().collect();
+        assert_eq!(encode(&entries, link.as_ref(), None), data);
+
+        let git_dir = TempDir::new("index-test").unwrap();
+        fs::write(git_dir.path().join("index"), &data).unwrap();
+        let shared_path = split_index::shared_path(git_dir.path(), &SHARED_INDEX_HASH.parse().unwrap());
+        fs::write(&shared_path, hex::decode(SHARED_INDEX).unwrap()).unwrap();
+        // As `git ls-files -s` lists it.
+        assert_eq!(
+            listing(&Index::read(git_dir.path()).unwrap()),
+            [
+                (&b"a"[..], "c1827f07e114c20547dc6a7296588870a4b5b62c".to_string()),
+                (b"b", "61780798228d17af2d34fce4cfbdf35556832472".to_string()),
+                (b"d", "4bcfe98e640c8284511312660fb8709b0afa888e".to_string()),
+            ]
+        );
+
+        // The shared index must be the one the link names.
+        let mut shared = hex::decode(SHARED_INDEX).unwrap();
+        let last = shared.len() - 1;
+        shared[last] ^= 1;
+        fs::write(&shared_path, shared).unwrap();
+        assert!(Index::read(git_dir.path()).is_err());
+    }
+
+    #[test]
+    fn corrupt_indexes_are_refused() {
+        let mut data = hex::decode(FLAGGED_INDEX).unwrap();
+        data[20] ^= 1;
+        assert!(Index::parse(&data).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! Choosing changes a hunk at a time, the machinery behind `restore
+//! --patch`, `reset --patch` and `commit --patch`.
+//!
+//! Each file's diff is shown one piece at a time (its mode change, then
+//! each hunk; or the whole file for an addition or deletion) and the user
+//! answers whether to act on it. A `Decision` then tells the caller what to
+//! turn the file into: the new side with the chosen pieces undone, or for
+//! `PatchMode::Stage` the old side with them applied.
+
+use crate::diff::{self, Hunk};
+use crate::tree_diff::{Change, Status};
+use std::io::{BufRead, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchMode {
+    /// Throw chosen worktree changes away (`restore --patch`).
+    Discard,
+    /// Take chosen changes back out of the index (`reset --patch`).
+    Unstage,
+    /// Put chosen worktree changes into the index (`commit --patch`).
+    Stage,
+}
+
+impl PatchMode {
+    fn prompt(self, what: &str) -> String {
+        match self {
+            PatchMode::Discard => format!("Discard {what} from worktree"),
+            PatchMode::Unstage => format!("Unstage {what}"),
+            PatchMode::Stage => format!("Stage {what}"),
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            PatchMode::Discard => {
+                "y - discard this hunk from worktree\n\
+                 n - do not discard this hunk from worktree\n\
+                 q - quit; do not discard this hunk or any of the remaining ones\n\
+                 a - discard this hunk and all later hunks in the file\n\
+                 d - do not discard this hunk or any of the later hunks in the file\n"
+            }
+            PatchMode::Unstage => {
+                "y - unstage this hunk\n\
+                 n - do not unstage this hunk\n\
+                 q - quit; do not unstage this hunk or any of the remaining ones\n\
+                 a - unstage this hunk and all later hunks in the file\n\
+                 d - do not unstage this hunk or any of the later hunks in the file\n"
+            }
+            PatchMode::Stage => {
+                "y - stage this hunk\n\
+                 n - do not stage this hunk\n\
+                 q - quit; do not stage this hunk or any of the remaining ones\n\
+                 a - stage this hunk and all later hunks in the file\n\
+                 d - do not stage this hunk or any of the later hunks in the file\n"
+            }
+        }
+    }
+}
+
+/// What to do with a file once its pieces are chosen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Nothing was chosen; leave it alone.
+    Keep,
+    /// Give it this mode and these contents.
+    Update { mode: u32, contents: Vec<u8> },
+    /// Undoing its addition, or applying its deletion, was chosen: remove
+    /// it.
+    Remove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    Quit,
+    All,
+    Done,
+}
+
+pub struct Selector<R, W> {
+    input: R,
+    output: W,
+    mode: PatchMode,
+    quit: bool,
+}
+
+impl<R: BufRead, W: Write> Selector<R, W> {
+    pub fn new(input: R, output: W, mode: PatchMode) -> Selector<R, W> {
+        Selector {
+            input,
+            output,
+            mode,
+            quit: false,
+        }
+    }
+
+    /// Whether the user quit; later files should not be asked about.
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Asks about each piece of `change`, whose two sides hold `old` and
+    /// `new`, and returns what the chosen pieces make of the file. Undoing
+    /// a piece moves the new side back to what `old` had; staging one moves
+    /// the old side on to what `new` has.
+    pub fn select(&mut self, change: &Change, old: &[u8], new: &[u8]) -> anyhow::Result<Decision> {
+        if self.quit {
+            return Ok(Decision::Keep);
+        }
+        let mut header = vec![];
+        let has_hunks = diff::write_file_header(&mut header, change, old, new, 7);
+        if matches!(change.status, Status::Added | Status::Deleted) {
+            if has_hunks {
+                diff::write_hunks(&mut header, old, new, diff::DEFAULT_CONTEXT);
+            }
+            self.output.write_all(&header)?;
+            let what = if change.status == Status::Added { "addition" } else { "deletion" };
+            let answer = self.ask(&format!("(1/1) {}", self.mode.prompt(what)))?;
+            writeln!(self.output)?;
+            let forward = self.mode == PatchMode::Stage;
+            return Ok(match (answer, change.status == Status::Added) {
+                (Answer::Yes | Answer::All, added) if added != forward => Decision::Remove,
+                (Answer::Yes | Answer::All, _) if forward => Decision::Update {
+                    mode: change.new_mode,
+                    contents: new.to_vec(),
+                },
+                (Answer::Yes | Answer::All, _) => Decision::Update {
+                    mode: change.old_mode,
+                    contents: old.to_vec(),
+                },
+                _ => Decision::Keep,
+            });
+        }
+
+        let old_lines = diff::split_lines(old);
+        let new_lines = diff::split_lines(new);
+        let hunks: Vec<Hunk> = if has_hunks {
+            diff::hunks(&old_lines, &new_lines, diff::DEFAULT_CONTEXT)
+        } else {
+            vec![]
+        };
+        let mode_change = change.old_mode != change.new_mode;
+        let total = hunks.len() + usize::from(mode_change);
+        if total == 0 {
+            return Ok(Decision::Keep);
+        }
+        // The mode change is shown as a piece of its own after the header.
+        let mode_lines = format!("old mode {:06o}\nnew mode {:06o}\n", change.old_mode, change.new_mode);
+        if mode_change && let Some(at) = header.windows(mode_lines.len()).position(|w| w == mode_lines.as_bytes()) {
+            header.drain(at..at + mode_lines.len());
+        }
+        self.output.write_all(&header)?;
+        // Pieces are numbered from 1, the mode change first.
+        let mut chosen = vec![false; total];
+        let mut i = 0;
+        while i < total {
+            let what = if mode_change && i == 0 {
+                self.output.write_all(mode_lines.as_bytes())?;
+                "mode change"
+            } else {
+                let mut out = vec![];
+                diff::write_hunk(&mut out, &hunks[i - usize::from(mode_change)], &old_lines, &new_lines);
+                self.output.write_all(&out)?;
+                "this hunk"
+            };
+            match self.ask(&format!("({}/{total}) {}", i + 1, self.mode.prompt(what)))? {
+                Answer::Yes => chosen[i] = true,
+                Answer::No => {}
+                Answer::All => {
+                    chosen[i..].fill(true);
+                    break;
+                }
+                Answer::Done | Answer::Quit => break,
+            }
+            i += 1;
+        }
+        writeln!(self.output)?;
+        if !chosen.contains(&true) {
+            return Ok(Decision::Keep);
+        }
+        let (mode_chosen, hunks_chosen) = chosen.split_at(usize::from(mode_change));
+        if self.mode == PatchMode::Stage {
+            // Staging applies just the chosen pieces to the old side.
+            let mode = if mode_chosen.first() == Some(&true) { change.new_mode } else { change.old_mode };
+            let contents = if has_hunks {
+                diff::apply_hunks(&old_lines, &new_lines, &hunks, hunks_chosen)
+            } else {
+                old.to_vec()
+            };
+            return Ok(Decision::Update { mode, contents });
+        }
+        let mode = if mode_chosen.first() == Some(&true) { change.old_mode } else { change.new_mode };
+        // Undoing a hunk means keeping only the hunks that weren't chosen.
+        // Without hunks (a binary file) only the mode can be undone.
+        let contents = if has_hunks {
+            let keep: Vec<bool> = hunks_chosen.iter().map(|chosen| !chosen).collect();
+            diff::apply_hunks(&old_lines, &new_lines, &hunks, &keep)
+        } else {
+            new.to_vec()
+        };
+        Ok(Decision::Update { mode, contents })
+    }
+
+    fn ask(&mut self, prompt: &str) -> anyhow::Result<Answer> {
+        loop {
+            write!(self.output, "{prompt} [y,n,q,a,d,?]? ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                // End of input counts as quitting.
+                self.quit = true;
+                return Ok(Answer::Quit);
+            }
+            match line.trim().chars().next() {
+                Some('y') => return Ok(Answer::Yes),
+                Some('n') => return Ok(Answer::No),
+                Some('a') => return Ok(Answer::All),
+                Some('d') => return Ok(Answer::Done),
+                Some('q') => {
+                    self.quit = true;
+                    return Ok(Answer::Quit);
+                }
+                None => {}
+                Some('?') | Some(_) => {
+                    self.output.write_all(self.mode.help().as_bytes())?;
+                    self.output.write_all(b"? - print help\n")?;
+                }
+            }
+        }
+    }
+}
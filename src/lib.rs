@@ -0,0 +1,54 @@
+pub mod am;
+pub mod apply;
+pub mod attributes;
+pub mod backup;
+pub mod blame;
+pub mod clone;
+pub mod column;
+pub mod commit_graph;
+pub mod config;
+pub mod convert;
+pub mod credential;
+pub mod credential_cache;
+pub mod diff;
+pub mod difftool;
+pub mod editor;
+pub mod ewah;
+pub mod gc;
+pub mod grafts;
+pub mod http;
+pub mod ignore;
+pub mod index;
+pub mod interactive;
+pub mod merge;
+pub mod object;
+pub mod oid;
+pub mod pack;
+pub mod path;
+pub mod patch_id;
+pub mod progress;
+pub mod quote;
+pub mod receive;
+pub mod reflog;
+pub mod refs;
+pub mod refspec;
+pub mod remote_helper;
+pub mod repack;
+pub mod repository;
+pub mod revision;
+pub mod revwalk;
+pub mod scan;
+pub mod sequencer;
+pub mod shallow;
+pub mod split_index;
+pub mod stash;
+pub mod submodule;
+pub mod subtree;
+pub mod temp;
+pub mod transport;
+pub mod tree;
+pub mod tree_diff;
+pub mod untracked_cache;
+pub mod verify;
+pub mod versioncmp;
+pub mod worktree;
@@ -1,21 +1,59 @@
 use anyhow::{Context, Ok};
-use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-use flate2::Compression;
+use got::attributes::Attributes;
+use got::clone::{self, CloneOptions};
+use got::column::{self, Columns};
+use got::commit_graph::CommitGraph;
+use got::config::Config;
+use got::convert::Converter;
+use got::credential::{self, Credential};
+use got::credential_cache;
+use got::ignore::{self, Ignore};
+use got::index::{self, Index};
+use got::merge::Resolution;
+use got::interactive::{Decision, PatchMode, Selector};
+use got::object::{Commit, Kind, Object, Signature};
+use got::oid::Oid;
+use got::quote::quote_path;
+use got::refspec::Refspec;
+use got::remote_helper::{self, PackLocks, RefValue, RemoteHelper};
+use got::repack::RepackOptions;
+use got::revwalk::{self, CommitInfo, RevWalk};
+use got::scan::{self, ScanOptions};
+use got::sequencer::{Action, Sequencer, Step};
+use got::transport::SmartHttp;
+use got::tree::{self, Mode, Tree, TreeWalk};
+use got::tree_diff::{Change, Status};
+use got::untracked_cache::UntrackedCache;
+use got::{path, refs, repository, revision, shallow, stash, submodule, tree_diff, versioncmp, worktree};
 use is_executable::IsExecutable;
-use std::collections::{HashMap, HashSet};
-use std::ffi::CStr;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::{fmt, fs};
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::{BufRead, IsTerminal, Read, Write};
 use std::path::Path;
 use std::str::FromStr;
-use sha1::{Sha1,Digest};
+
+/// Exit statuses scripts can rely on, as git's: 1 when the command ran but
+/// found differences, hit conflicts or refused part of what it was asked,
+/// 128 when it failed outright and 129 when it was used wrongly.
+const EXIT_FAILED: i32 = 1;
+const EXIT_FATAL: i32 = 128;
+const EXIT_USAGE: i32 = 129;
+/// What the shell reports for a command killed by SIGPIPE, as git is when
+/// what it writes to has gone away.
+const EXIT_BROKEN_PIPE: i32 = 141;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit status: 0 on success; 1 when differences or conflicts were found or part of the request was refused; \
+                  128 on a fatal error; 129 on wrong usage. Errors go to standard error."
+)]
 struct Args {
+    #[clap(long = "read-only", global = true)]
+    read_only: bool,
     #[command(subcommand)]
     command: Command,
 }
@@ -26,8 +64,17 @@ enum Command {
     CatFile {
         #[clap(short = 'p')]
         pretty_print: bool,
+        #[clap(long, conflicts_with_all = ["pretty_print", "textconv"])]
+        filters: bool,
+        #[clap(long, conflicts_with = "pretty_print")]
+        textconv: bool,
+        #[clap(long)]
+        path: Option<String>,
         hash: String,
     },
+    UnpackFile {
+        blob: String,
+    },
     HashObject {
         #[clap(short = 'w')]
         write: bool,
@@ -36,170 +83,610 @@ enum Command {
     LsTree {
         treehash: String,
     },
-    WriteTree,
+    WriteTree {
+        #[clap(long)]
+        reproducible: bool,
+    },
+    VerifyTree {
+        commit: Option<String>,
+    },
+    VerifyRemote {
+        remote: Option<String>,
+    },
+    PrunePacked {
+        #[clap(short = 'n', long = "dry-run")]
+        dry_run: bool,
+    },
+    ShowIndex,
+    UnpackObjects {
+        #[clap(short = 'n')]
+        dry_run: bool,
+    },
+    ReceivePack {
+        directory: String,
+    },
+    PackObjects {
+        #[clap(long)]
+        revs: bool,
+        #[clap(long)]
+        stdout: bool,
+        #[clap(long)]
+        window: Option<usize>,
+        #[clap(long)]
+        depth: Option<usize>,
+        #[clap(long)]
+        threads: Option<usize>,
+        #[clap(long = "no-reuse-delta")]
+        no_reuse_delta: bool,
+        #[clap(short = 'q', long)]
+        quiet: bool,
+        #[clap(required_unless_present = "stdout", conflicts_with = "stdout")]
+        base_name: Option<String>,
+    },
+    Repack {
+        #[clap(short = 'a')]
+        all: bool,
+        #[clap(short = 'd')]
+        delete: bool,
+        #[clap(long)]
+        window: Option<usize>,
+        #[clap(long)]
+        depth: Option<usize>,
+        #[clap(long)]
+        threads: Option<usize>,
+        #[clap(short = 'b', long = "write-bitmap-index")]
+        write_bitmap_index: bool,
+        #[clap(long = "pack-kept-objects")]
+        pack_kept_objects: bool,
+        #[clap(short = 'f')]
+        no_reuse_delta: bool,
+        #[clap(short = 'i', long = "delta-islands")]
+        delta_islands: bool,
+    },
+    Gc {
+        #[clap(long)]
+        aggressive: bool,
+        #[clap(long, num_args = 0..=1, default_missing_value = "now")]
+        prune: Option<String>,
+        #[clap(long = "no-prune", conflicts_with = "prune")]
+        no_prune: bool,
+        #[clap(short = 'q', long)]
+        quiet: bool,
+    },
+    CheckRefFormat {
+        #[clap(long, alias = "print")]
+        normalize: bool,
+        #[clap(long = "allow-onelevel")]
+        allow_onelevel: bool,
+        #[clap(long = "refspec-pattern")]
+        refspec_pattern: bool,
+        #[clap(long)]
+        branch: bool,
+        name: String,
+    },
     CommitTree {
         #[clap(short = 'p')]
-        has_parent: bool,
+        parents: Vec<String>,
         #[clap(short = 'm')]
-        inline_message: bool,
+        message: Option<String>,
         tree_hash: String,
-        parent: Option<String>,
+    },
+    Export {
+        #[clap(long)]
+        worktree: bool,
+        commit: String,
+        directory: String,
+    },
+    ImportSnapshots {
+        #[clap(short = 'b', long)]
+        branch: Option<String>,
+        #[clap(short = 'm', long)]
         message: Option<String>,
+        #[clap(required = true)]
+        directories: Vec<String>,
+    },
+    RevList {
+        #[clap(long)]
+        count: bool,
+        #[clap(long)]
+        objects: bool,
+        #[clap(long)]
+        all: bool,
+        #[clap(long = "max-parents")]
+        max_parents: Option<usize>,
+        #[clap(long = "min-parents", default_value_t = 0)]
+        min_parents: usize,
+        #[clap(short = 'n', long = "max-count")]
+        max_count: Option<usize>,
+        #[clap(long)]
+        merges: bool,
+        #[clap(long = "no-merges")]
+        no_merges: bool,
+        #[clap(long = "first-parent")]
+        first_parent: bool,
+        #[clap(long)]
+        reverse: bool,
+        #[clap(long = "topo-order", conflicts_with_all = ["date_order", "author_date_order"])]
+        topo_order: bool,
+        #[clap(long = "date-order", conflicts_with = "author_date_order")]
+        date_order: bool,
+        #[clap(long = "author-date-order")]
+        author_date_order: bool,
+        #[clap(long, conflicts_with_all = ["count", "objects", "bisect_all"])]
+        bisect: bool,
+        #[clap(long = "bisect-all", conflicts_with_all = ["count", "objects"])]
+        bisect_all: bool,
+        revisions: Vec<String>,
+    },
+    Whatchanged {
+        #[clap(long = "name-status")]
+        name_status: bool,
+        #[clap(long = "no-abbrev")]
+        no_abbrev: bool,
+        #[clap(short = 'n', long = "max-count")]
+        max_count: Option<usize>,
+        #[clap(long)]
+        merges: bool,
+        #[clap(long = "no-merges")]
+        no_merges: bool,
+        #[clap(long = "first-parent")]
+        first_parent: bool,
+        #[clap(long)]
+        reverse: bool,
+        #[clap(long = "topo-order", conflicts_with_all = ["date_order", "author_date_order"])]
+        topo_order: bool,
+        #[clap(long = "date-order", conflicts_with = "author_date_order")]
+        date_order: bool,
+        #[clap(long = "author-date-order")]
+        author_date_order: bool,
+        revisions: Vec<String>,
+    },
+    PatchId {
+        #[clap(long)]
+        stable: bool,
+        #[clap(long, conflicts_with = "stable")]
+        unstable: bool,
+    },
+    Stripspace {
+        #[clap(short = 's', long)]
+        strip_comments: bool,
+        #[clap(short = 'c', long, conflicts_with = "strip_comments")]
+        comment_lines: bool,
+    },
+    Replace {
+        #[clap(short = 'd', long = "delete")]
+        delete: bool,
+        #[clap(short = 'l', long = "list")]
+        list: bool,
+        #[clap(short = 'f', long = "force")]
+        force: bool,
+        #[clap(short = 'g', long, conflicts_with_all = ["delete", "list"])]
+        graft: bool,
+        #[clap(long = "convert-graft-file", conflicts_with_all = ["delete", "list", "graft"])]
+        convert_graft_file: bool,
+        objects: Vec<String>,
     },
     Add {
-
+        #[clap(short = 'N', long = "intent-to-add")]
+        intent_to_add: bool,
+        #[clap(short = 'u', long = "update", conflicts_with = "intent_to_add")]
+        update: bool,
+        #[clap(short = 'f', long)]
+        force: bool,
+        pathspecs: Vec<String>,
     },
     Commit {
-
+        #[clap(short = 'p', long)]
+        patch: bool,
+        #[clap(short = 'm', long)]
+        message: Vec<String>,
+        #[clap(short = 'F', long, conflicts_with = "message")]
+        file: Option<String>,
+        #[clap(short = 'e', long)]
+        edit: bool,
+        #[clap(long = "no-edit", conflicts_with = "edit")]
+        no_edit: bool,
+        #[clap(long)]
+        reproducible: bool,
+    },
+    UpdateIndex {
+        #[clap(long = "assume-unchanged")]
+        assume_unchanged: bool,
+        #[clap(long = "no-assume-unchanged", conflicts_with = "assume_unchanged")]
+        no_assume_unchanged: bool,
+        #[clap(long = "skip-worktree")]
+        skip_worktree: bool,
+        #[clap(long = "no-skip-worktree", conflicts_with = "skip_worktree")]
+        no_skip_worktree: bool,
+        #[clap(long = "untracked-cache")]
+        untracked_cache: bool,
+        #[clap(long = "no-untracked-cache", conflicts_with = "untracked_cache")]
+        no_untracked_cache: bool,
+        #[clap(long = "split-index")]
+        split_index: bool,
+        #[clap(long = "no-split-index", conflicts_with = "split_index")]
+        no_split_index: bool,
+        paths: Vec<String>,
     },
     Status {
-
+        #[clap(long, num_args = 0..=1, default_missing_value = "")]
+        column: Option<String>,
+        #[clap(long = "no-column")]
+        no_column: bool,
     },
     Diff {
-
+        #[clap(long = "no-ext-diff")]
+        no_ext_diff: bool,
+        #[clap(long = "exit-code")]
+        exit_code: bool,
+        #[clap(long)]
+        quiet: bool,
+        #[clap(long = "name-only")]
+        name_only: bool,
+        #[clap(long = "name-status")]
+        name_status: bool,
+        #[clap(long)]
+        raw: bool,
+        #[clap(long)]
+        textconv: bool,
+        #[clap(long = "no-textconv", conflicts_with = "textconv")]
+        no_textconv: bool,
+        #[clap(short = 'C', action = clap::ArgAction::Count)]
+        find_copies: u8,
+        #[clap(long = "find-copies-harder")]
+        find_copies_harder: bool,
+        #[clap(long, num_args = 0..=1, default_missing_value = "log")]
+        submodule: Option<String>,
+        #[clap(long, alias = "staged")]
+        cached: bool,
+        revisions: Vec<String>,
+    },
+    DiffFiles {
+        #[clap(long)]
+        raw: bool,
+        #[clap(long = "name-only", conflicts_with = "raw")]
+        name_only: bool,
+        #[clap(long = "name-status", conflicts_with_all = ["raw", "name_only"])]
+        name_status: bool,
+        #[clap(long = "exit-code")]
+        exit_code: bool,
+        #[clap(long)]
+        quiet: bool,
+        paths: Vec<String>,
+    },
+    DiffIndex {
+        #[clap(long)]
+        cached: bool,
+        #[clap(long)]
+        raw: bool,
+        #[clap(long = "name-only", conflicts_with = "raw")]
+        name_only: bool,
+        #[clap(long = "name-status", conflicts_with_all = ["raw", "name_only"])]
+        name_status: bool,
+        #[clap(long = "exit-code")]
+        exit_code: bool,
+        #[clap(long)]
+        quiet: bool,
+        tree_ish: String,
+        paths: Vec<String>,
+    },
+    Difftool {
+        #[clap(short = 't', long)]
+        tool: Option<String>,
+        #[clap(short = 'x', long)]
+        extcmd: Option<String>,
+        #[clap(short = 'd', long = "dir-diff")]
+        dir_diff: bool,
+        #[clap(short = 'y', long = "no-prompt")]
+        no_prompt: bool,
+        #[clap(long, conflicts_with = "no_prompt")]
+        prompt: bool,
+        revisions: Vec<String>,
     },
     Restore {
-
+        #[clap(short = 'p', long = "patch")]
+        patch: bool,
+        #[clap(short = 'S', long)]
+        staged: bool,
+        pathspecs: Vec<String>,
+    },
+    Reset {
+        #[clap(short = 'p', long = "patch")]
+        patch: bool,
+        #[clap(long, conflicts_with = "patch")]
+        hard: bool,
+        #[clap(long = "force-protected")]
+        force_protected: bool,
+        pathspecs: Vec<String>,
     },
     Branch {
-
+        #[clap(short = 'd', long)]
+        delete: bool,
+        #[clap(short = 'D')]
+        force_delete: bool,
+        #[clap(long = "force-protected")]
+        force_protected: bool,
+        #[clap(long)]
+        contains: Option<String>,
+        #[clap(long, num_args = 0..=1, default_missing_value = "HEAD")]
+        merged: Option<String>,
+        #[clap(long = "no-merged", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_merged: Option<String>,
+        #[clap(long, num_args = 0..=1, default_missing_value = "")]
+        column: Option<String>,
+        #[clap(long = "no-column")]
+        no_column: bool,
+        names: Vec<String>,
+    },
+    Tag {
+        #[clap(short = 'l', long)]
+        list: bool,
+        #[clap(long)]
+        sort: Vec<String>,
+        #[clap(long)]
+        contains: Option<String>,
+        #[clap(long, num_args = 0..=1, default_missing_value = "HEAD")]
+        merged: Option<String>,
+        #[clap(long = "no-merged", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_merged: Option<String>,
+        #[clap(long, num_args = 0..=1, default_missing_value = "")]
+        column: Option<String>,
+        #[clap(long = "no-column")]
+        no_column: bool,
+        patterns: Vec<String>,
     },
     Checkout {
-
+        #[clap(short = 'b')]
+        new_branch: Option<String>,
+        #[clap(long = "recurse-submodules")]
+        recurse_submodules: bool,
+        branch: Option<String>,
     },
     Log {
-
+        #[clap(short = 'p', long = "patch")]
+        patch: bool,
+        #[clap(long)]
+        follow: bool,
+        #[clap(short = 'n', long = "max-count")]
+        max_count: Option<usize>,
+        revisions: Vec<String>,
+        #[clap(last = true)]
+        paths: Vec<String>,
+    },
+    Blame {
+        #[clap(long)]
+        porcelain: bool,
+        #[clap(long = "line-porcelain")]
+        line_porcelain: bool,
+        #[clap(long, conflicts_with_all = ["porcelain", "line_porcelain"])]
+        incremental: bool,
+        args: Vec<String>,
     },
     Stash {
-
+        #[command(subcommand)]
+        command: Option<StashCommand>,
     },
     Fetch {
-
+        #[clap(long)]
+        unshallow: bool,
+        #[clap(long, conflicts_with = "remote")]
+        all: bool,
+        #[clap(short = 'j', long)]
+        jobs: Option<usize>,
+        remote: Option<String>,
+    },
+    RequestPull {
+        start: String,
+        url: String,
+        end: Option<String>,
+    },
+    CherryPick {
+        #[clap(short = 'n', long = "no-commit")]
+        no_commit: bool,
+        #[clap(short = 'e', long)]
+        edit: bool,
+        #[clap(long = "no-edit", conflicts_with = "edit")]
+        no_edit: bool,
+        #[clap(long = "continue", conflicts_with_all = ["skip", "abort"])]
+        resume: bool,
+        #[clap(long, conflicts_with = "abort")]
+        skip: bool,
+        #[clap(long)]
+        abort: bool,
+        commits: Vec<String>,
+    },
+    Revert {
+        #[clap(short = 'n', long = "no-commit")]
+        no_commit: bool,
+        #[clap(short = 'e', long)]
+        edit: bool,
+        #[clap(long = "no-edit", conflicts_with = "edit")]
+        no_edit: bool,
+        #[clap(long = "continue", conflicts_with_all = ["skip", "abort"])]
+        resume: bool,
+        #[clap(long, conflicts_with = "abort")]
+        skip: bool,
+        #[clap(long)]
+        abort: bool,
+        commits: Vec<String>,
+    },
+    Merge {
+        #[clap(long = "no-ff")]
+        no_ff: bool,
+        #[clap(long = "ff-only", conflicts_with = "no_ff")]
+        ff_only: bool,
+        #[clap(short = 'm', long)]
+        message: Option<String>,
+        #[clap(short = 'F', long, conflicts_with = "message")]
+        file: Option<String>,
+        #[clap(short = 'e', long)]
+        edit: bool,
+        #[clap(long = "no-edit", conflicts_with = "edit")]
+        no_edit: bool,
+        #[clap(long, conflicts_with_all = ["no_ff", "ff_only", "message", "file", "edit", "commit"])]
+        abort: bool,
+        commit: Option<String>,
+    },
+    Am {
+        #[clap(long = "continue", conflicts_with_all = ["skip", "abort"])]
+        resume: bool,
+        #[clap(long, conflicts_with = "abort")]
+        skip: bool,
+        #[clap(long)]
+        abort: bool,
+        mbox: Vec<String>,
+    },
+    Credential {
+        #[command(subcommand)]
+        command: CredentialCommand,
+    },
+    CredentialStore {
+        #[clap(long)]
+        file: Option<String>,
+        operation: String,
+    },
+    CredentialCache {
+        #[clap(long, default_value_t = credential_cache::DEFAULT_TIMEOUT)]
+        timeout: u64,
+        #[clap(long)]
+        socket: Option<String>,
+        operation: String,
+    },
+    #[command(name = "credential-cache--daemon", hide = true)]
+    CredentialCacheDaemon {
+        socket: String,
     },
     Pull {
-
+        #[clap(short = 'r', long)]
+        rebase: bool,
+        #[clap(long = "no-rebase", conflicts_with = "rebase")]
+        no_rebase: bool,
+        #[clap(long = "ff-only", conflicts_with = "rebase")]
+        ff_only: bool,
+        #[clap(long = "recurse-submodules")]
+        recurse_submodules: bool,
+        remote: Option<String>,
+        branch: Option<String>,
     },
     Push {
-
+        #[clap(short = 'f', long)]
+        force: bool,
+        #[clap(long = "force-protected")]
+        force_protected: bool,
+        remote: Option<String>,
+        refspecs: Vec<String>,
     },
     Clone {
+        #[clap(long)]
+        bare: bool,
+        #[clap(long)]
+        mirror: bool,
+        #[clap(long = "recurse-submodules", conflicts_with_all = ["bare", "mirror"])]
+        recurse_submodules: bool,
+        repository: String,
+        directory: Option<String>,
+    },
+    Submodule {
+        #[command(subcommand)]
+        command: SubmoduleCommand,
+    },
+    Subtree {
+        #[command(subcommand)]
+        command: SubtreeCommand,
+    },
+    CommitGraph {
+        #[command(subcommand)]
+        command: CommitGraphCommand,
+    },
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommand,
+    },
 
-    }
-
-}
-
-enum Kind {
-    Blob,
-    Commit,
-    Tree,
-    Tag,
 }
 
-struct Object {
-    hash: Vec<u8>,
-    kind: Kind,
-    size: usize,
-    contents: Vec<u8>,
-}
+impl Command {
+    /// Whether the command reads or changes the worktree, so cannot run in
+    /// a bare repository.
+    fn needs_worktree(&self) -> bool {
+        matches!(
+            self,
+            Command::WriteTree { .. }
+                | Command::Add { .. }
+                | Command::Commit { .. }
+                | Command::Status { .. }
+                | Command::DiffFiles { .. }
+                | Command::DiffIndex { cached: false, .. }
+                | Command::Restore { .. }
+                | Command::Reset { .. }
+                | Command::Checkout { .. }
+                | Command::Stash { .. }
+                | Command::Pull { .. }
+                | Command::Am { .. }
+                | Command::CherryPick { .. }
+                | Command::Revert { .. }
+                | Command::Merge { .. }
+                | Command::Submodule { .. }
+                | Command::Subtree { command: SubtreeCommand::Add { .. } | SubtreeCommand::Pull { .. } }
+        )
+    }
 
-struct Commit {
-    author: String,
-    timestamp: DateTime<Utc>,
-    hash: Vec<u8>,
-    parent_hash: Option<Vec<u8>>,
-    message: String,
+    /// Whether the command changes the repository, so cannot run in a
+    /// read-only one.
+    fn modifies_repository(&self) -> bool {
+        match self {
+            Command::Init
+            | Command::HashObject { .. }
+            | Command::WriteTree { .. }
+            | Command::Repack { .. }
+            | Command::Gc { .. }
+            | Command::CommitTree { .. }
+            | Command::ImportSnapshots { .. }
+            | Command::Add { .. }
+            | Command::Commit { .. }
+            | Command::UpdateIndex { .. }
+            | Command::Restore { .. }
+            | Command::Reset { .. }
+            | Command::Checkout { .. }
+            | Command::Fetch { .. }
+            | Command::CherryPick { .. }
+            | Command::Revert { .. }
+            | Command::Merge { .. }
+            | Command::Am { .. }
+            | Command::Pull { .. }
+            | Command::Push { .. }
+            | Command::Subtree { .. }
+            | Command::Submodule { command: SubmoduleCommand::Update { .. } }
+            | Command::CommitGraph { command: CommitGraphCommand::Write } => true,
+            Command::PrunePacked { dry_run } | Command::UnpackObjects { dry_run } => !dry_run,
+            Command::Replace { list, objects, convert_graft_file, .. } => !list && (!objects.is_empty() || *convert_graft_file),
+            Command::Branch { delete, force_delete, names, .. } => *delete || *force_delete || !names.is_empty(),
+            Command::Stash { command } => !matches!(command, Some(StashCommand::List | StashCommand::Show { .. })),
+            _ => false,
+        }
+    }
 }
 
-#[derive(Hash)]
+/// How a path differs, as `status` names it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State {
     Added,
     Deleted,
     Modified,
-    Untracked,
-}
-
-impl Object {
-    fn read(hash: &String) -> anyhow::Result<Object> {
-        let file = fs::File::open(format!(".got/objects/{}/{}", &hash[..2], &hash[2..]))
-            .context("read header from .got/objects")?;
-        let z = ZlibDecoder::new(file);
-        let mut z = BufReader::new(z);
-        let mut buf = Vec::new();
-        z.read_until(b'\x00', &mut buf)
-            .context("read header from .got/objects")?;
-        let header = CStr::from_bytes_with_nul(&buf)
-            .expect("know there is exactly one nul, and it's at the end");
-        let header = header
-            .to_str()
-            .context(".got/objects file header isn't valid UTF-8")?;
-        let Some((kind, size)) = header.split_once(' ') else {
-            anyhow::bail!(".got/objects file header did not start with a known type: '{header}'");
-        };
-        let kind = match kind {
-            "blob" => Kind::Blob,
-            "commit" => Kind::Commit,
-            "tree" => Kind::Tree,
-            "tag" => Kind::Tag,
-            _ => anyhow::bail!("we do not yet know how to print a '{kind}'"),
-        };
-        let size = size
-            .parse::<usize>()
-            .context(".got/objects file header has invalid size: {size}")?;
-
-        let mut buf = Vec::new();
-        buf.resize(size, 0);
-        z.read_exact(&mut buf[..])
-            .context("read true contents of .got/objects file")?;
-        let n = z.read(&mut [0])
-            .context("validate EOF in .got/object file")?;
-        anyhow::ensure!(n == 0, ".got/object file had {n} trailing bytes");
-        let hash = hex::decode(hash)?;
-        Ok(Object {
-            hash,
-            kind,
-            size,
-            contents: buf,
-        })
-    }
+    Renamed,
+    TypeChanged,
+}
 
-    fn write(path: &String, kind: &String, buf: &mut Vec<u8>) -> anyhow::Result<Object> {
-        let kind_str = kind.as_str();
-        let kind = match kind_str {
-            "blob" => Kind::Blob,
-            "commit" => Kind::Commit,
-            "tree" => Kind::Tree,
-            "tag" => Kind::Tag,
-            _ => anyhow::bail!("we do not yet know how to print a '{kind}'"),
-        };
-        match kind {
-            Kind::Blob => {
-                let mut reader = BufReader::new(fs::File::open(path)?);
-                let mut vec: Vec<u8> = vec![];
-                let content_length = reader.read_until(b'\x00', &mut vec)?;
-                let header = format!("{} {}\0", &kind_str, content_length);
-                buf.extend(header.as_bytes());
-                buf.extend(vec);
-            },
-            _ => println!("Who knows what I'll put here")
-        }
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        let mut hasher = Sha1::new();
-        let size = encoder.write(&buf)?;
-        hasher.update(&&buf[..size]);
-    
-        let compressed = encoder.finish()?;
-        let hash: Vec<u8> = hasher.finalize().to_vec();
-
-        let hash_str = hex::encode(&hash);
-        let hash_path = format!(".got/objects/{}/{}", &hash_str[..2], &hash_str[2..]);
-        fs::create_dir_all(format!(".got/objects/{}", &hash_str[..2]))?;
-        fs::write(&hash_path, &compressed).unwrap();
-        Ok(Object {
-            hash,
-            kind,
-            size,
-            contents: compressed,
-        })
+impl From<Status> for State {
+    fn from(status: Status) -> State {
+        match status {
+            Status::Added | Status::Copied(_) => State::Added,
+            Status::Deleted => State::Deleted,
+            // Unmerged paths aren't diffed for status.
+            Status::Modified | Status::Unmerged => State::Modified,
+            Status::TypeChanged => State::TypeChanged,
+            Status::Renamed(_) => State::Renamed,
+        }
     }
 }
 
@@ -209,296 +696,4768 @@ impl fmt::Display for State {
             State::Added => write!(f, "new file"),
             State::Deleted => write!(f, "deleted"),
             State::Modified => write!(f, "modified"),
-            State::Untracked => write!(f, ""),
+            State::Renamed => write!(f, "renamed"),
+            State::TypeChanged => write!(f, "typechange"),
         }
     }
 }
 
 fn init() {
     fs::create_dir(".got").unwrap();
-    fs::create_dir(".got/objects").unwrap();
+    fs::create_dir_all(repository::objects_dir(Path::new(".got"))).unwrap();
     fs::create_dir(".got/refs").unwrap();
     fs::write(".got/HEAD", "ref: refs/heads/main\n").unwrap();
     println!("Initialized got directory");
 }
 
 fn cat_file(hash: String) -> anyhow::Result<()> {
-    let object = Object::read(&hash)?;
+    let object = Object::read(&hash.parse()?)?;
     println!("Contents:\n{}", hex::encode(object.contents));
     Ok(())
 }
 
-fn hash_object(path: &String) -> anyhow::Result<Object> {
+/// Prints a blob as it would be checked out, or with `textconv` as text
+/// for diffs. The blob is named `<rev>:<path>` (or `:<path>` for the
+/// index), or by hash with its path given separately.
+fn cat_file_converted(textconv: bool, path: Option<String>, spec: &str) -> anyhow::Result<()> {
+    let flag = if textconv { "--textconv" } else { "--filters" };
+    let git_dir = repository::git_dir();
+    let (hash, path) = match (spec.split_once(':'), path) {
+        (_, Some(path)) => (revision::resolve(git_dir, spec)?, path),
+        (Some(("", path)), None) => {
+            let index = Index::read(git_dir)?;
+            let entry = index.get(path.as_bytes()).with_context(|| format!("path '{path}' is not in the index"))?;
+            (entry.hash, path.to_string())
+        }
+        (Some((rev, path)), None) => {
+            let tree = revision::peel(&revision::resolve(git_dir, rev)?, Some(Kind::Tree))?;
+            let entry = tree::lookup(&tree, path.as_bytes())?.with_context(|| format!("path '{path}' does not exist in '{rev}'"))?;
+            (entry.hash, path.to_string())
+        }
+        (None, None) => anyhow::bail!("got cat-file {flag} {spec}: <object> must be <sha1:path>"),
+    };
+    let object = Object::read(&hash)?;
+    anyhow::ensure!(object.kind == Kind::Blob, "{spec}: bad file");
+    let converter = Converter::load(git_dir)?;
+    let contents = match textconv {
+        true => converter.textconv(path.as_bytes(), &object.contents)?.unwrap_or(object.contents),
+        false => converter.smudge(path.as_bytes(), object.contents)?,
+    };
+    std::io::stdout().write_all(&contents)?;
+    Ok(())
+}
+
+fn hash_object(path: &Path) -> anyhow::Result<Object> {
     let kind = String::from_str("blob")?;
-    let mut buf: Vec<u8> = vec![];
-    let object = Object::write(&path, &kind, &mut buf)?;
-    Ok(object)
+    Object::write(path, &kind, &[])
 }
 
-fn print_tree(buf: Vec<u8>, size: usize) -> anyhow::Result<()> {
-    let mut start = 0;
-    let mut end = 0;
-    while end < size {
-        let item = CStr::from_bytes_until_nul(&buf[start..])
-            .expect("know there is exactly one nul, and it's at the end");
-        let item = item
-            .to_str()
-            .context(".got/objects file header isn't valid UTF-8")?;
-        let Some((mode, name)) = item.split_once(' ') else {
-            anyhow::bail!(".got/objects file header did not start with a known type: '{item}'");
-        };
-        start = item.as_bytes().to_vec().len() + end + 1;
-        end = start + 20;
-        let shabuf = &buf[start..end];
-        let hash = hex::encode(shabuf);
-        let object = Object::read(&hash)?;
-        let kind = match object.kind {
-            Kind::Blob => "blob",
-            Kind::Commit => "commit",
-            Kind::Tree => "tree",
-            Kind::Tag => "tag",
-        };
-        println!("{mode} {kind} {hash}\t{name}");
-        start = end;
+fn ls_tree(treehash: String) -> anyhow::Result<()> {
+    for entry in TreeWalk::new(&treehash.parse()?)?.shallow() {
+        let entry = entry?;
+        println!("{:06o} {} {}\t{}", entry.mode.bits(), entry.kind(), entry.hash, quote_path(&entry.name));
     }
     Ok(())
 }
 
-fn ls_tree(treehash: String) -> anyhow::Result<()> {
-    let file = fs::File::open(format!(
-        ".got/objects/{}/{}",
-        &treehash[..2],
-        &treehash[2..]
-    ))
-    .context("read header from .got/objects")?;
-    let z = ZlibDecoder::new(file);
-    let mut z = BufReader::new(z);
-    let mut buf = Vec::new();
-    z.read_until(0, &mut buf)
-        .context("read header from .got/objects")?;
-    let header = CStr::from_bytes_with_nul(&buf)
-        .expect("know there is exactly one nul, and it's at the end");
-    let header = header
-        .to_str()
-        .context(".got/objects file header isn't valid UTF-8")?;
-    let Some((kind, size)) = header.split_once(' ') else {
-        anyhow::bail!(".got/objects file header did not start with a known type: '{header}'");
-    };
-    match kind {
-        "tree" => Kind::Tree,
-        _ => anyhow::bail!("we do not yet know how to print a '{kind}'"),
-    };
-    let size = size
-        .parse::<usize>()
-        .context(".got/objects file header has invalid size: {size}")?;
-    buf.clear();
-    buf.resize(size, 0);
-    z.read_exact(&mut buf[..])
-        .context("read true contents of .got/objects file")?;
-    let n = z
-        .read(&mut [0])
-        .context("validate EOF in .got/object file")?;
-    anyhow::ensure!(n == 0, ".got/object file had {n} trailing bytes");
-    print_tree(buf, size)?;
-    Ok(())
-}
-
-fn write_tree(path: &String) -> anyhow::Result<Object> {
-    let file = fs::File::open(".gotignore")
-    .context("read header from .got/objects")?;
-    let reader = BufReader::new(file);
-    let mut ignore_list: Vec<String> = vec![];
-    for line in reader.lines() {
-        ignore_list.push(line?);
-    }
-
-    let kind = "tree";
-    let mut body_buf = Vec::new();
-    for entry in fs::read_dir(path).unwrap() {
-        let entry = entry.unwrap();
+/// `write-tree`: stores the tree of directory `path`, leaving out what is
+/// ignored. A `reproducible` tree only leaves out what `.gotignore` says,
+/// so that it comes out the same wherever the same files are.
+fn write_tree(path: &Path, reproducible: bool) -> anyhow::Result<Object> {
+    let ignore = match reproducible {
+        true => Ignore::load_shared()?,
+        false => Ignore::load(&Config::load()?, repository::git_dir())?,
+    };
+    write_tree_with(path, &ignore)
+}
+
+fn write_tree_with(path: &Path, ignore: &Ignore) -> anyhow::Result<Object> {
+    store_tree(path, &directory_tree(path, ignore)?)
+}
+
+/// The tree of what isn't ignored in directory `path`, with its subtrees
+/// stored. Directories with nothing in them are left out, as git has no
+/// way to record them.
+fn directory_tree(path: &Path, ignore: &Ignore) -> anyhow::Result<Tree> {
+    let mut tree = Tree::new();
+    for entry in fs::read_dir(path).with_context(|| format!("read directory {}", path.display()))? {
+        let entry = entry.with_context(|| format!("read directory {}", path.display()))?;
         let filename = entry.file_name();
-        let filename = filename.display().to_string();
-        let entry_path = entry.path().display().to_string();
-        if ignore_list.contains(&filename) {
+        let entry_path = entry.path();
+        let meta = entry.metadata()?;
+        let relative = entry_path.strip_prefix(".").unwrap_or(&entry_path);
+        if filename == ".got" || ignore.is_ignored(path::from_os(relative.as_os_str()), meta.is_dir()) {
             continue;
         }
-        let meta = entry.metadata()?;
-        let mut mode: &str = "100644";
-        let mut hashvec = vec![];
-        if meta.is_dir() {
-            mode = "040000";
-            let tree = write_tree(&entry_path)?;
-            hashvec = tree.hash;
+        let (mode, hash) = if meta.is_dir() {
+            let subtree = directory_tree(&entry_path, ignore)?;
+            if subtree.entries().is_empty() {
+                continue;
+            }
+            (Mode::Tree, store_tree(&entry_path, &subtree)?.hash)
         } else if meta.is_symlink() {
-            mode = "120000";
-            let object = hash_object(&entry_path)?;
-            hashvec = object.hash;
-        } else if meta.is_file() {
-            let file_path = Path::new(&entry_path);
-            if file_path.is_executable() {
-                mode = "100755";
-            }
-            let object = hash_object(&entry_path)?;
-            hashvec = object.hash;
-        };
-        let item_string = format!("{mode} {filename}");
-        let mut item = item_string.as_bytes().to_vec();
-        body_buf.append(&mut item);
-        body_buf.push(b'\x00');
-        body_buf.append(&mut hashvec);
-    }
-    let size = body_buf.len();
-    let mut header = format!("{} {}", kind, size).as_bytes().to_vec();
-    let mut buf = vec![];
-    buf.append(&mut header);
-    buf.push(b'\x00');
-    buf.append(&mut body_buf);
-    let kind = String::from_str(kind)?;
-    let tree_object = Object::write(path, &kind, &mut buf)?;
+            // The blob of a symlink is the path it points to.
+            let target = index::read_worktree_file(&entry_path, &meta)?;
+            (Mode::Symlink, Object::store(Kind::Blob, &target)?)
+        } else if entry_path.is_executable() {
+            (Mode::Executable, hash_object(&entry_path)?.hash)
+        } else {
+            (Mode::File, hash_object(&entry_path)?.hash)
+        };
+        tree.insert(mode, path::from_os(&filename), hash)?;
+    }
+    Ok(tree)
+}
+
+fn store_tree(path: &Path, tree: &Tree) -> anyhow::Result<Object> {
+    let body = tree.serialize();
+    let mut buf = format!("tree {}\0", body.len()).into_bytes();
+    buf.extend(body);
+    let tree_object = Object::write(path, &String::from("tree"), &buf)?;
     Ok(tree_object)
 }
 
-fn add() -> anyhow::Result<()> {
-    todo!()
+/// Lists the commits `revisions` select, or with `bisect` only the one that
+/// best halves them, or with `bisect_all` every one by how evenly it does.
+fn rev_list(count: bool, objects: bool, all: bool, bisect: bool, bisect_all: bool, mut walk: RevWalk, revisions: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let (include, exclude) = revision::parse_range(git_dir, &revisions)?;
+    walk.include = include;
+    walk.exclude = exclude;
+    // Annotated tags reached through --all are listed as objects too.
+    let mut tags = vec![];
+    if all {
+        let mut tips = refs::list(git_dir)?;
+        tips.push(("HEAD".to_string(), String::new()));
+        for (name, _) in tips {
+            if let Some(hash) = refs::resolve(git_dir, &name)?
+                && let std::result::Result::Ok(commit) = revision::peel(&hash, Some(Kind::Commit))
+            {
+                if commit != hash {
+                    let short = name.strip_prefix("refs/tags/").unwrap_or(&name).to_string();
+                    tags.push((hash, short));
+                }
+                walk.include.push(commit);
+            }
+        }
+    }
+    anyhow::ensure!(!walk.include.is_empty(), "usage: got rev-list [options] <commit>...");
+    let commits = walk.commits(git_dir)?;
+    if count {
+        println!("{}", commits.len());
+        return Ok(());
+    }
+    if bisect {
+        if let Some(midpoint) = revwalk::bisection_midpoint(&commits, walk.first_parent) {
+            println!("{}", midpoint.hash);
+        }
+        return Ok(());
+    }
+    if bisect_all {
+        let distances = revwalk::bisection_distances(&commits, walk.first_parent);
+        let mut listed: Vec<(usize, Oid)> = distances.into_iter().zip(commits.iter().map(|commit| commit.hash)).collect();
+        listed.sort_by_key(|&(distance, hash)| (std::cmp::Reverse(distance), hash));
+        let decorations = ref_decorations(git_dir)?;
+        for (distance, hash) in listed {
+            let names = decorations.get(&hash).map(|names| format!("{}, ", names.join(", "))).unwrap_or_default();
+            println!("{hash} ({names}dist={distance})");
+        }
+        return Ok(());
+    }
+    for commit in &commits {
+        println!("{}", commit.hash);
+    }
+    if objects {
+        for (hash, name) in tags {
+            println!("{hash} {name}");
+        }
+        let mut stdout = std::io::stdout();
+        for (hash, path) in walk.objects(git_dir, &commits)? {
+            stdout.write_all(&[format!("{hash} ").as_bytes(), &path, b"\n"].concat())?;
+        }
+    }
+    Ok(())
+}
+
+/// The names of the refs pointing at each object, as git decorates them:
+/// HEAD first (as `HEAD -> <branch>` with the branch it is on), then the
+/// others in reverse order of name, shortened and with `tag: ` before tags.
+/// Annotated tags decorate the commit they point to as well.
+fn ref_decorations(git_dir: &Path) -> anyhow::Result<HashMap<Oid, Vec<String>>> {
+    let mut decorations: HashMap<Oid, Vec<String>> = HashMap::new();
+    for (name, _) in refs::list(git_dir)?.into_iter().rev() {
+        let Some(hash) = refs::resolve(git_dir, &name)? else {
+            continue;
+        };
+        let short = match name.strip_prefix("refs/tags/") {
+            Some(tag) => format!("tag: {tag}"),
+            None => name.strip_prefix("refs/heads/").or_else(|| name.strip_prefix("refs/remotes/")).unwrap_or(&name).to_string(),
+        };
+        if let std::result::Result::Ok(commit) = revision::peel(&hash, Some(Kind::Commit))
+            && commit != hash
+        {
+            decorations.entry(commit).or_default().push(short.clone());
+        }
+        decorations.entry(hash).or_default().push(short);
+    }
+    if let Some(head) = refs::resolve(git_dir, "HEAD")? {
+        let names = decorations.entry(head).or_default();
+        let branch = refs::read(git_dir, "HEAD")?.and_then(|value| value.strip_prefix("ref: refs/heads/").map(str::to_string));
+        match branch.and_then(|branch| Some((names.iter().position(|name| *name == branch)?, branch))) {
+            Some((position, branch)) => {
+                names.remove(position);
+                names.insert(0, format!("HEAD -> {branch}"));
+            }
+            None => names.insert(0, "HEAD".to_string()),
+        }
+    }
+    Ok(decorations)
+}
+
+/// Splits a raw `<name> <<email>> <seconds> <zone>` signature into the
+/// identity and a date formatted the way `log` prints it.
+fn format_signature(signature: &Signature) -> (String, String) {
+    let identity = format!("{} <{}>", signature.name, signature.email);
+    let date = signature.time.format("%a %b %-d %H:%M:%S %Y %z").to_string();
+    (identity, date)
 }
 
-fn commit() -> anyhow::Result<()> {
+fn print_commit_header(out: &mut impl Write, commit: &CommitInfo) -> anyhow::Result<()> {
+    let (author, date) = format_signature(&commit.author);
+    writeln!(out, "commit {}", commit.hash)?;
+    if commit.parents.len() > 1 {
+        let parents: Vec<String> = commit.parents.iter().map(|parent| parent.abbrev(7)).collect();
+        writeln!(out, "Merge: {}", parents.join(" "))?;
+    }
+    writeln!(out, "Author: {author}")?;
+    writeln!(out, "Date:   {date}")?;
+    writeln!(out)?;
+    for line in commit.message.trim_end().lines() {
+        writeln!(out, "    {line}")?;
+    }
     Ok(())
 }
 
-fn commit_tree(has_parent: bool, inline_message: bool, tree_hash: String, parent: Option<String>, message: Option<String>) -> anyhow::Result<Object> {
-    let kind = "commit";
-    let author: String = String::from_str("afoster")?;
-    let timestamp = Utc::now();
-    let hash = hex::decode(tree_hash)?;
-    let mut parent_hash: Option<Vec<u8>> = None;
-    let m: String;
-    if inline_message {
-        m = message.unwrap();
+/// The walk order `--topo-order`, `--date-order` or `--author-date-order`
+/// asks for.
+fn walk_order(topo: bool, date: bool, author_date: bool) -> revwalk::Order {
+    if topo {
+        revwalk::Order::Topo
+    } else if date {
+        revwalk::Order::Date
+    } else if author_date {
+        revwalk::Order::AuthorDate
     } else {
-        m = create_message();
-    }
-    let kind = String::from_str(kind)?;
-    let path = String::from_str("")?;
-    let mut body: Vec<u8> = vec![];
-    body.extend(&hash);
-    body.extend("\x00author ".as_bytes());
-    body.extend(author.as_bytes());
-    body.extend("\x00timestamp ".as_bytes());
-    body.extend(timestamp.to_string().as_bytes());
-    body.extend("\x00message ".as_bytes());
-    body.extend(m.as_bytes());
-    if has_parent {
-        body.extend("\x00parent ".as_bytes());
-        let bytes = hex::decode(parent.unwrap())?;
-        parent_hash = Some(bytes);
-        let bytes = parent_hash.as_ref().unwrap();
-        body.extend(bytes);
-    }
-    let content_length = body.len();
-    let header = format!("{} {}\0", &kind, content_length);
-    let commit = Commit {
-        author,
-        timestamp,
-        hash,
-        parent_hash,
-        message: m,
+        revwalk::Order::Visit
+    }
+}
+
+/// Applies `--merges` (only commits with two or more parents) and
+/// `--no-merges` (only commits with at most one) to `walk`.
+fn filter_merges(walk: &mut RevWalk, merges: bool, no_merges: bool) {
+    if merges {
+        walk.min_parents = walk.min_parents.max(2);
+    }
+    if no_merges {
+        walk.max_parents = Some(walk.max_parents.map_or(1, |max| max.min(1)));
+    }
+}
+
+fn whatchanged(name_status: bool, no_abbrev: bool, max_count: Option<usize>, mut walk: RevWalk, revisions: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let revisions = if revisions.is_empty() { vec!["HEAD".to_string()] } else { revisions };
+    (walk.include, walk.exclude) = revision::parse_range(git_dir, &revisions)?;
+    // Merges never have a diff to show here, so they are always left out.
+    filter_merges(&mut walk, false, true);
+    // Commits without changes don't count towards the limit, so reversing
+    // waits until the commits to show are known.
+    let reverse = std::mem::take(&mut walk.reverse);
+    let abbrev = if no_abbrev { None } else { Some(7) };
+    let mut stdout = std::io::stdout();
+    let mut shown = vec![];
+    for commit in walk.commits(git_dir)? {
+        if max_count.is_some_and(|max| shown.len() >= max) {
+            break;
+        }
+        let parent_tree = match commit.parents.first() {
+            Some(parent) => Some(revwalk::read_commit(parent)?.tree),
+            None => None,
+        };
+        let changes = tree_diff::detect_renames(tree_diff::diff_trees(parent_tree.as_ref(), Some(&commit.tree))?)?;
+        if !changes.is_empty() {
+            shown.push((commit, changes));
+        }
+    }
+    if reverse {
+        shown.reverse();
+    }
+    for (i, (commit, changes)) in shown.iter().enumerate() {
+        if i > 0 {
+            writeln!(stdout)?;
+        }
+        print_commit_header(&mut stdout, commit)?;
+        writeln!(stdout)?;
+        for change in changes {
+            if name_status {
+                writeln!(stdout, "{}", change.format_name_status())?;
+            } else {
+                writeln!(stdout, "{}", change.format_raw(abbrev))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `log`: the commits reachable from `revisions` (HEAD by default), newest
+/// first, each followed by its patch with `patch`. Renames are detected,
+/// and merges are shown without a patch. With `paths`, only commits that
+/// change them are shown and their patches only cover them; `follow` takes
+/// a single file back through its renames.
+fn log(patch: bool, follow: bool, max_count: Option<usize>, revisions: Vec<String>, paths: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let revisions = if revisions.is_empty() { vec!["HEAD".to_string()] } else { revisions };
+    let mut walk = RevWalk::default();
+    (walk.include, walk.exclude) = revision::parse_range(git_dir, &revisions)?;
+    let mut paths = paths.iter().map(|spec| Ok(normalize_pathspec(spec)?.into_bytes())).collect::<anyhow::Result<Vec<_>>>()?;
+    anyhow::ensure!(!follow || paths.len() == 1, "--follow requires exactly one pathspec");
+    let tree_of = |commit: &Oid| -> anyhow::Result<Oid> { Ok(revwalk::read_commit(commit)?.tree) };
+    let mut stdout = std::io::stdout();
+    let mut shown = 0;
+    for commit in walk.commits(git_dir)? {
+        if max_count.is_some_and(|max| shown >= max) {
+            break;
+        }
+        let changes = match &commit.parents[..] {
+            [] | [_] => {
+                let parent_tree = commit.parents.first().map(tree_of).transpose()?;
+                let changes = tree_diff::diff_trees(parent_tree.as_ref(), Some(&commit.tree))?;
+                if follow {
+                    // Any file of the parent can be the old name.
+                    let changes = tree_diff::detect_renames_to(changes, |path| matches_pathspec(path, &paths))?;
+                    if let Some(change) = changes.iter().find(|change| matches!(change.status, Status::Renamed(_))) {
+                        paths = vec![change.old_path.clone()];
+                    }
+                    changes
+                } else {
+                    tree_diff::detect_renames(changes.into_iter().filter(|change| matches_pathspec(&change.path, &paths)).collect())?
+                }
+            }
+            parents => {
+                // A merge that matches one of its parents in the paths
+                // brought nothing to them.
+                if !paths.is_empty() {
+                    let mut treesame = false;
+                    for parent in parents {
+                        let changes = tree_diff::diff_trees(Some(&tree_of(parent)?), Some(&commit.tree))?;
+                        treesame |= !changes.iter().any(|change| matches_pathspec(&change.path, &paths));
+                    }
+                    if treesame {
+                        continue;
+                    }
+                }
+                vec![]
+            }
+        };
+        if !paths.is_empty() && commit.parents.len() <= 1 && changes.is_empty() {
+            continue;
+        }
+        if shown > 0 {
+            writeln!(stdout)?;
+        }
+        shown += 1;
+        print_commit_header(&mut stdout, &commit)?;
+        if patch && !changes.is_empty() {
+            writeln!(stdout)?;
+            let mut out = vec![];
+            for change in &changes {
+                got::diff::write_patch(&mut out, change, 7, None)?;
+            }
+            stdout.write_all(&out)?;
+        }
+    }
+    Ok(())
+}
+
+/// How `blame` prints its results.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlameFormat {
+    Default,
+    Porcelain,
+    LinePorcelain,
+    Incremental,
+}
+
+fn blame(format: BlameFormat, args: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let (revision, path) = match &args[..] {
+        [path] => ("HEAD", path),
+        [revision, path] => (revision.as_str(), path),
+        _ => anyhow::bail!("usage: got blame [<revision>] [--] <file>"),
     };
-    let mut buf: Vec<u8> = vec![];
-    buf.extend(header.as_bytes());
-    buf.extend(body);
-    let commit_object = Object::write(&path, &kind, &mut buf)?;
-    Ok(commit_object)
+    let start = revision::resolve_commit(git_dir, revision)?;
+    let path = normalize_pathspec(path)?;
+    let mut commits: HashMap<Oid, Commit> = HashMap::new();
+    let mut shown = HashSet::new();
+    let mut out = std::io::stdout().lock();
+
+    if format == BlameFormat::Incremental {
+        // Each entry goes out as soon as it is found, so editors can show
+        // annotations while the rest are still being worked out.
+        return got::blame::blame_cached(git_dir, &start, path.as_bytes(), |entry| {
+            writeln!(out, "{} {} {} {}", entry.commit, entry.orig_start + 1, entry.final_start + 1, entry.len)?;
+            if shown.insert(entry.commit) {
+                write_blame_details(&mut out, blame_commit(&mut commits, &entry.commit)?, entry)?;
+            }
+            write_blame_filename(&mut out, entry)?;
+            out.flush()?;
+            Ok(())
+        });
+    }
+
+    let mut entries = vec![];
+    got::blame::blame_cached(git_dir, &start, path.as_bytes(), |entry| {
+        entries.push(entry.clone());
+        Ok(())
+    })?;
+    entries.sort_by_key(|entry| entry.final_start);
+    let entries = got::blame::coalesce(entries);
+    let tree = revwalk::read_commit(&start)?.tree;
+    let blob = got::tree::lookup(&tree, path.as_bytes())?.context("blamed file vanished")?.hash;
+    let contents = Object::read(&blob)?.contents;
+    let lines = got::diff::split_lines(&contents);
+
+    let show_name = entries.iter().any(|entry| entry.path != path.as_bytes());
+    let name_width = entries.iter().map(|entry| path::display(&entry.path).chars().count()).max().unwrap_or(0);
+    let number_width = lines.len().to_string().len();
+    let mut author_width = 0;
+    for entry in &entries {
+        author_width = author_width.max(blame_commit(&mut commits, &entry.commit)?.author.name.chars().count());
+    }
+    for entry in &entries {
+        let commit = blame_commit(&mut commits, &entry.commit)?;
+        for i in 0..entry.len {
+            let line = lines[entry.final_start + i];
+            if format == BlameFormat::Default {
+                let hash = if entry.boundary { format!("^{}", entry.commit.abbrev(7)) } else { entry.commit.abbrev(8) };
+                write!(out, "{hash}")?;
+                if show_name {
+                    write!(out, " {:name_width$}", path::display(&entry.path))?;
+                }
+                let author = &commit.author;
+                let date = author.time.format("%Y-%m-%d %H:%M:%S %z");
+                write!(out, " ({:author_width$} {date:>10} {:>number_width$}) ", author.name, entry.final_start + i + 1)?;
+            } else {
+                write!(out, "{} {} {}", entry.commit, entry.orig_start + i + 1, entry.final_start + i + 1)?;
+                if i == 0 {
+                    write!(out, " {}", entry.len)?;
+                }
+                writeln!(out)?;
+                if format == BlameFormat::LinePorcelain || shown.insert(entry.commit) {
+                    write_blame_details(&mut out, commit, entry)?;
+                    write_blame_filename(&mut out, entry)?;
+                }
+                write!(out, "\t")?;
+            }
+            out.write_all(line)?;
+            if !line.ends_with(b"\n") {
+                writeln!(out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn blame_commit<'a>(commits: &'a mut HashMap<Oid, Commit>, hash: &Oid) -> anyhow::Result<&'a Commit> {
+    if !commits.contains_key(hash) {
+        let commit = Commit::parse(&Object::read(hash)?.contents).with_context(|| format!("parse commit {hash}"))?;
+        commits.insert(*hash, commit);
+    }
+    Ok(&commits[hash])
+}
+
+/// The author, committer and summary lines `blame --porcelain` gives the
+/// first time a commit appears.
+fn write_blame_details(out: &mut impl Write, commit: &Commit, entry: &got::blame::Entry) -> anyhow::Result<()> {
+    for (role, signature) in [("author", &commit.author), ("committer", &commit.committer)] {
+        writeln!(out, "{role} {}", signature.name)?;
+        writeln!(out, "{role}-mail <{}>", signature.email)?;
+        writeln!(out, "{role}-time {}", signature.time.timestamp())?;
+        writeln!(out, "{role}-tz {}", signature.time.format("%z"))?;
+    }
+    writeln!(out, "summary {}", commit.message.lines().next().unwrap_or(""))?;
+    if entry.boundary {
+        writeln!(out, "boundary")?;
+    }
+    Ok(())
+}
+
+fn write_blame_filename(out: &mut impl Write, entry: &got::blame::Entry) -> anyhow::Result<()> {
+    if let Some((commit, path)) = &entry.previous {
+        writeln!(out, "previous {commit} {}", quote_path(path))?;
+    }
+    writeln!(out, "filename {}", quote_path(&entry.path))?;
+    Ok(())
+}
+
+/// How `diff` prints each change.
+enum DiffFormat {
+    Patch,
+    Raw,
+    NameOnly,
+    NameStatus,
+}
+
+/// The format `diff-files` and `diff-index` print in: `--raw` unless told
+/// otherwise.
+fn plumbing_diff_format(name_only: bool, name_status: bool) -> DiffFormat {
+    if name_only {
+        DiffFormat::NameOnly
+    } else if name_status {
+        DiffFormat::NameStatus
+    } else {
+        DiffFormat::Raw
+    }
+}
+
+/// What `diff` compares.
+enum DiffSides {
+    /// The index with the worktree.
+    Worktree,
+    /// A commit or tree with the worktree.
+    Revision(String),
+    /// A commit, HEAD unless given, with the index.
+    Cached(Option<String>),
+    /// Two commits or trees.
+    Commits(Vec<String>),
+}
+
+/// The changes between the two commits `revisions` name, as `a..b` or
+/// `a b`.
+/// What `diff.renames` asks for: whether to detect renames, and copies
+/// too if it is `copies`.
+fn rename_detection(config: &Config) -> anyhow::Result<(bool, bool)> {
+    match config.get("diff.renames") {
+        None => Ok((true, false)),
+        Some("copies" | "copy") => Ok((true, true)),
+        Some(value) => Ok((got::config::parse_bool(value)?, false)),
+    }
+}
+
+/// The changes between the two commits or trees `revisions` name, or the
+/// two ends of an `a..b` range. `find_copies` looks for copies among the
+/// changed files, or with `Some(true)` among all files of the old side.
+fn commit_changes(revisions: &[String], find_copies: Option<bool>) -> anyhow::Result<Vec<Change>> {
+    let git_dir = repository::git_dir();
+    let tree = |spec: &str| -> anyhow::Result<Oid> {
+        let hash = revision::resolve(git_dir, spec)?;
+        revision::peel(&hash, Some(Kind::Tree)).with_context(|| format!("'{spec}' does not name a commit or tree"))
+    };
+    let (old, new) = match revisions {
+        [old, new] if !revisions.iter().any(|rev| rev.starts_with('^') || rev.contains("..")) => (tree(old)?, tree(new)?),
+        _ => {
+            let (old, new) = match revision::parse_range(git_dir, revisions)? {
+                (include, exclude) if include.len() == 1 && exclude.len() == 1 => (exclude[0], include[0]),
+                (include, exclude) if include.len() == 2 && exclude.is_empty() => (include[0], include[1]),
+                _ => anyhow::bail!("give one commit to compare with the worktree, or two to compare with each other"),
+            };
+            (revision::peel(&old, Some(Kind::Tree))?, revision::peel(&new, Some(Kind::Tree))?)
+        }
+    };
+    let changes = tree_diff::diff_trees(Some(&old), Some(&new))?;
+    detect_renames(changes, Some(&old), find_copies)
 }
 
-fn create_message() -> String {
-    todo!()
+/// The changes from tree-ish `revision` (HEAD by default) to the index:
+/// what committing would record.
+fn cached_changes(revision: Option<&str>, find_copies: Option<bool>) -> anyhow::Result<Vec<Change>> {
+    let git_dir = repository::git_dir();
+    let old = match revision {
+        Some(spec) => Some(revision::peel(&revision::resolve(git_dir, spec)?, Some(Kind::Tree))?),
+        None => refs::resolve(git_dir, "HEAD")?.map(|head| revision::peel(&head, Some(Kind::Tree))).transpose()?,
+    };
+    detect_renames(index_changes(old.as_ref(), &Index::read(git_dir)?)?, old.as_ref(), find_copies)
 }
 
-fn status() -> anyhow::Result<()> {
-    let mut staged: HashSet<String> = HashSet::new();
-    let mut file_states: HashMap<String, State> = HashMap::new();
-    file_states.insert("testFolder".to_string(), State::Added);
-    file_states.insert("deleted.txt".to_string(), State::Deleted);
-    file_states.insert("test.txt".to_string(), State::Modified);
-    file_states.insert("untracked.txt".to_string(), State::Untracked);
-    staged.insert("testFolder".to_string());
-    let remote_name = "origin";
-    let branch_name = "main";
-    println!("On branch {}", branch_name);
-    println!("Your branch is up to date with {}/{}", remote_name, branch_name);
-    println!("Changes to be commited:");
+/// The changes from tree `old` (`None` for an empty tree) to `index`,
+/// without looking for renames. Unmerged paths are left out.
+fn index_changes(old: Option<&Oid>, index: &Index) -> anyhow::Result<Vec<Change>> {
+    let mut files = match old {
+        Some(tree) => tree_files(tree)?,
+        None => BTreeMap::new(),
+    };
+    let mut changes = vec![];
+    // Unmerged paths and paths added with -N have nothing to compare.
+    for entry in index.entries().iter().filter(|entry| entry.stage == 0 && !entry.intent_to_add) {
+        let (new_mode, new_hash) = (entry.mode.bits(), entry.hash);
+        let (status, old_mode, old_hash) = match files.remove(&entry.path) {
+            None => (Status::Added, 0, Oid::ZERO),
+            Some((mode, hash)) if mode == entry.mode && hash == entry.hash => continue,
+            Some((mode, hash)) if mode.bits() & 0o170000 == new_mode & 0o170000 => (Status::Modified, mode.bits(), hash),
+            Some((mode, hash)) => (Status::TypeChanged, mode.bits(), hash),
+        };
+        changes.push(Change { status, old_mode, new_mode, old_hash, new_hash, old_path: entry.path.clone(), path: entry.path.clone() });
+    }
+    let unmerged: HashSet<&[u8]> = index.entries().iter().filter(|entry| entry.stage != 0).map(|entry| &entry.path[..]).collect();
+    for (path, (mode, hash)) in files {
+        if !unmerged.contains(&path[..]) {
+            changes.push(Change { status: Status::Deleted, old_mode: mode.bits(), new_mode: 0, old_hash: hash, new_hash: Oid::ZERO, old_path: path.clone(), path });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
 
-    println!("\t(use got \"restore --staged <file>...\" to unstage)");
-    for filename in staged.iter() {
-        println!("\t\t{}:\t{}", file_states[filename], filename);
+/// Pairs up `changes` from tree `old` as renames, and copies with
+/// `find_copies`, as `diff.renames` asks.
+fn detect_renames(mut changes: Vec<Change>, old: Option<&Oid>, find_copies: Option<bool>) -> anyhow::Result<Vec<Change>> {
+    let (renames, copies) = rename_detection(&Config::load()?)?;
+    let find_copies = find_copies.or(copies.then_some(false));
+    if renames || find_copies.is_some() {
+        changes = tree_diff::detect_renames(changes)?;
+    }
+    if let Some(harder) = find_copies {
+        changes = tree_diff::detect_copies(changes, old, harder)?;
     }
+    Ok(changes)
+}
 
-    println!("Changes not staged for commit:");
-    println!("\t(use \"got add/rm <file>...\" to update what will be committed)");
-    println!("\t(use \"got restore <file>...\" to discard changes in working directory)");
-    
-    for (filename, state) in file_states.iter() {
-        let tracked = match state {
-            State::Untracked => false,
-            _ => true
+/// The changes from the index to the worktree in tracked files. Their
+/// blobs are only stored with `store`, as the new sides of patches can be
+/// read from the worktree itself.
+fn worktree_changes(store: bool) -> anyhow::Result<Vec<Change>> {
+    let git_dir = repository::git_dir();
+    let mut index = Index::read(git_dir)?;
+    let options = ScanOptions {
+        store,
+        ..ScanOptions::from_config(&Config::load()?)?
+    };
+    let scan = scan::scan(&mut index, &options)?;
+    let mut changes = vec![];
+    for entry in scan.modified {
+        let staged = index.get(&entry.path).expect("modified paths are tracked");
+        // What was added with -N has nothing staged, so all of it is new.
+        let (old_mode, old_hash) = match staged.intent_to_add {
+            true => (0, Oid::ZERO),
+            false => (staged.mode.bits(), staged.hash),
         };
-        if !staged.contains(filename) && tracked {
-            println!("\t\t{}:\t{}", state, filename);
+        let new_mode = entry.mode.bits();
+        let status = match old_mode {
+            0 => Status::Added,
+            _ if old_mode & 0o170000 == new_mode & 0o170000 => Status::Modified,
+            _ => Status::TypeChanged,
+        };
+        changes.push(Change { status, old_mode, new_mode, old_hash, new_hash: entry.hash, old_path: entry.path.clone(), path: entry.path });
+    }
+    for path in scan.deleted {
+        let staged = index.get(&path).expect("deleted paths are tracked");
+        let (old_mode, old_hash) = (staged.mode.bits(), staged.hash);
+        changes.push(Change { status: Status::Deleted, old_mode, new_mode: 0, old_hash, new_hash: Oid::ZERO, old_path: path.clone(), path });
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// `diff-files`: the changes from the index to the worktree. Worktree sides
+/// have the zero hash, as their blobs aren't stored. An unmerged path gets a
+/// `U` line and then how the worktree differs from our side of the conflict.
+fn diff_files_changes() -> anyhow::Result<Vec<Change>> {
+    let index = Index::read(repository::git_dir())?;
+    let mut changes = worktree_changes(false)?;
+    for change in &mut changes {
+        change.new_hash = Oid::ZERO;
+    }
+    let mut unmerged: BTreeMap<&[u8], Vec<&index::Entry>> = BTreeMap::new();
+    for entry in index.entries().iter().filter(|entry| entry.stage != 0) {
+        unmerged.entry(&entry.path[..]).or_default().push(entry);
+    }
+    for (path, entries) in unmerged {
+        let worktree = worktree_blob(path)?;
+        let new_mode = worktree.map_or(0, |(mode, _)| mode);
+        changes.push(unmerged_change(path, 0, Oid::ZERO, new_mode));
+        if let Some(ours) = entries.into_iter().find(|entry| entry.stage == 2) {
+            let (old_mode, old_hash) = (ours.mode.bits(), ours.hash);
+            let status = match worktree {
+                None => Status::Deleted,
+                Some((mode, hash)) if mode == old_mode && hash == old_hash => continue,
+                Some((mode, _)) if mode & 0o170000 == old_mode & 0o170000 => Status::Modified,
+                Some(_) => Status::TypeChanged,
+            };
+            changes.push(Change { status, old_mode, new_mode, old_hash, new_hash: Oid::ZERO, old_path: path.to_vec(), path: path.to_vec() });
         }
     }
+    // Each path's unmerged line comes before the change that follows it.
+    changes.sort_by(|a, b| a.path.cmp(&b.path).then((b.status == Status::Unmerged).cmp(&(a.status == Status::Unmerged))));
+    Ok(changes)
+}
 
-    println!("Untracked files:");
-    println!("\t(use \"got add <file>...\" to include in what will be committed)");
-    
-    for (filename, state) in file_states.iter() {
-        let untracked = match state {
-            State::Untracked => true,
-            _ => false
+/// `diff-index`: the changes from `tree` to the index with `cached`, or else
+/// to the worktree. Worktree files that differ from the index have the zero
+/// hash, as their blobs aren't stored; otherwise the index's blob stands for
+/// them. With `cached`, an unmerged path gets a `U` line.
+fn diff_index_changes(tree: &Oid, cached: bool) -> anyhow::Result<Vec<Change>> {
+    let index = Index::read(repository::git_dir())?;
+    let unmerged: BTreeSet<&[u8]> = index.entries().iter().filter(|entry| entry.stage != 0).map(|entry| &entry.path[..]).collect();
+    let mut files = tree_files(tree)?;
+    if cached {
+        let mut changes = index_changes(Some(tree), &index)?;
+        for path in unmerged {
+            let (old_mode, old_hash) = files.get(path).map_or((0, Oid::ZERO), |(mode, hash)| (mode.bits(), *hash));
+            changes.push(unmerged_change(path, old_mode, old_hash, 0));
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        return Ok(changes);
+    }
+    // What each tracked path holds in the worktree, and whether that
+    // differs from what the index holds.
+    let mut sides: BTreeMap<Vec<u8>, Option<(u32, Oid, bool)>> = BTreeMap::new();
+    for entry in index.entries().iter().filter(|entry| entry.stage == 0) {
+        sides.insert(entry.path.clone(), Some((entry.mode.bits(), entry.hash, false)));
+    }
+    for change in worktree_changes(false)? {
+        let side = (change.status != Status::Deleted).then_some((change.new_mode, change.new_hash, true));
+        sides.insert(change.path, side);
+    }
+    for path in unmerged {
+        sides.insert(path.to_vec(), worktree_blob(path)?.map(|(mode, hash)| (mode, hash, true)));
+    }
+    let mut changes = vec![];
+    for (path, side) in sides {
+        let old = files.remove(&path).map(|(mode, hash)| (mode.bits(), hash));
+        let (status, (old_mode, old_hash), (new_mode, new_hash)) = match (old, side) {
+            (None, None) => continue,
+            (Some(old), None) => (Status::Deleted, old, (0, Oid::ZERO)),
+            (Some(old), Some((mode, hash, _))) if old == (mode, hash) => continue,
+            (None, Some((mode, hash, dirty))) => (Status::Added, (0, Oid::ZERO), (mode, if dirty { Oid::ZERO } else { hash })),
+            (Some(old), Some((mode, hash, dirty))) => {
+                let status = match old.0 & 0o170000 == mode & 0o170000 {
+                    true => Status::Modified,
+                    false => Status::TypeChanged,
+                };
+                (status, old, (mode, if dirty { Oid::ZERO } else { hash }))
+            }
         };
-        if !staged.contains(filename) && untracked {
-            println!("\t\t{}", filename);
+        changes.push(Change { status, old_mode, new_mode, old_hash, new_hash, old_path: path.clone(), path });
+    }
+    for (path, (mode, hash)) in files {
+        changes.push(Change { status: Status::Deleted, old_mode: mode.bits(), new_mode: 0, old_hash: hash, new_hash: Oid::ZERO, old_path: path.clone(), path });
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+/// The changes from the commit or tree `revision` to the worktree in
+/// tracked files. Worktree files that differ from the index have the zero
+/// hash unless `hash`, and their blobs are only stored with `store`.
+fn revision_worktree_changes(revision: &str, hash: bool, store: bool) -> anyhow::Result<Vec<Change>> {
+    let tree = revision::peel(&revision::resolve(repository::git_dir(), revision)?, Some(Kind::Tree))
+        .with_context(|| format!("'{revision}' does not name a commit or tree"))?;
+    let mut changes = diff_index_changes(&tree, false)?;
+    if !hash {
+        return Ok(changes);
+    }
+    // Files that differ from the index have no hash yet.
+    for change in &mut changes {
+        if !change.new_hash.is_zero() || change.new_mode == 0 || change.new_mode == tree::MODE_SUBMODULE {
+            continue;
         }
+        let path = path::to_path(&change.path);
+        let meta = fs::symlink_metadata(path).with_context(|| format!("stat {}", path::display(&change.path)))?;
+        let contents = index::read_worktree_file(path, &meta)?;
+        change.new_hash = match store {
+            true => Object::store(Kind::Blob, &contents)?,
+            false => Object::hash_of(Kind::Blob, &contents),
+        };
     }
+    // A file changed since it was staged may be back to what `revision` has.
+    changes.retain(|change| (change.old_mode, change.old_hash) != (change.new_mode, change.new_hash));
+    Ok(changes)
+}
 
-    Ok(())
+/// The `U` line for a path that is unmerged in the index.
+fn unmerged_change(path: &[u8], old_mode: u32, old_hash: Oid, new_mode: u32) -> Change {
+    Change { status: Status::Unmerged, old_mode, new_mode, old_hash, new_hash: Oid::ZERO, old_path: path.to_vec(), path: path.to_vec() }
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+/// The mode and blob hash of the worktree file at `path`, if there is one
+/// that could be staged.
+fn worktree_blob(path: &[u8]) -> anyhow::Result<Option<(u32, Oid)>> {
+    let std::result::Result::Ok(meta) = fs::symlink_metadata(path::to_path(path)) else {
+        return Ok(None);
+    };
+    let Some(mode) = index::worktree_mode(&meta) else {
+        return Ok(None);
+    };
+    let contents = index::read_worktree_file(path::to_path(path), &meta)?;
+    Ok(Some((mode.bits(), Object::hash_of(Kind::Blob, &contents))))
+}
 
-    match args.command {
-        Command::Init => init(),
-        Command::CatFile { pretty_print, hash } => {
-            anyhow::ensure!(
-                pretty_print,
-                "mode must be given without -p, and we don't support mode"
-            );
-            cat_file(hash)?;
+/// Prints `changes` to the paths matching `pathspecs` as the diff plumbing
+/// does, with whole hashes, unless `quiet`. Returns whether there were any.
+fn print_plumbing_diff(changes: Vec<Change>, format: DiffFormat, quiet: bool, pathspecs: &[String]) -> anyhow::Result<bool> {
+    let specs = pathspecs.iter().map(|spec| normalize_pathspec(spec)).collect::<anyhow::Result<Vec<_>>>()?;
+    let changes: Vec<Change> = changes.into_iter().filter(|change| matches_pathspec(&change.path, &specs)).collect();
+    if !quiet {
+        let mut out = String::new();
+        for change in &changes {
+            match format {
+                DiffFormat::NameOnly => out.push_str(&format!("{}\n", quote_path(&change.path))),
+                DiffFormat::NameStatus => out.push_str(&format!("{}\n", change.format_name_status())),
+                DiffFormat::Raw | DiffFormat::Patch => out.push_str(&format!("{}\n", change.format_raw(None))),
+            }
         }
-        Command::HashObject { write, path } => {
-            anyhow::ensure!(write, "Only write to file is supported for now");
-            let object = hash_object(&path)?;
-            println!(
-                "{} with contents:\n{}",
-                hex::encode(&object.hash),
-                hex::encode(&object.contents)
-            );
+        print!("{out}");
+    }
+    Ok(!changes.is_empty())
+}
+
+/// Prints the differences between the two `sides`, and returns whether
+/// there were any. Patches go through the `diff.external` command if one is
+/// set, unless `no_ext_diff`. A submodule's change is shown as a patch of its
+/// commit line, or as the log of commits between the two with `submodule`
+/// (or `diff.submodule`) set to `log`.
+fn diff(
+    format: DiffFormat,
+    quiet: bool,
+    no_ext_diff: bool,
+    textconv: bool,
+    find_copies: Option<bool>,
+    submodule: Option<String>,
+    sides: DiffSides,
+) -> anyhow::Result<bool> {
+    let config = Config::load()?;
+    let external = config.get("diff.external").filter(|_| !no_ext_diff);
+    let worktree = matches!(sides, DiffSides::Worktree | DiffSides::Revision(_));
+    // An external diff is given files made from the blobs.
+    let store = external.is_some() && !quiet && matches!(format, DiffFormat::Patch);
+    let changes = match sides {
+        DiffSides::Worktree => worktree_changes(store)?,
+        // Only patches need the hashes of what is in the worktree; raw
+        // output leaves them zero, as `diff-index` does.
+        DiffSides::Revision(revision) => revision_worktree_changes(&revision, matches!(format, DiffFormat::Patch), store)?,
+        DiffSides::Cached(revision) => cached_changes(revision.as_deref(), find_copies)?,
+        DiffSides::Commits(revisions) => commit_changes(&revisions, find_copies)?,
+    };
+    if quiet {
+        return Ok(!changes.is_empty());
+    }
+    let submodule_log = match submodule.as_deref().or(config.get("diff.submodule")) {
+        None | Some("short") => false,
+        Some("log") => true,
+        Some(format) => anyhow::bail!("failed to parse --submodule option parameter: '{format}'"),
+    };
+    let converter = match textconv {
+        true => Some(Converter::new(config.clone(), Attributes::load(repository::git_dir())?)),
+        false => None,
+    };
+    let mut stdout = std::io::stdout();
+    let mut out = vec![];
+    for change in &changes {
+        match format {
+            DiffFormat::Patch => match external {
+                Some(command) => {
+                    // Keep the output in order with what the command prints.
+                    stdout.write_all(&out)?;
+                    stdout.flush()?;
+                    out.clear();
+                    got::difftool::external_diff(command, change)?;
+                }
+                None if submodule_log && (change.old_mode == tree::MODE_SUBMODULE || change.new_mode == tree::MODE_SUBMODULE) => {
+                    write_submodule_log(&mut out, change)?
+                }
+                None if worktree => got::diff::write_worktree_patch(&mut out, change, 7, converter.as_ref())?,
+                None => got::diff::write_patch(&mut out, change, 7, converter.as_ref())?,
+            },
+            DiffFormat::Raw => out.extend(format!("{}\n", change.format_raw(Some(7))).as_bytes()),
+            DiffFormat::NameOnly => out.extend(format!("{}\n", quote_path(&change.path)).as_bytes()),
+            DiffFormat::NameStatus => out.extend(format!("{}\n", change.format_name_status()).as_bytes()),
         }
-        Command::LsTree { treehash } => ls_tree(treehash)?,
-        Command::WriteTree => {
-            let path = String::from_str(".")?;
-            let tree = write_tree(&path)?;
-            println!("{}", hex::encode(&tree.hash))
-        },
-        Command::Add {  } => add()?,
-        Command::Commit { } => commit()?,
-        Command::CommitTree { has_parent, inline_message, tree_hash, parent, message } => {
-            let commit = commit_tree(has_parent, inline_message, tree_hash, parent, message)?;
-            println!("{}", hex::encode(&commit.hash));
-        },
-        Command::Status { } => status()?,
-        _ => println!("There is no matching command for that input"),
+    }
+    stdout.write_all(&out)?;
+    Ok(!changes.is_empty())
+}
+
+/// Writes `--submodule=log`'s summary of a submodule's change: a
+/// `Submodule <path> <old>..<new>:` line (`...` when the new commit doesn't
+/// build on the old one) and a `  < subject` or `  > subject` line for
+/// each commit only the old or only the new one has, read from the
+/// submodule's own repository. A submodule that was added or deleted, or
+/// whose commits aren't there, gets a note instead of the commits.
+fn write_submodule_log(out: &mut Vec<u8>, change: &Change) -> anyhow::Result<()> {
+    let (old, new) = (change.old_hash, change.new_hash);
+    let mut message = match (old.is_zero(), new.is_zero()) {
+        (true, _) => Some("(new submodule)"),
+        (_, true) => Some("(submodule deleted)"),
+        _ => None,
+    };
+    let (mut forward, mut backward, mut lines) = (false, false, vec![]);
+    if message.is_none() {
+        let found = submodule::is_populated(&change.path)
+            && in_directory(path::to_path(&change.path), || {
+                let git_dir = repository::git_dir();
+                if !Object::exists(&old) || !Object::exists(&new) {
+                    return Ok(false);
+                }
+                let base = revwalk::merge_base(git_dir, &old, &new)?;
+                (forward, backward) = (base == Some(old), base == Some(new));
+                let only_old: HashSet<Oid> = RevWalk { include: vec![old], exclude: vec![new], ..RevWalk::default() }
+                    .commits(git_dir)?
+                    .into_iter()
+                    .map(|commit| commit.hash)
+                    .collect();
+                let walk = RevWalk { include: vec![old, new], exclude: base.into_iter().collect(), first_parent: true, ..RevWalk::default() };
+                for commit in walk.commits(git_dir)? {
+                    let side = if only_old.contains(&commit.hash) { '<' } else { '>' };
+                    lines.push(format!("  {side} {}\n", commit.message.lines().next().unwrap_or_default()));
+                }
+                Ok(true)
+            })?;
+        if !found {
+            message = Some("(commits not present)");
+        }
+    }
+    let dots = if forward || backward { ".." } else { "..." };
+    out.extend(b"Submodule ");
+    out.extend(&change.path);
+    out.extend(format!(" {}{dots}{}", old.abbrev(7), new.abbrev(7)).as_bytes());
+    match message {
+        Some(message) => out.extend(format!(" {message}\n").as_bytes()),
+        None => out.extend(format!("{}:\n", if backward { " (rewind)" } else { "" }).as_bytes()),
+    }
+    for line in lines {
+        out.extend(line.as_bytes());
+    }
+    Ok(())
+}
+
+/// Shows each change between two commits in a diff tool, or with
+/// `dir_diff` the whole of both sides at once.
+fn difftool(tool: Option<String>, extcmd: Option<String>, dir_diff: bool, prompt: Option<bool>, revisions: Vec<String>) -> anyhow::Result<()> {
+    let changes = commit_changes(&revisions, None)?;
+    let config = Config::load()?;
+    let (name, command) = match (extcmd, tool.or_else(|| config.get("diff.tool").map(str::to_string))) {
+        (Some(extcmd), _) => (extcmd.clone(), format!("{extcmd} \"$LOCAL\" \"$REMOTE\"")),
+        (None, Some(tool)) => (tool.clone(), got::difftool::tool_command(&config, &tool)),
+        (None, None) => anyhow::bail!("no diff tool is configured; set diff.tool or use --tool or --extcmd"),
+    };
+    let prompt = match prompt {
+        Some(prompt) => prompt,
+        None => config.get_bool("difftool.prompt")?.unwrap_or(true),
+    };
+
+    if dir_diff {
+        let temp = got::temp::TempDir::new("difftool")?;
+        let (left, right) = (temp.path().join("left"), temp.path().join("right"));
+        fs::create_dir_all(&left)?;
+        fs::create_dir_all(&right)?;
+        for change in &changes {
+            if !change.old_hash.is_zero() {
+                got::difftool::materialize(&left, &change.old_path, change.old_mode, &change.old_hash)?;
+            }
+            if !change.new_hash.is_zero() {
+                got::difftool::materialize(&right, &change.path, change.new_mode, &change.new_hash)?;
+            }
+        }
+        got::difftool::launch(&command, &left, &right, Path::new(""))?;
+        return Ok(());
+    }
+
+    let mut stdin = std::io::stdin().lock();
+    for (i, change) in changes.iter().enumerate() {
+        if prompt {
+            print!("\nViewing ({}/{}): '{}'\nLaunch '{name}' [Y/n]? ", i + 1, changes.len(), path::display(&change.path));
+            std::io::stdout().flush()?;
+            let mut answer = String::new();
+            stdin.read_line(&mut answer)?;
+            if answer.trim().eq_ignore_ascii_case("n") {
+                continue;
+            }
+        }
+        let temp = got::temp::TempDir::new("difftool")?;
+        let side = |name: &str, file: &[u8], mode: u32, hash: &Oid| -> anyhow::Result<std::path::PathBuf> {
+            if hash.is_zero() {
+                return Ok("/dev/null".into());
+            }
+            // Keep the file name, so tools can tell the file type.
+            got::difftool::materialize(&temp.path().join(name), path::file_name(file), mode, hash)
+        };
+        let local = side("left", &change.old_path, change.old_mode, &change.old_hash)?;
+        let remote = side("right", &change.path, change.new_mode, &change.new_hash)?;
+        got::difftool::launch(&command, &local, &remote, path::to_path(&change.path))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Subcommand)]
+enum CredentialCommand {
+    Fill,
+    Approve,
+    Reject,
+}
+
+/// Runs the credential protocol on the credential given on standard input;
+/// `fill` prints the completed credential.
+fn credential(command: CredentialCommand) -> anyhow::Result<()> {
+    let config = Config::load()?;
+    let mut credential = Credential::read(&mut std::io::stdin().lock())?;
+    match command {
+        CredentialCommand::Fill => {
+            credential::fill(&config, &mut credential)?;
+            credential.write(&mut std::io::stdout())?;
+        },
+        CredentialCommand::Approve => credential::approve(&config, &credential)?,
+        CredentialCommand::Reject => credential::reject(&config, &credential)?,
+    }
+    Ok(())
+}
+
+#[derive(Debug, Subcommand)]
+enum StashCommand {
+    List,
+    Show {
+        #[clap(short = 'p', long = "patch")]
+        patch: bool,
+        stash: Option<String>,
+    },
+    Drop {
+        stash: Option<String>,
+    },
+    Apply {
+        stash: Option<String>,
+    },
+    Pop {
+        stash: Option<String>,
+    },
+    Push {
+        #[clap(short = 'm', long)]
+        message: Option<String>,
+    },
+}
+
+fn stash(command: Option<StashCommand>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let selector = |spec: Option<String>| spec.map_or(Ok(0), |spec| stash::parse_selector(&spec));
+    match command.unwrap_or(StashCommand::Push { message: None }) {
+        StashCommand::List => {
+            for (n, entry) in stash::list(git_dir)?.iter().enumerate() {
+                println!("stash@{{{n}}}: {}", entry.message);
+            }
+        },
+        StashCommand::Show { patch, stash: spec } => {
+            let entry = stash::get(git_dir, selector(spec)?)?;
+            let commit = revwalk::read_commit(&entry.new)?;
+            let base = commit.parents.first().context("stash commit has no parent")?;
+            let mut changes = tree_diff::diff_trees(Some(&revwalk::read_commit(base)?.tree), Some(&commit.tree))?;
+            if rename_detection(&Config::load()?)?.0 {
+                changes = tree_diff::detect_renames(changes)?;
+            }
+            let mut out = vec![];
+            if patch {
+                for change in &changes {
+                    got::diff::write_patch(&mut out, change, 7, None)?;
+                }
+            } else {
+                got::diff::write_stat(&mut out, &changes, column::terminal_width())?;
+            }
+            std::io::stdout().write_all(&out)?;
+        },
+        StashCommand::Drop { stash: spec } => {
+            let n = selector(spec)?;
+            let dropped = stash::drop(git_dir, n)?;
+            println!("Dropped stash@{{{n}}} ({})", dropped.new);
+        },
+        StashCommand::Apply { stash: spec } => {
+            if !stash_apply(selector(spec)?)? {
+                exit(EXIT_FAILED);
+            }
+        },
+        StashCommand::Pop { stash: spec } => {
+            let n = selector(spec)?;
+            if !stash_apply(n)? {
+                println!("The stash entry is kept in case you need it again.");
+                exit(EXIT_FAILED);
+            }
+            let dropped = stash::drop(git_dir, n)?;
+            println!("Dropped stash@{{{n}}} ({})", dropped.new);
+        },
+        StashCommand::Push { message } => stash_push(message.as_deref())?,
+    }
+    Ok(())
+}
+
+/// Applies stash `n`, merging the changes it holds with what has changed
+/// since it was made. The result is left unstaged, as git leaves it, except
+/// for new files. Conflicts are recorded in the index as stages 1 to 3 and
+/// written to the worktree with markers; returns whether there were none.
+fn stash_apply(n: usize) -> anyhow::Result<bool> {
+    let git_dir = repository::git_dir();
+    let entry = stash::get(git_dir, n)?;
+    let mut index = Index::read(git_dir)?;
+    anyhow::ensure!(
+        index.entries().iter().all(|entry| entry.stage == 0),
+        "you need to resolve your current index first"
+    );
+    let stashed = revwalk::read_commit(&entry.new)?;
+    let base_commit = stashed.parents.first().context("stash commit has no parent")?;
+    let base = tree_files(&revwalk::read_commit(base_commit)?.tree)?;
+    let theirs = tree_files(&stashed.tree)?;
+    let ours: BTreeMap<Vec<u8>, index::Entry> = index.entries().iter().map(|entry| (entry.path.clone(), entry.clone())).collect();
+    let untracked = match stashed.parents.get(2) {
+        Some(commit) => tree_files(&revwalk::read_commit(commit)?.tree)?,
+        None => BTreeMap::new(),
+    };
+
+    for path in untracked.keys() {
+        anyhow::ensure!(
+            fs::symlink_metadata(path::to_path(path)).is_err(),
+            "{} already exists, no checkout\ncould not restore untracked files from stash",
+            path::display(path)
+        );
+    }
+    let resolutions = merge_changes(&index, &base, &theirs, "Updated upstream", "Stashed changes")?;
+    let conflicts = write_resolutions(&mut index, resolutions)?;
+    for (path, (mode, hash)) in &untracked {
+        index::write_worktree_file(path, *mode, &Object::read(hash)?.contents)?;
+    }
+    if conflicts == 0 {
+        // Only new files stay staged; everything else goes back to what
+        // was staged before, leaving the stashed changes in the worktree.
+        for path in base.keys().chain(theirs.keys()) {
+            match ours.get(path) {
+                Some(entry) => index.insert(index::Entry {
+                    stat: index::Stat::default(),
+                    ..entry.clone()
+                }),
+                None if !theirs.contains_key(path) => {
+                    index.remove(path);
+                }
+                None => {}
+            }
+        }
+    }
+    index.write(git_dir)?;
+    Ok(conflicts == 0)
+}
+
+/// Saves what is staged and the changes to tracked files in the worktree
+/// as a new `stash@{0}`, then puts the index and worktree back to HEAD.
+/// The stash commit's tree is the worktree; its parents are HEAD and a
+/// commit of the index, as git makes them.
+fn stash_push(message: Option<&str>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let config = Config::load()?;
+    let head = revision::resolve_commit(git_dir, "HEAD").context("you do not have the initial commit yet")?;
+    let head_commit = Commit::parse(&Object::read(&head)?.contents)?;
+    let head_files = tree_files(&head_commit.tree)?;
+    let mut index = Index::read(git_dir)?;
+    anyhow::ensure!(
+        index.entries().iter().all(|entry| entry.stage == 0),
+        "you need to resolve your current index first"
+    );
+    let options = ScanOptions {
+        store: true,
+        ..ScanOptions::from_config(&config)?
+    };
+    let scan = scan::scan(&mut index, &options)?;
+    let index_tree = index.write_tree()?;
+    let mut worktree = index.clone();
+    for entry in &scan.modified {
+        worktree.insert(entry.clone());
+    }
+    for path in &scan.deleted {
+        worktree.remove(path);
+    }
+    let worktree_tree = worktree.write_tree()?;
+    if index_tree == head_commit.tree && worktree_tree == head_commit.tree {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let head_ref = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    let branch = head_ref.strip_prefix("ref: refs/heads/").unwrap_or("(no branch)");
+    let subject = head_commit.message.lines().next().unwrap_or_default();
+    let description = match message {
+        Some(message) => format!("On {branch}: {message}"),
+        None => format!("WIP on {branch}: {} {subject}", head.abbrev(7)),
+    };
+    let author = Signature::current(&config, "author")?;
+    let committer = Signature::current(&config, "committer")?;
+    let commit = |tree: Oid, parents: Vec<Oid>, message: String| {
+        let commit = Commit {
+            tree,
+            parents,
+            author: author.clone(),
+            committer: committer.clone(),
+            extra_headers: vec![],
+            gpgsig: None,
+            message,
+        };
+        Object::store(Kind::Commit, &commit.serialize())
+    };
+    let index_commit = commit(index_tree, vec![head], format!("index on {branch}: {} {subject}\n", head.abbrev(7)))?;
+    let stash_commit = commit(worktree_tree, vec![head, index_commit], format!("{description}\n"))?;
+    stash::store(git_dir, &stash_commit, committer, &description)?;
+
+    // Everything stashed goes back to HEAD.
+    let changed: HashSet<&[u8]> = scan.modified.iter().map(|entry| &entry.path[..]).chain(scan.deleted.iter().map(Vec::as_slice)).collect();
+    let mut paths: BTreeSet<Vec<u8>> = head_files.keys().cloned().collect();
+    paths.extend(index.entries().iter().map(|entry| entry.path.clone()));
+    for path in paths {
+        let staged = index.get(&path).filter(|entry| !entry.intent_to_add).map(|entry| (entry.mode, entry.hash));
+        if staged.as_ref() == head_files.get(&path) && !changed.contains(&path[..]) {
+            continue;
+        }
+        match head_files.get(&path) {
+            None => {
+                worktree::remove(&path)?;
+                index.remove(&path);
+            }
+            Some(&(Mode::Submodule, hash)) => index.insert(index::Entry::new(&path, Mode::Submodule, hash, index::Stat::default())),
+            Some(&(mode, hash)) => {
+                index::write_worktree_file(&path, mode, &Object::read(&hash)?.contents)?;
+                let meta = fs::symlink_metadata(path::to_path(&path)).with_context(|| format!("stat {}", path::display(&path)))?;
+                index.insert(index::Entry::new(&path, mode, hash, index::Stat::from_metadata(&meta)));
+            }
+        }
+    }
+    index.write(git_dir)?;
+    println!("Saved working directory and index state {description}");
+    Ok(())
+}
+
+/// Merges the changes from `base` to `theirs` into what `index` has
+/// staged, returning how each path they touch comes out where that isn't
+/// what is staged. Fails before anything is written if that would lose
+/// changes in the worktree.
+fn merge_changes(
+    index: &Index,
+    base: &BTreeMap<Vec<u8>, (Mode, Oid)>,
+    theirs: &BTreeMap<Vec<u8>, (Mode, Oid)>,
+    ours_label: &str,
+    theirs_label: &str,
+) -> anyhow::Result<Vec<(Vec<u8>, Resolution)>> {
+    let ours: BTreeMap<&[u8], (Mode, Oid)> = index.entries().iter().map(|entry| (&entry.path[..], (entry.mode, entry.hash))).collect();
+    let changed: BTreeSet<&Vec<u8>> = base.keys().chain(theirs.keys()).filter(|path| base.get(*path) != theirs.get(*path)).collect();
+    let mut resolutions = vec![];
+    for path in changed {
+        let ours_side = ours.get(&path[..]).copied();
+        let resolution = got::merge::merge_path(
+            path,
+            base.get(path).copied(),
+            ours_side,
+            theirs.get(path).copied(),
+            ours_label,
+            theirs_label,
+        )?;
+        if resolution != Resolution::Clean(ours_side) {
+            resolutions.push((path.clone(), resolution));
+        }
+    }
+    let blocked: Vec<_> = resolutions
+        .iter()
+        .filter(|(path, resolution)| worktree::is_blocked(index, path, *resolution == Resolution::Clean(None)))
+        .map(|(path, _)| path::display(path))
+        .collect();
+    anyhow::ensure!(
+        blocked.is_empty(),
+        "your local changes to the following files would be overwritten by merge:\n\t{}\n\
+         Please commit your changes or stash them before you merge.",
+        blocked.join("\n\t")
+    );
+    Ok(resolutions)
+}
+
+/// Writes `resolutions` to `index` and the worktree, recording each
+/// conflict as stages 1 to 3 and leaving markers in the file. Returns how
+/// many paths conflicted.
+fn write_resolutions(index: &mut Index, resolutions: Vec<(Vec<u8>, Resolution)>) -> anyhow::Result<usize> {
+    let mut conflicts = 0;
+    for (path, resolution) in resolutions {
+        let path = &path[..];
+        match resolution {
+            Resolution::Clean(None) => {
+                worktree::remove(path)?;
+                index.remove(path);
+            }
+            Resolution::Clean(Some((mode, hash))) => {
+                if mode == Mode::Submodule {
+                    fs::create_dir_all(path::to_path(path)).with_context(|| format!("create {}", path::display(path)))?;
+                    index.insert(index::Entry::new(path, mode, hash, index::Stat::default()));
+                    continue;
+                }
+                index::write_worktree_file(path, mode, &Object::read(&hash)?.contents)?;
+                let meta = fs::symlink_metadata(path::to_path(path)).with_context(|| format!("stat {}", path::display(path)))?;
+                index.insert(index::Entry::new(path, mode, hash, index::Stat::from_metadata(&meta)));
+            }
+            Resolution::Conflict { stages, worktree: contents, message } => {
+                println!("{message}");
+                conflicts += 1;
+                index.remove(path);
+                for (stage, side) in (1..).zip(stages) {
+                    if let Some((mode, hash)) = side {
+                        let mut entry = index::Entry::new(path, mode, hash, index::Stat::default());
+                        entry.stage = stage;
+                        index.insert(entry);
+                    }
+                }
+                match contents {
+                    Some((Mode::Submodule, _)) => {}
+                    Some((mode, contents)) => index::write_worktree_file(path, mode, &contents)?,
+                    None => worktree::remove(path)?,
+                }
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Every file, symlink and submodule in `tree`, by path.
+fn tree_files(tree: &Oid) -> anyhow::Result<BTreeMap<Vec<u8>, (Mode, Oid)>> {
+    let mut files = BTreeMap::new();
+    for entry in TreeWalk::new(tree)? {
+        let entry = entry?;
+        if !entry.is_tree() {
+            files.insert(entry.path, (entry.mode, entry.hash));
+        }
+    }
+    Ok(files)
+}
+
+fn patch_id(stable: bool, unstable: bool) -> anyhow::Result<()> {
+    let stable = stable || (!unstable && Config::load()?.get_bool("patchid.stable")?.unwrap_or(false));
+    for (id, commit) in got::patch_id::patch_ids(std::io::stdin().lock(), stable)? {
+        println!("{id} {commit}");
+    }
+    Ok(())
+}
+
+/// Tidies the message on standard input as commit messages are, for hooks
+/// and scripts, or with `comment_lines` makes every line a comment instead.
+fn stripspace(strip_comments: bool, comment_lines: bool) -> anyhow::Result<()> {
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text).context("read standard input")?;
+    let text = match comment_lines {
+        true => got::editor::comment_lines(&text),
+        false => got::editor::cleanup(&text, strip_comments),
+    };
+    print!("{text}");
+    Ok(())
+}
+
+fn replace(delete: bool, list: bool, force: bool, graft: bool, convert_graft_file: bool, objects: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    if graft {
+        let Some((commit, parents)) = objects.split_first() else {
+            anyhow::bail!("expected <commit> [<parent>...]");
+        };
+        return replace_with_graft(git_dir, force, false, commit, parents);
+    }
+    if convert_graft_file {
+        anyhow::ensure!(objects.is_empty(), "--convert-graft-file takes no argument");
+        return convert_grafts(git_dir, force);
+    }
+    if list || (!delete && objects.is_empty()) {
+        let pattern = objects.first().map(String::as_str).unwrap_or("");
+        for (name, _) in refs::list(git_dir)? {
+            if let Some(hash) = name.strip_prefix("refs/replace/")
+                && hash.starts_with(pattern)
+            {
+                println!("{hash}");
+            }
+        }
+        return Ok(());
+    }
+    if delete {
+        for hash in objects {
+            let name = format!("refs/replace/{hash}");
+            anyhow::ensure!(
+                refs::read(git_dir, &name)?.is_some(),
+                "replace ref '{hash}' not found"
+            );
+            fs::remove_file(git_dir.join(&name)).with_context(|| format!("delete {name}"))?;
+            println!("Deleted replace ref '{hash}'");
+        }
+        return Ok(());
+    }
+    let [original, replacement] = &objects[..] else {
+        anyhow::bail!("expected <object> <replacement>");
+    };
+    anyhow::ensure!(original != replacement, "new object is the same as the old one: '{original}'");
+    let name = format!("refs/replace/{original}");
+    anyhow::ensure!(
+        force || refs::read(git_dir, &name)?.is_none(),
+        "replace ref '{original}' already exists"
+    );
+    let original_kind = Object::read_original(&original.parse()?)
+        .with_context(|| format!("failed to resolve '{original}' as a valid object"))?
+        .kind;
+    let replacement_kind = Object::read_original(&replacement.parse()?)
+        .with_context(|| format!("failed to resolve '{replacement}' as a valid object"))?
+        .kind;
+    anyhow::ensure!(
+        force || original_kind == replacement_kind,
+        "objects must be of the same type: '{original}' points to a {original_kind} \
+         while '{replacement}' points to a {replacement_kind}"
+    );
+    refs::write(git_dir, &name, replacement)
+}
+
+/// Replaces `commit` with a copy of it that has `parents` instead of its
+/// own. With `gentle`, a graft that changes nothing is quietly accepted, as
+/// a grafts file being converted may have such lines.
+fn replace_with_graft(git_dir: &Path, force: bool, gentle: bool, commit: &str, parents: &[String]) -> anyhow::Result<()> {
+    let original = revision::resolve_commit(git_dir, commit)?;
+    let mut replacement = Commit::parse(&Object::read_original(&original)?.contents)
+        .with_context(|| format!("could not parse {commit} as a commit"))?;
+    replacement.parents = parents
+        .iter()
+        .map(|parent| revision::resolve_commit(git_dir, parent))
+        .collect::<anyhow::Result<_>>()?;
+    if replacement.gpgsig.take().is_some() {
+        eprintln!("warning: the original commit '{commit}' has a gpg signature");
+        eprintln!("warning: the signature will be removed in the replacement commit!");
+    }
+    let hash = Object::store(Kind::Commit, &replacement.serialize())?;
+    if hash == original {
+        anyhow::ensure!(gentle, "new commit is the same as the old one: '{original}'");
+        return Ok(());
+    }
+    let name = format!("refs/replace/{original}");
+    anyhow::ensure!(
+        force || refs::read(git_dir, &name)?.is_none(),
+        "replace ref '{original}' already exists"
+    );
+    refs::write(git_dir, &name, &hash.to_string())
+}
+
+/// Turns each graft in `.got/info/grafts` into a replacement commit and
+/// removes the file, unless some of them couldn't be converted.
+fn convert_grafts(git_dir: &Path, force: bool) -> anyhow::Result<()> {
+    let path = got::grafts::path(git_dir);
+    let text = match fs::read_to_string(&path) {
+        std::result::Result::Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context("read .got/info/grafts"),
+    };
+    let mut failed = vec![];
+    for line in got::grafts::lines(&text) {
+        let words: Vec<String> = line.split(' ').map(String::from).collect();
+        if let Err(e) = replace_with_graft(git_dir, force, true, &words[0], &words[1..]) {
+            eprintln!("error: {e:#}");
+            failed.push(line);
+        }
+    }
+    if !failed.is_empty() {
+        eprintln!("warning: could not convert the following graft(s):");
+        for line in failed {
+            eprintln!("{line}");
+        }
+        exit(EXIT_FAILED);
+    }
+    fs::remove_file(&path).context("remove .got/info/grafts")
+}
+
+fn add(intent_to_add: bool, update: bool, force: bool, pathspecs: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    got::object::remove_stale_temp_objects()?;
+    let mut index = Index::read(git_dir)?;
+    let pathspecs = pathspecs.iter().map(|spec| normalize_pathspec(spec)).collect::<anyhow::Result<Vec<_>>>()?;
+    if update {
+        // Restage every tracked file that changed and drop the ones that
+        // are gone; untracked files are left alone.
+        let options = ScanOptions {
+            store: true,
+            ..ScanOptions::from_config(&Config::load()?)?
+        };
+        let scan = scan::scan(&mut index, &options)?;
+        for entry in scan.modified.into_iter().chain(scan.stale) {
+            if matches_pathspec(&entry.path, &pathspecs) {
+                index.insert(entry);
+            }
+        }
+        for path in scan.deleted {
+            if matches_pathspec(&path, &pathspecs) {
+                index.remove(&path);
+            }
+        }
+        // Staging what is in the worktree marks a conflict resolved.
+        let unmerged: BTreeSet<Vec<u8>> = index.entries().iter().filter(|entry| entry.stage != 0).map(|entry| entry.path.clone()).collect();
+        for path in unmerged.into_iter().filter(|path| matches_pathspec(path, &pathspecs)) {
+            if fs::symlink_metadata(path::to_path(&path)).is_ok() {
+                index.insert(index::stage_file(&path)?);
+            } else {
+                index.remove(&path);
+            }
+        }
+    } else if intent_to_add {
+        anyhow::ensure!(!pathspecs.is_empty(), "nothing specified, nothing added");
+        for path in pathspecs {
+            if index.get(path.as_bytes()).is_some() {
+                continue;
+            }
+            let meta = fs::symlink_metadata(&path).with_context(|| format!("pathspec '{path}' did not match any files"))?;
+            anyhow::ensure!(!meta.is_dir(), "adding directories is not supported yet: '{path}'");
+            let mode = index::worktree_mode(&meta).with_context(|| format!("'{path}' is not a file"))?;
+            let mut entry = index::Entry::new(path.as_bytes(), mode, Object::store(Kind::Blob, b"")?, index::Stat::default());
+            entry.intent_to_add = true;
+            index.insert(entry);
+        }
+    } else {
+        anyhow::ensure!(!pathspecs.is_empty(), "nothing specified, nothing added");
+        let config = Config::load()?;
+        let ignore = Ignore::load(&config, git_dir)?;
+        let mut ignored = vec![];
+        // Files named outright are staged as they are; directories and
+        // globs are expanded by scanning the worktree, which leaves out
+        // what is ignored.
+        let mut files = BTreeSet::new();
+        let mut expand = vec![];
+        for path in pathspecs {
+            match fs::symlink_metadata(&path) {
+                std::result::Result::Ok(meta) if !meta.is_dir() && !is_glob(path.as_bytes()) => {
+                    // Ignore rules only keep out what isn't tracked already.
+                    if !force && index.get(path.as_bytes()).is_none() && is_ignored_path(&ignore, path.as_bytes()) {
+                        ignored.push(path);
+                    } else {
+                        files.insert(path.into_bytes());
+                    }
+                }
+                _ => expand.push(path),
+            }
+        }
+        if !expand.is_empty() {
+            let options = ScanOptions {
+                untracked: Some(if force { Ignore::default() } else { ignore.clone() }),
+                store: true,
+                ..ScanOptions::from_config(&config)?
+            };
+            let scan = scan::scan(&mut index, &options)?;
+            let mut matched = vec![false; expand.len()];
+            let mut matches = |path: &[u8]| {
+                let mut any = false;
+                for (spec, matched) in expand.iter().zip(&mut matched) {
+                    if matches_pathspec(path, std::slice::from_ref(spec)) {
+                        *matched = true;
+                        any = true;
+                    }
+                }
+                any
+            };
+            // Tracked files match even when there is nothing to stage, and
+            // adding what is gone stages its removal.
+            let tracked: BTreeSet<Vec<u8>> = index.entries().iter().map(|entry| entry.path.clone()).collect();
+            let tracked: Vec<Vec<u8>> = tracked.into_iter().filter(|path| matches(path)).collect();
+            for entry in scan.modified.into_iter().chain(scan.stale) {
+                if matches(&entry.path) {
+                    index.insert(entry);
+                }
+            }
+            for path in scan.deleted {
+                if matches(&path) {
+                    index.remove(&path);
+                }
+            }
+            // Nested repositories, listed as `dir/`, aren't added.
+            files.extend(scan.untracked.into_iter().filter(|path| !path.ends_with(b"/") && matches(path)));
+            // Staging what is in the worktree marks a conflict resolved.
+            for path in tracked {
+                if index.entries().iter().any(|entry| entry.path == path && entry.stage != 0) {
+                    if fs::symlink_metadata(path::to_path(&path)).is_ok() {
+                        files.insert(path);
+                    } else {
+                        index.remove(&path);
+                    }
+                }
+            }
+            for (spec, _) in expand.into_iter().zip(matched).filter(|(_, matched)| !matched) {
+                // A directory with nothing to add is no mistake, unless it
+                // is ignored.
+                let is_dir = fs::metadata(&spec).is_ok_and(|meta| meta.is_dir());
+                anyhow::ensure!(is_dir, "pathspec '{spec}' did not match any files");
+                if !force && (ignore.is_ignored(spec.as_bytes(), true) || is_ignored_path(&ignore, spec.as_bytes())) {
+                    ignored.push(spec);
+                }
+            }
+        }
+        let threshold = index::big_file_threshold(&config)?;
+        let progress = std::io::stderr().is_terminal();
+        for path in files {
+            index.insert(index::stage_file_with(&path, threshold, progress)?);
+        }
+        if !ignored.is_empty() {
+            index.write(git_dir)?;
+            eprintln!("The following paths are ignored by one of your .gotignore files:");
+            for path in &ignored {
+                eprintln!("{path}");
+            }
+            eprintln!("hint: Use -f if you really want to add them.");
+            exit(EXIT_FAILED);
+        }
+    }
+    index.write(git_dir)
+}
+
+/// Whether `path` or any directory above it is ignored.
+fn is_ignored_path(ignore: &Ignore, path: &[u8]) -> bool {
+    path::parents(path).any(|parent| ignore.is_ignored(parent, true)) || ignore.is_ignored(path, false)
+}
+
+/// Sets or clears the assume-unchanged and skip-worktree bits of tracked
+/// paths, adds or drops the untracked cache, and splits the index or puts
+/// it back together.
+fn update_index(
+    assume_unchanged: Option<bool>,
+    skip_worktree: Option<bool>,
+    untracked_cache: Option<bool>,
+    split_index: Option<bool>,
+    paths: Vec<String>,
+) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let mut index = Index::read(git_dir)?;
+    let config = Config::load()?;
+    if let Some(split) = split_index {
+        if config.get_bool("core.splitIndex")? == Some(!split) {
+            let (value, action) = if split { ("false", "enable") } else { ("true", "disable") };
+            eprintln!("warning: core.splitIndex is set to {value}; remove or change it, if you really want to {action} split index");
+        }
+        index.set_split(split);
+    }
+    match untracked_cache {
+        Some(true) => {
+            if config.get_bool("core.untrackedCache")? == Some(false) {
+                eprintln!("warning: core.untrackedCache is set to false; remove or change it, if you really want to enable the untracked cache");
+            }
+            if index.untracked_cache.is_none() {
+                index.untracked_cache = Some(UntrackedCache::new(&config, git_dir));
+            }
+        }
+        Some(false) => index.untracked_cache = None,
+        None => {}
+    }
+    for path in paths {
+        let path = normalize_pathspec(&path)?;
+        let mut found = false;
+        for entry in index.entries_mut().filter(|entry| entry.path == path.as_bytes()) {
+            found = true;
+            if let Some(assume_unchanged) = assume_unchanged {
+                entry.assume_valid = assume_unchanged;
+            }
+            if let Some(skip_worktree) = skip_worktree {
+                entry.skip_worktree = skip_worktree;
+            }
+        }
+        anyhow::ensure!(found, "Unable to mark file {path}");
+    }
+    index.write(git_dir)
+}
+
+fn restore(patch: bool, staged: bool, pathspecs: Vec<String>) -> anyhow::Result<()> {
+    match (patch, staged) {
+        (true, true) => unstage_patch(pathspecs),
+        (true, false) => discard_patch(pathspecs),
+        (false, true) => unstage(pathspecs),
+        (false, false) => restore_worktree(pathspecs),
+    }
+}
+
+/// `restore --staged`: puts back what HEAD has (nothing, for paths HEAD
+/// doesn't have) in the index for the paths `pathspecs` match, leaving the
+/// worktree alone.
+fn unstage(pathspecs: Vec<String>) -> anyhow::Result<()> {
+    anyhow::ensure!(!pathspecs.is_empty(), "you must specify path(s) to restore");
+    let git_dir = repository::git_dir();
+    let mut index = Index::read(git_dir)?;
+    let specs = pathspecs.iter().map(|spec| normalize_pathspec(spec)).collect::<anyhow::Result<Vec<_>>>()?;
+    let head = match refs::resolve(git_dir, "HEAD")? {
+        Some(commit) => tree_files(&revwalk::read_commit(&commit)?.tree)?,
+        None => BTreeMap::new(),
+    };
+    let mut paths: BTreeSet<Vec<u8>> = head.keys().cloned().collect();
+    paths.extend(index.entries().iter().map(|entry| entry.path.clone()));
+    let paths: Vec<Vec<u8>> = paths.into_iter().filter(|path| matches_pathspec(path, &specs)).collect();
+    check_pathspecs_matched(&pathspecs, &specs, &paths)?;
+    for path in paths {
+        match head.get(&path) {
+            // Unchanged entries keep their stat data, so the file needn't
+            // be hashed again.
+            Some(&(mode, hash)) if index.get(&path).is_some_and(|entry| entry.mode == mode && entry.hash == hash && !entry.intent_to_add) => {}
+            Some(&(mode, hash)) => {
+                index.remove(&path);
+                index.insert(index::Entry::new(&path, mode, hash, index::Stat::default()));
+            }
+            None => {
+                index.remove(&path);
+            }
+        }
+    }
+    index.write(git_dir)
+}
+
+/// `restore`: writes what is staged back to the worktree for the paths
+/// `pathspecs` match, throwing away the changes made since.
+fn restore_worktree(pathspecs: Vec<String>) -> anyhow::Result<()> {
+    anyhow::ensure!(!pathspecs.is_empty(), "you must specify path(s) to restore");
+    let git_dir = repository::git_dir();
+    let mut index = Index::read(git_dir)?;
+    let specs = pathspecs.iter().map(|spec| normalize_pathspec(spec)).collect::<anyhow::Result<Vec<_>>>()?;
+    let paths: BTreeSet<Vec<u8>> = index.entries().iter().filter(|entry| matches_pathspec(&entry.path, &specs)).map(|entry| entry.path.clone()).collect();
+    let paths: Vec<Vec<u8>> = paths.into_iter().collect();
+    check_pathspecs_matched(&pathspecs, &specs, &paths)?;
+    if let Some(path) = paths.iter().find(|path| index.get(path).is_none()) {
+        anyhow::bail!("path '{}' is unmerged", path::display(path));
+    }
+    for path in &paths {
+        let entry = index.get(path).expect("unmerged paths are refused above").clone();
+        // Intent-to-add entries have nothing staged, and submodules are
+        // their own repositories.
+        if entry.intent_to_add || entry.mode == Mode::Submodule {
+            continue;
+        }
+        if fs::symlink_metadata(path::to_path(path)).is_ok() && !worktree::is_blocked(&index, path, false) {
+            continue;
+        }
+        index::write_worktree_file(path, entry.mode, &Object::read(&entry.hash)?.contents)?;
+        let meta = fs::symlink_metadata(path::to_path(path)).with_context(|| format!("stat {}", path::display(path)))?;
+        index.insert(index::Entry::new(path, entry.mode, entry.hash, index::Stat::from_metadata(&meta)));
+    }
+    index.write(git_dir)
+}
+
+/// Fails on the first of `pathspecs` (`specs` once normalized) that matches
+/// none of `paths`.
+fn check_pathspecs_matched(pathspecs: &[String], specs: &[String], paths: &[Vec<u8>]) -> anyhow::Result<()> {
+    for (pathspec, spec) in pathspecs.iter().zip(specs) {
+        anyhow::ensure!(
+            paths.iter().any(|path| matches_pathspec(path, std::slice::from_ref(spec))),
+            "pathspec '{pathspec}' did not match any file(s) known to got"
+        );
+    }
+    Ok(())
+}
+
+fn reset(patch: bool, hard: bool, force_protected: bool, pathspecs: Vec<String>) -> anyhow::Result<()> {
+    if hard {
+        anyhow::ensure!(pathspecs.len() <= 1, "Cannot do hard reset with paths.");
+        return reset_hard(pathspecs.first().map_or("HEAD", String::as_str), force_protected);
+    }
+    anyhow::ensure!(patch, "resetting without --patch or --hard is not supported yet");
+    unstage_patch(pathspecs)
+}
+
+/// `reset --hard`: points HEAD (or the branch it is on) at `target` and
+/// makes the index and worktree match it, throwing away every change to
+/// tracked files and any merge in progress.
+fn reset_hard(target: &str, force_protected: bool) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    if let Some(branch) = head.strip_prefix("ref: refs/heads/") {
+        check_unprotected(&Config::load()?, branch, force_protected)?;
+    }
+    let commit = revision::resolve_commit(git_dir, target)?;
+    let files = tree_files(&revision::peel(&commit, Some(Kind::Tree))?)?;
+    update_head(&commit, &format!("reset: moving to {target}"))?;
+    let mut index = Index::read(git_dir)?;
+    let mut paths: BTreeSet<Vec<u8>> = files.keys().cloned().collect();
+    paths.extend(index.entries().iter().map(|entry| entry.path.clone()));
+    for path in paths {
+        let staged = index.get(&path).map(|entry| (entry.mode, entry.hash));
+        let conflicted = index.entries().iter().any(|entry| entry.path == path && entry.stage != 0);
+        let missing = fs::symlink_metadata(path::to_path(&path)).is_err();
+        if staged.as_ref() == files.get(&path) && !conflicted && !missing && !worktree::is_blocked(&index, &path, false) {
+            continue;
+        }
+        match files.get(&path) {
+            None => {
+                worktree::remove(&path)?;
+                index.remove(&path);
+            }
+            Some(&(Mode::Submodule, hash)) => index.insert(index::Entry::new(&path, Mode::Submodule, hash, index::Stat::default())),
+            Some(&(mode, hash)) => {
+                index::write_worktree_file(&path, mode, &Object::read(&hash)?.contents)?;
+                let meta = fs::symlink_metadata(path::to_path(&path)).with_context(|| format!("stat {}", path::display(&path)))?;
+                index.insert(index::Entry::new(&path, mode, hash, index::Stat::from_metadata(&meta)));
+            }
+        }
+    }
+    index.write(git_dir)?;
+    remove_merge_state(git_dir)?;
+    let message = Commit::parse(&Object::read(&commit)?.contents)?.message;
+    println!("HEAD is now at {} {}", commit.abbrev(7), message.lines().next().unwrap_or(""));
+    Ok(())
+}
+
+/// Fails unless `branch.<branch>.protect` leaves `branch` open to being
+/// reset, deleted or force-pushed, or `force_protected` overrides it.
+fn check_unprotected(config: &Config, branch: &str, force_protected: bool) -> anyhow::Result<()> {
+    if !force_protected && config.get_bool(&format!("branch.{branch}.protect"))? == Some(true) {
+        anyhow::bail!("branch '{branch}' is protected; use --force-protected if you really mean it");
+    }
+    Ok(())
+}
+
+/// The contents of blob `hash`, or nothing for the zero ID.
+fn blob_contents(hash: &Oid) -> anyhow::Result<Vec<u8>> {
+    if hash.is_zero() {
+        Ok(vec![])
+    } else {
+        Ok(Object::read(hash)?.contents)
+    }
+}
+
+/// `restore --patch`: offers each change between the index and the
+/// worktree to be thrown away.
+fn discard_patch(pathspecs: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let mut index = Index::read(git_dir)?;
+    let pathspecs = pathspecs.iter().map(|spec| normalize_pathspec(spec)).collect::<anyhow::Result<Vec<_>>>()?;
+    let entries: Vec<index::Entry> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage == 0 && !entry.intent_to_add && !entry.ignores_worktree() && entry.mode != Mode::Submodule)
+        .filter(|entry| matches_pathspec(&entry.path, &pathspecs))
+        .cloned()
+        .collect();
+    let mut selector = Selector::new(std::io::stdin().lock(), std::io::stdout(), PatchMode::Discard);
+    let mut shown = false;
+    for entry in entries {
+        if selector.has_quit() {
+            break;
+        }
+        let mut change = Change {
+            status: Status::Deleted,
+            old_mode: entry.mode.bits(),
+            new_mode: 0,
+            old_hash: entry.hash,
+            new_hash: Oid::ZERO,
+            old_path: entry.path.clone(),
+            path: entry.path.clone(),
+        };
+        let meta = fs::symlink_metadata(path::to_path(&entry.path)).ok();
+        let new = match meta.as_ref().and_then(|meta| Some((meta, index::worktree_mode(meta)?))) {
+            Some((meta, _)) if entry.stat_matches(meta) => continue,
+            Some((meta, mode)) => {
+                let new = index::read_worktree_file(path::to_path(&entry.path), meta)?;
+                change.status = Status::Modified;
+                change.new_mode = mode.bits();
+                change.new_hash = Object::hash_of(Kind::Blob, &new);
+                if change.new_hash == change.old_hash && change.new_mode == change.old_mode {
+                    continue;
+                }
+                new
+            }
+            None => vec![],
+        };
+        shown = true;
+        let old = blob_contents(&entry.hash)?;
+        if let Decision::Update { mode, contents } = selector.select(&change, &old, &new)? {
+            let mode = Mode::from_bits(mode)?;
+            index::write_worktree_file(&entry.path, mode, &contents)?;
+            if mode == entry.mode && contents == old {
+                // The file matches the index again; record its new stat data.
+                let meta = fs::symlink_metadata(path::to_path(&entry.path))?;
+                index.insert(index::Entry { stat: index::Stat::from_metadata(&meta), ..entry });
+            }
+        }
+    }
+    if !shown {
+        eprintln!("No changes.");
+    }
+    index.write(git_dir)
+}
+
+/// `commit --patch`: offers each change between the index and the worktree
+/// to be staged.
+fn stage_patch() -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let mut index = Index::read(git_dir)?;
+    let entries: Vec<index::Entry> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage == 0 && !entry.ignores_worktree() && entry.mode != Mode::Submodule)
+        .cloned()
+        .collect();
+    let mut selector = Selector::new(std::io::stdin().lock(), std::io::stdout(), PatchMode::Stage);
+    let mut shown = false;
+    for entry in entries {
+        if selector.has_quit() {
+            break;
+        }
+        // A path added with -N is offered as a new file.
+        let mut change = Change {
+            status: if entry.intent_to_add { Status::Added } else { Status::Deleted },
+            old_mode: if entry.intent_to_add { 0 } else { entry.mode.bits() },
+            new_mode: 0,
+            old_hash: if entry.intent_to_add { Oid::ZERO } else { entry.hash },
+            new_hash: Oid::ZERO,
+            old_path: entry.path.clone(),
+            path: entry.path.clone(),
+        };
+        let meta = fs::symlink_metadata(path::to_path(&entry.path)).ok();
+        let new = match meta.as_ref().and_then(|meta| Some((meta, index::worktree_mode(meta)?))) {
+            Some((meta, _)) if !entry.intent_to_add && entry.stat_matches(meta) => continue,
+            Some((meta, mode)) => {
+                let new = index::read_worktree_file(path::to_path(&entry.path), meta)?;
+                if !entry.intent_to_add {
+                    change.status = Status::Modified;
+                }
+                change.new_mode = mode.bits();
+                change.new_hash = Object::hash_of(Kind::Blob, &new);
+                if change.new_hash == change.old_hash && change.new_mode == change.old_mode {
+                    continue;
+                }
+                new
+            }
+            None if entry.intent_to_add => continue,
+            None => vec![],
+        };
+        shown = true;
+        match selector.select(&change, &blob_contents(&change.old_hash)?, &new)? {
+            Decision::Keep => {}
+            Decision::Remove => {
+                index.remove(&entry.path);
+            }
+            Decision::Update { mode, contents } => {
+                let hash = Object::store(Kind::Blob, &contents)?;
+                let mode = Mode::from_bits(mode)?;
+                // Only a file staged whole still matches its stat data.
+                let stat = match meta {
+                    Some(meta) if hash == change.new_hash && mode.bits() == change.new_mode => index::Stat::from_metadata(&meta),
+                    _ => index::Stat::default(),
+                };
+                index.insert(index::Entry { mode, hash, stat, intent_to_add: false, ..entry });
+            }
+        }
+    }
+    if !shown {
+        eprintln!("No changes.");
+    }
+    index.write(git_dir)
+}
+
+/// `reset --patch`: offers each change between `HEAD` and the index to be
+/// taken back out of the index.
+fn unstage_patch(pathspecs: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let mut index = Index::read(git_dir)?;
+    let pathspecs = pathspecs.iter().map(|spec| normalize_pathspec(spec)).collect::<anyhow::Result<Vec<_>>>()?;
+    let mut head = BTreeMap::new();
+    if let Some(commit) = refs::resolve(git_dir, "HEAD")? {
+        for entry in TreeWalk::new(&revwalk::read_commit(&commit)?.tree)? {
+            let entry = entry?;
+            if !entry.is_tree() {
+                head.insert(entry.path, (entry.mode, entry.hash));
+            }
+        }
+    }
+    let conflicted: BTreeSet<Vec<u8>> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage != 0)
+        .map(|entry| entry.path.clone())
+        .collect();
+    let mut paths: BTreeSet<Vec<u8>> = head.keys().cloned().collect();
+    paths.extend(index.entries().iter().filter(|entry| !entry.intent_to_add).map(|entry| entry.path.clone()));
+
+    let mut selector = Selector::new(std::io::stdin().lock(), std::io::stdout(), PatchMode::Unstage);
+    let mut shown = false;
+    for path in paths {
+        if selector.has_quit() {
+            break;
+        }
+        if conflicted.contains(&path) || !matches_pathspec(&path, &pathspecs) {
+            continue;
+        }
+        let staged = index
+            .get(&path)
+            .filter(|entry| !entry.intent_to_add)
+            .map(|entry| (entry.mode, entry.hash));
+        let (status, (old_mode, old_hash), (new_mode, new_hash)) = match (head.get(&path).copied(), staged) {
+            (Some(old), Some(new)) if old != new => (Status::Modified, old, new),
+            (Some(old), None) => (Status::Deleted, old, (Mode::File, Oid::ZERO)),
+            (None, Some(new)) => (Status::Added, (Mode::File, Oid::ZERO), new),
+            _ => continue,
+        };
+        if old_mode == Mode::Submodule || new_mode == Mode::Submodule {
+            continue;
+        }
+        let change = Change {
+            status,
+            old_mode: if old_hash.is_zero() { 0 } else { old_mode.bits() },
+            new_mode: if new_hash.is_zero() { 0 } else { new_mode.bits() },
+            old_hash,
+            new_hash,
+            old_path: path.clone(),
+            path: path.clone(),
+        };
+        shown = true;
+        match selector.select(&change, &blob_contents(&old_hash)?, &blob_contents(&new_hash)?)? {
+            Decision::Keep => {}
+            Decision::Remove => {
+                index.remove(&path);
+            }
+            Decision::Update { mode, contents } => {
+                let hash = Object::store(Kind::Blob, &contents)?;
+                let mode = Mode::from_bits(mode)?;
+                // The cached stat data no longer describes this content, so
+                // clear it to make the file look changed until restaged.
+                let entry = match index.get(&path) {
+                    Some(entry) => index::Entry { mode, hash, stat: index::Stat::default(), ..entry.clone() },
+                    None => index::Entry::new(&path, mode, hash, index::Stat::default()),
+                };
+                index.insert(entry);
+            }
+        }
+    }
+    if !shown {
+        eprintln!("No changes.");
+    }
+    index.write(git_dir)
+}
+
+/// A pathspec as a path from the top of the worktree, without `./` or a
+/// trailing slash; the empty string stands for the whole tree.
+fn normalize_pathspec(spec: &str) -> anyhow::Result<String> {
+    let mut parts = vec![];
+    for part in spec.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => anyhow::ensure!(parts.pop().is_some(), "'{spec}' is outside repository"),
+            _ => parts.push(part),
+        }
+    }
+    anyhow::ensure!(!spec.starts_with('/'), "'{spec}' is outside repository");
+    Ok(parts.join("/"))
+}
+
+/// Whether `path` is one of `pathspecs` or inside one, or matches one that
+/// is a glob; no pathspecs match everything.
+fn matches_pathspec(path: &[u8], pathspecs: &[impl AsRef<[u8]>]) -> bool {
+    pathspecs.is_empty()
+        || pathspecs.iter().map(AsRef::as_ref).any(|spec| {
+            spec.is_empty()
+                || path == spec
+                || path.strip_prefix(spec).is_some_and(|rest| rest.starts_with(b"/"))
+                || (is_glob(spec) && ignore::wildmatch(&pathspec_glob(spec), path))
+        })
+}
+
+fn is_glob(spec: &[u8]) -> bool {
+    spec.iter().any(|byte| matches!(byte, b'*' | b'?' | b'['))
+}
+
+/// `spec` as a pattern for [`ignore::wildmatch`]. In a pathspec, unlike an
+/// ignore rule, `*` matches `/` too, so `*.c` finds C files at any depth.
+fn pathspec_glob(spec: &[u8]) -> Vec<u8> {
+    let mut pattern = vec![];
+    let mut bytes = spec.iter().copied();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\\' => pattern.extend([b].into_iter().chain(bytes.next())),
+            b'*' => pattern.extend(b"**"),
+            _ => pattern.push(b),
+        }
+    }
+    pattern
+}
+
+/// `commit`: records the index as a new commit on HEAD, with `patch`
+/// first offering each worktree change to be staged. The message is the
+/// `-m` paragraphs or the `-F` file (`-` for standard input), and goes
+/// through the editor when `edit` says so or neither gives one. Finishing
+/// a merge, the message starts out as the merge's. A `reproducible`
+/// commit is dated `SOURCE_DATE_EPOCH`, so that committing the same index
+/// with the same message and identity always makes the same commit.
+fn commit(patch: bool, messages: Vec<String>, file: Option<String>, edit: Option<bool>, reproducible: bool) -> anyhow::Result<()> {
+    let given = match file.as_deref() {
+        Some(file) => Some(read_message_file(file)?),
+        None if !messages.is_empty() => Some(messages.join("\n\n")),
+        None => None,
+    };
+    let edit = edit.unwrap_or(given.is_none());
+    let given = match given {
+        None if merge_head(repository::git_dir())?.is_some() => {
+            Some(fs::read_to_string(repository::git_dir().join("MERGE_MSG")).context("read .got/MERGE_MSG")?)
+        }
+        given => given,
+    };
+    if patch {
+        stage_patch()?;
+    }
+    let message = edit_message(&given.unwrap_or_default(), edit)?;
+    anyhow::ensure!(!message.is_empty(), "aborting commit due to empty commit message");
+    commit_index(&message, reproducible)
+}
+
+/// The message `-F <file>` gives, read from standard input for `-`.
+fn read_message_file(file: &str) -> anyhow::Result<String> {
+    if file == "-" {
+        let mut message = String::new();
+        std::io::stdin().read_to_string(&mut message).context("read message from standard input")?;
+        return Ok(message);
+    }
+    fs::read_to_string(file).with_context(|| format!("could not read log file '{file}'"))
+}
+
+/// What `--edit` and `--no-edit` say, if either was given.
+fn edit_flag(edit: bool, no_edit: bool) -> Option<bool> {
+    match (edit, no_edit) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Tidies commit message `message`, first letting the user change it in
+/// the editor through `.got/COMMIT_EDITMSG` when `edit` is set.
+fn edit_message(message: &str, edit: bool) -> anyhow::Result<String> {
+    if !edit {
+        return Ok(got::editor::cleanup(message, false));
+    }
+    let text = format!(
+        "{}\n{}",
+        got::editor::cleanup(message, false),
+        got::editor::comment_lines(
+            "Please enter the commit message for your changes. Lines starting\n\
+             with '#' will be ignored, and an empty message aborts the commit."
+        )
+    );
+    let editor = got::editor::editor(&Config::load()?)?;
+    let edited = got::editor::edit(&editor, &repository::git_dir().join("COMMIT_EDITMSG"), &text)?;
+    Ok(got::editor::cleanup(&edited, true))
+}
+
+/// Commits the index on top of HEAD, or as a root commit on an unborn
+/// branch, and says where it went and what changed. A merge that stopped
+/// at conflicts is finished with the merged commit as second parent.
+fn commit_index(message: &str, reproducible: bool) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let index = Index::read(git_dir)?;
+    anyhow::ensure!(
+        index.entries().iter().all(|entry| entry.stage == 0),
+        "Committing is not possible because you have unmerged files.\n\
+         hint: Fix them up in the work tree, and then use 'got add -u'\n\
+         hint: to mark the resolution."
+    );
+    let tree = index.write_tree()?;
+    let parent = refs::resolve(git_dir, "HEAD")?;
+    let parent_tree = parent.as_ref().map(|parent| revision::peel(parent, Some(Kind::Tree))).transpose()?;
+    let merged = merge_head(git_dir)?;
+    anyhow::ensure!(merged.is_some() || parent_tree != Some(tree), "no changes added to commit");
+    let config = Config::load()?;
+    let signature = match reproducible {
+        true => Signature::reproducible,
+        false => Signature::current,
+    };
+    let commit = Commit {
+        tree,
+        parents: parent.into_iter().chain(merged).collect(),
+        author: signature(&config, "author")?,
+        committer: signature(&config, "committer")?,
+        extra_headers: vec![],
+        gpgsig: None,
+        message: message.to_string(),
+    };
+    let hash = Object::store(Kind::Commit, &commit.serialize())?;
+    let subject = commit.message.lines().next().unwrap_or_default();
+    let (command, root) = match commit.parents.len() {
+        0 => ("commit (initial)", " (root-commit)"),
+        1 => ("commit", ""),
+        _ => ("commit (merge)", ""),
+    };
+    update_head(&hash, &format!("{command}: {subject}"))?;
+    remove_merge_state(git_dir)?;
+    let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    let branch = head.strip_prefix("ref: refs/heads/").unwrap_or("detached HEAD");
+    println!("[{branch}{root} {}] {subject}", hash.abbrev(7));
+    // As with git, a merge's changes go unsummarized.
+    if merged.is_some() {
+        return Ok(());
+    }
+    let changes = tree_diff::detect_renames(tree_diff::diff_trees(parent_tree.as_ref(), Some(&tree))?)?;
+    let mut out = vec![];
+    got::diff::write_shortstat(&mut out, &changes)?;
+    got::diff::write_summary(&mut out, &changes);
+    std::io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+/// `commit-tree`: stores a commit of `tree_hash` on `parents`, in the
+/// order given, by the current author and committer, and returns its ID.
+/// A parent given twice is only recorded once. The message comes from
+/// `-m`, or else from standard input.
+fn commit_tree(tree_hash: String, parent_specs: Vec<String>, message: Option<String>) -> anyhow::Result<Oid> {
+    let git_dir = repository::git_dir();
+    let tree = revision::peel(&revision::resolve(git_dir, &tree_hash)?, Some(Kind::Tree))?;
+    let mut parents = vec![];
+    for spec in &parent_specs {
+        let parent = revision::resolve_commit(git_dir, spec)?;
+        if parents.contains(&parent) {
+            eprintln!("error: duplicate parent {parent} ignored");
+            continue;
+        }
+        parents.push(parent);
+    }
+    let message = match message {
+        Some(message) => format!("{message}\n"),
+        None => create_message()?,
+    };
+    let config = Config::load()?;
+    let commit = Commit {
+        tree,
+        parents,
+        author: Signature::current(&config, "author")?,
+        committer: Signature::current(&config, "committer")?,
+        extra_headers: vec![],
+        gpgsig: None,
+        message,
+    };
+    Object::store(Kind::Commit, &commit.serialize())
+}
+
+/// Writes out the tree of `commit` in `directory`, which must be empty or
+/// not exist yet, as a checkout would, without touching the worktree or the
+/// index. Submodules are left as empty directories.
+fn export_worktree(commit: &str, directory: &str) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let tree = revision::peel(&revision::resolve(git_dir, commit)?, Some(Kind::Tree))?;
+    let empty = fs::read_dir(directory).map_or(true, |mut entries| entries.next().is_none());
+    anyhow::ensure!(empty, "destination path '{directory}' already exists and is not an empty directory");
+    fs::create_dir_all(directory).with_context(|| format!("create {directory}"))?;
+    let converter = Converter::load(git_dir)?;
+    for entry in TreeWalk::new(&tree)? {
+        let entry = entry?;
+        let path = Path::new(directory).join(path::to_path(&entry.path));
+        let file = path::from_os(path.as_os_str());
+        match entry.mode {
+            Mode::Tree | Mode::Submodule => fs::create_dir_all(&path).with_context(|| format!("create {}", path.display()))?,
+            Mode::Symlink => index::write_worktree_file(file, entry.mode, &Object::read(&entry.hash)?.contents)?,
+            mode => {
+                let contents = converter.smudge(&entry.path, Object::read(&entry.hash)?.contents)?;
+                index::write_worktree_file(file, mode, &contents)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Commits each of `directories` in turn, oldest first, on top of
+/// `branch` (HEAD's branch by default), as a history of snapshots taken
+/// without version control. Each commit is dated by its directory's
+/// modification time and its message is `message` or names the directory;
+/// a snapshot the same as the one before is skipped. If the branch is
+/// checked out, the worktree and index move to the last snapshot.
+fn import_snapshots(branch: Option<String>, message: Option<String>, directories: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let config = Config::load()?;
+    let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    let refname = match branch {
+        Some(branch) => format!("refs/heads/{branch}"),
+        None => head.strip_prefix("ref: ").context("HEAD is detached; name a branch to import onto with -b")?.to_string(),
+    };
+    let old = refs::resolve(git_dir, &refname)?;
+    let tree_of = |commit: &Oid| revision::peel(commit, Some(Kind::Tree));
+    let old_tree = old.as_ref().map(tree_of).transpose()?;
+
+    // Snapshots come from elsewhere, so the repository's ignore rules
+    // aren't theirs.
+    let ignore = Ignore::default();
+    let (mut tip, mut tip_tree) = (old, old_tree);
+    for directory in &directories {
+        let meta = fs::metadata(directory).with_context(|| format!("read {directory}"))?;
+        anyhow::ensure!(meta.is_dir(), "'{directory}' is not a directory");
+        let tree = write_tree_with(Path::new(directory), &ignore)?.hash;
+        if tip_tree == Some(tree) {
+            eprintln!("skipping '{directory}': nothing changed since the snapshot before");
+            continue;
+        }
+        let mut author = Signature::current(&config, "author")?;
+        if let std::result::Result::Ok(modified) = meta.modified() {
+            author.time = chrono::DateTime::<chrono::Local>::from(modified).fixed_offset();
+        }
+        let name = Path::new(directory).file_name().map_or(directory.clone(), |name| name.to_string_lossy().into_owned());
+        let commit = Commit {
+            tree,
+            parents: tip.into_iter().collect(),
+            author,
+            committer: Signature::current(&config, "committer")?,
+            extra_headers: vec![],
+            gpgsig: None,
+            message: format!("{}\n", message.clone().unwrap_or_else(|| format!("Import snapshot {name}"))),
+        };
+        let hash = Object::store(Kind::Commit, &commit.serialize())?;
+        println!("{} {directory}", hash.abbrev(7));
+        (tip, tip_tree) = (Some(hash), Some(tree));
+    }
+    let Some(tip) = tip.filter(|tip| Some(*tip) != old) else {
+        return Ok(());
+    };
+
+    let checked_out = head.strip_prefix("ref: ") == Some(refname.as_str());
+    if checked_out && !repository::is_bare(&config)? {
+        let mut index = Index::read(git_dir)?;
+        worktree::checkout(&mut index, old_tree.as_ref(), &tree_of(&tip)?)?;
+        index.write(git_dir)?;
+    }
+    refs::write(git_dir, &refname, &tip.to_string())?;
+    let entry = got::reflog::Entry {
+        old: old.unwrap_or(Oid::ZERO),
+        new: tip,
+        committer: Signature::current(&config, "committer")?,
+        message: "import-snapshots".to_string(),
+    };
+    got::reflog::append(git_dir, &refname, entry.clone())?;
+    if checked_out {
+        got::reflog::append(git_dir, "HEAD", entry)?;
+    }
+    Ok(())
+}
+
+/// A commit message read from standard input, as given.
+fn create_message() -> anyhow::Result<String> {
+    let mut message = String::new();
+    std::io::stdin().read_to_string(&mut message).context("read commit message from standard input")?;
+    Ok(message)
+}
+
+/// `status`: where HEAD is, then what is staged (HEAD against the index),
+/// what isn't (the index against the worktree), what is unmerged and what
+/// is untracked, in git's long format, with the untracked paths laid out
+/// in `columns`.
+fn status(columns: Columns) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let mut stdout = std::io::stdout();
+    let config = Config::load()?;
+    let mut index = Index::read(git_dir)?;
+    let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    let head_commit = refs::resolve(git_dir, "HEAD")?;
+    match (head.strip_prefix("ref: refs/heads/"), &head_commit) {
+        (Some(branch), _) => writeln!(stdout, "On branch {branch}")?,
+        (None, Some(commit)) => writeln!(stdout, "HEAD detached at {}", commit.abbrev(7))?,
+        (None, None) => writeln!(stdout, "Not currently on any branch.")?,
+    }
+    if let (Some(branch), Some(commit)) = (head.strip_prefix("ref: refs/heads/"), &head_commit)
+        && print_tracking(&mut stdout, &config, branch, commit)?
+    {
+        writeln!(stdout)?;
+    }
+    if head_commit.is_none() {
+        writeln!(stdout, "\nNo commits yet\n")?;
+    }
+
+    let unmerged: BTreeMap<Vec<u8>, Vec<u8>> = index.entries().iter().filter(|entry| entry.stage != 0).fold(BTreeMap::new(), |mut unmerged, entry| {
+        unmerged.entry(entry.path.clone()).or_insert_with(Vec::new).push(entry.stage);
+        unmerged
+    });
+    if let Some(sequencer) = Sequencer::load(git_dir)? {
+        let command = sequencer.todo.first().map_or(Action::Pick, |step| step.action).command();
+        if let Some(step) = sequencer.todo.first() {
+            let doing = match step.action {
+                Action::Pick => "cherry-picking",
+                Action::Revert => "reverting",
+            };
+            writeln!(stdout, "You are currently {doing} commit {}.", step.commit.abbrev(7))?;
+        }
+        match unmerged.is_empty() {
+            true => writeln!(stdout, "  (all conflicts fixed: run \"got {command} --continue\")")?,
+            false => writeln!(stdout, "  (fix conflicts and run \"got {command} --continue\")")?,
+        }
+        writeln!(stdout, "  (use \"got {command} --skip\" to skip this patch)")?;
+        writeln!(stdout, "  (use \"got {command} --abort\" to cancel the {command} operation)")?;
+        writeln!(stdout)?;
+    }
+    let merging = merge_head(git_dir)?.is_some();
+    if merging {
+        match unmerged.is_empty() {
+            true => {
+                writeln!(stdout, "All conflicts fixed but you are still merging.")?;
+                writeln!(stdout, "  (use \"got commit\" to conclude merge)")?;
+            }
+            false => {
+                writeln!(stdout, "You have unmerged paths.")?;
+                writeln!(stdout, "  (fix conflicts and run \"got commit\")")?;
+                writeln!(stdout, "  (use \"got merge --abort\" to abort the merge)")?;
+            }
+        }
+        writeln!(stdout)?;
+    }
+
+    // Staged: HEAD's tree against the index.
+    let head_files = match &head_commit {
+        Some(commit) => tree_files(&revision::peel(commit, Some(Kind::Tree))?)?,
+        None => BTreeMap::new(),
+    };
+    let staged_files: BTreeMap<Vec<u8>, (Mode, Oid)> = index
+        .entries()
+        .iter()
+        .filter(|entry| entry.stage == 0 && !entry.intent_to_add)
+        .map(|entry| (entry.path.clone(), (entry.mode, entry.hash)))
+        .collect();
+    let mut paths: BTreeSet<&Vec<u8>> = head_files.keys().collect();
+    paths.extend(staged_files.keys());
+    let mut changes = vec![];
+    for path in paths {
+        if unmerged.contains_key(path) {
+            continue;
+        }
+        let (old, new) = (head_files.get(path), staged_files.get(path));
+        let status = match (old, new) {
+            (Some(old), Some(new)) if old == new => continue,
+            (Some((old_mode, _)), Some((new_mode, _))) if old_mode.kind() != new_mode.kind() || (*old_mode == Mode::Symlink) != (*new_mode == Mode::Symlink) => Status::TypeChanged,
+            (Some(_), Some(_)) => Status::Modified,
+            (Some(_), None) => Status::Deleted,
+            (None, Some(_)) => Status::Added,
+            (None, None) => continue,
+        };
+        changes.push(Change {
+            status,
+            old_mode: old.map_or(0, |(mode, _)| mode.bits()),
+            new_mode: new.map_or(0, |(mode, _)| mode.bits()),
+            old_hash: old.map_or(Oid::ZERO, |(_, hash)| *hash),
+            new_hash: new.map_or(Oid::ZERO, |(_, hash)| *hash),
+            old_path: path.clone(),
+            path: path.clone(),
+        });
+    }
+    let staged = tree_diff::detect_renames(changes)?;
+
+    // Not staged: the index against the worktree.
+    let ignore = Ignore::load(&config, git_dir)?;
+    let options = ScanOptions {
+        untracked: Some(ignore),
+        ..ScanOptions::from_config(&config)?
+    };
+    let scan = scan::scan(&mut index, &options)?;
+    let mut unstaged: BTreeMap<Vec<u8>, State> = BTreeMap::new();
+    for entry in &scan.modified {
+        let added = index.get(&entry.path).is_some_and(|staged| staged.intent_to_add);
+        let state = match index.get(&entry.path) {
+            _ if added => State::Added,
+            Some(staged) if (staged.mode == Mode::Symlink) != (entry.mode == Mode::Symlink) => State::TypeChanged,
+            _ => State::Modified,
+        };
+        unstaged.insert(entry.path.clone(), state);
+    }
+    for path in &scan.deleted {
+        unstaged.insert(path.clone(), State::Deleted);
+    }
+    unstaged.retain(|path, _| !unmerged.contains_key(path));
+    // Fresh stat data saves rehashing those files next time, unless the
+    // repository is to be left alone.
+    if !repository::is_read_only(&config)? {
+        for entry in scan.stale {
+            index.insert(entry);
+        }
+        index.write(git_dir)?;
+    }
+
+    // An untracked directory with nothing tracked in it is shown whole.
+    let tracked_dirs: HashSet<&[u8]> = index.entries().iter().flat_map(|entry| path::parents(&entry.path)).collect();
+    let untracked: BTreeSet<Vec<u8>> = scan
+        .untracked
+        .iter()
+        .filter(|path| !unmerged.contains_key(*path))
+        .map(|path| {
+            let trimmed = path.strip_suffix(b"/").unwrap_or(path);
+            let slashes = trimmed.iter().enumerate().filter(|&(_, &byte)| byte == b'/');
+            let dir = slashes.map(|(at, _)| &path[..at]).find(|dir| !tracked_dirs.contains(dir));
+            dir.map_or_else(|| path.clone(), |dir| [dir, b"/"].concat())
+        })
+        .collect();
+
+    let label = |state: String, width: usize| format!("{state}:{:pad$}", "", pad = width - state.len() - 1);
+    if !staged.is_empty() {
+        writeln!(stdout, "Changes to be committed:")?;
+        if !merging {
+            writeln!(stdout, "  (use \"got restore --staged <file>...\" to unstage)")?;
+        }
+        for change in &staged {
+            let state = State::from(change.status);
+            match state {
+                State::Renamed => writeln!(stdout, "\t{}{} -> {}", label(state.to_string(), 12), quote_path(&change.old_path), quote_path(&change.path))?,
+                _ => writeln!(stdout, "\t{}{}", label(state.to_string(), 12), quote_path(&change.path))?,
+            }
+        }
+        writeln!(stdout)?;
+    }
+    if !unmerged.is_empty() {
+        writeln!(stdout, "Unmerged paths:")?;
+        writeln!(stdout, "  (use \"got add <file>...\" to mark resolution)")?;
+        for (path, stages) in &unmerged {
+            let how = match (stages.contains(&1), stages.contains(&2), stages.contains(&3)) {
+                (true, false, false) => "both deleted",
+                (false, true, false) => "added by us",
+                (true, false, true) => "deleted by us",
+                (false, false, true) => "added by them",
+                (true, true, false) => "deleted by them",
+                (false, true, true) => "both added",
+                _ => "both modified",
+            };
+            writeln!(stdout, "\t{}{}", label(how.to_string(), 17), quote_path(path))?;
+        }
+        writeln!(stdout)?;
+    }
+    if !unstaged.is_empty() {
+        writeln!(stdout, "Changes not staged for commit:")?;
+        // Adding a deleted path stages its deletion.
+        writeln!(stdout, "  (use \"got add <file>...\" to update what will be committed)")?;
+        writeln!(stdout, "  (use \"got restore <file>...\" to discard changes in working directory)")?;
+        for (path, state) in &unstaged {
+            writeln!(stdout, "\t{}{}", label(state.to_string(), 12), quote_path(path))?;
+        }
+        writeln!(stdout)?;
+    }
+    if !untracked.is_empty() {
+        writeln!(stdout, "Untracked files:")?;
+        writeln!(stdout, "  (use \"got add <file>...\" to include in what will be committed)")?;
+        let paths: Vec<String> = untracked.iter().map(|path| quote_path(path).into_owned()).collect();
+        write!(stdout, "{}", columns.format(&paths, "\t", 1))?;
+        writeln!(stdout)?;
+    }
+
+    if !staged.is_empty() {
+        // Something will be committed; nothing more to say.
+    } else if !unstaged.is_empty() || !unmerged.is_empty() {
+        writeln!(stdout, "no changes added to commit (use \"got add\" to stage)")?;
+    } else if !untracked.is_empty() {
+        writeln!(stdout, "nothing added to commit but untracked files present (use \"got add\" to track)")?;
+    } else if head_commit.is_none() {
+        writeln!(stdout, "nothing to commit (create/copy files and use \"got add\" to track)")?;
+    } else {
+        writeln!(stdout, "nothing to commit, working tree clean")?;
+    }
+    Ok(())
+}
+
+/// Prints how `branch`, at `commit`, stands against the upstream branch it
+/// tracks (`branch.<name>.remote` and `branch.<name>.merge`), if any.
+/// Returns whether it printed anything.
+fn print_tracking(out: &mut impl Write, config: &Config, branch: &str, commit: &Oid) -> anyhow::Result<bool> {
+    let git_dir = repository::git_dir();
+    let (Some(remote), Some(merge)) = (config.get(&format!("branch.{branch}.remote")), config.get(&format!("branch.{branch}.merge"))) else {
+        return Ok(false);
+    };
+    let (upstream_ref, upstream) = match (remote, merge.strip_prefix("refs/heads/")) {
+        (".", _) => (merge.to_string(), merge.strip_prefix("refs/heads/").unwrap_or(merge).to_string()),
+        (remote, Some(name)) => (format!("refs/remotes/{remote}/{name}"), format!("{remote}/{name}")),
+        (_, None) => return Ok(false),
+    };
+    let Some(upstream_commit) = refs::resolve(git_dir, &upstream_ref)? else {
+        writeln!(out, "Your branch is based on '{upstream}', but the upstream is gone.")?;
+        return Ok(true);
+    };
+    let count = |include: &Oid, exclude: &Oid| -> anyhow::Result<usize> {
+        let walk = RevWalk { include: vec![*include], exclude: vec![*exclude], ..RevWalk::default() };
+        Ok(walk.commits(git_dir)?.len())
+    };
+    let (ahead, behind) = (count(commit, &upstream_commit)?, count(&upstream_commit, commit)?);
+    let commits = |n: usize| if n == 1 { "1 commit".to_string() } else { format!("{n} commits") };
+    match (ahead, behind) {
+        (0, 0) => writeln!(out, "Your branch is up to date with '{upstream}'.")?,
+        (ahead, 0) => {
+            writeln!(out, "Your branch is ahead of '{upstream}' by {}.", commits(ahead))?;
+            writeln!(out, "  (use \"got push\" to publish your local commits)")?;
+        }
+        (0, behind) => {
+            writeln!(out, "Your branch is behind '{upstream}' by {}, and can be fast-forwarded.", commits(behind))?;
+            writeln!(out, "  (use \"got pull\" to update your local branch)")?;
+        }
+        (ahead, behind) => {
+            writeln!(out, "Your branch and '{upstream}' have diverged,")?;
+            writeln!(out, "and have {ahead} and {behind} different commits each, respectively.")?;
+            writeln!(out, "  (use \"got pull\" to merge the remote branch into yours)")?;
+        }
+    }
+    Ok(true)
+}
+
+/// Prints the summary of the changes from `start` to `end` (HEAD by
+/// default), published at `url`, that a pull request mail carries: where
+/// to fetch them, a shortlog and a diffstat. Returns false, after warning,
+/// if `url` is a local repository without `end` in it.
+fn request_pull(start: &str, url: &str, end: Option<&str>) -> anyhow::Result<bool> {
+    let git_dir = repository::git_dir();
+    let end = end.unwrap_or("HEAD");
+    let tip = revision::resolve(git_dir, end)?;
+    let head_commit = revision::peel(&tip, Some(Kind::Commit))?;
+    let start_commit = revision::resolve_commit(git_dir, start)?;
+    let base = revwalk::merge_base(git_dir, &start_commit, &head_commit)?
+        .with_context(|| format!("no commits in common between {start} and {end}"))?;
+    let branch = match end {
+        "HEAD" => refs::read(git_dir, "HEAD")?
+            .and_then(|head| head.strip_prefix("ref: refs/heads/").map(str::to_string))
+            .unwrap_or_else(|| "HEAD".to_string()),
+        name => name.strip_prefix("refs/heads/").or(name.strip_prefix("refs/tags/")).unwrap_or(name).to_string(),
+    };
+
+    let mut published = true;
+    if let std::result::Result::Ok(remote) = clone::find_git_dir(Path::new(url)) {
+        let mut found = false;
+        for (name, _) in refs::list(&remote)? {
+            found |= refs::resolve(&remote, &name)? == Some(tip);
+        }
+        if !found {
+            eprintln!("warn: No match for commit {head_commit} found at {url}");
+            eprintln!("warn: Are you sure you pushed '{branch}' there?");
+            published = false;
+        }
+    }
+
+    let describe = |hash: &Oid| -> anyhow::Result<String> {
+        let object = Object::read(hash)?;
+        let commit = Commit::parse(&object.contents)?;
+        let subject = commit.message.lines().next().unwrap_or_default().to_string();
+        Ok(format!("{subject} ({})", commit.committer.time.format("%Y-%m-%d %H:%M:%S %z")))
+    };
+    println!("The following changes since commit {base}:\n");
+    println!("  {}\n", describe(&base)?);
+    println!("are available in the Git repository at:\n");
+    println!("  {url} {branch}\n");
+    println!("for you to fetch changes up to {head_commit}:\n");
+    println!("  {}\n", describe(&head_commit)?);
+    println!("----------------------------------------------------------------");
+    let object = Object::read(&tip)?;
+    if object.kind == Kind::Tag {
+        let tag = got::object::Tag::parse(&object.contents)?;
+        println!("{}\n", tag.message.trim_end());
+        println!("----------------------------------------------------------------");
+    }
+
+    // The shortlog: subjects grouped by author, oldest first.
+    let walk = RevWalk { include: vec![head_commit], exclude: vec![base], ..RevWalk::default() };
+    let mut authors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for commit in walk.commits(git_dir)?.into_iter().rev() {
+        let mut subject = commit.message.lines().next().unwrap_or_default().trim();
+        while let Some((_, rest)) = subject.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+            subject = rest.trim_start();
+        }
+        authors.entry(commit.author.name.clone()).or_default().push(subject.to_string());
+    }
+    for (author, subjects) in &authors {
+        println!("{author} ({}):", subjects.len());
+        for subject in subjects {
+            println!("      {subject}");
+        }
+        println!();
+    }
+
+    let tree = |commit: &Oid| revision::peel(commit, Some(Kind::Tree));
+    let changes = tree_diff::detect_renames(tree_diff::diff_trees(Some(&tree(&base)?), Some(&tree(&head_commit)?))?)?;
+    let mut out = vec![];
+    got::diff::write_stat(&mut out, &changes, 80)?;
+    got::diff::write_summary(&mut out, &changes);
+    std::io::stdout().write_all(&out)?;
+    Ok(published)
+}
+
+fn am(resume: bool, skip: bool, abort: bool, mbox: Vec<String>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let state = got::am::State::load(git_dir)?;
+    if !resume && !skip && !abort {
+        anyhow::ensure!(
+            state.is_none(),
+            "previous rebase directory .got/rebase-apply still exists; use --continue, --skip or --abort"
+        );
+        let mut data = vec![];
+        if mbox.is_empty() {
+            std::io::stdin().read_to_end(&mut data).context("read patches from standard input")?;
+        }
+        for path in &mbox {
+            data.extend(fs::read(path).with_context(|| format!("read {path}"))?);
+        }
+        let mails = got::am::split_mbox(&data);
+        anyhow::ensure!(!mails.is_empty(), "patch is empty");
+        let head = revision::resolve_commit(git_dir, "HEAD")?;
+        anyhow::ensure!(
+            Index::read(git_dir)?.write_tree()? == revision::peel(&head, Some(Kind::Tree))?,
+            "dirty index: cannot apply patches"
+        );
+        refs::write(git_dir, "ORIG_HEAD", &head.to_string())?;
+        return am_run(got::am::State::start(git_dir, &mails, &head)?);
+    }
+    let mut state = state.context("resolve operation not in progress, we are not resuming")?;
+    if abort {
+        let orig_head = state.orig_head;
+        reset_to_head()?;
+        let head = revision::resolve_commit(git_dir, "HEAD")?;
+        if head != orig_head {
+            let update = HeadUpdate::prepare()?;
+            let mut index = Index::read(git_dir)?;
+            let tree = |commit: &Oid| revision::peel(commit, Some(Kind::Tree));
+            worktree::checkout(&mut index, Some(&tree(&head)?), &tree(&orig_head)?)?;
+            index.write(git_dir)?;
+            update.apply(&orig_head, "am --abort")?;
+        }
+        return state.remove();
+    }
+    if skip {
+        reset_to_head()?;
+    } else {
+        let mail = state.current()?;
+        println!("Applying: {}", mail.subject);
+        let index = Index::read(git_dir)?;
+        anyhow::ensure!(
+            index.entries().iter().all(|entry| entry.stage == 0),
+            "you still have unmerged paths in your index; did you forget to use 'got add'?"
+        );
+        let head = revision::resolve_commit(git_dir, "HEAD")?;
+        let tree = index.write_tree()?;
+        anyhow::ensure!(
+            tree != revision::peel(&head, Some(Kind::Tree))?,
+            "no changes - did you forget to use 'got add'?\n\
+             If there is nothing left to stage, chances are that something else\n\
+             already introduced the same changes; you might want to skip this patch."
+        );
+        am_commit(&mail, &tree, &head)?;
+    }
+    state.advance()?;
+    am_run(state)
+}
+
+/// Applies the rest of the series, stopping at the first patch that doesn't
+/// apply.
+fn am_run(mut state: got::am::State) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    while !state.is_done() {
+        let mail = state.current()?;
+        println!("Applying: {}", mail.subject);
+        let mut index = Index::read(git_dir)?;
+        let applied = got::apply::parse_patch(&mail.patch).and_then(|patches| {
+            anyhow::ensure!(!patches.is_empty(), "patch is empty");
+            got::apply::apply_to_index(&mut index, &patches)
+        });
+        if let Err(e) = applied {
+            for cause in e.chain().collect::<Vec<_>>().into_iter().rev() {
+                eprintln!("error: {cause}");
+            }
+            println!("Patch failed at {:04} {}", state.next, mail.subject);
+            println!("When you have resolved this problem, run \"got am --continue\".");
+            println!("If you prefer to skip this patch, run \"got am --skip\" instead.");
+            println!("To restore the original branch and stop patching, run \"got am --abort\".");
+            exit(EXIT_FAILED);
+        }
+        index.write(git_dir)?;
+        let head = revision::resolve_commit(git_dir, "HEAD")?;
+        am_commit(&mail, &index.write_tree()?, &head)?;
+        state.advance()?;
+    }
+    state.remove()
+}
+
+/// Commits `tree` on top of `head` as the mail's author and with its
+/// message.
+fn am_commit(mail: &got::am::Mail, tree: &Oid, head: &Oid) -> anyhow::Result<()> {
+    let commit = Commit {
+        tree: *tree,
+        parents: vec![*head],
+        author: mail.author.clone(),
+        committer: Signature::current(&Config::load()?, "committer")?,
+        extra_headers: vec![],
+        gpgsig: None,
+        message: mail.message(),
+    };
+    let hash = Object::store(Kind::Commit, &commit.serialize())?;
+    update_head(&hash, &format!("am: {}", mail.subject))
+}
+
+/// `cherry-pick` and `revert`: starts applying (or undoing) `commits` one
+/// after another, or with `resume`, `skip` or `abort` deals with the run
+/// that stopped at a conflict. Messages go through the editor when `edit`
+/// says so; by default only a revert's do, and only at a terminal.
+fn sequence(
+    action: Action,
+    no_commit: bool,
+    edit: Option<bool>,
+    resume: bool,
+    skip: bool,
+    abort: bool,
+    commits: Vec<String>,
+) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let command = action.command();
+    let sequencer = Sequencer::load(git_dir)?;
+    if !resume && !skip && !abort {
+        anyhow::ensure!(!commits.is_empty(), "empty commit set passed");
+        let head = revision::resolve_commit(git_dir, "HEAD")?;
+        // Without committing, the changes pile up on whatever is staged.
+        anyhow::ensure!(
+            no_commit || Index::read(git_dir)?.write_tree()? == revision::peel(&head, Some(Kind::Tree))?,
+            "your local changes would be overwritten by {command}.\nhint: commit your changes or stash them to proceed."
+        );
+        let mut todo = vec![];
+        for spec in &commits {
+            let commit = revision::resolve_commit(git_dir, spec)?;
+            let subject = revwalk::read_commit(&commit)?.message.lines().next().unwrap_or_default().to_string();
+            todo.push(Step { action, commit, subject });
+        }
+        let edit = edit.unwrap_or(action == Action::Revert && std::io::stdout().is_terminal());
+        return sequence_run(Sequencer::start(git_dir, todo, &head, no_commit, edit)?);
+    }
+    let mut sequencer = sequencer.context("no cherry-pick or revert in progress")?;
+    if abort {
+        let orig_head = sequencer.head;
+        reset_to_head()?;
+        let head = revision::resolve_commit(git_dir, "HEAD")?;
+        if head != orig_head {
+            let update = HeadUpdate::prepare()?;
+            let mut index = Index::read(git_dir)?;
+            let tree = |commit: &Oid| revision::peel(commit, Some(Kind::Tree));
+            worktree::checkout(&mut index, Some(&tree(&head)?), &tree(&orig_head)?)?;
+            index.write(git_dir)?;
+            update.apply(&orig_head, &format!("{command}: abort"))?;
+        }
+        return sequencer.remove();
+    }
+    if skip {
+        reset_to_head()?;
+    } else {
+        anyhow::ensure!(
+            Index::read(git_dir)?.entries().iter().all(|entry| entry.stage == 0),
+            "Committing is not possible because you have unmerged files.\n\
+             hint: Fix them up in the work tree, and then use 'got add -u'\n\
+             hint: to mark the resolution."
+        );
+        if let Some(step) = sequencer.todo.first().filter(|_| !sequencer.no_commit) {
+            let head = revision::resolve_commit(git_dir, "HEAD")?;
+            sequence_commit(step, &Index::read(git_dir)?.write_tree()?, &head, sequencer.edit)?;
+        }
+    }
+    sequencer.advance()?;
+    sequence_run(sequencer)
+}
+
+/// Applies the rest of the run, stopping at the first step that conflicts.
+fn sequence_run(mut sequencer: Sequencer) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    while let Some(step) = sequencer.todo.first().cloned() {
+        let commit = revwalk::read_commit(&step.commit)?;
+        anyhow::ensure!(commit.parents.len() <= 1, "commit {} is a merge, which cannot be picked or reverted", step.commit);
+        let parent = match commit.parents.first() {
+            Some(parent) => tree_files(&revwalk::read_commit(parent)?.tree)?,
+            None => BTreeMap::new(),
+        };
+        let label = format!("{} ({})", step.commit.abbrev(7), step.subject);
+        let (base, theirs, label) = match step.action {
+            Action::Pick => (parent, tree_files(&commit.tree)?, label),
+            Action::Revert => (tree_files(&commit.tree)?, parent, format!("parent of {label}")),
+        };
+        let mut index = Index::read(git_dir)?;
+        anyhow::ensure!(
+            index.entries().iter().all(|entry| entry.stage == 0),
+            "you need to resolve your current index first"
+        );
+        let resolutions = merge_changes(&index, &base, &theirs, "HEAD", &label)?;
+        let conflicts = write_resolutions(&mut index, resolutions)?;
+        index.write(git_dir)?;
+        if conflicts > 0 {
+            let command = step.action.command();
+            let verb = match step.action {
+                Action::Pick => "apply",
+                Action::Revert => "revert",
+            };
+            eprintln!("error: could not {verb} {}... {}", step.commit.abbrev(7), step.subject);
+            eprintln!("hint: After resolving the conflicts, mark them with");
+            eprintln!("hint: \"got add -u\", then run \"got {command} --continue\".");
+            eprintln!("hint: You can instead skip this commit with \"got {command} --skip\".");
+            eprintln!("hint: To abort and get back to the state before \"got {command}\",");
+            eprintln!("hint: run \"got {command} --abort\".");
+            exit(EXIT_FAILED);
+        }
+        if !sequencer.no_commit {
+            let head = revision::resolve_commit(git_dir, "HEAD")?;
+            sequence_commit(&step, &index.write_tree()?, &head, sequencer.edit)?;
+        }
+        sequencer.advance()?;
+    }
+    sequencer.remove()
+}
+
+/// `merge`: brings the history of `commit` into the current branch, by
+/// moving the branch up to it when it is ahead (unless `no_ff`), or else
+/// with a merge commit of the two on their merge base (refused with
+/// `ff_only`), whose `message` the user gets to change first with `edit`.
+/// Where the merge conflicts, the conflicts are left for the
+/// user to resolve and `commit` to finish, with `.got/MERGE_HEAD` naming
+/// the commit being merged and `.got/MERGE_MSG` holding the message;
+/// `abort` gives up on such a merge. Returns whether there were no
+/// conflicts.
+fn merge(no_ff: bool, ff_only: bool, message: Option<String>, edit: bool, abort: bool, commit: Option<String>) -> anyhow::Result<bool> {
+    let git_dir = repository::git_dir();
+    if abort {
+        anyhow::ensure!(merge_head(git_dir)?.is_some(), "There is no merge to abort (MERGE_HEAD missing).");
+        reset_to_head()?;
+        remove_merge_state(git_dir)?;
+        return Ok(true);
+    }
+    let spec = commit.context("usage: got merge [--no-ff | --ff-only] [-m <message> | -F <file>] [--edit | --no-edit] <commit>")?;
+    let mut index = Index::read(git_dir)?;
+    anyhow::ensure!(
+        index.entries().iter().all(|entry| entry.stage == 0),
+        "Merging is not possible because you have unmerged files.\n\
+         hint: Fix them up in the work tree, and then use 'got add <file>'\n\
+         hint: as appropriate to mark resolution and make a commit."
+    );
+    anyhow::ensure!(
+        merge_head(git_dir)?.is_none(),
+        "You have not concluded your merge (MERGE_HEAD exists).\nhint: Please, commit your changes before merging."
+    );
+    let theirs = revision::resolve_commit(git_dir, &spec)?;
+    let their_tree = revision::peel(&theirs, Some(Kind::Tree))?;
+    let Some(head) = refs::resolve(git_dir, "HEAD")? else {
+        // An unborn branch just starts at the merged commit.
+        let update = HeadUpdate::prepare()?;
+        worktree::checkout(&mut index, None, &their_tree)?;
+        index.write(git_dir)?;
+        update.apply(&theirs, &format!("merge {spec}: Fast-forward"))?;
+        return Ok(true);
+    };
+    let ancestry = revwalk::Ancestry::new(git_dir)?;
+    if ancestry.is_ancestor(&theirs, &head)? {
+        println!("Already up to date.");
+        return Ok(true);
+    }
+    let head_tree = revision::peel(&head, Some(Kind::Tree))?;
+    anyhow::ensure!(
+        index.write_tree()? == head_tree,
+        "your local changes would be overwritten by merge.\nhint: commit your changes or stash them to proceed."
+    );
+    refs::write(git_dir, "ORIG_HEAD", &head.to_string())?;
+    if !no_ff && ancestry.is_ancestor(&head, &theirs)? {
+        let update = HeadUpdate::prepare()?;
+        println!("Updating {}..{}", head.abbrev(7), theirs.abbrev(7));
+        println!("Fast-forward");
+        worktree::checkout(&mut index, Some(&head_tree), &their_tree)?;
+        index.write(git_dir)?;
+        update.apply(&theirs, &format!("merge {spec}: Fast-forward"))?;
+        print_diffstat(&head_tree, &their_tree)?;
+        return Ok(true);
+    }
+    anyhow::ensure!(!ff_only, "Not possible to fast-forward, aborting.");
+    let base = ancestry.merge_base(&head, &theirs)?.context("refusing to merge unrelated histories")?;
+    let base_files = tree_files(&revision::peel(&base, Some(Kind::Tree))?)?;
+    let resolutions = merge_changes(&index, &base_files, &tree_files(&their_tree)?, "HEAD", &spec)?;
+    let conflicts = write_resolutions(&mut index, resolutions)?;
+    index.write(git_dir)?;
+    let message = match message {
+        Some(message) => format!("{}\n", message.trim_end()),
+        None => merge_message(git_dir, &spec)?,
+    };
+    if conflicts > 0 {
+        let mut unmerged: Vec<&[u8]> = index.entries().iter().filter(|entry| entry.stage != 0).map(|entry| &entry.path[..]).collect();
+        unmerged.dedup();
+        let conflicts: Vec<u8> = unmerged.iter().flat_map(|path| [b"#\t", *path, b"\n"].concat()).collect();
+        fs::write(git_dir.join("MERGE_HEAD"), format!("{theirs}\n")).context("write .got/MERGE_HEAD")?;
+        fs::write(git_dir.join("MERGE_MSG"), [format!("{message}\n# Conflicts:\n").as_bytes(), &conflicts].concat())
+            .context("write .got/MERGE_MSG")?;
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+        return Ok(false);
+    }
+    let message = edit_message(&message, edit)?;
+    anyhow::ensure!(!message.is_empty(), "Not committing merge; use 'got commit' to complete the merge.");
+    let tree = index.write_tree()?;
+    let config = Config::load()?;
+    let commit = Commit {
+        tree,
+        parents: vec![head, theirs],
+        author: Signature::current(&config, "author")?,
+        committer: Signature::current(&config, "committer")?,
+        extra_headers: vec![],
+        gpgsig: None,
+        message,
+    };
+    let hash = Object::store(Kind::Commit, &commit.serialize())?;
+    update_head(&hash, &format!("merge {spec}: Merge made by the 'resolve' strategy."))?;
+    println!("Merge made by the 'resolve' strategy.");
+    print_diffstat(&head_tree, &tree)?;
+    Ok(true)
+}
+
+/// The default message for merging `spec`, naming what kind of ref it is
+/// and, off the main branches, the branch it is merged into.
+fn merge_message(git_dir: &Path, spec: &str) -> anyhow::Result<String> {
+    let what = if refs::resolve(git_dir, &format!("refs/heads/{spec}"))?.is_some() {
+        format!("branch '{spec}'")
+    } else if refs::resolve(git_dir, &format!("refs/tags/{spec}"))?.is_some() {
+        format!("tag '{spec}'")
+    } else if refs::resolve(git_dir, &format!("refs/remotes/{spec}"))?.is_some() {
+        format!("remote-tracking branch '{spec}'")
+    } else {
+        format!("commit '{spec}'")
+    };
+    let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    let into = match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) if branch != "master" && branch != "main" => format!(" into {branch}"),
+        _ => String::new(),
+    };
+    Ok(format!("Merge {what}{into}\n"))
+}
+
+/// The commit a merge that stopped at conflicts is merging, if there is
+/// one.
+fn merge_head(git_dir: &Path) -> anyhow::Result<Option<Oid>> {
+    match fs::read_to_string(git_dir.join("MERGE_HEAD")) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        result => Ok(Some(result.context("read .got/MERGE_HEAD")?.trim().parse().context("corrupt .got/MERGE_HEAD")?)),
+    }
+}
+
+/// Forgets a merge that stopped at conflicts.
+fn remove_merge_state(git_dir: &Path) -> anyhow::Result<()> {
+    for name in ["MERGE_HEAD", "MERGE_MSG"] {
+        match fs::remove_file(git_dir.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e).with_context(|| format!("remove .got/{name}")),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Prints the diffstat and summary of the changes from tree `old` to
+/// `new`, as a merge reports them.
+fn print_diffstat(old: &Oid, new: &Oid) -> anyhow::Result<()> {
+    let mut changes = tree_diff::diff_trees(Some(old), Some(new))?;
+    if rename_detection(&Config::load()?)?.0 {
+        changes = tree_diff::detect_renames(changes)?;
+    }
+    let mut out = vec![];
+    got::diff::write_stat(&mut out, &changes, column::terminal_width())?;
+    got::diff::write_summary(&mut out, &changes);
+    std::io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+/// Commits `tree` on top of `head` for `step`: a pick keeps the picked
+/// commit's author and message, a revert says what it undoes. With `edit`
+/// the user gets to change the message first.
+fn sequence_commit(step: &Step, tree: &Oid, head: &Oid, edit: bool) -> anyhow::Result<()> {
+    let command = step.action.command();
+    anyhow::ensure!(
+        *tree != revision::peel(head, Some(Kind::Tree))?,
+        "the previous {command} is now empty, possibly due to conflict resolution.\n\
+         hint: use \"got {command} --skip\" to go on without it"
+    );
+    let config = Config::load()?;
+    let original = revwalk::read_commit(&step.commit)?;
+    let (author, message) = match step.action {
+        Action::Pick => (original.author, original.message),
+        Action::Revert => (
+            Signature::current(&config, "author")?,
+            format!("Revert \"{}\"\n\nThis reverts commit {}.\n", step.subject, step.commit),
+        ),
+    };
+    let message = match edit {
+        true => edit_message(&message, true)?,
+        false => message,
+    };
+    anyhow::ensure!(!message.is_empty(), "aborting commit due to empty commit message");
+    let commit = Commit {
+        tree: *tree,
+        parents: vec![*head],
+        author,
+        committer: Signature::current(&config, "committer")?,
+        extra_headers: vec![],
+        gpgsig: None,
+        message,
+    };
+    let hash = Object::store(Kind::Commit, &commit.serialize())?;
+    update_head(&hash, &format!("{command}: {}", step.subject))?;
+    let head = refs::read(repository::git_dir(), "HEAD")?.unwrap_or_default();
+    let branch = head.strip_prefix("ref: refs/heads/").unwrap_or("detached HEAD");
+    println!("[{branch} {}] {}", hash.abbrev(7), commit.message.lines().next().unwrap_or_default());
+    Ok(())
+}
+
+/// Puts every path whose index entry differs from HEAD back to HEAD's
+/// version, in the index and the worktree, resolving any conflicts.
+fn reset_to_head() -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let head = tree_files(&revision::peel(&revision::resolve_commit(git_dir, "HEAD")?, Some(Kind::Tree))?)?;
+    let mut index = Index::read(git_dir)?;
+    let mut paths: BTreeSet<Vec<u8>> = head.keys().cloned().collect();
+    paths.extend(index.entries().iter().map(|entry| entry.path.clone()));
+    for path in paths {
+        let staged = index.get(&path).map(|entry| (entry.mode, entry.hash));
+        let conflicted = index.entries().iter().any(|entry| entry.path == path && entry.stage != 0);
+        if staged.as_ref() == head.get(&path) && !conflicted {
+            continue;
+        }
+        match head.get(&path) {
+            None => {
+                worktree::remove(&path)?;
+                index.remove(&path);
+            }
+            Some(&(Mode::Submodule, hash)) => index.insert(index::Entry::new(&path, Mode::Submodule, hash, index::Stat::default())),
+            Some(&(mode, hash)) => {
+                index::write_worktree_file(&path, mode, &Object::read(&hash)?.contents)?;
+                let meta = fs::symlink_metadata(path::to_path(&path)).with_context(|| format!("stat {}", path::display(&path)))?;
+                index.insert(index::Entry::new(&path, mode, hash, index::Stat::from_metadata(&meta)));
+            }
+        }
+    }
+    index.write(git_dir)
+}
+
+/// The URL configured for `remote`.
+fn remote_url(config: &Config, remote: &str) -> anyhow::Result<String> {
+    let url = config
+        .get(&format!("remote.{remote}.url"))
+        .with_context(|| format!("'{remote}' does not appear to be a got repository"))?;
+    Ok(url.to_string())
+}
+
+/// A ref name as fetch and push report it: without `refs/heads/`,
+/// `refs/tags/` or `refs/remotes/`.
+fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// How an update of remote ref `name` from `old` to `new` is reported: the
+/// flag, the summary and a note on why, or None if the update may not be
+/// made without `force`.
+fn describe_update(ancestry: &revwalk::Ancestry, name: &str, old: Option<Oid>, new: &Oid, force: bool) -> anyhow::Result<Option<(char, String, &'static str)>> {
+    let Some(old) = old else {
+        let kind = match name {
+            name if name.starts_with("refs/heads/") => "[new branch]",
+            name if name.starts_with("refs/tags/") => "[new tag]",
+            _ => "[new ref]",
+        };
+        return Ok(Some(('*', kind.to_string(), "")));
+    };
+    if ancestry.is_ancestor(&old, new)? {
+        return Ok(Some((' ', format!("{}..{}", old.abbrev(7), new.abbrev(7)), "")));
+    }
+    if !force {
+        return Ok(None);
+    }
+    Ok(Some(('+', format!("{}...{}", old.abbrev(7), new.abbrev(7)), "  (forced update)")))
+}
+
+/// How `fetch` and `push` reach a remote.
+enum Connection {
+    Helper(RemoteHelper),
+    Http(Box<SmartHttp>),
+}
+
+/// Fetches from `remote` (by default `origin`) over smart HTTP or through
+/// its remote helper, updating the refs its fetch refspecs map the remote's
+/// refs to. With `unshallow` the rest of a shallow repository's history is
+/// fetched too. Returns whether every update could be made.
+fn fetch(unshallow: bool, remote: Option<&str>) -> anyhow::Result<bool> {
+    let (ok, report) = fetch_from(unshallow, remote.unwrap_or("origin"))?;
+    print!("{report}");
+    Ok(ok)
+}
+
+/// `fetch --all`: fetches from every remote with a URL, `jobs` at a time
+/// (`fetch.parallel` if not given, 1 unless set; 0 means one for each
+/// CPU). Each remote's report is printed whole once its fetch is done, so
+/// that the reports of remotes fetched together don't run into each other.
+/// Returns whether every fetch went through and every update could be made.
+fn fetch_all(unshallow: bool, jobs: Option<usize>) -> anyhow::Result<bool> {
+    let config = Config::load()?;
+    let remotes: Vec<&str> = config
+        .subsections("remote")
+        .into_iter()
+        .filter(|remote| config.get(&format!("remote.{remote}.url")).is_some())
+        .collect();
+    let jobs = match jobs {
+        Some(jobs) => jobs,
+        None => match config.get_int("fetch.parallel")? {
+            Some(jobs) => usize::try_from(jobs).context("fetch.parallel must not be negative")?,
+            None => 1,
+        },
+    };
+    let jobs = match jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let ok = std::sync::atomic::AtomicBool::new(true);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(remotes.len()) {
+            scope.spawn(|| {
+                while let Some(remote) = remotes.get(next.fetch_add(1, std::sync::atomic::Ordering::Relaxed)) {
+                    let result = fetch_from(unshallow, remote);
+                    let mut stdout = std::io::stdout().lock();
+                    let _ = writeln!(stdout, "Fetching {remote}");
+                    let fetched = match result {
+                        std::result::Result::Ok((fetched, report)) => {
+                            let _ = stdout.write_all(report.as_bytes());
+                            fetched
+                        }
+                        Err(e) => {
+                            eprintln!("error: could not fetch {remote}: {e:#}");
+                            false
+                        }
+                    };
+                    let _ = stdout.flush();
+                    if !fetched {
+                        ok.store(false, std::sync::atomic::Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+    Ok(ok.into_inner())
+}
+
+/// Fetches from `remote` as [`fetch`] does, returning whether every update
+/// could be made and the report of the updates to print.
+fn fetch_from(unshallow: bool, remote: &str) -> anyhow::Result<(bool, String)> {
+    let git_dir = repository::git_dir();
+    if unshallow {
+        anyhow::ensure!(
+            shallow::is_shallow(git_dir)?,
+            "--unshallow on a complete repository does not make sense"
+        );
+    }
+    let config = Config::load()?;
+    let url = remote_url(&config, remote)?;
+    let mut connection = match remote_helper::helper_for(&url) {
+        Some((transport, address)) => {
+            anyhow::ensure!(!unshallow, "--unshallow is not supported by remote helpers");
+            Connection::Helper(RemoteHelper::spawn(transport, remote, address, git_dir)?)
+        }
+        None if url.starts_with("http://") || url.starts_with("https://") => Connection::Http(Box::new(SmartHttp::new(&config, &url)?)),
+        None => anyhow::bail!("fetching from '{url}' is not supported yet"),
+    };
+    let specs = config
+        .get_all(&format!("remote.{remote}.fetch"))
+        .into_iter()
+        .map(Refspec::from_str)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let listed = match &mut connection {
+        Connection::Helper(helper) => helper.list(false)?,
+        Connection::Http(http) => http.list(&config, false)?,
+    };
+    // Symbolic refs take the value of the ref they point to.
+    let hashes: HashMap<&str, Oid> = listed
+        .iter()
+        .filter_map(|r| match r.value {
+            RefValue::Hash(hash) => Some((r.name.as_str(), hash)),
+            _ => None,
+        })
+        .collect();
+    let mut updates: Vec<(&str, String, Oid, bool)> = vec![];
+    for listed_ref in &listed {
+        let hash = match &listed_ref.value {
+            RefValue::Hash(hash) => *hash,
+            RefValue::Symref(target) => match hashes.get(target.as_str()) {
+                Some(hash) => *hash,
+                None => continue,
+            },
+            RefValue::Unknown => continue,
+        };
+        if let Some((spec, dst)) = specs.iter().find_map(|spec| Some((spec, spec.map(&listed_ref.name)?))) {
+            updates.push((&listed_ref.name, dst, hash, spec.force));
+        }
+    }
+    // Deepening needs the tips asked for even though they are here.
+    let mut wanted: Vec<(Oid, String)> = vec![];
+    for (name, _, hash, _) in &updates {
+        if (unshallow || Object::read(hash).is_err()) && !wanted.iter().any(|(wanted, _)| wanted == hash) {
+            wanted.push((*hash, name.to_string()));
+        }
+    }
+    // The packs received stay kept until the refs below point into them.
+    let _locks = match &mut connection {
+        _ if wanted.is_empty() => PackLocks::default(),
+        Connection::Helper(helper) => helper.fetch(&wanted)?,
+        Connection::Http(http) => {
+            let wants: Vec<Oid> = wanted.iter().map(|(hash, _)| *hash).collect();
+            http.fetch(&config, git_dir, &wants, unshallow, false)?
+        }
+    };
+    if let Connection::Helper(helper) = connection {
+        helper.finish()?;
+    }
+
+    // Refuse to point refs at history that didn't fully arrive.
+    let tips: Vec<Oid> = updates.iter().map(|(_, _, hash, _)| *hash).collect();
+    let mut known = vec![];
+    for (name, _) in refs::list(git_dir)? {
+        known.extend(refs::resolve(git_dir, &name)?);
+    }
+    let skip = got::verify::skip_list(&config, &["fetch.fsck.skipList", "fsck.skipList"])?;
+    let problems = got::verify::check_connectivity(git_dir, &tips, &known, &skip)?;
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("error: {problem}");
+        }
+        anyhow::bail!("{url} did not send all necessary objects");
+    }
+
+    let ancestry = revwalk::Ancestry::new(git_dir)?;
+    let width = updates.iter().map(|(name, ..)| short_ref_name(name).len()).max().unwrap_or(0);
+    let mut lines = vec![];
+    let mut ok = true;
+    for (name, dst, hash, force) in &updates {
+        let old = refs::resolve(git_dir, dst)?;
+        if old == Some(*hash) {
+            continue;
+        }
+        let (from, to) = (short_ref_name(name), short_ref_name(dst));
+        match describe_update(&ancestry, name, old, hash, *force)? {
+            Some((flag, summary, note)) => {
+                refs::write(git_dir, dst, &hash.to_string())?;
+                lines.push(format!(" {flag} {summary:<17} {from:<width$} -> {to}{note}"));
+            }
+            None => {
+                lines.push(format!(" ! {:<17} {from:<width$} -> {to}  (non-fast-forward)", "[rejected]"));
+                ok = false;
+            }
+        }
+    }
+    let mut report = String::new();
+    if !lines.is_empty() {
+        report.push_str(&format!("From {url}\n"));
+        for line in lines {
+            report.push_str(&format!("{line}\n"));
+        }
+    }
+    Ok((ok, report))
+}
+
+/// `pull`: fetches from `remote` and brings `branch` of it into the current
+/// branch, by default the remote and branch the current branch tracks
+/// (`branch.<name>.remote` and `branch.<name>.merge`). The remote-tracking
+/// branch is merged, fast-forwarding when it is ahead (required with
+/// `ff_only`), or with `rebase` (or `branch.<name>.rebase` or `pull.rebase`
+/// set, unless `no_rebase`) the current branch's own commits are replayed on
+/// top of it. Returns whether the fetch and the merge or rebase went through.
+fn pull(rebase: bool, no_rebase: bool, ff_only: bool, remote: Option<String>, branch: Option<String>) -> anyhow::Result<bool> {
+    let git_dir = repository::git_dir();
+    let config = Config::load()?;
+    let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    let current = head.strip_prefix("ref: refs/heads/").context("You are not currently on a branch.")?;
+    let rebase = rebase
+        || (!no_rebase
+            && match config.get_bool(&format!("branch.{current}.rebase"))? {
+                Some(rebase) => rebase,
+                None => config.get_bool("pull.rebase")?.unwrap_or(false),
+            });
+    let remote = match remote {
+        Some(remote) => remote,
+        None => config.get(&format!("branch.{current}.remote")).unwrap_or("origin").to_string(),
+    };
+    let merge_ref = match branch {
+        Some(branch) if branch.starts_with("refs/") => branch,
+        Some(branch) => format!("refs/heads/{branch}"),
+        None => config.get(&format!("branch.{current}.merge")).map(str::to_string).with_context(|| {
+            format!(
+                "There is no tracking information for the current branch.
+                 hint: Please specify which branch you want to merge with, or set it with
+                 hint: got config branch.{current}.merge refs/heads/<branch>"
+            )
+        })?,
+    };
+    if !fetch(false, Some(&remote))? {
+        return Ok(false);
+    }
+    // What was fetched is merged from where the fetch put it.
+    let tracking = config
+        .get_all(&format!("remote.{remote}.fetch"))
+        .into_iter()
+        .map(Refspec::from_str)
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .iter()
+        .find_map(|spec| spec.map(&merge_ref))
+        .with_context(|| format!("no fetch refspec of '{remote}' covers {merge_ref}"))?;
+    let upstream = refs::resolve(git_dir, &tracking)?.with_context(|| format!("couldn't find remote ref {merge_ref}"))?;
+    if rebase {
+        return rebase_onto(&upstream, &tracking);
+    }
+    let url = remote_url(&config, &remote)?;
+    let message = format!("Merge branch '{}' of {url}", short_ref_name(&merge_ref));
+    merge(false, ff_only, Some(message), false, false, Some(upstream.to_string()))
+}
+
+/// Replays the commits of the current branch that `upstream` (named
+/// `name`) lacks on top of it, oldest first and leaving out merges, as
+/// cherry-picks. A conflict stops the replay for `cherry-pick --continue`
+/// to finish, or `cherry-pick --abort` to go back to where the branch was.
+fn rebase_onto(upstream: &Oid, name: &str) -> anyhow::Result<bool> {
+    let git_dir = repository::git_dir();
+    let head = revision::resolve_commit(git_dir, "HEAD")?;
+    let head_tree = revision::peel(&head, Some(Kind::Tree))?;
+    let mut index = Index::read(git_dir)?;
+    anyhow::ensure!(
+        index.entries().iter().all(|entry| entry.stage == 0) && index.write_tree()? == head_tree,
+        "cannot pull with rebase: You have unstaged changes.
+hint: commit your changes or stash them to proceed."
+    );
+    let ancestry = revwalk::Ancestry::new(git_dir)?;
+    if ancestry.is_ancestor(upstream, &head)? {
+        println!("Current branch is up to date.");
+        return Ok(true);
+    }
+    let walk = RevWalk {
+        include: vec![head],
+        exclude: vec![*upstream],
+        max_parents: Some(1),
+        order: revwalk::Order::Topo,
+        reverse: true,
+        ..RevWalk::default()
+    };
+    let mut todo = vec![];
+    for commit in walk.commits(git_dir)? {
+        let subject = commit.message.lines().next().unwrap_or_default().to_string();
+        todo.push(Step { action: Action::Pick, commit: commit.hash, subject });
+    }
+    refs::write(git_dir, "ORIG_HEAD", &head.to_string())?;
+    let update = HeadUpdate::prepare()?;
+    worktree::checkout(&mut index, Some(&head_tree), &revision::peel(upstream, Some(Kind::Tree))?)?;
+    index.write(git_dir)?;
+    update.apply(upstream, &format!("pull --rebase: checkout {name}"))?;
+    sequence_run(Sequencer::start(git_dir, todo, &head, false, false)?)?;
+    let branch = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    println!("Successfully rebased and updated {}.", branch.strip_prefix("ref: ").unwrap_or("HEAD"));
+    Ok(true)
+}
+
+/// Pushes `refspecs` (by default the current branch to the branch of the
+/// same name) to `remote` (by default `origin`) over smart HTTP or through
+/// its remote helper.
+/// Forced updates and deletions of protected branches are refused unless
+/// `force_protected`. Returns whether the remote took every update.
+fn push(force: bool, force_protected: bool, remote: Option<&str>, refspecs: &[String]) -> anyhow::Result<bool> {
+    let git_dir = repository::git_dir();
+    let remote = remote.unwrap_or("origin");
+    let config = Config::load()?;
+    let url = remote_url(&config, remote)?;
+    let mut connection = match remote_helper::helper_for(&url) {
+        Some((transport, address)) => Connection::Helper(RemoteHelper::spawn(transport, remote, address, git_dir)?),
+        None if url.starts_with("http://") || url.starts_with("https://") => Connection::Http(Box::new(SmartHttp::new(&config, &url)?)),
+        None => anyhow::bail!("pushing to '{url}' is not supported yet"),
+    };
+    let mut specs = vec![];
+    if refspecs.is_empty() {
+        let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+        let branch = head.strip_prefix("ref: ").context("You are not currently on a branch.")?;
+        let plus = if force { "+" } else { "" };
+        specs.push(Refspec::from_str(&format!("{plus}{branch}:{branch}"))?);
+    }
+    for spec in refspecs {
+        let mut spec = Refspec::from_str(spec)?;
+        anyhow::ensure!(!spec.src.contains('*'), "pushing refspec patterns is not supported yet: '{spec}'");
+        // Short names are looked up as branches and then tags, and name the
+        // same kind of ref on the remote.
+        if !spec.src.is_empty() && !spec.src.starts_with("refs/") {
+            let full = ["refs/heads/", "refs/tags/"]
+                .iter()
+                .map(|prefix| format!("{prefix}{}", spec.src))
+                .find(|name| refs::read(git_dir, name).is_ok_and(|value| value.is_some()))
+                .with_context(|| format!("src refspec {} does not match any", spec.src))?;
+            let dst = spec.dst.take().unwrap_or_else(|| spec.src.clone());
+            spec.dst = Some(match dst.starts_with("refs/") {
+                true => dst,
+                false => format!("{}{dst}", &full[..full.len() - spec.src.len()]),
+            });
+            spec.src = full;
+        } else if let Some(dst) = spec.dst.as_mut().filter(|dst| spec.src.is_empty() && !dst.starts_with("refs/")) {
+            *dst = format!("refs/heads/{dst}");
+        } else if spec.dst.is_none() {
+            spec.dst = Some(spec.src.clone());
+        }
+        spec.force |= force;
+        specs.push(spec);
+    }
+
+    let listed = match &mut connection {
+        Connection::Helper(helper) => helper.list(true)?,
+        Connection::Http(http) => http.list(&config, true)?,
+    };
+    let remote_refs: HashMap<String, Oid> = listed
+        .into_iter()
+        .filter_map(|r| match r.value {
+            RefValue::Hash(hash) => Some((r.name, hash)),
+            _ => None,
+        })
+        .collect();
+    // What to report for each refspec, and the updates to send.
+    let ancestry = revwalk::Ancestry::new(git_dir)?;
+    let mut lines: Vec<(String, String)> = vec![];
+    let mut sent: Vec<(Refspec, Option<Oid>)> = vec![];
+    let mut ok = true;
+    let is_protected = |dst: &str| match dst.strip_prefix("refs/heads/") {
+        Some(branch) => check_unprotected(&config, branch, force_protected).is_err(),
+        None => false,
+    };
+    for spec in specs {
+        let dst = spec.dst.clone().unwrap_or_else(|| spec.src.clone());
+        let (from, to) = (short_ref_name(&spec.src), short_ref_name(&dst));
+        if spec.src.is_empty() {
+            if !remote_refs.contains_key(&dst) {
+                eprintln!("error: unable to delete '{to}': remote ref does not exist");
+                ok = false;
+                continue;
+            }
+            if is_protected(&dst) {
+                lines.push((dst.clone(), format!(" ! {:<17} {to} (protected branch)", "[rejected]")));
+                ok = false;
+                continue;
+            }
+            lines.push((dst.clone(), format!(" - {:<17} {to}", "[deleted]")));
+            sent.push((spec, None));
+            continue;
+        }
+        let new = refs::resolve(git_dir, &spec.src)?.with_context(|| format!("src refspec {} does not match any", spec.src))?;
+        let old = remote_refs.get(&dst).copied();
+        let line = if old == Some(new) {
+            format!(" = {:<17} {from} -> {to}", "[up to date]")
+        } else if old.is_some_and(|old| Object::read(&old).is_err()) && !spec.force {
+            ok = false;
+            format!(" ! {:<17} {from} -> {to} (fetch first)", "[rejected]")
+        } else {
+            // Overwriting what we don't have can lose history as surely as
+            // a forced update.
+            let known_old = old.filter(|old| Object::read(old).is_ok());
+            match describe_update(&ancestry, &dst, known_old, &new, spec.force)? {
+                Some((flag, _, _)) if (flag == '+' || old != known_old) && is_protected(&dst) => {
+                    ok = false;
+                    format!(" ! {:<17} {from} -> {to} (protected branch)", "[rejected]")
+                }
+                Some((flag, summary, note)) => {
+                    lines.push((dst.clone(), format!(" {flag} {summary:<17} {from} -> {to}{note}")));
+                    sent.push((spec, Some(new)));
+                    continue;
+                }
+                None => {
+                    ok = false;
+                    format!(" ! {:<17} {from} -> {to} (non-fast-forward)", "[rejected]")
+                }
+            }
+        };
+        lines.push((dst, line));
+    }
+    let statuses = match &mut connection {
+        _ if sent.is_empty() => vec![],
+        Connection::Helper(helper) => helper.push(&sent.iter().map(|(spec, _)| spec.clone()).collect::<Vec<_>>())?,
+        Connection::Http(http) => {
+            let updates: Vec<(String, Option<Oid>, Option<Oid>)> = sent
+                .iter()
+                .map(|(spec, new)| {
+                    let dst = spec.dst.clone().unwrap_or_else(|| spec.src.clone());
+                    let old = remote_refs.get(&dst).copied();
+                    (dst, old, *new)
+                })
+                .collect();
+            http.push(&config, git_dir, &updates)?
+        }
+    };
+    if let Connection::Helper(helper) = connection {
+        helper.finish()?;
+    }
+
+    let tracking = config
+        .get_all(&format!("remote.{remote}.fetch"))
+        .into_iter()
+        .map(Refspec::from_str)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for (spec, new) in &sent {
+        let dst = spec.dst.as_deref().unwrap_or(&spec.src);
+        let status = statuses.iter().find(|status| status.dst == dst);
+        if let Some(why) = status.map_or(Some("no status reported"), |status| status.error.as_deref()) {
+            let (from, to) = (short_ref_name(&spec.src), short_ref_name(dst));
+            if let Some((_, line)) = lines.iter_mut().find(|(name, _)| name == dst) {
+                *line = format!(" ! [remote rejected] {from} -> {to} ({why})");
+            }
+            ok = false;
+            continue;
+        }
+        let Some(tracking_ref) = tracking.iter().find_map(|tracking| tracking.map(dst)) else {
+            continue;
+        };
+        match new {
+            Some(new) => refs::write(git_dir, &tracking_ref, &new.to_string())?,
+            None => {
+                fs::remove_file(git_dir.join(&tracking_ref)).ok();
+            }
+        }
+    }
+    if !lines.is_empty() {
+        println!("To {url}");
+        for (_, line) in lines {
+            println!("{line}");
+        }
+    }
+    if !ok {
+        eprintln!("error: failed to push some refs to '{url}'");
+    }
+    Ok(ok)
+}
+
+/// Clones `repository` and, unless the clone is bare, checks out its HEAD
+/// and with `recurse_submodules` every submodule below it.
+fn clone_repository(options: &CloneOptions, recurse_submodules: bool, repository: &str, directory: Option<&str>) -> anyhow::Result<()> {
+    let directory = clone::clone(repository, directory, options)?;
+    if options.bare || options.mirror {
+        return Ok(());
+    }
+    in_directory(&directory, || {
+        let git_dir = repository::git_dir();
+        if let Some(head) = refs::resolve(git_dir, "HEAD")? {
+            let mut index = Index::new();
+            worktree::checkout(&mut index, None, &revision::peel(&head, Some(Kind::Tree))?)?;
+            index.write(git_dir)?;
+        }
+        if recurse_submodules {
+            submodule_update(true, true, "")?;
+        }
+        Ok(())
+    })
+}
+
+#[derive(Debug, Subcommand)]
+enum SubmoduleCommand {
+    Update {
+        #[clap(long)]
+        init: bool,
+        #[clap(long)]
+        recursive: bool,
+    },
+    Foreach {
+        #[clap(long)]
+        recursive: bool,
+        #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
+
+/// Checks out every initialized submodule at the commit the index records,
+/// cloning any not cloned yet. `init` first initializes the rest, and
+/// `recursive` does the same inside each submodule. `prefix` is where this
+/// repository sits in the outermost one, for messages.
+fn submodule_update(init: bool, recursive: bool, prefix: &str) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let index = Index::read(git_dir)?;
+    let modules = submodule::read_gitmodules()?;
+    let config = Config::load()?;
+    let base = match config.get("remote.origin.url") {
+        Some(url) => url.to_string(),
+        None => fs::canonicalize(".")?.display().to_string(),
+    };
+    for entry in index.entries().iter().filter(|entry| entry.mode == Mode::Submodule && entry.stage == 0) {
+        let module = modules
+            .iter()
+            .find(|module| module.path.as_bytes() == entry.path)
+            .with_context(|| format!("no submodule mapping found in .gitmodules for path '{}'", path::display(&entry.path)))?;
+        let path = format!("{prefix}{}", module.path);
+        let key = format!("submodule.{}.url", module.name);
+        let url = match config.get(&key) {
+            Some(url) => url.to_string(),
+            None if init => {
+                let url = submodule::resolve_url(&module.url, &base);
+                got::config::set_value(&git_dir.join("config"), &key, &url)?;
+                println!("Submodule '{}' ({url}) registered for path '{path}'", module.name);
+                url
+            }
+            None => continue,
+        };
+        if !submodule::is_populated(&entry.path) {
+            clone::clone(&url, Some(&module.path), &CloneOptions::default())?;
+        }
+        in_directory(Path::new(&module.path), || {
+            if checkout_detached(&entry.hash)? {
+                println!("Submodule path '{path}': checked out '{}'", entry.hash);
+            }
+            if recursive {
+                submodule_update(init, recursive, &format!("{path}/"))?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Runs `command` in each cloned submodule, with `$name`, `$sm_path`,
+/// `$displaypath`, `$sha1` and `$toplevel` describing it. A single argument
+/// is a shell command; several are a program and its arguments.
+fn submodule_foreach(recursive: bool, command: &[String], prefix: &str) -> anyhow::Result<()> {
+    let index = Index::read(repository::git_dir())?;
+    let modules = submodule::read_gitmodules()?;
+    let toplevel = std::env::current_dir()?;
+    for entry in index.entries().iter().filter(|entry| entry.mode == Mode::Submodule && entry.stage == 0) {
+        if !submodule::is_populated(&entry.path) {
+            continue;
+        }
+        let sm_path = path::display(&entry.path);
+        let name = modules
+            .iter()
+            .find(|module| module.path.as_bytes() == entry.path)
+            .map_or(&*sm_path, |module| module.name.as_str());
+        let path = format!("{prefix}{sm_path}");
+        println!("Entering '{path}'");
+        std::io::stdout().flush()?;
+        let mut process = match command {
+            [script] => {
+                let mut process = std::process::Command::new("sh");
+                process.arg("-c").arg(script);
+                process
+            }
+            _ => {
+                let mut process = std::process::Command::new(&command[0]);
+                process.args(&command[1..]);
+                process
+            }
+        };
+        let status = process
+            .current_dir(path::to_path(&entry.path))
+            .env("name", name)
+            .env("sm_path", path::to_path(&entry.path))
+            .env("displaypath", &path)
+            .env("sha1", entry.hash.to_string())
+            .env("toplevel", &toplevel)
+            .status()
+            .with_context(|| format!("run '{}' in {path}", command.join(" ")))?;
+        anyhow::ensure!(status.success(), "Stopping at '{path}'; script returned non-zero status.");
+        if recursive {
+            in_directory(path::to_path(&entry.path), || submodule_foreach(recursive, command, &format!("{path}/")))?;
+        }
+    }
+    Ok(())
+}
+
+/// `branch -d`: deletes each of `names`, refusing the current branch,
+/// protected branches unless `force_protected`, and (unless `force`)
+/// branches not merged into HEAD. Returns whether all were deleted.
+fn delete_branches(names: &[String], force: bool, force_protected: bool) -> anyhow::Result<bool> {
+    anyhow::ensure!(!names.is_empty(), "branch name required");
+    let git_dir = repository::git_dir();
+    let config = Config::load()?;
+    let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    let head_commit = refs::resolve(git_dir, "HEAD")?;
+    let ancestry = revwalk::Ancestry::new(git_dir)?;
+    let mut ok = true;
+    for name in names {
+        let full = format!("refs/heads/{name}");
+        let Some(commit) = refs::resolve(git_dir, &full)? else {
+            eprintln!("error: branch '{name}' not found");
+            ok = false;
+            continue;
+        };
+        if head.strip_prefix("ref: ") == Some(full.as_str()) {
+            eprintln!("error: cannot delete branch '{name}', which you are currently on");
+            ok = false;
+            continue;
+        }
+        if let Err(e) = check_unprotected(&config, name, force_protected) {
+            eprintln!("error: {e}");
+            ok = false;
+            continue;
+        }
+        let merged = match head_commit {
+            Some(head) => ancestry.is_ancestor(&commit, &head)?,
+            None => false,
+        };
+        if !force && !merged {
+            eprintln!("error: the branch '{name}' is not fully merged");
+            eprintln!("hint: If you are sure you want to delete it, run 'got branch -D {name}'");
+            ok = false;
+            continue;
+        }
+        fs::remove_file(git_dir.join(&full)).with_context(|| format!("delete ref {full}"))?;
+        match fs::remove_file(git_dir.join("logs").join(&full)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e).with_context(|| format!("delete reflog of {full}")),
+            _ => {}
+        }
+        println!("Deleted branch {name} (was {}).", commit.abbrev(7));
+    }
+    Ok(ok)
+}
+
+/// Which refs branch and tag listing show: those whose commit contains
+/// `contains`, and is (or for `no_merged`, isn't) reachable from `merged`.
+struct RefFilter {
+    ancestry: revwalk::Ancestry,
+    contains: Option<Oid>,
+    merged: Option<Oid>,
+    no_merged: Option<Oid>,
+}
+
+impl RefFilter {
+    fn new(contains: Option<String>, merged: Option<String>, no_merged: Option<String>) -> anyhow::Result<RefFilter> {
+        let git_dir = repository::git_dir();
+        let resolve = |spec: Option<String>| spec.map(|spec| revision::resolve_commit(git_dir, &spec)).transpose();
+        Ok(RefFilter {
+            ancestry: revwalk::Ancestry::new(git_dir)?,
+            contains: resolve(contains)?,
+            merged: resolve(merged)?,
+            no_merged: resolve(no_merged)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.contains.is_none() && self.merged.is_none() && self.no_merged.is_none()
+    }
+
+    fn matches(&self, commit: &Oid) -> anyhow::Result<bool> {
+        if let Some(contains) = &self.contains
+            && !self.ancestry.is_ancestor(contains, commit)?
+        {
+            return Ok(false);
+        }
+        if let Some(merged) = &self.merged
+            && !self.ancestry.is_ancestor(commit, merged)?
+        {
+            return Ok(false);
+        }
+        if let Some(no_merged) = &self.no_merged
+            && self.ancestry.is_ancestor(commit, no_merged)?
+        {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// The refs under `prefix` that match, by their names without it. Refs
+    /// that aren't commits (or tags of commits) only match without filters.
+    fn refs(&self, prefix: &str) -> anyhow::Result<Vec<(String, Oid)>> {
+        let git_dir = repository::git_dir();
+        let mut matching = vec![];
+        for (name, _) in refs::list(git_dir)? {
+            let Some(short) = name.strip_prefix(prefix) else {
+                continue;
+            };
+            let Some(hash) = refs::resolve(git_dir, &name)? else {
+                continue;
+            };
+            if !self.is_empty() {
+                let std::result::Result::Ok(commit) = revision::peel(&hash, Some(Kind::Commit)) else {
+                    continue;
+                };
+                if !self.matches(&commit)? {
+                    continue;
+                }
+            }
+            matching.push((short.to_string(), hash));
+        }
+        Ok(matching)
+    }
+}
+
+/// Lists the tags matching any of `patterns` (all of them if there are
+/// none) and the filters, sorted by the `sort` keys or `tag.sort`, laid
+/// out in `columns`.
+fn tag(
+    list: bool,
+    sort: Vec<String>,
+    contains: Option<String>,
+    merged: Option<String>,
+    no_merged: Option<String>,
+    columns: Columns,
+    patterns: Vec<String>,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(list || patterns.is_empty(), "creating tags is not supported; use -l to list tags matching a pattern");
+    let config = Config::load()?;
+    let sort = if sort.is_empty() { config.get_all("tag.sort").into_iter().map(str::to_string).collect() } else { sort };
+    let keys = sort.iter().map(|key| SortKey::parse(key)).collect::<anyhow::Result<Vec<_>>>()?;
+    let suffixes = versioncmp::suffixes(&config);
+    let mut tags: Vec<String> = RefFilter::new(contains, merged, no_merged)?
+        .refs("refs/tags/")?
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| patterns.is_empty() || patterns.iter().any(|pattern| ignore::wildmatch(pattern.as_bytes(), name.as_bytes())))
+        .collect();
+    // The last key given decides first, and the name breaks ties.
+    tags.sort_by(|a, b| {
+        keys.iter().rev().map(|key| key.compare(a, b, &suffixes)).fold(std::cmp::Ordering::Equal, std::cmp::Ordering::then).then_with(|| a.cmp(b))
+    });
+    print!("{}", columns.format(&tags, "", 2));
+    Ok(())
+}
+
+/// A `--sort` key for listing refs: `refname` or `version:refname` (also
+/// `v:refname`), reversed by a leading `-`.
+struct SortKey {
+    version: bool,
+    reverse: bool,
+}
+
+impl SortKey {
+    fn parse(key: &str) -> anyhow::Result<SortKey> {
+        let (reverse, field) = match key.strip_prefix('-') {
+            Some(field) => (true, field),
+            None => (false, key),
+        };
+        let version = match field {
+            "refname" => false,
+            "version:refname" | "v:refname" => true,
+            _ => anyhow::bail!("unknown field name: {field}"),
+        };
+        Ok(SortKey { version, reverse })
+    }
+
+    fn compare(&self, a: &str, b: &str, suffixes: &[String]) -> std::cmp::Ordering {
+        let order = if self.version { versioncmp::compare(a, b, suffixes) } else { a.cmp(b) };
+        if self.reverse { order.reverse() } else { order }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum CommitGraphCommand {
+    Write,
+    Verify,
+}
+
+fn commit_graph(command: CommitGraphCommand) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    match command {
+        CommitGraphCommand::Write => {
+            anyhow::ensure!(!shallow::is_shallow(git_dir)?, "commit-graph is not supported in a shallow repository");
+            let mut names: Vec<String> = refs::list(git_dir)?.into_iter().map(|(name, _)| name).collect();
+            names.push("HEAD".to_string());
+            let mut tips = vec![];
+            for name in names {
+                if let Some(hash) = refs::resolve(git_dir, &name)?
+                    && let std::result::Result::Ok(commit) = revision::peel(&hash, Some(Kind::Commit))
+                {
+                    tips.push(commit);
+                }
+            }
+            let graph = CommitGraph::build(&tips)?;
+            graph.write(git_dir)?;
+            println!("Wrote {} commits to the commit-graph", graph.oids().len());
+        }
+        CommitGraphCommand::Verify => {
+            let path = repository::objects_dir(git_dir).join("info/commit-graph");
+            let data = fs::read(&path).context("read commit-graph")?;
+            let graph = CommitGraph::parse(&data)?;
+            let rebuilt = CommitGraph::build(graph.oids())?;
+            for hash in graph.oids() {
+                if graph.get(hash) != rebuilt.get(hash) {
+                    eprintln!("error: commit-graph entry for {hash} does not match the commit");
+                    exit(EXIT_FAILED);
+                }
+            }
+            anyhow::ensure!(graph == rebuilt, "commit-graph is missing commits reachable from its own");
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Subcommand)]
+enum BackupCommand {
+    Create {
+        archive: Option<String>,
+    },
+    Restore {
+        archive: String,
+        directory: String,
+    },
+}
+
+fn backup(command: BackupCommand) -> anyhow::Result<()> {
+    match command {
+        BackupCommand::Create { archive } => {
+            let git_dir = repository::git_dir();
+            let archive = archive.unwrap_or_else(|| format!("got-backup-{}.tar", chrono::Utc::now().format("%Y%m%dT%H%M%SZ")));
+            // Written under a temporary name, so that an archive by the
+            // name is always a whole one.
+            let temp = format!("{archive}.tmp{}", std::process::id());
+            let file = fs::File::create(&temp).with_context(|| format!("create {temp}"))?;
+            let mut out = std::io::BufWriter::new(file);
+            let progress = std::io::stderr().is_terminal();
+            let count = got::backup::create(git_dir, &Config::load()?, &mut out, progress).and_then(|count| {
+                out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+                fs::rename(&temp, &archive).with_context(|| format!("write {archive}"))?;
+                Ok(count)
+            });
+            if count.is_err() {
+                fs::remove_file(&temp).ok();
+            }
+            println!("Backed up {} refs to '{archive}'", count?);
+        }
+        BackupCommand::Restore { archive, directory } => {
+            let directory = Path::new(&directory);
+            let empty = fs::read_dir(directory).map_or(true, |mut entries| entries.next().is_none());
+            anyhow::ensure!(empty, "destination path '{}' already exists and is not an empty directory", directory.display());
+            let file = fs::File::open(&archive).with_context(|| format!("open {archive}"))?;
+            fs::create_dir_all(directory).with_context(|| format!("create {}", directory.display()))?;
+            got::backup::restore(&mut std::io::BufReader::new(file), &directory.join(".got"))?;
+            in_directory(directory, || {
+                let git_dir = repository::git_dir();
+                if let Some(head) = refs::resolve(git_dir, "HEAD")? {
+                    let mut index = Index::new();
+                    worktree::checkout(&mut index, None, &revision::peel(&head, Some(Kind::Tree))?)?;
+                    index.write(git_dir)?;
+                }
+                Ok(())
+            })?;
+            println!("Restored '{archive}' into '{}'", directory.display());
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Subcommand)]
+enum SubtreeCommand {
+    Add {
+        #[clap(short = 'P', long)]
+        prefix: String,
+        #[clap(short = 'm', long)]
+        message: Option<String>,
+        source: String,
+        reference: Option<String>,
+    },
+    Pull {
+        #[clap(short = 'P', long)]
+        prefix: String,
+        #[clap(short = 'm', long)]
+        message: Option<String>,
+        repository: String,
+        reference: String,
+    },
+    Split {
+        #[clap(short = 'P', long)]
+        prefix: String,
+        #[clap(short = 'b', long)]
+        branch: Option<String>,
+        commit: Option<String>,
+    },
+}
+
+fn subtree(command: SubtreeCommand) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let tree = |commit: &Oid| revision::peel(commit, Some(Kind::Tree));
+    match command {
+        SubtreeCommand::Add { prefix, message, source, reference } => {
+            let prefix = normalize_pathspec(&prefix)?;
+            anyhow::ensure!(!prefix.is_empty(), "you must provide a prefix");
+            let head = revision::resolve_commit(git_dir, "HEAD")?;
+            let head_tree = tree(&head)?;
+            anyhow::ensure!(tree::lookup(&head_tree, prefix.as_bytes())?.is_none(), "prefix '{prefix}' already exists.");
+            let added = match reference {
+                Some(reference) => got::clone::fetch_local(&source, &reference)?,
+                None => revision::resolve_commit(git_dir, &source)?,
+            };
+            let new_tree = got::subtree::with_entry(Some(&head_tree), prefix.as_bytes(), Some((Mode::Tree, tree(&added)?)))?;
+            let message = format!(
+                "{}\n\ngit-subtree-dir: {prefix}\ngit-subtree-mainline: {head}\ngit-subtree-split: {added}\n",
+                message.map_or_else(|| format!("Add '{prefix}/' from commit '{added}'"), |message| message.trim_end().to_string())
+            );
+            let commit = commit_merge(&head_tree, &new_tree, &[head, added], &message)?;
+            update_head(&commit, &format!("subtree add: {prefix}"))?;
+        }
+        SubtreeCommand::Pull { prefix, message, repository, reference } => {
+            let prefix = normalize_pathspec(&prefix)?;
+            let head = revision::resolve_commit(git_dir, "HEAD")?;
+            let head_tree = tree(&head)?;
+            let ours = got::subtree::subtree_of(&head_tree, prefix.as_bytes())?
+                .with_context(|| format!("'{prefix}' does not exist; use 'got subtree add'"))?;
+            let fetched = got::clone::fetch_local(&repository, &reference)?;
+            if revwalk::is_ancestor(git_dir, &fetched, &head)? {
+                println!("Already up to date.");
+                return Ok(());
+            }
+            let base = revwalk::merge_base(git_dir, &head, &fetched)?.map(|base| tree(&base)).transpose()?;
+            let merged = got::subtree::merge_trees(base.as_ref(), &ours, &tree(&fetched)?)
+                .with_context(|| format!("cannot merge {fetched} into '{prefix}'"))?;
+            let new_tree = got::subtree::with_entry(Some(&head_tree), prefix.as_bytes(), Some((Mode::Tree, merged)))?;
+            let message = match message {
+                Some(message) => format!("{}\n", message.trim_end()),
+                None => format!("Merge commit '{fetched}'\n"),
+            };
+            let commit = commit_merge(&head_tree, &new_tree, &[head, fetched], &message)?;
+            update_head(&commit, &format!("subtree pull: {prefix}"))?;
+        }
+        SubtreeCommand::Split { prefix, branch, commit } => {
+            let prefix = normalize_pathspec(&prefix)?;
+            let start = revision::resolve_commit(git_dir, commit.as_deref().unwrap_or("HEAD"))?;
+            if let Some(branch) = &branch {
+                anyhow::ensure!(refs::is_valid_branch_name(branch), "'{branch}' is not a valid branch name");
+            }
+            let split = got::subtree::split(git_dir, &start, &prefix)?.context("no new revisions were found")?;
+            if let Some(branch) = branch {
+                let name = format!("refs/heads/{branch}");
+                match refs::resolve(git_dir, &name)? {
+                    Some(old) => {
+                        anyhow::ensure!(
+                            revwalk::is_ancestor(git_dir, &old, &split)?,
+                            "branch '{branch}' is not an ancestor of commit '{split}'."
+                        );
+                        eprintln!("Updated branch '{branch}'");
+                    }
+                    None => eprintln!("Created branch '{branch}'"),
+                }
+                refs::write(git_dir, &name, &split.to_string())?;
+            }
+            println!("{split}");
+        }
+    }
+    Ok(())
+}
+
+/// Commits tree `new_tree` with `parents`, the first of them HEAD, and
+/// moves the worktree and index over from HEAD's tree `old_tree`.
+fn commit_merge(old_tree: &Oid, new_tree: &Oid, parents: &[Oid], message: &str) -> anyhow::Result<Oid> {
+    let git_dir = repository::git_dir();
+    let config = Config::load()?;
+    let commit = Commit {
+        tree: *new_tree,
+        parents: parents.to_vec(),
+        author: Signature::current(&config, "author")?,
+        committer: Signature::current(&config, "committer")?,
+        extra_headers: vec![],
+        gpgsig: None,
+        message: message.to_string(),
+    };
+    let mut index = Index::read(git_dir)?;
+    worktree::checkout(&mut index, Some(old_tree), new_tree)?;
+    index.write(git_dir)?;
+    Object::store(Kind::Commit, &commit.serialize())
+}
+
+/// Moves HEAD's branch (or HEAD itself, when detached) to `commit`, noting
+/// `message` in the reflogs.
+fn update_head(commit: &Oid, message: &str) -> anyhow::Result<()> {
+    HeadUpdate::prepare()?.apply(commit, message)
+}
+
+/// A move of HEAD worked out up front, so that a command which also
+/// rewrites the worktree can fail before touching it rather than after.
+struct HeadUpdate {
+    /// What HEAD holds: `ref: <branch>`, or a commit when detached.
+    head: String,
+    old: Oid,
+    committer: Signature,
+}
+
+impl HeadUpdate {
+    fn prepare() -> anyhow::Result<HeadUpdate> {
+        let git_dir = repository::git_dir();
+        Ok(HeadUpdate {
+            head: refs::read(git_dir, "HEAD")?.unwrap_or_default(),
+            old: refs::resolve(git_dir, "HEAD")?.unwrap_or(Oid::ZERO),
+            committer: Signature::reflog(&Config::load()?),
+        })
+    }
+
+    fn apply(self, commit: &Oid, message: &str) -> anyhow::Result<()> {
+        let git_dir = repository::git_dir();
+        let entry = got::reflog::Entry {
+            old: self.old,
+            new: *commit,
+            committer: self.committer,
+            message: message.to_string(),
+        };
+        if let Some(branch) = self.head.strip_prefix("ref: ") {
+            refs::write(git_dir, branch, &commit.to_string())?;
+            got::reflog::append(git_dir, branch, entry.clone())?;
+        } else {
+            refs::write(git_dir, "HEAD", &commit.to_string())?;
+        }
+        got::reflog::append(git_dir, "HEAD", entry)
+    }
+}
+
+/// Switches to branch `name`: moves the worktree and index over to its
+/// commit and points HEAD at it. With `create`, the branch is made first,
+/// at `start` (HEAD by default). A `name` that is no branch but names a
+/// commit checks that out with HEAD detached at it.
+fn checkout_branch(name: &str, create: bool, start: Option<&str>) -> anyhow::Result<()> {
+    let git_dir = repository::git_dir();
+    let config = Config::load()?;
+    let refname = format!("refs/heads/{name}");
+    let head = refs::read(git_dir, "HEAD")?.unwrap_or_default();
+    let mut detach = false;
+    let commit = if create {
+        anyhow::ensure!(refs::is_valid_branch_name(name), "'{name}' is not a valid branch name");
+        anyhow::ensure!(refs::read(git_dir, &refname)?.is_none(), "a branch named '{name}' already exists");
+        match start {
+            Some(start) => Some(revision::resolve_commit(git_dir, start)?),
+            // A new branch on an unborn HEAD is unborn too.
+            None => refs::resolve(git_dir, "HEAD")?,
+        }
+    } else if let Some(commit) = refs::resolve(git_dir, &refname)? {
+        if head.strip_prefix("ref: ") == Some(refname.as_str()) {
+            eprintln!("Already on '{name}'");
+            print_tracking(&mut std::io::stdout(), &config, name, &commit)?;
+            return Ok(());
+        }
+        Some(commit)
+    } else {
+        let commit = revision::resolve_commit(git_dir, name)
+            .map_err(|_| anyhow::anyhow!("pathspec '{name}' did not match any file(s) known to got"))?;
+        detach = true;
+        Some(commit)
+    };
+    let mut index = Index::read(git_dir)?;
+    anyhow::ensure!(
+        index.entries().iter().all(|entry| entry.stage == 0),
+        "you need to resolve your current index first"
+    );
+    let old = refs::resolve(git_dir, "HEAD")?;
+    let committer = Signature::reflog(&config);
+    if let Some(commit) = &commit
+        && old != Some(*commit)
+    {
+        let tree = |commit: &Oid| revision::peel(commit, Some(Kind::Tree));
+        let from = old.as_ref().map(tree).transpose()?;
+        worktree::checkout(&mut index, from.as_ref(), &tree(commit)?)?;
+        index.write(git_dir)?;
+    }
+
+    if create && let Some(commit) = commit {
+        refs::write(git_dir, &refname, &commit.to_string())?;
+        let entry = got::reflog::Entry {
+            old: Oid::ZERO,
+            new: commit,
+            committer: committer.clone(),
+            message: format!("branch: Created from {}", start.unwrap_or("HEAD")),
+        };
+        got::reflog::append(git_dir, &refname, entry)?;
+    }
+    match commit {
+        Some(commit) if detach => refs::write(git_dir, "HEAD", &commit.to_string())?,
+        _ => refs::write(git_dir, "HEAD", &format!("ref: {refname}"))?,
+    }
+    if let Some(commit) = commit {
+        let from_name = match (head.strip_prefix("ref: refs/heads/"), &old) {
+            (Some(branch), _) => branch.to_string(),
+            (None, Some(old)) => old.to_string(),
+            (None, None) => "HEAD".to_string(),
+        };
+        let entry = got::reflog::Entry {
+            old: old.unwrap_or(Oid::ZERO),
+            new: commit,
+            committer,
+            message: format!("checkout: moving from {from_name} to {name}"),
+        };
+        got::reflog::append(git_dir, "HEAD", entry)?;
+    }
+
+    let describe = |commit: &Oid| -> anyhow::Result<String> {
+        let message = revwalk::read_commit(commit)?.message;
+        Ok(format!("{} {}", commit.abbrev(7), message.lines().next().unwrap_or_default()))
+    };
+    if !head.starts_with("ref: ")
+        && let Some(old) = old
+        && Some(old) != commit
+    {
+        eprintln!("Previous HEAD position was {}", describe(&old)?);
+    }
+    match commit {
+        Some(commit) if detach => {
+            if head.starts_with("ref: ") {
+                eprintln!(
+                    "Note: switching to '{name}'.\n\n\
+                     You are in 'detached HEAD' state. You can look around, make experimental\n\
+                     changes and commit them, and you can discard any commits you make in this\n\
+                     state without impacting any branches by switching back to a branch.\n\n\
+                     If you want to create a new branch to retain commits you create, you may\n\
+                     do so (now or later) by using -b with the checkout command. Example:\n\n  \
+                     got checkout -b <new-branch-name>\n"
+                );
+            }
+            eprintln!("HEAD is now at {}", describe(&commit)?);
+        }
+        _ if create => eprintln!("Switched to a new branch '{name}'"),
+        _ => eprintln!("Switched to branch '{name}'"),
+    }
+    if let Some(commit) = commit.filter(|_| !detach) {
+        print_tracking(&mut std::io::stdout(), &config, name, &commit)?;
+    }
+    Ok(())
+}
+
+/// Checks out `commit` with HEAD detached at it, unless that is already
+/// checked out. Returns whether anything had to change.
+fn checkout_detached(commit: &Oid) -> anyhow::Result<bool> {
+    let git_dir = repository::git_dir();
+    let mut index = Index::read(git_dir)?;
+    // A fresh clone has no index and nothing checked out yet.
+    let from = match refs::resolve(git_dir, "HEAD")? {
+        Some(head) if git_dir.join("index").exists() => Some(head),
+        _ => None,
+    };
+    if from == Some(*commit) {
+        return Ok(false);
+    }
+    let tree = |commit: &Oid| revision::peel(commit, Some(Kind::Tree));
+    let from = from.map(|head| tree(&head)).transpose()?;
+    worktree::checkout(&mut index, from.as_ref(), &tree(commit)?)?;
+    index.write(git_dir)?;
+    refs::write(git_dir, "HEAD", &commit.to_string())?;
+    Ok(true)
+}
+
+/// Runs `f` with `dir` as the current directory, for working in another
+/// repository such as a submodule, then changes back.
+fn in_directory<T>(dir: &Path, f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let back = std::env::current_dir()?;
+    std::env::set_current_dir(dir).with_context(|| format!("enter {}", dir.display()))?;
+    let result = f();
+    std::env::set_current_dir(&back).with_context(|| format!("return to {}", back.display()))?;
+    result
+}
+
+fn main() {
+    let args = match Args::try_parse() {
+        std::result::Result::Ok(args) => args,
+        Err(e) => {
+            // Help and the version are asked for; anything else is misuse.
+            let _ = e.print();
+            exit(if e.use_stderr() { EXIT_USAGE } else { 0 });
+        }
+    };
+    if let Err(e) = run(args) {
+        // A reader that stopped early, as `got log | head` does, isn't a
+        // failure worth reporting; git is killed by SIGPIPE there instead.
+        if e.chain().any(|cause| cause.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)) {
+            std::process::exit(EXIT_BROKEN_PIPE);
+        }
+        eprintln!("fatal: {e:#}");
+        exit(EXIT_FATAL);
+    }
+}
+
+/// Ends the run with `status`, once what has been printed is flushed.
+fn exit(status: i32) -> ! {
+    let _ = std::io::stdout().flush();
+    std::process::exit(status)
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    if args.read_only {
+        repository::set_read_only();
+    }
+    if args.command.needs_worktree() {
+        repository::require_worktree(&Config::load()?)?;
+    }
+    if args.command.modifies_repository() {
+        repository::require_writable(&Config::load()?)?;
+    }
+
+    match args.command {
+        Command::Init => init(),
+        Command::CatFile { pretty_print, filters, textconv, path, hash } => {
+            if filters || textconv {
+                cat_file_converted(textconv, path, &hash)?;
+            } else {
+                anyhow::ensure!(path.is_none(), "'--path' requires '--filters' or '--textconv'");
+                anyhow::ensure!(
+                    pretty_print,
+                    "mode must be given without -p, and we don't support mode"
+                );
+                cat_file(hash)?;
+            }
+        }
+        Command::UnpackFile { blob } => {
+            let hash = revision::resolve(repository::git_dir(), &blob)?;
+            let path = got::temp::unpack_blob(&hash, Path::new("."))?;
+            println!("{}", path.file_name().expect("temporary files have names").display());
+        },
+        Command::HashObject { write, path } => {
+            anyhow::ensure!(write, "Only write to file is supported for now");
+            println!("{}", hash_object(Path::new(&path))?.hash);
+        }
+        Command::LsTree { treehash } => ls_tree(treehash)?,
+        Command::WriteTree { reproducible } => {
+            let tree = write_tree(Path::new("."), reproducible)?;
+            println!("{}", tree.hash)
+        },
+        Command::VerifyTree { commit } => {
+            let commit = revision::resolve_commit(repository::git_dir(), commit.as_deref().unwrap_or("HEAD"))?;
+            let problems = got::verify::verify_commit(&commit)?;
+            for problem in &problems {
+                eprintln!("error: {problem}");
+            }
+            if !problems.is_empty() {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::VerifyRemote { remote } => {
+            let git_dir = repository::git_dir();
+            let prefix = format!("refs/remotes/{}/", remote.as_deref().unwrap_or("origin"));
+            let mut tips = vec![];
+            for (name, _) in refs::list(git_dir)? {
+                if name.starts_with(&prefix) {
+                    tips.extend(refs::resolve(git_dir, &name)?);
+                }
+            }
+            let skip = got::verify::skip_list(&Config::load()?, &["fsck.skipList"])?;
+            let problems = got::verify::check_connectivity(git_dir, &tips, &[], &skip)?;
+            for problem in &problems {
+                eprintln!("error: {problem}");
+            }
+            if !problems.is_empty() {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::PrunePacked { dry_run } => {
+            for path in got::pack::prune_packed(&repository::objects_dir(repository::git_dir()), dry_run)? {
+                if dry_run {
+                    println!("rm -f {}", path.display());
+                }
+            }
+        },
+        Command::UnpackObjects { dry_run } => {
+            let mut data = vec![];
+            std::io::stdin().read_to_end(&mut data).context("read pack from standard input")?;
+            got::pack::unpack_objects(&data, dry_run)?;
+        },
+        Command::PackObjects { revs, window, depth, threads, no_reuse_delta, quiet, base_name, .. } => {
+            let git_dir = repository::git_dir();
+            let mut options = RepackOptions::from_config(&Config::load()?)?;
+            options.window = window.unwrap_or(options.window);
+            options.depth = depth.unwrap_or(options.depth);
+            options.threads = threads.unwrap_or(options.threads);
+            options.reuse_deltas = !no_reuse_delta;
+            options.progress = !quiet && std::io::stderr().is_terminal();
+            let temp = got::temp::TempDir::new("pack-objects")?;
+            let dir = match &base_name {
+                Some(base) => Path::new(base).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf(),
+                None => temp.path().to_path_buf(),
+            };
+            let mut tips = vec![];
+            let mut exclude = vec![];
+            let mut objects = vec![];
+            for line in std::io::stdin().lines() {
+                let line = line.context("read standard input")?;
+                if revs {
+                    match line.strip_prefix('^') {
+                        Some(spec) => exclude.push(revision::resolve_commit(git_dir, spec)?),
+                        None if !line.is_empty() => tips.push(revision::resolve(git_dir, &line)?),
+                        None => {}
+                    }
+                    continue;
+                }
+                let (hash, path) = line.split_once(' ').unwrap_or((&line, ""));
+                let hash: Oid = hash.parse().with_context(|| format!("expected object ID, got garbage:\n {line}"))?;
+                anyhow::ensure!(Object::exists(&hash), "object {hash} does not exist");
+                objects.push((hash, path.as_bytes().to_vec()));
+            }
+            let (path, index) = match revs {
+                true => got::repack::pack_reachable_from(git_dir, &tips, &exclude, &dir, &options)?,
+                false => got::repack::pack_objects(git_dir, objects, &dir, &options)?,
+            };
+            let Some(base) = base_name else {
+                let data = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+                std::io::stdout().write_all(&data).context("write pack to standard output")?;
+                return Ok(());
+            };
+            let name = format!("{base}-{}", index.pack_checksum);
+            for extension in ["pack", "idx"] {
+                let to = format!("{name}.{extension}");
+                fs::rename(path.with_extension(extension), &to).with_context(|| format!("write {to}"))?;
+            }
+            println!("{}", index.pack_checksum);
+        },
+        Command::ReceivePack { directory } => {
+            // The repository is the one named, not the one the client runs
+            // in, so the usual checks wait until it has been entered.
+            std::env::set_current_dir(&directory).with_context(|| format!("'{directory}' does not appear to be a got repository"))?;
+            let config = Config::load()?;
+            repository::require_writable(&config)?;
+            got::receive::receive_pack(repository::git_dir(), &config, &mut std::io::stdin().lock(), &mut std::io::stdout().lock())?;
+        },
+        Command::ShowIndex => {
+            let mut data = vec![];
+            std::io::stdin().read_to_end(&mut data).context("read pack index from standard input")?;
+            let index = got::pack::PackIndex::parse(&data).context("unable to read index")?;
+            for (i, (hash, offset)) in index.oids.iter().zip(&index.offsets).enumerate() {
+                match index.crcs.get(i) {
+                    Some(crc) => println!("{offset} {hash} ({crc:08x})"),
+                    None => println!("{offset} {hash}"),
+                }
+            }
+        },
+        Command::Gc { aggressive, prune, no_prune, quiet } => {
+            let options = got::gc::GcOptions {
+                aggressive,
+                prune_expire: if no_prune { Some("never".to_string()) } else { prune },
+                progress: !quiet && std::io::stderr().is_terminal(),
+            };
+            got::gc::gc(repository::git_dir(), &Config::load()?, &options)?;
+        },
+        Command::Repack { all, delete, window, depth, threads, write_bitmap_index, pack_kept_objects, no_reuse_delta, delta_islands } => {
+            let mut options = RepackOptions::from_config(&Config::load()?)?;
+            anyhow::ensure!(all || !write_bitmap_index, "incremental repacks are incompatible with bitmap indexes; use -a");
+            options.all = all;
+            options.delete = delete;
+            options.window = window.unwrap_or(options.window);
+            options.depth = depth.unwrap_or(options.depth);
+            options.threads = threads.unwrap_or(options.threads);
+            options.write_bitmap |= write_bitmap_index;
+            options.reuse_deltas = !no_reuse_delta;
+            options.delta_islands |= delta_islands;
+            if pack_kept_objects {
+                options.pack_kept_objects = Some(true);
+            }
+            if got::repack::repack(repository::git_dir(), &options)?.is_none() {
+                println!("Nothing new to pack.");
+            }
+        },
+        Command::CheckRefFormat { normalize, allow_onelevel, refspec_pattern, branch, name } => {
+            if branch {
+                anyhow::ensure!(refs::is_valid_branch_name(&name), "'{name}' is not a valid branch name");
+                println!("{name}");
+                return Ok(());
+            }
+            let mut name = name;
+            if normalize {
+                // Leading slashes go, and repeated ones become one.
+                let mut normalized = String::new();
+                for c in name.trim_start_matches('/').chars() {
+                    if c != '/' || !normalized.ends_with('/') {
+                        normalized.push(c);
+                    }
+                }
+                name = normalized;
+            }
+            if !refs::is_valid_name(&name, refs::NameRules { allow_onelevel, refspec_pattern }) {
+                exit(EXIT_FAILED);
+            }
+            if normalize {
+                println!("{name}");
+            }
+        },
+        Command::RevList {
+            count,
+            objects,
+            all,
+            max_parents,
+            min_parents,
+            max_count,
+            merges,
+            no_merges,
+            first_parent,
+            reverse,
+            topo_order,
+            date_order,
+            author_date_order,
+            bisect,
+            bisect_all,
+            revisions,
+        } => {
+            let order = walk_order(topo_order, date_order, author_date_order);
+            let mut walk = RevWalk { max_parents, min_parents, max_count, first_parent, order, reverse, ..RevWalk::default() };
+            filter_merges(&mut walk, merges, no_merges);
+            rev_list(count, objects, all, bisect, bisect_all, walk, revisions)?;
+        },
+        Command::Whatchanged {
+            name_status,
+            no_abbrev,
+            max_count,
+            merges,
+            no_merges,
+            first_parent,
+            reverse,
+            topo_order,
+            date_order,
+            author_date_order,
+            revisions,
+        } => {
+            let order = walk_order(topo_order, date_order, author_date_order);
+            let mut walk = RevWalk { first_parent, order, reverse, ..RevWalk::default() };
+            filter_merges(&mut walk, merges, no_merges);
+            whatchanged(name_status, no_abbrev, max_count, walk, revisions)?;
+        },
+        Command::PatchId { stable, unstable } => patch_id(stable, unstable)?,
+        Command::Stripspace { strip_comments, comment_lines } => stripspace(strip_comments, comment_lines)?,
+        Command::Log { patch, follow, max_count, revisions, paths } => log(patch, follow, max_count, revisions, paths)?,
+        Command::Blame { porcelain, line_porcelain, incremental, args } => {
+            let format = if incremental {
+                BlameFormat::Incremental
+            } else if line_porcelain {
+                BlameFormat::LinePorcelain
+            } else if porcelain {
+                BlameFormat::Porcelain
+            } else {
+                BlameFormat::Default
+            };
+            blame(format, args)?;
+        },
+        Command::DiffFiles { raw: _, name_only, name_status, exit_code, quiet, paths } => {
+            let format = plumbing_diff_format(name_only, name_status);
+            if print_plumbing_diff(diff_files_changes()?, format, quiet, &paths)? && (exit_code || quiet) {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::DiffIndex { cached, raw: _, name_only, name_status, exit_code, quiet, tree_ish, paths } => {
+            let git_dir = repository::git_dir();
+            let tree = revision::peel(&revision::resolve(git_dir, &tree_ish)?, Some(Kind::Tree))
+                .with_context(|| format!("'{tree_ish}' does not name a commit or tree"))?;
+            let format = plumbing_diff_format(name_only, name_status);
+            if print_plumbing_diff(diff_index_changes(&tree, cached)?, format, quiet, &paths)? && (exit_code || quiet) {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::Diff { no_ext_diff, exit_code, quiet, name_only, name_status, raw, textconv: _, no_textconv, find_copies, find_copies_harder, submodule, cached, revisions } => {
+            let format = if name_only {
+                DiffFormat::NameOnly
+            } else if name_status {
+                DiffFormat::NameStatus
+            } else if raw {
+                DiffFormat::Raw
+            } else {
+                DiffFormat::Patch
+            };
+            let find_copies = (find_copies > 0 || find_copies_harder).then_some(find_copies > 1 || find_copies_harder);
+            let sides = if cached {
+                anyhow::ensure!(revisions.len() <= 1, "--cached compares the index with one commit at most");
+                DiffSides::Cached(revisions.into_iter().next())
+            } else if revisions.is_empty() {
+                DiffSides::Worktree
+            } else if let [revision] = &revisions[..]
+                && !revision.starts_with('^')
+                && !revision.contains("..")
+            {
+                DiffSides::Revision(revision.clone())
+            } else {
+                DiffSides::Commits(revisions)
+            };
+            if diff(format, quiet, no_ext_diff, !no_textconv, find_copies, submodule, sides)? && (exit_code || quiet) {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::Difftool { tool, extcmd, dir_diff, no_prompt, prompt, revisions } => {
+            let prompt = if no_prompt { Some(false) } else if prompt { Some(true) } else { None };
+            difftool(tool, extcmd, dir_diff, prompt, revisions)?;
+        },
+        Command::Replace { delete, list, force, graft, convert_graft_file, objects } => {
+            replace(delete, list, force, graft, convert_graft_file, objects)?
+        },
+        Command::Add { intent_to_add, update, force, pathspecs } => add(intent_to_add, update, force, pathspecs)?,
+        Command::Commit { patch, message, file, edit, no_edit, reproducible } => commit(patch, message, file, edit_flag(edit, no_edit), reproducible)?,
+        Command::UpdateIndex { assume_unchanged, no_assume_unchanged, skip_worktree, no_skip_worktree, untracked_cache, no_untracked_cache, split_index, no_split_index, paths } => {
+            let flag = |set: bool, clear: bool| (set || clear).then_some(set);
+            update_index(
+                flag(assume_unchanged, no_assume_unchanged),
+                flag(skip_worktree, no_skip_worktree),
+                flag(untracked_cache, no_untracked_cache),
+                flag(split_index, no_split_index),
+                paths,
+            )?;
+        },
+        Command::CommitTree { parents, message, tree_hash } => {
+            println!("{}", commit_tree(tree_hash, parents, message)?);
+        },
+        Command::Export { worktree, commit, directory } => {
+            anyhow::ensure!(worktree, "Only exporting to a directory with --worktree is supported for now");
+            export_worktree(&commit, &directory)?;
+        },
+        Command::ImportSnapshots { branch, message, directories } => import_snapshots(branch, message, directories)?,
+        Command::Restore { patch, staged, pathspecs } => restore(patch, staged, pathspecs)?,
+        Command::Reset { patch, hard, force_protected, pathspecs } => reset(patch, hard, force_protected, pathspecs)?,
+        Command::Status { column, no_column } => {
+            let mut columns = Columns::from_config(&Config::load()?, "status")?;
+            columns.apply_option(column.as_deref(), no_column)?;
+            status(columns)?
+        },
+        Command::Stash { command } => stash(command)?,
+        Command::Branch { delete, force_delete, force_protected, contains, merged, no_merged, column, no_column, names } => {
+            if delete || force_delete {
+                if !delete_branches(&names, force_delete, force_protected)? {
+                    exit(EXIT_FAILED);
+                }
+                return Ok(());
+            }
+            anyhow::ensure!(names.is_empty(), "creating branches is not supported; use -d or -D to delete them");
+            let mut columns = Columns::from_config(&Config::load()?, "branch")?;
+            columns.apply_option(column.as_deref(), no_column)?;
+            let filter = RefFilter::new(contains, merged, no_merged)?;
+            let head = refs::read(repository::git_dir(), "HEAD")?.unwrap_or_default();
+            let current = head.strip_prefix("ref: ").and_then(|name| name.strip_prefix("refs/heads/"));
+            let mut lines = vec![];
+            if current.is_none()
+                && let Some(commit) = refs::resolve(repository::git_dir(), "HEAD")?
+                && filter.matches(&commit)?
+            {
+                lines.push(format!("* (HEAD detached at {})", commit.abbrev(7)));
+            }
+            for (name, _) in filter.refs("refs/heads/")? {
+                let marker = if Some(name.as_str()) == current { '*' } else { ' ' };
+                lines.push(format!("{marker} {name}"));
+            }
+            print!("{}", columns.format(&lines, "", 1));
+        },
+        Command::Checkout { new_branch, recurse_submodules, branch } => {
+            match new_branch {
+                Some(new_branch) => checkout_branch(&new_branch, true, branch.as_deref())?,
+                None => checkout_branch(&branch.context("usage: got checkout [-b <new-branch>] <branch>")?, false, None)?,
+            }
+            if recurse_submodules {
+                submodule_update(false, true, "")?;
+            }
+        },
+        Command::Tag { list, sort, contains, merged, no_merged, column, no_column, patterns } => {
+            let mut columns = Columns::from_config(&Config::load()?, "tag")?;
+            columns.apply_option(column.as_deref(), no_column)?;
+            tag(list, sort, contains, merged, no_merged, columns, patterns)?
+        },
+        Command::Credential { command } => credential(command)?,
+        Command::CredentialStore { file, operation } => {
+            let file = file.as_deref().map(Path::new);
+            credential::store_helper(file, &operation, &mut std::io::stdin().lock(), &mut std::io::stdout())?;
+        },
+        Command::CredentialCache { timeout, socket, operation } => {
+            let socket = match socket {
+                Some(socket) => socket.into(),
+                None => credential_cache::default_socket()?,
+            };
+            credential_cache::cache_helper(&socket, timeout, &operation, &mut std::io::stdin().lock(), &mut std::io::stdout())?;
+        },
+        Command::CredentialCacheDaemon { socket } => credential_cache::daemon(Path::new(&socket))?,
+        Command::Fetch { unshallow, all, jobs, remote } => {
+            let fetched = match all {
+                true => fetch_all(unshallow, jobs)?,
+                false => fetch(unshallow, remote.as_deref())?,
+            };
+            if !fetched {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::Pull { rebase, no_rebase, ff_only, recurse_submodules, remote, branch } => {
+            if !pull(rebase, no_rebase, ff_only, remote, branch)? {
+                exit(EXIT_FAILED);
+            }
+            // Initialized submodules follow the commits the update records.
+            if recurse_submodules {
+                submodule_update(false, true, "")?;
+            }
+        },
+        Command::Push { force, force_protected, remote, refspecs } => {
+            if !push(force, force_protected, remote.as_deref(), &refspecs)? {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::Am { resume, skip, abort, mbox } => am(resume, skip, abort, mbox)?,
+        Command::CherryPick { no_commit, edit, no_edit, resume, skip, abort, commits } => {
+            sequence(Action::Pick, no_commit, edit_flag(edit, no_edit), resume, skip, abort, commits)?
+        },
+        Command::Revert { no_commit, edit, no_edit, resume, skip, abort, commits } => {
+            sequence(Action::Revert, no_commit, edit_flag(edit, no_edit), resume, skip, abort, commits)?
+        },
+        Command::Merge { no_ff, ff_only, message, file, edit, no_edit, abort, commit } => {
+            let message = match file {
+                Some(file) => Some(read_message_file(&file)?),
+                None => message,
+            };
+            // Unlike a commit, a merge has a message without the editor.
+            if !merge(no_ff, ff_only, message, edit_flag(edit, no_edit).unwrap_or(false), abort, commit)? {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::RequestPull { start, url, end } => {
+            if !request_pull(&start, &url, end.as_deref())? {
+                exit(EXIT_FAILED);
+            }
+        },
+        Command::Clone { bare, mirror, recurse_submodules, repository, directory } => {
+            let options = CloneOptions { bare, mirror };
+            clone_repository(&options, recurse_submodules, &repository, directory.as_deref())?;
+        },
+        Command::Subtree { command } => subtree(command)?,
+        Command::CommitGraph { command } => commit_graph(command)?,
+        Command::Backup { command } => backup(command)?,
+        Command::Submodule { command } => match command {
+            SubmoduleCommand::Update { init, recursive } => submodule_update(init, recursive, "")?,
+            SubmoduleCommand::Foreach { recursive, command } => submodule_foreach(recursive, &command, "")?,
+        },
     }
     Ok(())
 }
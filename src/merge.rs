@@ -0,0 +1,298 @@
+//! Three-way merges of files and of single paths, as stash apply, revert
+//! and merge need them.
+//!
+//! A file merge diffs the base against each side and takes each side's
+//! changes where the other left the base alone. Where both changed the
+//! same or adjacent lines differently, the result gets git's conflict
+//! markers, labelled with the names given for the two sides:
+//!
+//! ```text
+//! <<<<<<< Updated upstream
+//! our lines
+//! =======
+//! their lines
+//! >>>>>>> Stashed changes
+//! ```
+//!
+//! Lines both sides agree on at the start and end of a conflict are moved
+//! out of it, as git does.
+
+use crate::diff::{self, Edit};
+use crate::object::{Kind, Object};
+use crate::oid::Oid;
+use crate::path;
+use crate::tree::Mode;
+
+/// The result of merging a file: the merged contents, with markers around
+/// each of `conflicts` conflicting regions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMerge {
+    pub contents: Vec<u8>,
+    pub conflicts: usize,
+}
+
+/// Merges the changes from `base` to `theirs` into `ours`.
+pub fn merge_file(base: &[u8], ours: &[u8], theirs: &[u8], ours_label: &str, theirs_label: &str) -> FileMerge {
+    let base = diff::split_lines(base);
+    let ours = diff::split_lines(ours);
+    let theirs = diff::split_lines(theirs);
+    let ours_edits = diff::diff_lines(&base, &ours);
+    let theirs_edits = diff::diff_lines(&base, &theirs);
+
+    let mut out = vec![];
+    let mut conflicts = 0;
+    let mut at = 0;
+    let (mut i, mut j) = (0, 0);
+    while i < ours_edits.len() || j < theirs_edits.len() {
+        // A group is a run of edits from both sides whose base ranges
+        // overlap or touch, starting with whichever comes first.
+        let first = match (ours_edits.get(i), theirs_edits.get(j)) {
+            (Some(a), Some(b)) => a.old_start.min(b.old_start),
+            (Some(a), None) => a.old_start,
+            (None, Some(b)) => b.old_start,
+            (None, None) => unreachable!(),
+        };
+        let (ours_from, theirs_from) = (i, j);
+        let mut end = first;
+        loop {
+            if let Some(edit) = ours_edits.get(i).filter(|edit| edit.old_start <= end) {
+                end = end.max(edit.old_start + edit.old_len);
+                i += 1;
+            } else if let Some(edit) = theirs_edits.get(j).filter(|edit| edit.old_start <= end) {
+                end = end.max(edit.old_start + edit.old_len);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+        out.extend(base[at..first].concat());
+        at = end;
+
+        let ours_group = &ours_edits[ours_from..i];
+        let theirs_group = &theirs_edits[theirs_from..j];
+        let ours_text = side(&base, &ours, ours_group, first, end);
+        let theirs_text = side(&base, &theirs, theirs_group, first, end);
+        if theirs_group.is_empty() || ours_text == theirs_text {
+            out.extend(ours_text.concat());
+        } else if ours_group.is_empty() {
+            out.extend(theirs_text.concat());
+        } else {
+            conflicts += 1;
+            write_conflict(&mut out, ours_text, theirs_text, ours_label, theirs_label);
+        }
+    }
+    out.extend(base[at..].concat());
+    FileMerge { contents: out, conflicts }
+}
+
+/// The lines a side has in place of base lines `start..end`, given its
+/// edits in that range.
+fn side<'a>(base: &[&'a [u8]], lines: &[&'a [u8]], edits: &[Edit], start: usize, end: usize) -> Vec<&'a [u8]> {
+    let (Some(first), Some(last)) = (edits.first(), edits.last()) else {
+        return base[start..end].to_vec();
+    };
+    let from = first.new_start - (first.old_start - start);
+    let to = last.new_start + last.new_len + (end - (last.old_start + last.old_len));
+    lines[from..to].to_vec()
+}
+
+fn write_conflict(out: &mut Vec<u8>, ours: Vec<&[u8]>, theirs: Vec<&[u8]>, ours_label: &str, theirs_label: &str) {
+    let prefix = ours.iter().zip(&theirs).take_while(|(a, b)| a == b).count();
+    let suffix = ours[prefix..]
+        .iter()
+        .rev()
+        .zip(theirs[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    out.extend(ours[..prefix].concat());
+    let section = |out: &mut Vec<u8>, lines: &[&[u8]]| {
+        out.extend(lines.concat());
+        if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+            out.push(b'\n');
+        }
+    };
+    out.extend(format!("<<<<<<< {ours_label}\n").into_bytes());
+    section(out, &ours[prefix..ours.len() - suffix]);
+    out.extend(b"=======\n");
+    section(out, &theirs[prefix..theirs.len() - suffix]);
+    out.extend(format!(">>>>>>> {theirs_label}\n").into_bytes());
+    out.extend(ours[ours.len() - suffix..].concat());
+}
+
+/// An entry of one side of a merge, `None` where the path doesn't exist.
+pub type Side = Option<(Mode, Oid)>;
+
+/// How a path came out of a merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// The merged entry, or `None` for a deleted path.
+    Clean(Side),
+    /// The three versions to record as index stages 1 to 3, and what to
+    /// leave in the worktree for the user to fix: the file with conflict
+    /// markers, or whichever side still has it.
+    Conflict {
+        stages: [Side; 3],
+        worktree: Option<(Mode, Vec<u8>)>,
+        message: String,
+    },
+}
+
+/// Merges the three versions of the file at `path`, storing the merged
+/// blob of a clean content merge. Says what it does on standard output,
+/// as git's merges do.
+pub fn merge_path(
+    path: &[u8],
+    base: Side,
+    ours: Side,
+    theirs: Side,
+    ours_label: &str,
+    theirs_label: &str,
+) -> anyhow::Result<Resolution> {
+    if ours == theirs || base == theirs {
+        return Ok(Resolution::Clean(ours));
+    }
+    let path = path::display(path);
+    if base == ours {
+        return Ok(Resolution::Clean(theirs));
+    }
+    let read = |side: Side| -> anyhow::Result<Vec<u8>> {
+        match side {
+            Some((mode, hash)) if mode != Mode::Submodule => Ok(Object::read(&hash)?.contents),
+            _ => Ok(vec![]),
+        }
+    };
+    let stages = [base, ours, theirs];
+    let (Some((ours_mode, _)), Some((theirs_mode, _))) = (ours, theirs) else {
+        // Changed on one side and deleted on the other.
+        let (deleted, modified, kept) = match ours {
+            None => (ours_label, theirs_label, theirs),
+            Some(_) => (theirs_label, ours_label, ours),
+        };
+        let worktree = kept.map(|(mode, _)| Ok::<_, anyhow::Error>((mode, read(kept)?))).transpose()?;
+        return Ok(Resolution::Conflict {
+            stages,
+            worktree,
+            message: format!(
+                "CONFLICT (modify/delete): {path} deleted in {deleted} and modified in {modified}.  Version {modified} of {path} left in tree."
+            ),
+        });
+    };
+    let kind = if base.is_none() { "add/add" } else { "content" };
+    let mergeable = |mode: Mode| mode == Mode::File || mode == Mode::Executable;
+    let base_mode = base.map(|(mode, _)| mode);
+    let mode = if base_mode == Some(ours_mode) { theirs_mode } else { ours_mode };
+    let (ours_data, theirs_data, base_data) = (read(ours)?, read(theirs)?, read(base)?);
+    if !mergeable(ours_mode)
+        || !mergeable(theirs_mode)
+        || [&ours_data, &theirs_data, &base_data].iter().any(|data| diff::is_binary(data))
+    {
+        // Nothing to merge line by line; ours stays in the worktree.
+        return Ok(Resolution::Conflict {
+            stages,
+            worktree: Some((ours_mode, ours_data)),
+            message: format!("CONFLICT ({kind}): Merge conflict in {path}"),
+        });
+    }
+    println!("Auto-merging {path}");
+    let merged = merge_file(&base_data, &ours_data, &theirs_data, ours_label, theirs_label);
+    let mode_conflict = ours_mode != theirs_mode && base_mode != Some(ours_mode) && base_mode != Some(theirs_mode);
+    if merged.conflicts == 0 && !mode_conflict {
+        let hash = Object::store(Kind::Blob, &merged.contents)?;
+        return Ok(Resolution::Clean(Some((mode, hash))));
+    }
+    Ok(Resolution::Conflict {
+        stages,
+        worktree: Some((mode, merged.contents)),
+        message: format!("CONFLICT ({kind}): Merge conflict in {path}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::ScratchRepository;
+
+    // What `git merge-file -L HEAD -L base -L side` and `git merge` made
+    // of the same three versions.
+
+    const CLEAN: (&str, &str, &str, &str) = (
+        "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n",
+        "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n10\n",
+        "1\n2\n3\n4\n5\n6\n7\neight\n9\n10\n",
+        "1\ntwo\n3\n4\n5\n6\n7\neight\n9\n10\n",
+    );
+
+    /// Both sides change 4 and 5 alike but 5 differently, so the line both
+    /// agree on is left out of the conflict.
+    const CONFLICT: (&str, &str, &str, &str) = (
+        "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n",
+        "1\n2\n3\nfour\nfive\n6\n7\n8\n9\n10\n",
+        "1\n2\n3\nfour\nFIVE\n6\n7\n8\n9\n10\n",
+        "1\n2\n3\nfour\n<<<<<<< HEAD\nfive\n=======\nFIVE\n>>>>>>> side\n6\n7\n8\n9\n10\n",
+    );
+
+    const ADDED: (&str, &str, &str, &str) =
+        ("", "a\nb\nc\n", "a\nB\nc\n", "a\n<<<<<<< HEAD\nb\n=======\nB\n>>>>>>> side\nc\n");
+
+    fn blob(contents: &str) -> Side {
+        Some((Mode::File, Object::store(Kind::Blob, contents.as_bytes()).unwrap()))
+    }
+
+    fn hash(side: Side) -> String {
+        side.map_or(String::new(), |(_, hash)| hash.to_string())
+    }
+
+    #[test]
+    fn files_merge_as_git_merges_them() {
+        for ((base, ours, theirs, merged), conflicts) in [(CLEAN, 0), (CONFLICT, 1), (ADDED, 1)] {
+            let merge = merge_file(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), "HEAD", "side");
+            assert_eq!(String::from_utf8(merge.contents).unwrap(), merged);
+            assert_eq!(merge.conflicts, conflicts);
+        }
+    }
+
+    #[test]
+    fn changes_to_different_lines_merge_cleanly() {
+        let _repository = ScratchRepository::new("merge-clean-test");
+        let base = blob("1\n2\n3\n4\n5\n");
+        let resolution = merge_path(b"f", base, blob("one\n2\n3\n4\n5\n"), blob("1\n2\n3\n4\nfive\n"), "HEAD", "side").unwrap();
+        let Resolution::Clean(merged) = resolution else { panic!("{resolution:?}") };
+        assert_eq!(hash(merged), "4decb40478693c40c0dbc86288b8fd8776e6c79d");
+        // A side that left the path alone takes the other's version.
+        let theirs = blob("1\n2\n");
+        assert_eq!(merge_path(b"f", base, base, theirs, "HEAD", "side").unwrap(), Resolution::Clean(theirs));
+        assert_eq!(merge_path(b"f", base, None, base, "HEAD", "side").unwrap(), Resolution::Clean(None));
+    }
+
+    #[test]
+    fn different_additions_conflict() {
+        let _repository = ScratchRepository::new("merge-add-test");
+        let (ours, theirs) = (blob("y\n"), blob("x\n"));
+        let Resolution::Conflict { stages, worktree, message } = merge_path(b"n", None, ours, theirs, "HEAD", "side").unwrap() else {
+            panic!("no conflict");
+        };
+        assert_eq!(
+            stages.map(hash),
+            ["", "975fbec8256d3e8a3797e7a3611380f27c49f4ac", "587be6b4c3f93f93c489c0111bba5596147a26cb"]
+        );
+        assert_eq!(worktree, Some((Mode::File, b"<<<<<<< HEAD\ny\n=======\nx\n>>>>>>> side\n".to_vec())));
+        assert_eq!(message, "CONFLICT (add/add): Merge conflict in n");
+        // The same file added on both sides is no conflict.
+        assert_eq!(merge_path(b"n", None, ours, ours, "HEAD", "side").unwrap(), Resolution::Clean(ours));
+    }
+
+    #[test]
+    fn a_file_modified_on_one_side_and_deleted_on_the_other_conflicts() {
+        let _repository = ScratchRepository::new("merge-delete-test");
+        let (base, ours) = (blob("1\n2\n3\n4\n5\n"), blob("1\n2\n3\n4\n5\n6\n"));
+        let Resolution::Conflict { stages, worktree, message } = merge_path(b"f", base, ours, None, "HEAD", "side").unwrap() else {
+            panic!("no conflict");
+        };
+        assert_eq!(
+            stages.map(hash),
+            ["8a1218a1024a212bb3db30becd860315f9f3ac52", "b414108e81e5091fe0974a1858b4d0d22b107f70", ""]
+        );
+        assert_eq!(worktree, Some((Mode::File, b"1\n2\n3\n4\n5\n6\n".to_vec())));
+        assert_eq!(message, "CONFLICT (modify/delete): f deleted in side and modified in HEAD.  Version HEAD of f left in tree.");
+    }
+}
@@ -0,0 +1,818 @@
+use anyhow::Context;
+use chrono::{DateTime, FixedOffset};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::str::FromStr;
+use std::{fmt, fs};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::grafts;
+use crate::oid::Oid;
+use crate::pack;
+use crate::refs;
+use crate::repository;
+
+/// How many `refs/replace/` hops `Object::read` follows before giving up on
+/// what is probably a cycle.
+const MAX_REPLACE_DEPTH: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Blob,
+    Commit,
+    Tree,
+    Tag,
+}
+
+impl FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Kind> {
+        match s {
+            "blob" => Ok(Kind::Blob),
+            "commit" => Ok(Kind::Commit),
+            "tree" => Ok(Kind::Tree),
+            "tag" => Ok(Kind::Tag),
+            _ => anyhow::bail!("unknown object type '{s}'"),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Blob => write!(f, "blob"),
+            Kind::Commit => write!(f, "commit"),
+            Kind::Tree => write!(f, "tree"),
+            Kind::Tag => write!(f, "tag"),
+        }
+    }
+}
+
+pub struct Object {
+    pub hash: Oid,
+    pub kind: Kind,
+    pub size: usize,
+    pub contents: Vec<u8>,
+}
+
+/// Who made a commit or tag and when: `Name <email> <seconds> <+hhmm>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// The time in the signer's own time zone.
+    pub time: DateTime<FixedOffset>,
+}
+
+impl Signature {
+    /// Who is making a new commit or tag, as its `role` ("author" or
+    /// "committer"), and when: `GOT_<ROLE>_NAME`, `GOT_<ROLE>_EMAIL` and
+    /// `GOT_<ROLE>_DATE` (`<seconds> <+hhmm>`) from the environment, else
+    /// `user.name`, `user.email` and the current time.
+    pub fn current(config: &Config, role: &str) -> anyhow::Result<Signature> {
+        Signature::current_or(config, role, || Ok(chrono::Local::now().fixed_offset()))
+    }
+
+    /// As `current`, but without `GOT_<ROLE>_DATE` the time is
+    /// `SOURCE_DATE_EPOCH` in UTC rather than now, so that doing the same
+    /// again signs the same way.
+    pub fn reproducible(config: &Config, role: &str) -> anyhow::Result<Signature> {
+        Signature::current_or(config, role, || {
+            let epoch = std::env::var("SOURCE_DATE_EPOCH").context("SOURCE_DATE_EPOCH must be set to sign reproducibly")?;
+            Ok(format!("x <x> {epoch} +0000").parse::<Signature>().context("invalid SOURCE_DATE_EPOCH")?.time)
+        })
+    }
+
+    /// Who to record in a reflog entry. As `current` for the committer,
+    /// but never fails: reflogs are bookkeeping, so without a configured
+    /// identity this falls back to the login name at the host name, as git
+    /// does, rather than refusing to move a ref.
+    pub fn reflog(config: &Config) -> Signature {
+        if let Ok(signature) = Signature::current(config, "committer") {
+            return signature;
+        }
+        let env = |field: &str| std::env::var(format!("GOT_COMMITTER_{field}")).ok();
+        let user = std::env::var("USER").or_else(|_| std::env::var("LOGNAME")).unwrap_or_else(|_| "unknown".to_string());
+        let host = fs::read_to_string("/etc/hostname")
+            .ok()
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| "localhost".to_string());
+        let time = env("DATE")
+            .and_then(|date| format!("x <x> {date}").parse::<Signature>().ok())
+            .map_or_else(|| chrono::Local::now().fixed_offset(), |signature| signature.time);
+        Signature {
+            name: env("NAME").or_else(|| config.get("user.name").map(str::to_string)).unwrap_or_else(|| user.clone()),
+            email: env("EMAIL")
+                .or_else(|| config.get("user.email").map(str::to_string))
+                .unwrap_or_else(|| format!("{user}@{host}")),
+            time,
+        }
+    }
+
+    fn current_or(config: &Config, role: &str, now: impl FnOnce() -> anyhow::Result<DateTime<FixedOffset>>) -> anyhow::Result<Signature> {
+        let env = |field: &str| std::env::var(format!("GOT_{}_{field}", role.to_ascii_uppercase())).ok();
+        let name = env("NAME").or_else(|| config.get("user.name").map(str::to_string));
+        let email = env("EMAIL").or_else(|| config.get("user.email").map(str::to_string));
+        let (Some(name), Some(email)) = (name, email) else {
+            anyhow::bail!("{role} identity unknown; set user.name and user.email");
+        };
+        let time = match env("DATE") {
+            Some(date) => format!("x <x> {date}").parse::<Signature>().context("invalid date")?.time,
+            None => now()?,
+        };
+        Ok(Signature { name, email, time })
+    }
+}
+
+impl FromStr for Signature {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Signature> {
+        let (Some(open), Some(close)) = (s.find('<'), s.rfind('>')) else {
+            anyhow::bail!("signature '{s}' has no email");
+        };
+        anyhow::ensure!(open < close, "signature '{s}' has no email");
+        let Some((seconds, zone)) = s[close + 1..].trim().split_once(' ') else {
+            anyhow::bail!("signature '{s}' has no date");
+        };
+        let seconds: i64 = seconds.parse().with_context(|| format!("signature '{s}' has an invalid date"))?;
+        let offset = parse_zone(zone).with_context(|| format!("signature '{s}' has an invalid time zone"))?;
+        let time = DateTime::from_timestamp(seconds, 0)
+            .with_context(|| format!("signature '{s}' has an out of range date"))?
+            .with_timezone(&offset);
+        Ok(Signature {
+            name: s[..open].trim_end().to_string(),
+            email: s[open + 1..close].to_string(),
+            time,
+        })
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} <{}> {} {}", self.name, self.email, self.time.timestamp(), self.time.format("%z"))
+    }
+}
+
+/// Parses a `+hhmm`/`-hhmm` zone.
+fn parse_zone(zone: &str) -> Option<FixedOffset> {
+    let (sign, digits) = match zone.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Splits an object's header lines from its message, joining the
+/// continuation lines (those starting with a space) of multi-line values
+/// such as `gpgsig` back onto their header.
+fn parse_headers(text: &str) -> anyhow::Result<(Vec<(String, String)>, String)> {
+    let (header, message) = match text.split_once("\n\n") {
+        Some((header, message)) => (header, message),
+        None => (text.strip_suffix('\n').unwrap_or(text), ""),
+    };
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in header.split('\n') {
+        if let Some(continued) = line.strip_prefix(' ') {
+            let Some((_, value)) = headers.last_mut() else {
+                anyhow::bail!("continuation line before the first header");
+            };
+            value.push('\n');
+            value.push_str(continued);
+            continue;
+        }
+        let Some((name, value)) = line.split_once(' ') else {
+            anyhow::bail!("malformed header line '{line}'");
+        };
+        headers.push((name.to_string(), value.to_string()));
+    }
+    Ok((headers, message.to_string()))
+}
+
+fn write_header(out: &mut String, name: &str, value: &str) {
+    out.push_str(name);
+    out.push(' ');
+    out.push_str(&value.replace('\n', "\n "));
+    out.push('\n');
+}
+
+/// A commit object's contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: Oid,
+    pub parents: Vec<Oid>,
+    pub author: Signature,
+    pub committer: Signature,
+    /// Headers after `committer` other than the signature, such as
+    /// `encoding` or `mergetag`, in their original order.
+    pub extra_headers: Vec<(String, String)>,
+    /// The armored signature from the `gpgsig` header.
+    pub gpgsig: Option<String>,
+    pub message: String,
+}
+
+impl Commit {
+    pub fn parse(contents: &[u8]) -> anyhow::Result<Commit> {
+        let text = std::str::from_utf8(contents).context("commit is not valid UTF-8")?;
+        let (headers, message) = parse_headers(text)?;
+        let mut headers = headers.into_iter().peekable();
+        let tree = match headers.next() {
+            Some((name, value)) if name == "tree" => value.parse()?,
+            _ => anyhow::bail!("commit does not start with a tree"),
+        };
+        let mut parents = vec![];
+        while let Some((_, parent)) = headers.next_if(|(name, _)| name == "parent") {
+            parents.push(parent.parse()?);
+        }
+        let author = match headers.next() {
+            Some((name, value)) if name == "author" => value.parse()?,
+            _ => anyhow::bail!("commit has no author"),
+        };
+        let committer = match headers.next() {
+            Some((name, value)) if name == "committer" => value.parse()?,
+            _ => anyhow::bail!("commit has no committer"),
+        };
+        let mut extra_headers = vec![];
+        let mut gpgsig = None;
+        for (name, value) in headers {
+            match name.as_str() {
+                "gpgsig" if gpgsig.is_none() => gpgsig = Some(value),
+                _ => extra_headers.push((name, value)),
+            }
+        }
+        Ok(Commit {
+            tree,
+            parents,
+            author,
+            committer,
+            extra_headers,
+            gpgsig,
+            message,
+        })
+    }
+
+    /// The commit's contents in the format git writes (and hashes).
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = String::new();
+        write_header(&mut out, "tree", &self.tree.to_string());
+        for parent in &self.parents {
+            write_header(&mut out, "parent", &parent.to_string());
+        }
+        write_header(&mut out, "author", &self.author.to_string());
+        write_header(&mut out, "committer", &self.committer.to_string());
+        for (name, value) in &self.extra_headers {
+            write_header(&mut out, name, value);
+        }
+        if let Some(gpgsig) = &self.gpgsig {
+            write_header(&mut out, "gpgsig", gpgsig);
+        }
+        out.push('\n');
+        out.push_str(&self.message);
+        out.into_bytes()
+    }
+}
+
+/// An annotated tag object's contents. A signed tag's signature is the
+/// tail of its message, as git stores it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub object: Oid,
+    pub kind: Kind,
+    pub name: String,
+    /// Missing from some very old tags.
+    pub tagger: Option<Signature>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn parse(contents: &[u8]) -> anyhow::Result<Tag> {
+        let text = std::str::from_utf8(contents).context("tag is not valid UTF-8")?;
+        let (headers, message) = parse_headers(text)?;
+        let mut headers = headers.into_iter();
+        let mut expect = |expected: &str| match headers.next() {
+            Some((name, value)) if name == expected => Ok(value),
+            _ => Err(anyhow::anyhow!("tag has no {expected} header")),
+        };
+        let object = expect("object")?.parse()?;
+        let kind = expect("type")?.parse()?;
+        let name = expect("tag")?;
+        let tagger = match headers.next() {
+            Some((name, value)) if name == "tagger" => Some(value.parse()?),
+            Some((name, _)) => anyhow::bail!("unexpected tag header '{name}'"),
+            None => None,
+        };
+        Ok(Tag {
+            object,
+            kind,
+            name,
+            tagger,
+            message,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = String::new();
+        write_header(&mut out, "object", &self.object.to_string());
+        write_header(&mut out, "type", &self.kind.to_string());
+        write_header(&mut out, "tag", &self.name);
+        if let Some(tagger) = &self.tagger {
+            write_header(&mut out, "tagger", &tagger.to_string());
+        }
+        out.push('\n');
+        out.push_str(&self.message);
+        out.into_bytes()
+    }
+}
+
+impl Object {
+    /// Reads the object named `hash`, or the object it has been replaced with
+    /// by a `refs/replace/<hash>` ref. The returned object keeps the requested
+    /// hash, so replacements are invisible to whoever is traversing history.
+    /// A commit grafted in `.got/info/grafts` comes with its grafted parents.
+    pub fn read(hash: &Oid) -> anyhow::Result<Object> {
+        let mut target = *hash;
+        if replace_refs_enabled() {
+            let mut depth = 0;
+            while let Some(replacement) = refs::read(repository::git_dir(), &format!("refs/replace/{target}"))? {
+                depth += 1;
+                anyhow::ensure!(depth <= MAX_REPLACE_DEPTH, "replace depth too high for object {hash}");
+                target = replacement
+                    .parse()
+                    .with_context(|| format!("invalid replace ref for object {hash}"))?;
+            }
+        }
+        let mut object = Object::read_original(&target)?;
+        object.hash = *hash;
+        if object.kind == Kind::Commit
+            && let Some(parents) = grafts().get(hash)
+        {
+            let mut commit = Commit::parse(&object.contents).with_context(|| format!("parse grafted commit {hash}"))?;
+            commit.parents = parents.clone();
+            object.contents = commit.serialize();
+            object.size = object.contents.len();
+        }
+        Ok(object)
+    }
+
+    /// Reads the object named `hash` from the object store, ignoring any
+    /// replacement for it. Packs are looked in first, so a loose copy of a
+    /// packed object is never read.
+    pub fn read_original(hash: &Oid) -> anyhow::Result<Object> {
+        for pack in pack::packs(&repository::objects_dir(repository::git_dir()))?.iter() {
+            if let Some((kind, contents)) = pack.read(hash)? {
+                return Ok(Object {
+                    hash: *hash,
+                    kind,
+                    size: contents.len(),
+                    contents,
+                });
+            }
+        }
+        let file = fs::File::open(object_path(hash))
+            .context("read header from .got/objects")?;
+        let z = ZlibDecoder::new(file);
+        let mut z = BufReader::new(z);
+        let mut buf = Vec::new();
+        z.read_until(b'\x00', &mut buf)
+            .context("read header from .got/objects")?;
+        let header = CStr::from_bytes_with_nul(&buf)
+            .expect("know there is exactly one nul, and it's at the end");
+        let header = header
+            .to_str()
+            .context(".got/objects file header isn't valid UTF-8")?;
+        let Some((kind, size)) = header.split_once(' ') else {
+            anyhow::bail!(".got/objects file header did not start with a known type: '{header}'");
+        };
+        let kind = match kind {
+            "blob" => Kind::Blob,
+            "commit" => Kind::Commit,
+            "tree" => Kind::Tree,
+            "tag" => Kind::Tag,
+            _ => anyhow::bail!("we do not yet know how to print a '{kind}'"),
+        };
+        let size = size
+            .parse::<usize>()
+            .context(".got/objects file header has invalid size: {size}")?;
+
+        let mut buf = vec![0; size];
+        z.read_exact(&mut buf[..])
+            .context("read true contents of .got/objects file")?;
+        let n = z.read(&mut [0])
+            .context("validate EOF in .got/object file")?;
+        anyhow::ensure!(n == 0, ".got/object file had {n} trailing bytes");
+        Ok(Object {
+            hash: *hash,
+            kind,
+            size,
+            contents: buf,
+        })
+    }
+
+    /// Whether the object named `hash` is in the object store, packed or
+    /// loose, without reading it.
+    pub fn exists(hash: &Oid) -> bool {
+        let packed = pack::packs(&repository::objects_dir(repository::git_dir()))
+            .is_ok_and(|packs| packs.iter().any(|pack| pack.contains(hash)));
+        packed || object_path(hash).exists()
+    }
+
+    /// The ID of an object of `kind` holding `contents`: the SHA-1 of its
+    /// `<kind> <size>\0` header and contents.
+    pub fn hash_of(kind: Kind, contents: &[u8]) -> Oid {
+        let mut hasher = Sha1::new();
+        hasher.update(format!("{kind} {}\0", contents.len()));
+        hasher.update(contents);
+        Oid::from_bytes(&hasher.finalize()).expect("SHA-1 digests are 20 bytes")
+    }
+
+    /// Stores `contents` as a loose object of `kind` unless it is already
+    /// present, packed or loose, and returns its ID.
+    pub fn store(kind: Kind, contents: &[u8]) -> anyhow::Result<Oid> {
+        let hash = Object::hash_of(kind, contents);
+        if Object::exists(&hash) {
+            return Ok(hash);
+        }
+        Object::store_loose(kind, contents)
+    }
+
+    /// Stores `contents` as a loose object of `kind` even if it is packed,
+    /// as when the pack is about to go, and returns its ID.
+    pub fn store_loose(kind: Kind, contents: &[u8]) -> anyhow::Result<Oid> {
+        let hash = Object::hash_of(kind, contents);
+        let path = object_path(&hash);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(format!("{kind} {}\0", contents.len()).as_bytes())?;
+        encoder.write_all(contents)?;
+        let compressed = encoder.finish()?;
+        fs::create_dir_all(path.parent().expect("object paths have a directory"))
+            .with_context(|| format!("create directory for object {hash}"))?;
+        // Write under a temporary name and rename, so a reader never sees
+        // a half-written object. The name is unique to this call, as other
+        // threads may be storing the same object.
+        static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp = path.with_extension(format!("tmp{}-{n}", std::process::id()));
+        fs::write(&temp, &compressed).with_context(|| format!("write object {hash}"))?;
+        fs::rename(&temp, &path).with_context(|| format!("write object {hash}"))?;
+        Ok(hash)
+    }
+
+    /// Stores the `size` bytes `reader` gives as a loose object of `kind`,
+    /// a chunk at a time so that memory use doesn't grow with the size, and
+    /// returns its ID. The compressed object goes to a temporary file until
+    /// its ID, and so its name, is known.
+    pub fn store_stream(kind: Kind, mut reader: impl Read, size: u64) -> anyhow::Result<Oid> {
+        let objects = repository::objects_dir(repository::git_dir());
+        static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp = objects.join(format!("tmp_obj_{}-{n}", std::process::id()));
+        let file = fs::File::create(&temp).with_context(|| format!("create {}", temp.display()))?;
+        let mut encoder = ZlibEncoder::new(std::io::BufWriter::new(file), Compression::default());
+        let mut hasher = Sha1::new();
+        let header = format!("{kind} {size}\0");
+        hasher.update(header.as_bytes());
+        let result = (|| -> anyhow::Result<()> {
+            encoder.write_all(header.as_bytes())?;
+            let mut chunk = vec![0; 64 * 1024];
+            let mut total = 0;
+            loop {
+                let n = reader.read(&mut chunk)?;
+                if n == 0 {
+                    break;
+                }
+                total += n as u64;
+                hasher.update(&chunk[..n]);
+                encoder.write_all(&chunk[..n])?;
+            }
+            anyhow::ensure!(total == size, "read {total} bytes where {size} were expected; did the file change?");
+            encoder.finish()?.flush()?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            fs::remove_file(&temp).ok();
+            return Err(e);
+        }
+        let hash = Oid::from_bytes(&hasher.finalize()).expect("SHA-1 digests are 20 bytes");
+        if Object::exists(&hash) {
+            fs::remove_file(&temp).ok();
+            return Ok(hash);
+        }
+        let path = object_path(&hash);
+        fs::create_dir_all(path.parent().expect("object paths have a directory"))
+            .with_context(|| format!("create directory for object {hash}"))?;
+        fs::rename(&temp, &path).with_context(|| format!("write object {hash}"))?;
+        Ok(hash)
+    }
+
+    pub fn write(path: &Path, kind: &String, buf: &[u8]) -> anyhow::Result<Object> {
+        let kind_str = kind.as_str();
+        let kind = match kind_str {
+            "blob" => Kind::Blob,
+            "commit" => Kind::Commit,
+            "tree" => Kind::Tree,
+            "tag" => Kind::Tag,
+            _ => anyhow::bail!("we do not yet know how to print a '{kind}'"),
+        };
+        if kind == Kind::Blob {
+            // Files can be any size, so they are streamed rather than read
+            // into `buf`.
+            let file = fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+            let size = file.metadata().with_context(|| format!("stat {}", path.display()))?.len();
+            let hash = Object::store_stream(kind, BufReader::new(file), size)?;
+            return Ok(Object {
+                hash,
+                kind,
+                size: size as usize,
+                contents: vec![],
+            });
+        }
+        // `buf` is the object as stored, header and all. Its ID is the hash
+        // of the whole of it, taken before anything is compressed.
+        let nul = buf.iter().position(|&b| b == 0).context("object has no header")?;
+        let header = String::from_utf8_lossy(&buf[..nul]);
+        let contents = &buf[nul + 1..];
+        anyhow::ensure!(
+            header == format!("{kind} {}", contents.len()),
+            "object header '{header}' does not match a {kind} of {} bytes",
+            contents.len()
+        );
+        let hash = Object::store(kind, contents)?;
+        Ok(Object {
+            hash,
+            kind,
+            size: contents.len(),
+            contents: contents.to_vec(),
+        })
+    }
+}
+
+/// How long a temporary object must have gone untouched to be taken for
+/// the leftover of a write that was interrupted, as by Ctrl-C, rather than
+/// one still going on: a streamed write touches its file as it goes.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Removes what streamed writes that never finished left in the object
+/// store, so that an interrupted `add` of a large file doesn't leave a
+/// partial object behind for good.
+pub fn remove_stale_temp_objects() -> anyhow::Result<()> {
+    let objects = repository::objects_dir(repository::git_dir());
+    let Ok(entries) = fs::read_dir(&objects) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry.with_context(|| format!("list {}", objects.display()))?;
+        if !entry.file_name().to_string_lossy().starts_with("tmp_obj_") {
+            continue;
+        }
+        let age = entry.metadata().and_then(|meta| meta.modified()).map(|modified| modified.elapsed().unwrap_or_default());
+        if age.is_ok_and(|age| age >= STALE_TEMP_AGE) {
+            fs::remove_file(entry.path()).with_context(|| format!("remove {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Where the loose object `hash` is stored.
+pub fn object_path(hash: &Oid) -> PathBuf {
+    let hex = hash.to_string();
+    repository::objects_dir(repository::git_dir()).join(&hex[..2]).join(&hex[2..])
+}
+
+/// Replacement refs are honored unless `GOT_NO_REPLACE_OBJECTS` is set or
+/// `core.useReplaceRefs` is false.
+fn replace_refs_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        if std::env::var_os("GOT_NO_REPLACE_OBJECTS").is_some() {
+            return false;
+        }
+        Config::load()
+            .ok()
+            .and_then(|config| config.get_bool("core.useReplaceRefs").ok().flatten())
+            .unwrap_or(true)
+    })
+}
+
+/// The repository's grafts, read once.
+fn grafts() -> &'static HashMap<Oid, Vec<Oid>> {
+    static GRAFTS: OnceLock<HashMap<Oid, Vec<Oid>>> = OnceLock::new();
+    GRAFTS.get_or_init(|| grafts::read(repository::git_dir()).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::ScratchRepository;
+
+    // Objects written by git itself, with the hashes git gave them.
+
+    const ROOT_COMMIT: (&str, &str) = (
+        "b52e59b6caf7cf35f0983db475d100412c301a01",
+        "tree aaff74984cccd156a469afa7d9ab10e4777beb24
+author A U Thor <author@example.com> 1700000000 +0530
+committer A U Thor <author@example.com> 1700000100 -0800
+
+Initial commit
+",
+    );
+
+    const COMMIT_WITH_BODY: (&str, &str) = (
+        "4b86fd8946eb0eccc452def84b65873f5d90dfc9",
+        "tree 3a247983d5372d3d195a08a8905eea1712cb881c
+parent b52e59b6caf7cf35f0983db475d100412c301a01
+author A U Thor <author@example.com> 1700000300 +0100
+committer A U Thor <author@example.com> 1700000300 +0100
+
+Add c
+
+With a body paragraph.
+",
+    );
+
+    const MERGE_COMMIT: (&str, &str) = (
+        "b099bad0c7a38148b84e97ec28f98ae2ce1a9b2d",
+        "tree 04a59185a0c5f4047e4fd3fa87b0c84e671b00ee
+parent 4b86fd8946eb0eccc452def84b65873f5d90dfc9
+parent cd8c51f22cb21715d9f54bc64dcf423892b701ef
+author A U Thor <author@example.com> 1700000400 +0100
+committer A U Thor <author@example.com> 1700000400 +0100
+
+Merge branch 'side'
+",
+    );
+
+    const SIGNED_COMMIT: (&str, &str) = (
+        "545e4860b1a7673be24f5b45fa238898719b58ab",
+        "tree 425b679dfe63c98f9f3e8ffa38e06e556acadf58
+parent b099bad0c7a38148b84e97ec28f98ae2ce1a9b2d
+author A U Thor <author@example.com> 1700000500 +0000
+committer A U Thor <author@example.com> 1700000500 +0000
+gpgsig -----BEGIN PGP SIGNATURE-----
+ 
+ iIkEABYIADEWIQR/VMLOKk/IIkAS6C4/0los2PhW9wUCatJYORMcc2lnbmVyQGV4
+ YW1wbGUuY29tAAoJED/SWizY+Fb3fGMA/36AmTwZ75nCgu9M3Xjpu2zAbyRcTnkj
+ Go3Ajtgggm8tAP4xPoTxJqmrNVXEj+mi3oS+/OZ0K/5l0RGbBg1EP65uDQ==
+ =E3VU
+ -----END PGP SIGNATURE-----
+
+Signed commit
+",
+    );
+
+    const TAG: (&str, &str) = (
+        "ce41b371927fce4df9fc3566ae81a164b60d67c4",
+        "object b099bad0c7a38148b84e97ec28f98ae2ce1a9b2d
+type commit
+tag v1
+tagger A U Thor <author@example.com> 1700000600 +0200
+
+Version 1
+",
+    );
+
+    const SIGNED_TAG: (&str, &str) = (
+        "f04889139a1a00b966de0e5a9a146464bc5533eb",
+        "object 545e4860b1a7673be24f5b45fa238898719b58ab
+type commit
+tag v2
+tagger A U Thor <author@example.com> 1700000700 +0200
+
+Signed version
+-----BEGIN PGP SIGNATURE-----
+
+iIkEABYIADEWIQR/VMLOKk/IIkAS6C4/0los2PhW9wUCatJYORMcc2lnbmVyQGV4
+YW1wbGUuY29tAAoJED/SWizY+Fb3f2IA/jSbYmAaElgWZzWzW0ofuxHe5pRM8Igt
+GMuuaLiwJTIiAQCOMemOhejow9+lJs2ySxUZkGxh0c8Rut7TZpe03zxABw==
+=9Bm5
+-----END PGP SIGNATURE-----
+",
+    );
+
+    fn object_hash(kind: Kind, contents: &[u8]) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(format!("{kind} {}\0", contents.len()));
+        hasher.update(contents);
+        hex::encode(hasher.finalize())
+    }
+
+    #[test]
+    fn commits_round_trip() {
+        for (hash, raw) in [ROOT_COMMIT, COMMIT_WITH_BODY, MERGE_COMMIT, SIGNED_COMMIT] {
+            assert_eq!(object_hash(Kind::Commit, raw.as_bytes()), hash);
+            let commit = Commit::parse(raw.as_bytes()).unwrap();
+            assert_eq!(String::from_utf8(commit.serialize()).unwrap(), raw);
+        }
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        for (hash, raw) in [TAG, SIGNED_TAG] {
+            assert_eq!(object_hash(Kind::Tag, raw.as_bytes()), hash);
+            let tag = Tag::parse(raw.as_bytes()).unwrap();
+            assert_eq!(String::from_utf8(tag.serialize()).unwrap(), raw);
+        }
+    }
+
+    #[test]
+    fn commit_fields() {
+        let commit = Commit::parse(ROOT_COMMIT.1.as_bytes()).unwrap();
+        assert_eq!(commit.tree.to_string(), "aaff74984cccd156a469afa7d9ab10e4777beb24");
+        assert!(commit.parents.is_empty());
+        assert_eq!(commit.author.name, "A U Thor");
+        assert_eq!(commit.author.email, "author@example.com");
+        assert_eq!(commit.author.time.timestamp(), 1700000000);
+        assert_eq!(commit.author.time.offset().local_minus_utc(), 5 * 3600 + 30 * 60);
+        assert_eq!(commit.committer.time.offset().local_minus_utc(), -8 * 3600);
+        assert_eq!(commit.gpgsig, None);
+        assert_eq!(commit.message, "Initial commit\n");
+
+        let merge = Commit::parse(MERGE_COMMIT.1.as_bytes()).unwrap();
+        let parents: Vec<String> = merge.parents.iter().map(Oid::to_string).collect();
+        assert_eq!(
+            parents,
+            ["4b86fd8946eb0eccc452def84b65873f5d90dfc9", "cd8c51f22cb21715d9f54bc64dcf423892b701ef"]
+        );
+
+        let body = Commit::parse(COMMIT_WITH_BODY.1.as_bytes()).unwrap();
+        assert_eq!(body.message, "Add c\n\nWith a body paragraph.\n");
+    }
+
+    #[test]
+    fn commit_signature() {
+        let commit = Commit::parse(SIGNED_COMMIT.1.as_bytes()).unwrap();
+        let gpgsig = commit.gpgsig.unwrap();
+        assert!(gpgsig.starts_with("-----BEGIN PGP SIGNATURE-----\n\niIkE"));
+        assert!(gpgsig.ends_with("=E3VU\n-----END PGP SIGNATURE-----"));
+        assert!(commit.extra_headers.is_empty());
+        assert_eq!(commit.message, "Signed commit\n");
+    }
+
+    #[test]
+    fn tag_fields() {
+        let tag = Tag::parse(SIGNED_TAG.1.as_bytes()).unwrap();
+        assert_eq!(tag.object.to_string(), "545e4860b1a7673be24f5b45fa238898719b58ab");
+        assert_eq!(tag.kind, Kind::Commit);
+        assert_eq!(tag.name, "v2");
+        let tagger = tag.tagger.unwrap();
+        assert_eq!(tagger.to_string(), "A U Thor <author@example.com> 1700000700 +0200");
+        assert!(tag.message.starts_with("Signed version\n-----BEGIN PGP SIGNATURE-----\n"));
+    }
+
+    #[test]
+    fn objects_round_trip_through_the_store() {
+        let _repository = ScratchRepository::new("object-test");
+
+        // Big enough that the compressor can't take it in one write.
+        let message = "line of a long message\n".repeat(100_000);
+        let big = format!("{}\n{message}", ROOT_COMMIT.1.trim_end());
+        let path = Path::new("unused");
+        for (kind, raw) in [(Kind::Commit, ROOT_COMMIT.1), (Kind::Tag, TAG.1), (Kind::Commit, big.as_str())] {
+            let mut buf = format!("{kind} {}\0", raw.len()).into_bytes();
+            buf.extend(raw.as_bytes());
+            let written = Object::write(path, &kind.to_string(), &buf).unwrap();
+            assert_eq!(written.hash.to_string(), object_hash(kind, raw.as_bytes()));
+            let read = Object::read(&written.hash).unwrap();
+            assert_eq!(read.kind, kind);
+            assert_eq!(read.contents, raw.as_bytes());
+        }
+        let (hash, raw) = ROOT_COMMIT;
+        assert_eq!(Object::store(Kind::Commit, raw.as_bytes()).unwrap().to_string(), hash);
+
+        let mut buf = b"tree 5\0four".to_vec();
+        assert!(Object::write(path, &String::from("tree"), &buf).is_err());
+        buf.clear();
+        assert!(Object::write(path, &String::from("tree"), &buf).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_objects() {
+        assert!(Commit::parse(b"parent abc\n\nmessage\n").is_err());
+        assert!(Commit::parse(b"tree abc\nauthor A <a@b> 1 +0000\ncommitter A <a@b> 1 +0000\n\nshort tree\n").is_err());
+        let tree = "tree aaff74984cccd156a469afa7d9ab10e4777beb24\n";
+        assert!(Commit::parse(format!("{tree}author A <a@b> 1 +0000\n\nno committer\n").as_bytes()).is_err());
+        assert!(
+            Commit::parse(format!("{tree}author A <a@b> 1 0000\ncommitter A <a@b> 1 +0000\n\nbad zone\n").as_bytes())
+                .is_err()
+        );
+        let object = "object b099bad0c7a38148b84e97ec28f98ae2ce1a9b2d\n";
+        assert!(Tag::parse(format!("{object}type banana\ntag v1\n\nmessage\n").as_bytes()).is_err());
+        assert!("A U Thor 1700000000 +0000".parse::<Signature>().is_err());
+    }
+}
@@ -0,0 +1,58 @@
+//! Object IDs: the 20-byte SHA-1 hash naming every object.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Oid([u8; 20]);
+
+impl Oid {
+    /// The all-zero ID, which git uses to mean "no object".
+    pub const ZERO: Oid = Oid([0; 20]);
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Oid> {
+        let bytes: [u8; 20] = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("object ID must be 20 bytes, not {}", bytes.len()))?;
+        Ok(Oid(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Oid::ZERO
+    }
+
+    /// The first `len` hex digits (all 40 if `len` is larger).
+    pub fn abbrev(&self, len: usize) -> String {
+        let mut hex = self.to_string();
+        hex.truncate(len);
+        hex
+    }
+}
+
+impl FromStr for Oid {
+    type Err = anyhow::Error;
+
+    /// Parses a full 40-digit hex ID, in either case.
+    fn from_str(s: &str) -> anyhow::Result<Oid> {
+        anyhow::ensure!(s.len() == 40, "invalid object ID '{s}'");
+        let mut bytes = [0; 20];
+        hex::decode_to_slice(s, &mut bytes).map_err(|_| anyhow::anyhow!("invalid object ID '{s}'"))?;
+        Ok(Oid(bytes))
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Oid({self})")
+    }
+}
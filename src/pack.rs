@@ -0,0 +1,936 @@
+//! Pack files, which store many objects in one file, most of them as
+//! deltas against similar objects, alongside an index for finding them.
+//!
+//! Packs live in `.got/objects/pack` as `pack-<hash>.pack`, each with a
+//! `pack-<hash>.idx`, and sometimes a `pack-<hash>.keep` whose contents
+//! say why the pack must not be repacked away. The index lists the
+//! objects' hashes in order, with a fan-out table by first byte, the CRC-32
+//! of each object's packed data and its offset in the pack. Version 1
+//! indexes have no CRCs and only 32-bit offsets.
+//!
+//! In the pack, each object has a header giving its type and inflated size,
+//! followed by its zlib-compressed data. A delta is either against the
+//! object a given distance back in the same pack (`OFS_DELTA`) or against
+//! an object named by its hash (`REF_DELTA`), and holds instructions to copy
+//! ranges of the base object or insert new bytes. Packs got writes only use
+//! `OFS_DELTA`, with every base before the deltas against it.
+
+use crate::object::{Kind, Object};
+use crate::oid::Oid;
+use anyhow::Context;
+use flate2::Compression;
+use flate2::Crc;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+const PACK_MAGIC: &[u8; 4] = b"PACK";
+const IDX_MAGIC: &[u8; 4] = b"\xfftOc";
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+/// How many deltas deep a chain may be before it is taken to be a cycle.
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// The contents of a pack's `.idx` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackIndex {
+    pub version: u32,
+    fanout: Vec<u32>,
+    /// Sorted.
+    pub oids: Vec<Oid>,
+    /// Empty for version 1.
+    pub crcs: Vec<u32>,
+    pub offsets: Vec<u64>,
+    pub pack_checksum: Oid,
+}
+
+impl PackIndex {
+    pub fn read(path: &Path) -> anyhow::Result<PackIndex> {
+        let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        PackIndex::parse(&data).with_context(|| format!("index file {} is corrupt", path.display()))
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<PackIndex> {
+        let (version, fanout_start) = match data.strip_prefix(IDX_MAGIC) {
+            Some(rest) => {
+                let version = u32::from_be_bytes(rest.get(..4).context("truncated header")?.try_into()?);
+                anyhow::ensure!(version == 2, "unsupported index version {version}");
+                (version, 8)
+            }
+            None => (1, 0),
+        };
+        let fanout: Vec<u32> = data
+            .get(fanout_start..fanout_start + 256 * 4)
+            .context("truncated fan-out table")?
+            .chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().expect("chunks of 4")))
+            .collect();
+        anyhow::ensure!(fanout.windows(2).all(|pair| pair[0] <= pair[1]), "non-monotonic fan-out table");
+        let count = fanout[255] as usize;
+        let mut pos = fanout_start + 256 * 4;
+        let mut take = |len: usize| -> anyhow::Result<&[u8]> {
+            let bytes = data.get(pos..pos + len).context("index is truncated")?;
+            pos += len;
+            Ok(bytes)
+        };
+        let mut index = PackIndex { version, fanout, ..PackIndex::default() };
+        if version == 1 {
+            for _ in 0..count {
+                let entry = take(24)?;
+                index.offsets.push(u32::from_be_bytes(entry[..4].try_into()?).into());
+                index.oids.push(Oid::from_bytes(&entry[4..])?);
+            }
+        } else {
+            for _ in 0..count {
+                index.oids.push(Oid::from_bytes(take(20)?)?);
+            }
+            for _ in 0..count {
+                index.crcs.push(u32::from_be_bytes(take(4)?.try_into()?));
+            }
+            let small: Vec<u32> = (0..count).map(|_| Ok(u32::from_be_bytes(take(4)?.try_into()?))).collect::<anyhow::Result<_>>()?;
+            for offset in small {
+                // With the top bit set, the rest indexes the table of large
+                // offsets that follows.
+                if offset & 0x8000_0000 == 0 {
+                    index.offsets.push(offset.into());
+                } else {
+                    let at = pos + (offset & 0x7fff_ffff) as usize * 8;
+                    let large = data.get(at..at + 8).context("large offset out of range")?;
+                    index.offsets.push(u64::from_be_bytes(large.try_into()?));
+                }
+            }
+        }
+        anyhow::ensure!(index.oids.windows(2).all(|pair| pair[0] < pair[1]), "object names are not sorted");
+        let trailer = data.len().checked_sub(40).context("index is truncated")?;
+        index.pack_checksum = Oid::from_bytes(&data[trailer..trailer + 20])?;
+        Ok(index)
+    }
+
+    /// The index in version 2, whatever version it was read in. Offsets
+    /// that don't fit in 31 bits go in a table of 64-bit ones.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = IDX_MAGIC.to_vec();
+        out.extend(2u32.to_be_bytes());
+        for count in &self.fanout {
+            out.extend(count.to_be_bytes());
+        }
+        for oid in &self.oids {
+            out.extend(oid.as_bytes());
+        }
+        for i in 0..self.oids.len() {
+            out.extend(self.crcs.get(i).copied().unwrap_or(0).to_be_bytes());
+        }
+        let mut large = vec![];
+        for &offset in &self.offsets {
+            match u32::try_from(offset) {
+                Ok(small) if small & 0x8000_0000 == 0 => out.extend(small.to_be_bytes()),
+                _ => {
+                    out.extend((0x8000_0000 | large.len() as u32).to_be_bytes());
+                    large.push(offset);
+                }
+            }
+        }
+        for offset in large {
+            out.extend(offset.to_be_bytes());
+        }
+        out.extend(self.pack_checksum.as_bytes());
+        let checksum = Sha1::digest(&out);
+        out.extend(checksum);
+        out
+    }
+
+    /// Where in the list `hash` is, if it is there.
+    pub fn position(&self, hash: &Oid) -> Option<usize> {
+        let first = hash.as_bytes()[0] as usize;
+        let start = if first == 0 { 0 } else { self.fanout[first - 1] as usize };
+        let end = self.fanout[first] as usize;
+        self.oids[start..end].binary_search(hash).ok().map(|i| start + i)
+    }
+
+    /// The objects whose hex names start with `prefix`.
+    pub fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a Oid> + 'a {
+        let first = u8::from_str_radix(prefix.get(..2).unwrap_or("00"), 16).unwrap_or(0) as usize;
+        let start = if first == 0 { 0 } else { self.fanout[first - 1] as usize };
+        let end = self.fanout[first] as usize;
+        self.oids[start..end].iter().filter(move |oid| oid.to_string().starts_with(prefix))
+    }
+}
+
+/// A pack and its index.
+#[derive(Debug)]
+pub struct Pack {
+    pub path: PathBuf,
+    pub index: PackIndex,
+    /// The position in the index of the object at each offset, built the
+    /// first time a delta's base is looked up by offset.
+    positions: OnceLock<HashMap<u64, usize>>,
+}
+
+impl Pack {
+    /// Opens the pack whose index is at `idx_path`.
+    pub fn open(idx_path: &Path) -> anyhow::Result<Pack> {
+        Ok(Pack {
+            path: idx_path.with_extension("pack"),
+            index: PackIndex::read(idx_path)?,
+            positions: OnceLock::new(),
+        })
+    }
+
+    pub fn contains(&self, hash: &Oid) -> bool {
+        self.index.position(hash).is_some()
+    }
+
+    /// Whether a `.keep` file protects the pack from being repacked away.
+    pub fn is_kept(&self) -> bool {
+        self.path.with_extension("keep").exists()
+    }
+
+    /// Reads object `hash` from the pack, if it is there.
+    pub fn read(&self, hash: &Oid) -> anyhow::Result<Option<(Kind, Vec<u8>)>> {
+        let Some(position) = self.index.position(hash) else {
+            return Ok(None);
+        };
+        let file = fs::File::open(&self.path).with_context(|| format!("open {}", self.path.display()))?;
+        let mut file = BufReader::new(file);
+        let object = self
+            .read_at(&mut file, self.index.offsets[position])
+            .with_context(|| format!("read object {hash} from {}", self.path.display()))?;
+        Ok(Some(object))
+    }
+
+    /// The base and the delta object `hash` is stored as, if the pack has it
+    /// as a delta, so that a new pack can copy the delta rather than look for
+    /// one again.
+    pub fn read_delta(&self, hash: &Oid) -> anyhow::Result<Option<(Oid, Vec<u8>)>> {
+        let Some(position) = self.index.position(hash) else {
+            return Ok(None);
+        };
+        let offset = self.index.offsets[position];
+        let file = fs::File::open(&self.path).with_context(|| format!("open {}", self.path.display()))?;
+        let mut file = BufReader::new(file);
+        file.seek(SeekFrom::Start(offset)).context("seek in pack")?;
+        let (kind, size) = read_header(&mut file)?;
+        let base = match kind {
+            OFS_DELTA => {
+                let distance = read_offset(&mut file)?;
+                let base = offset.checked_sub(distance).context("delta base offset out of range")?;
+                let positions = self
+                    .positions
+                    .get_or_init(|| self.index.offsets.iter().enumerate().map(|(position, &offset)| (offset, position)).collect());
+                let position = positions.get(&base).with_context(|| format!("delta base of {hash} is not in the index"))?;
+                self.index.oids[*position]
+            }
+            REF_DELTA => {
+                let mut base = [0; 20];
+                file.read_exact(&mut base).context("read delta base name")?;
+                Oid::from_bytes(&base)?
+            }
+            _ => return Ok(None),
+        };
+        let delta = inflate(&mut file, size).with_context(|| format!("read object {hash} from {}", self.path.display()))?;
+        Ok(Some((base, delta)))
+    }
+
+    /// Reads the object at `offset`, applying any deltas.
+    fn read_at(&self, file: &mut BufReader<fs::File>, mut offset: u64) -> anyhow::Result<(Kind, Vec<u8>)> {
+        let mut deltas = vec![];
+        let (kind, mut contents) = loop {
+            anyhow::ensure!(deltas.len() < MAX_DELTA_DEPTH, "delta chain too deep");
+            file.seek(SeekFrom::Start(offset)).context("seek in pack")?;
+            let (kind, size) = read_header(file)?;
+            let base = match kind {
+                OFS_DELTA => {
+                    let distance = read_offset(file)?;
+                    Some(offset.checked_sub(distance).context("delta base offset out of range")?)
+                }
+                REF_DELTA => {
+                    let mut hash = [0; 20];
+                    file.read_exact(&mut hash).context("read delta base name")?;
+                    let hash = Oid::from_bytes(&hash)?;
+                    let position = self.index.position(&hash).with_context(|| format!("delta base {hash} is not in the pack"))?;
+                    Some(self.index.offsets[position])
+                }
+                _ => None,
+            };
+            let data = inflate(file, size)?;
+            match base {
+                Some(base) => {
+                    deltas.push(data);
+                    offset = base;
+                }
+                None => break (object_kind(kind)?, data),
+            }
+        };
+        while let Some(delta) = deltas.pop() {
+            contents = apply_delta(&contents, &delta)?;
+        }
+        Ok((kind, contents))
+    }
+}
+
+/// Writes a pack, object by object, into the pack directory, then its
+/// index, naming both by the pack's checksum.
+pub struct PackWriter {
+    dir: PathBuf,
+    temp: PathBuf,
+    file: BufWriter<fs::File>,
+    hasher: Sha1,
+    offset: u64,
+    count: u32,
+    /// Each object written with the CRC-32 of its packed data and its
+    /// offset, in the order written.
+    written: Vec<(Oid, u32, u64)>,
+    offsets: HashMap<Oid, u64>,
+}
+
+impl PackWriter {
+    /// Starts a pack in `dir` that is to hold `count` objects.
+    pub fn new(dir: &Path, count: u32) -> anyhow::Result<PackWriter> {
+        static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+        let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp = dir.join(format!("tmp_pack_{}-{n}", std::process::id()));
+        let file = fs::File::create(&temp).with_context(|| format!("create {}", temp.display()))?;
+        let mut writer = PackWriter {
+            dir: dir.to_path_buf(),
+            temp,
+            file: BufWriter::new(file),
+            hasher: Sha1::new(),
+            offset: 0,
+            count,
+            written: vec![],
+            offsets: HashMap::new(),
+        };
+        let mut header = PACK_MAGIC.to_vec();
+        header.extend(2u32.to_be_bytes());
+        header.extend(count.to_be_bytes());
+        writer.write(&header)?;
+        Ok(writer)
+    }
+
+    /// Adds object `hash`: its contents as `data`, or with `base`, a delta
+    /// against that object, which must already be in the pack.
+    pub fn add(&mut self, hash: &Oid, kind: Kind, data: &[u8], base: Option<&Oid>) -> anyhow::Result<()> {
+        anyhow::ensure!(self.written.len() < self.count as usize, "more objects than the pack was started for");
+        let mut entry = vec![];
+        let code = match base {
+            Some(_) => OFS_DELTA,
+            None => kind_code(kind),
+        };
+        let mut size = data.len() as u64;
+        let mut byte = code << 4 | (size & 0x0f) as u8;
+        size >>= 4;
+        while size != 0 {
+            entry.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        entry.push(byte);
+        if let Some(base) = base {
+            let base_offset = *self.offsets.get(base).with_context(|| format!("delta base {base} is not in the pack yet"))?;
+            let mut distance = self.offset - base_offset;
+            let mut encoded = vec![(distance & 0x7f) as u8];
+            distance >>= 7;
+            while distance != 0 {
+                distance -= 1;
+                encoded.push(0x80 | (distance & 0x7f) as u8);
+                distance >>= 7;
+            }
+            entry.extend(encoded.iter().rev());
+        }
+        let mut encoder = ZlibEncoder::new(entry, Compression::default());
+        encoder.write_all(data).context("compress object")?;
+        let entry = encoder.finish().context("compress object")?;
+        let mut crc = Crc::new();
+        crc.update(&entry);
+        self.written.push((*hash, crc.sum(), self.offset));
+        self.offsets.insert(*hash, self.offset);
+        self.write(&entry)
+    }
+
+    /// Whether object `hash` has been added.
+    pub fn contains(&self, hash: &Oid) -> bool {
+        self.offsets.contains_key(hash)
+    }
+
+    /// Finishes the pack and writes its index, returning the pack's path
+    /// and its index.
+    pub fn finish(mut self) -> anyhow::Result<(PathBuf, PackIndex)> {
+        anyhow::ensure!(self.written.len() == self.count as usize, "fewer objects than the pack was started for");
+        let checksum = self.hasher.finalize_reset();
+        self.file.write_all(&checksum).context("write pack")?;
+        self.file.into_inner().map_err(|e| e.into_error()).and_then(|file| file.sync_all()).context("write pack")?;
+        let checksum = Oid::from_bytes(&checksum)?;
+        let mut written = self.written;
+        written.sort();
+        let oids: Vec<Oid> = written.iter().map(|(oid, ..)| *oid).collect();
+        let index = PackIndex {
+            version: 2,
+            fanout: fanout(&oids),
+            oids,
+            crcs: written.iter().map(|(_, crc, _)| *crc).collect(),
+            offsets: written.iter().map(|(.., offset)| *offset).collect(),
+            pack_checksum: checksum,
+        };
+        let path = self.dir.join(format!("pack-{checksum}.pack"));
+        fs::rename(&self.temp, &path).with_context(|| format!("write {}", path.display()))?;
+        let idx = path.with_extension("idx");
+        let temp = self.temp.with_extension("idx");
+        fs::write(&temp, index.serialize()).with_context(|| format!("write {}", idx.display()))?;
+        fs::rename(&temp, &idx).with_context(|| format!("write {}", idx.display()))?;
+        Ok((path, index))
+    }
+
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.hasher.update(data);
+        self.offset += data.len() as u64;
+        self.file.write_all(data).context("write pack")
+    }
+}
+
+/// Stores `data`, a whole pack received from elsewhere, in `dir` under its
+/// checksum and writes its index, as `git index-pack` does, returning the
+/// pack's path and index. With `keep`, a `.keep` file holding that text is
+/// written before the pack appears. Every delta's base must be in the pack.
+pub fn index_pack(dir: &Path, data: &[u8], keep: Option<&str>) -> anyhow::Result<(PathBuf, PackIndex)> {
+    let (checksum, mut written) = walk_pack(data, |_, _, _| Ok(()))?;
+    written.sort();
+    written.dedup_by_key(|(oid, ..)| *oid);
+
+    let oids: Vec<Oid> = written.iter().map(|(oid, ..)| *oid).collect();
+    let index = PackIndex {
+        version: 2,
+        fanout: fanout(&oids),
+        oids,
+        crcs: written.iter().map(|(_, crc, _)| *crc).collect(),
+        offsets: written.iter().map(|(.., offset)| *offset).collect(),
+        pack_checksum: checksum,
+    };
+    fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    let path = dir.join(format!("pack-{checksum}.pack"));
+    if let Some(reason) = keep {
+        let keep = path.with_extension("keep");
+        fs::write(&keep, format!("{reason}\n")).with_context(|| format!("write {}", keep.display()))?;
+    }
+    let idx = index.serialize();
+    // Packs may be fetched from several remotes at once, so the temporary
+    // names are unique to this call.
+    static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    for (extension, contents) in [("pack", data), ("idx", &idx[..])] {
+        let target = path.with_extension(extension);
+        let temp = dir.join(format!("tmp_{extension}_{}-{n}", std::process::id()));
+        fs::write(&temp, contents).with_context(|| format!("write {}", target.display()))?;
+        fs::rename(&temp, &target).with_context(|| format!("write {}", target.display()))?;
+    }
+    Ok((path, index))
+}
+
+/// Calls `found` with the kind, contents and hash of each object in `data`,
+/// a whole pack received from elsewhere, without storing any of them.
+/// Every delta's base must be in the pack.
+pub fn for_each_object(data: &[u8], found: impl FnMut(Kind, &[u8], &Oid) -> anyhow::Result<()>) -> anyhow::Result<()> {
+    walk_pack(data, found).map(|_| ())
+}
+
+/// Stores each object in `data`, a whole pack received from elsewhere, as a
+/// loose object in the repository, as `git unpack-objects` does, unless
+/// `dry_run`. Returns how many objects the pack holds. Every delta's base
+/// must be in the pack.
+pub fn unpack_objects(data: &[u8], dry_run: bool) -> anyhow::Result<usize> {
+    let (_, objects) = walk_pack(data, |kind, contents, _| {
+        if !dry_run {
+            Object::store(kind, contents)?;
+        }
+        Ok(())
+    })?;
+    Ok(objects.len())
+}
+
+/// How many objects the pack `data` says it holds.
+pub fn object_count(data: &[u8]) -> anyhow::Result<usize> {
+    anyhow::ensure!(data.len() >= 12 && data.starts_with(PACK_MAGIC), "not a pack");
+    Ok(u32::from_be_bytes(data[8..12].try_into()?) as usize)
+}
+
+/// An object's hash, the CRC-32 of its packed bytes and its offset in its
+/// pack.
+type PackedObject = (Oid, u32, u64);
+
+/// Checks and parses `data`, a whole pack, calling `found` with the type,
+/// contents and hash of each object once resolved, each base before the
+/// deltas against it. Returns the pack's checksum and its objects.
+fn walk_pack(data: &[u8], mut found: impl FnMut(Kind, &[u8], &Oid) -> anyhow::Result<()>) -> anyhow::Result<(Oid, Vec<PackedObject>)> {
+    let end = data.len().checked_sub(20).filter(|&end| end >= 12).context("pack is truncated")?;
+    anyhow::ensure!(data.starts_with(PACK_MAGIC), "not a pack");
+    let version = u32::from_be_bytes(data[4..8].try_into()?);
+    anyhow::ensure!(version == 2 || version == 3, "unsupported pack version {version}");
+    let count = object_count(data)?;
+    anyhow::ensure!(Sha1::digest(&data[..end]).as_slice() == &data[end..], "pack checksum mismatch");
+    let checksum = Oid::from_bytes(&data[end..])?;
+    // Each object as it is stored: its type, what it is a delta against if
+    // it is one, its inflated data and the CRC-32 of its packed bytes.
+    struct Entry {
+        offset: u64,
+        kind: u8,
+        base: Option<Result<u64, Oid>>,
+        data: Vec<u8>,
+        crc: u32,
+    }
+    let mut entries = Vec::with_capacity(count.min(1 << 20));
+    let mut pos = 12;
+    for _ in 0..count {
+        let mut input = &data[pos..end];
+        let (kind, size) = read_header(&mut input)?;
+        let base = match kind {
+            OFS_DELTA => {
+                let distance = read_offset(&mut input)?;
+                Some(Ok((pos as u64).checked_sub(distance).context("delta base offset out of range")?))
+            }
+            REF_DELTA => {
+                let hash = input.get(..20).context("pack is truncated")?;
+                input = &input[20..];
+                Some(Err(Oid::from_bytes(hash)?))
+            }
+            _ => None,
+        };
+        let mut decoder = flate2::bufread::ZlibDecoder::new(input);
+        let mut contents = Vec::with_capacity(size.min(1 << 24) as usize);
+        decoder.read_to_end(&mut contents).context("inflate packed object")?;
+        anyhow::ensure!(contents.len() as u64 == size, "packed object is {} bytes, expected {size}", contents.len());
+        let next = end - decoder.into_inner().len();
+        let mut crc = Crc::new();
+        crc.update(&data[pos..next]);
+        entries.push(Entry {
+            offset: pos as u64,
+            kind,
+            base,
+            data: contents,
+            crc: crc.sum(),
+        });
+        pos = next;
+    }
+    anyhow::ensure!(pos == end, "pack has {} bytes of garbage after its objects", end - pos);
+
+    // Resolve each whole object and then, depth first, the deltas against it.
+    let mut by_offset: HashMap<u64, Vec<usize>> = HashMap::new();
+    let mut by_hash: HashMap<Oid, Vec<usize>> = HashMap::new();
+    let mut stack = vec![];
+    for (i, entry) in entries.iter().enumerate() {
+        match entry.base {
+            Some(Ok(offset)) => by_offset.entry(offset).or_default().push(i),
+            Some(Err(hash)) => by_hash.entry(hash).or_default().push(i),
+            None => stack.push((i, object_kind(entry.kind)?, entry.data.clone())),
+        }
+    }
+    let mut written = vec![];
+    while let Some((i, kind, contents)) = stack.pop() {
+        let hash = Object::hash_of(kind, &contents);
+        found(kind, &contents, &hash)?;
+        written.push((hash, entries[i].crc, entries[i].offset));
+        let deltas = by_offset.remove(&entries[i].offset).into_iter().chain(by_hash.remove(&hash)).flatten();
+        for delta in deltas {
+            stack.push((delta, kind, apply_delta(&contents, &entries[delta].data)?));
+        }
+    }
+    anyhow::ensure!(written.len() == count, "pack has {} unresolved deltas", count - written.len());
+    Ok((checksum, written))
+}
+
+/// How many of the sorted `oids` start with each byte or a lower one.
+fn fanout(oids: &[Oid]) -> Vec<u32> {
+    let mut fanout = vec![0u32; 256];
+    for oid in oids {
+        fanout[oid.as_bytes()[0] as usize] += 1;
+    }
+    for i in 1..256 {
+        fanout[i] += fanout[i - 1];
+    }
+    fanout
+}
+
+fn kind_code(kind: Kind) -> u8 {
+    match kind {
+        Kind::Commit => 1,
+        Kind::Tree => 2,
+        Kind::Blob => 3,
+        Kind::Tag => 4,
+    }
+}
+
+fn object_kind(kind: u8) -> anyhow::Result<Kind> {
+    Ok(match kind {
+        1 => Kind::Commit,
+        2 => Kind::Tree,
+        3 => Kind::Blob,
+        4 => Kind::Tag,
+        _ => anyhow::bail!("unknown object type {kind}"),
+    })
+}
+
+/// Reads an object header: the type in bits 4-6 of the first byte, and the
+/// size in its low four bits and seven bits of each further byte.
+fn read_header(input: &mut impl Read) -> anyhow::Result<(u8, u64)> {
+    let mut byte = read_byte(input)?;
+    let kind = (byte >> 4) & 7;
+    let mut size = u64::from(byte & 0x0f);
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = read_byte(input)?;
+        anyhow::ensure!(shift < 64, "object size too large");
+        size |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+    }
+    Ok((kind, size))
+}
+
+/// Reads the distance back to an `OFS_DELTA`'s base, big-endian with one
+/// added at each continuation so that there is only one way to write each
+/// distance.
+fn read_offset(input: &mut impl Read) -> anyhow::Result<u64> {
+    let mut byte = read_byte(input)?;
+    let mut offset = u64::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        byte = read_byte(input)?;
+        offset = offset.checked_add(1).and_then(|offset| offset.checked_mul(128)).context("delta base offset too large")?;
+        offset |= u64::from(byte & 0x7f);
+    }
+    Ok(offset)
+}
+
+fn read_byte(input: &mut impl Read) -> anyhow::Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte).context("pack is truncated")?;
+    Ok(byte[0])
+}
+
+/// Inflates the zlib stream at the reader's position, which must hold `size`
+/// bytes.
+fn inflate(input: &mut impl Read, size: u64) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size.min(1 << 24) as usize);
+    ZlibDecoder::new(input).read_to_end(&mut data).context("inflate packed object")?;
+    anyhow::ensure!(data.len() as u64 == size, "packed object is {} bytes, expected {size}", data.len());
+    Ok(data)
+}
+
+/// Rebuilds an object from `base` and `delta`: the sizes of the base and the
+/// result, then instructions that either copy a range of the base (high bit
+/// set, with flags for which offset and size bytes follow) or insert the
+/// next 1 to 127 bytes of the delta.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut input = delta;
+    let base_size = read_size(&mut input)?;
+    anyhow::ensure!(base_size == base.len() as u64, "delta expects a base of {base_size} bytes, not {}", base.len());
+    let size = read_size(&mut input)?;
+    let mut out = Vec::with_capacity(size.min(1 << 24) as usize);
+    while let Some((&op, rest)) = input.split_first() {
+        input = rest;
+        if op & 0x80 != 0 {
+            let mut field = |bits: u8, count: usize| -> anyhow::Result<usize> {
+                let mut value = 0;
+                for i in 0..count {
+                    if bits & (1 << i) != 0 {
+                        let (&byte, rest) = input.split_first().context("truncated delta")?;
+                        input = rest;
+                        value |= usize::from(byte) << (8 * i);
+                    }
+                }
+                Ok(value)
+            };
+            let offset = field(op, 4)?;
+            let len = match field(op >> 4, 3)? {
+                0 => 0x10000,
+                len => len,
+            };
+            let copy = offset.checked_add(len).and_then(|end| base.get(offset..end)).context("delta copies past the end of its base")?;
+            out.extend_from_slice(copy);
+        } else {
+            anyhow::ensure!(op != 0, "delta has a reserved instruction");
+            let (insert, rest) = input.split_at_checked(op as usize).context("truncated delta")?;
+            out.extend_from_slice(insert);
+            input = rest;
+        }
+    }
+    anyhow::ensure!(out.len() as u64 == size, "delta produced {} bytes, expected {size}", out.len());
+    Ok(out)
+}
+
+/// Where each block of a delta base is, for finding where a target
+/// matches it.
+pub struct DeltaIndex {
+    blocks: HashMap<u64, Vec<usize>>,
+}
+
+/// How many bytes must match for a copy to be worth encoding.
+const BLOCK: usize = 16;
+/// How many places with the same block to try, so that a base full of
+/// repeats doesn't make finding a match slow.
+const MAX_CANDIDATES: usize = 64;
+
+impl DeltaIndex {
+    pub fn new(base: &[u8]) -> DeltaIndex {
+        let mut blocks: HashMap<u64, Vec<usize>> = HashMap::new();
+        for start in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+            let places = blocks.entry(block_hash(&base[start..start + BLOCK])).or_default();
+            if places.len() < MAX_CANDIDATES {
+                places.push(start);
+            }
+        }
+        DeltaIndex { blocks }
+    }
+
+    /// A delta turning `base`, which the index was made from, into
+    /// `target`, if one of at most `max_size` bytes can be made.
+    pub fn delta(&self, base: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+        let mut out = vec![];
+        write_size(&mut out, base.len());
+        write_size(&mut out, target.len());
+        let mut literal = 0;
+        let mut i = 0;
+        while i + BLOCK <= target.len() {
+            let best = self
+                .blocks
+                .get(&block_hash(&target[i..i + BLOCK]))
+                .into_iter()
+                .flatten()
+                .map(|&start| (start, common_prefix(&base[start..], &target[i..])))
+                .max_by_key(|&(_, len)| len);
+            let Some((mut start, mut len)) = best.filter(|&(_, len)| len >= BLOCK) else {
+                i += 1;
+                continue;
+            };
+            // Take back what matched before the block from the literal.
+            while i > literal && start > 0 && base[start - 1] == target[i - 1] {
+                start -= 1;
+                i -= 1;
+                len += 1;
+            }
+            write_insert(&mut out, &target[literal..i]);
+            write_copy(&mut out, start, len);
+            i += len;
+            literal = i;
+            if out.len() > max_size {
+                return None;
+            }
+        }
+        write_insert(&mut out, &target[literal..]);
+        (out.len() <= max_size).then_some(out)
+    }
+}
+
+fn block_hash(block: &[u8]) -> u64 {
+    block.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+fn write_size(out: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        out.push(0x80 | (size & 0x7f) as u8);
+        size >>= 7;
+    }
+    out.push(size as u8);
+}
+
+fn write_insert(out: &mut Vec<u8>, mut data: &[u8]) {
+    while !data.is_empty() {
+        let (chunk, rest) = data.split_at(data.len().min(0x7f));
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+        data = rest;
+    }
+}
+
+/// Writes instructions copying `len` bytes from `start` of the base, in
+/// pieces small enough for the three size bytes.
+fn write_copy(out: &mut Vec<u8>, mut start: usize, mut len: usize) {
+    while len > 0 {
+        let piece = len.min(0xff_ffff);
+        let mut op = 0x80;
+        let at = out.len();
+        out.push(0);
+        for (i, byte) in (start as u32).to_le_bytes().into_iter().enumerate() {
+            if byte != 0 {
+                op |= 1 << i;
+                out.push(byte);
+            }
+        }
+        for (i, byte) in (piece as u32).to_le_bytes().into_iter().take(3).enumerate() {
+            if byte != 0 {
+                op |= 0x10 << i;
+                out.push(byte);
+            }
+        }
+        out[at] = op;
+        start += piece;
+        len -= piece;
+    }
+}
+
+/// Reads a size at the start of a delta: seven bits a byte, little-endian.
+fn read_size(input: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first().context("truncated delta")?;
+        *input = rest;
+        anyhow::ensure!(shift < 64, "delta size too large");
+        size |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+/// Removes the loose objects in `objects_dir` that are also packed, and the
+/// directories that leaves empty, returning the objects' paths. With
+/// `dry_run` nothing is removed.
+pub fn prune_packed(objects_dir: &Path, dry_run: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let packs = packs(objects_dir)?;
+    let mut pruned = vec![];
+    for first in 0..=255u8 {
+        let dir = objects_dir.join(format!("{first:02x}"));
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries {
+            let entry = entry.with_context(|| format!("list {}", dir.display()))?;
+            let name = entry.file_name();
+            let Some(hash) = name.to_str().and_then(|rest| format!("{first:02x}{rest}").parse::<Oid>().ok()) else {
+                continue;
+            };
+            if !packs.iter().any(|pack| pack.contains(&hash)) {
+                continue;
+            }
+            if !dry_run {
+                fs::remove_file(entry.path()).with_context(|| format!("remove {}", entry.path().display()))?;
+            }
+            pruned.push(entry.path());
+        }
+        if !dry_run {
+            // Only succeeds if nothing is left.
+            let _ = fs::remove_dir(&dir);
+        }
+    }
+    Ok(pruned)
+}
+
+/// The packs in `objects_dir`, opened once and opened again whenever the
+/// pack directory changes.
+pub fn packs(objects_dir: &Path) -> anyhow::Result<Arc<Vec<Pack>>> {
+    type Cache = HashMap<PathBuf, (Option<SystemTime>, Arc<Vec<Pack>>)>;
+    static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+    let dir = std::path::absolute(objects_dir.join("pack")).context("find pack directory")?;
+    let modified = fs::metadata(&dir).and_then(|meta| meta.modified()).ok();
+    let mut cache = CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let cache = cache.get_or_insert_with(HashMap::new);
+    if let Some((when, packs)) = cache.get(&dir)
+        && *when == modified
+    {
+        return Ok(packs.clone());
+    }
+    let packs = Arc::new(open_packs(&dir)?);
+    cache.insert(dir, (modified, packs.clone()));
+    Ok(packs)
+}
+
+/// Opens every pack in `dir` that has both its files.
+fn open_packs(dir: &Path) -> anyhow::Result<Vec<Pack>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("list {}", dir.display())),
+    };
+    let mut paths = vec![];
+    for entry in entries {
+        let path = entry.with_context(|| format!("list {}", dir.display()))?.path();
+        if path.extension().is_some_and(|ext| ext == "idx") && path.with_extension("pack").is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths.iter().map(|path| Pack::open(path)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp::TempDir;
+
+    // Packs written by git itself with `pack-objects`, holding two blobs,
+    // the shorter as a delta against the longer: against its offset with
+    // `--delta-base-offset`, else against its hash.
+    const OFS_DELTA_PACK: &str = "5041434b0000000200000002b218789c95cac90180200c05d13b55fc12dcad07252a2a4411d7eaa5855c675e9c08fb69fb055de0db63e007f3e9b6037c51404c7bd5df0bc3233215053a17e942a44b91ae44ba16e946a45ba5bd017b82e390baf5a47e2ef888ef675b789c6b62fec0b4a18011000b500299db4475ac0de0cccd48f8f51d4efca867fc012a19";
+    const REF_DELTA_PACK: &str = "5041434b0000000200000002b218789c95cac90180200c05d13b55fc12dcad07252a2a4411d7eaa5855c675e9c08fb69fb055de0db63e007f3e9b6037c51404c7bd5df0bc3233215053a17e942a44b91ae44ba16e946a45ba5bd017b82e390baf5a47e2ef888ef7751c01c6fbae14825a79471d2f109afeb807cf3e6789c6b62fec0b4a18011000b5002993d3e5c5e6002c5740149ca0d757a22c71591bea0";
+    const LONGER: &str = "51c01c6fbae14825a79471d2f109afeb807cf3e6";
+    const SHORTER: &str = "696dbcd60c267d662b535256a980007ddf2adc95";
+
+    /// Each pack, with the CRC-32 `git show-index` gives the delta in it.
+    fn packs() -> [(Vec<u8>, u32); 2] {
+        [(hex::decode(OFS_DELTA_PACK).unwrap(), 0xb3091594), (hex::decode(REF_DELTA_PACK).unwrap(), 0xf2fbbb99)]
+    }
+
+    fn shorter() -> String {
+        (0..8).map(|i| format!("the quick brown fox jumps over the lazy dog {i}\n")).collect()
+    }
+
+    fn longer() -> String {
+        shorter() + "and one more line\n"
+    }
+
+    #[test]
+    fn deltas_against_offsets_and_hashes_are_resolved() {
+        for (data, _) in packs() {
+            let mut objects = vec![];
+            for_each_object(&data, |kind, contents, hash| {
+                objects.push((kind, contents.to_vec(), hash.to_string()));
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(
+                objects,
+                [(Kind::Blob, longer().into_bytes(), LONGER.to_string()), (Kind::Blob, shorter().into_bytes(), SHORTER.to_string())]
+            );
+        }
+    }
+
+    #[test]
+    fn packs_are_indexed_and_read_back_as_git_indexes_them() {
+        for (data, delta_crc) in packs() {
+            let dir = TempDir::new("pack-test").unwrap();
+            let (path, index) = index_pack(dir.path(), &data, None).unwrap();
+            // As `git show-index` lists the index git wrote.
+            assert_eq!(index.oids.iter().map(Oid::to_string).collect::<Vec<_>>(), [LONGER, SHORTER]);
+            assert_eq!(index.offsets, [12, 103]);
+            assert_eq!(index.crcs, [0x431b00a2, delta_crc]);
+            assert_eq!(PackIndex::parse(&index.serialize()).unwrap(), index);
+
+            let pack = Pack::open(&path.with_extension("idx")).unwrap();
+            let shorter_hash: Oid = SHORTER.parse().unwrap();
+            assert_eq!(pack.read(&shorter_hash).unwrap(), Some((Kind::Blob, shorter().into_bytes())));
+            let (base, delta) = pack.read_delta(&shorter_hash).unwrap().unwrap();
+            assert_eq!(base.to_string(), LONGER);
+            assert_eq!(apply_delta(longer().as_bytes(), &delta).unwrap(), shorter().as_bytes());
+            assert_eq!(pack.read_delta(&base).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn corrupt_packs_are_refused() {
+        let (mut data, _) = packs().into_iter().next().unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(for_each_object(&data, |_, _, _| Ok(())).is_err());
+        assert!(apply_delta(b"short", &[5, 10, 0x90, 10]).is_err());
+    }
+}
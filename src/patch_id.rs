@@ -0,0 +1,190 @@
+//! Patch IDs: a hash of a patch that ignores whitespace and line numbers, so
+//! the same change applied at different places in history gets the same ID.
+//!
+//! The computation matches `git patch-id`. Only the diff header lines from
+//! `---` onwards and the hunk bodies contribute, with all whitespace removed.
+//! The default ("unstable") ID hashes the whole patch in order, while the
+//! stable variant hashes each file separately and sums the results so that
+//! reordering the files of a patch doesn't change its ID.
+
+use sha1::{Digest, Sha1};
+use std::io::BufRead;
+
+/// Reads patches from `input`, such as the output of `got log -p`, and
+/// returns `(patch id, commit)` pairs. A patch starts after a line beginning
+/// with a commit hash, optionally preceded by `commit `, `From ` or
+/// `diff-tree `; patches without one are reported against the zero hash.
+pub fn patch_ids(mut input: impl BufRead, stable: bool) -> anyhow::Result<Vec<(String, String)>> {
+    let mut ids = vec![];
+    let mut commit = "0".repeat(40);
+    loop {
+        let (id, len, next) = one_patch_id(&mut input, stable)?;
+        if len > 0 {
+            ids.push((hex::encode(id), commit));
+        }
+        match next {
+            Some(next) => commit = next,
+            None => break,
+        }
+    }
+    Ok(ids)
+}
+
+/// The ID of a single patch given as text.
+pub fn patch_id(patch: &[u8], stable: bool) -> anyhow::Result<Option<String>> {
+    let (id, len, _) = one_patch_id(&mut &patch[..], stable)?;
+    Ok((len > 0).then(|| hex::encode(id)))
+}
+
+/// Hashes lines until the next commit hash (returned) or the end of input.
+fn one_patch_id(input: &mut impl BufRead, stable: bool) -> anyhow::Result<([u8; 20], usize, Option<String>)> {
+    let mut result = [0u8; 20];
+    let mut hasher = Sha1::new();
+    let mut patch_len = 0;
+    // Lines remaining in the current hunk, or -1 while reading a file header.
+    let mut before: i64 = -1;
+    let mut after: i64 = -1;
+    let mut binary = false;
+    let mut pre_oid = String::new();
+    let mut post_oid = String::new();
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        if input.read_until(b'\n', &mut buf)? == 0 {
+            flush_hunk(&mut result, &mut hasher);
+            return Ok((result, patch_len, None));
+        }
+        let line = &buf[..];
+
+        let rest = [&b"diff-tree "[..], b"commit ", b"From "]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(*prefix));
+        if rest.is_none() && line.starts_with(b"\\ ") && line.len() > 12 {
+            // "\ No newline at end of file"
+            continue;
+        }
+        if let Some(hash) = leading_hash(rest.unwrap_or(line)) {
+            flush_hunk(&mut result, &mut hasher);
+            return Ok((result, patch_len, Some(hash)));
+        }
+
+        // Skip the commit message.
+        if patch_len == 0 && !line.starts_with(b"diff ") {
+            continue;
+        }
+
+        if before == -1 {
+            if line.starts_with(b"GIT binary patch") || line.starts_with(b"Binary files") {
+                binary = true;
+                before = 0;
+                hasher.update(pre_oid.as_bytes());
+                hasher.update(post_oid.as_bytes());
+                if stable {
+                    flush_hunk(&mut result, &mut hasher);
+                }
+                continue;
+            } else if let Some(index) = line.strip_prefix(b"index ") {
+                let index = String::from_utf8_lossy(index);
+                if let Some((pre, post)) = index.trim_end().split_once("..") {
+                    pre_oid = pre.to_string();
+                    post_oid = post.split(' ').next().unwrap_or("").to_string();
+                }
+                continue;
+            } else if line.starts_with(b"--- ") {
+                before = 1;
+                after = 1;
+            } else if !line.first().is_some_and(u8::is_ascii_alphabetic) {
+                break;
+            }
+        }
+
+        if binary {
+            if line.starts_with(b"diff ") {
+                binary = false;
+                before = -1;
+            }
+            continue;
+        }
+
+        if before == 0 && after == 0 {
+            if line.starts_with(b"@@ -") {
+                if let Some((b, a)) = hunk_counts(line) {
+                    before = b;
+                    after = a;
+                }
+                continue;
+            }
+            if !line.starts_with(b"diff ") {
+                break;
+            }
+            if stable {
+                flush_hunk(&mut result, &mut hasher);
+            }
+            before = -1;
+            after = -1;
+        }
+
+        match line.first() {
+            Some(b'-') => before -= 1,
+            Some(b'+') => after -= 1,
+            Some(b' ') => {
+                before -= 1;
+                after -= 1;
+            }
+            _ => {}
+        }
+
+        let stripped: Vec<u8> = line.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect();
+        patch_len += stripped.len();
+        hasher.update(&stripped);
+    }
+    // Anything after the patch proper (a signature, say) up to the next
+    // commit is ignored.
+    loop {
+        buf.clear();
+        if input.read_until(b'\n', &mut buf)? == 0 {
+            flush_hunk(&mut result, &mut hasher);
+            return Ok((result, patch_len, None));
+        }
+        let rest = [&b"diff-tree "[..], b"commit ", b"From "]
+            .iter()
+            .find_map(|prefix| buf.strip_prefix(*prefix));
+        if let Some(hash) = leading_hash(rest.unwrap_or(&buf)) {
+            flush_hunk(&mut result, &mut hasher);
+            return Ok((result, patch_len, Some(hash)));
+        }
+    }
+}
+
+fn leading_hash(text: &[u8]) -> Option<String> {
+    let hash = text.get(..40)?;
+    hash.iter()
+        .all(u8::is_ascii_hexdigit)
+        .then(|| String::from_utf8_lossy(hash).to_ascii_lowercase())
+}
+
+/// The old and new line counts from a `@@ -a,b +c,d @@` header.
+fn hunk_counts(line: &[u8]) -> Option<(i64, i64)> {
+    let text = String::from_utf8_lossy(&line[4..]);
+    let (old, rest) = text.split_once(" +")?;
+    let new = rest.split(' ').next()?;
+    let count = |range: &str| -> Option<i64> {
+        match range.split_once(',') {
+            Some((_, count)) => count.parse().ok(),
+            None => range.parse::<i64>().ok().map(|_| 1),
+        }
+    };
+    Some((count(old)?, count(new)?))
+}
+
+/// Adds the hash of what's been fed to `hasher` into `result` as a 160-bit
+/// little-endian sum, and resets the hasher.
+fn flush_hunk(result: &mut [u8; 20], hasher: &mut Sha1) {
+    let hash = hasher.finalize_reset();
+    let mut carry: u16 = 0;
+    for (r, h) in result.iter_mut().zip(hash.iter()) {
+        carry += u16::from(*r) + u16::from(*h);
+        *r = carry as u8;
+        carry >>= 8;
+    }
+}
@@ -0,0 +1,59 @@
+//! Paths as trees and the index hold them: bytes, `/`-separated and
+//! relative to the top of the worktree.
+//!
+//! File names needn't be UTF-8, so a path is kept as the bytes the file
+//! system gave and only turned into text to be shown, quoted (see
+//! [`crate::quote`]) or, in messages, with anything that isn't UTF-8
+//! replaced.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// `path` as a file system path, relative to the current directory.
+pub fn to_path(path: &[u8]) -> &Path {
+    Path::new(OsStr::from_bytes(path))
+}
+
+/// The bytes of `path`, a name from the file system.
+pub fn from_os(path: &OsStr) -> &[u8] {
+    path.as_bytes()
+}
+
+/// `path` for messages, with anything that isn't UTF-8 replaced.
+pub fn display(path: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(path)
+}
+
+/// `name` in directory `dir`, where an empty `dir` is the top.
+pub fn join(dir: &[u8], name: &[u8]) -> Vec<u8> {
+    if dir.is_empty() {
+        return name.to_vec();
+    }
+    [dir, b"/", name].concat()
+}
+
+/// The directory `path` is in and its last component, or `None` for a
+/// path at the top.
+pub fn split_parent(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    let slash = path.iter().rposition(|&byte| byte == b'/')?;
+    Some((&path[..slash], &path[slash + 1..]))
+}
+
+/// The first component of `path` and the rest, or `None` for a single
+/// component.
+pub fn split_first(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    let slash = path.iter().position(|&byte| byte == b'/')?;
+    Some((&path[..slash], &path[slash + 1..]))
+}
+
+/// The last component of `path`.
+pub fn file_name(path: &[u8]) -> &[u8] {
+    split_parent(path).map_or(path, |(_, name)| name)
+}
+
+/// The directories `path` is in, innermost first.
+pub fn parents(path: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::successors(split_parent(path).map(|(dir, _)| dir), |&dir| split_parent(dir).map(|(dir, _)| dir))
+}
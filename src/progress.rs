@@ -0,0 +1,118 @@
+//! Progress meters for long-running commands, written to standard error
+//! the way git writes them: `Writing objects:  42% (420/1000)`, redrawn in
+//! place as the count grows and ending `, done.` once the work is finished.
+//! A meter without a total shows just the count.
+//!
+//! [`ProgressReader`] drives a meter from the bytes read through it, for
+//! work such as hashing a large file that is measured in bytes.
+
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+
+/// How often a meter without a total is redrawn.
+const INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Progress {
+    title: String,
+    total: Option<u64>,
+    count: u64,
+    enabled: bool,
+    /// The percentage last shown, for meters with a total.
+    shown_percent: Option<u64>,
+    /// When the meter was last drawn, for meters without one.
+    shown_at: Option<Instant>,
+}
+
+impl Progress {
+    /// A meter titled `title` counting up to `total`, if known. A meter
+    /// that isn't `enabled` shows nothing, so callers needn't check.
+    pub fn new(title: &str, total: Option<u64>, enabled: bool) -> Progress {
+        Progress {
+            title: title.to_string(),
+            total,
+            count: 0,
+            enabled,
+            shown_percent: None,
+            shown_at: None,
+        }
+    }
+
+    /// Counts one more.
+    pub fn tick(&mut self) {
+        self.set(self.count + 1);
+    }
+
+    pub fn set(&mut self, count: u64) {
+        self.count = count;
+        if !self.enabled {
+            return;
+        }
+        let due = match self.total {
+            Some(_) => self.shown_percent != Some(self.percent()),
+            None => self.shown_at.is_none_or(|at| at.elapsed() >= INTERVAL),
+        };
+        if due {
+            self.draw("");
+        }
+    }
+
+    /// Shows the final count, followed by `, done.`.
+    pub fn done(mut self) {
+        if self.enabled {
+            self.draw(", done.\n");
+        }
+    }
+
+    fn percent(&self) -> u64 {
+        match self.total {
+            Some(0) | None => 100,
+            Some(total) => self.count * 100 / total,
+        }
+    }
+
+    fn draw(&mut self, end: &str) {
+        let line = match self.total {
+            Some(total) => format!("{}: {:3}% ({}/{total}){end}", self.title, self.percent(), self.count),
+            None => format!("{}: {}{end}", self.title, self.count),
+        };
+        let mut err = std::io::stderr().lock();
+        // A meter that can't be shown isn't worth failing over.
+        let _ = write!(err, "\r{line}");
+        let _ = err.flush();
+        self.shown_percent = Some(self.percent());
+        self.shown_at = Some(Instant::now());
+    }
+}
+
+/// Counts what is read through it on a meter, in mebibytes.
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+    read: u64,
+}
+
+impl<R: Read> ProgressReader<R> {
+    /// Reads from `inner`, showing `title` over the `size` bytes expected.
+    pub fn new(inner: R, title: &str, size: u64, enabled: bool) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            progress: Progress::new(title, Some(size.div_ceil(MIB)), enabled),
+            read: 0,
+        }
+    }
+
+    pub fn done(self) {
+        self.progress.done();
+    }
+}
+
+const MIB: u64 = 1 << 20;
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        self.progress.set(self.read.div_ceil(MIB));
+        Ok(n)
+    }
+}
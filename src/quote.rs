@@ -0,0 +1,98 @@
+//! Quoting paths for output the way git does, so that a name with a
+//! newline, a tab or a quote in it can't be mistaken for something else.
+//!
+//! A path that needs it is put in double quotes with C-style escapes:
+//! `\t`, `\n`, `\"`, `\\` and the like, and `\ooo` in octal for other
+//! control characters. Bytes outside ASCII are escaped in octal too unless
+//! `core.quotePath` is false, in which case they are written as they are.
+
+use crate::config::Config;
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+/// `path` as output should show it, following `core.quotePath`.
+pub fn quote_path(path: &[u8]) -> Cow<'_, str> {
+    quote_path_with(path, quote_non_ascii())
+}
+
+/// `path` quoted if it needs to be, escaping bytes outside ASCII only if
+/// `non_ascii` is set. A path that isn't UTF-8 has them all escaped
+/// either way, since it can't be shown as it is.
+pub fn quote_path_with(path: &[u8], non_ascii: bool) -> Cow<'_, str> {
+    let non_ascii = non_ascii || std::str::from_utf8(path).is_err();
+    let needs_escape = |byte: u8| byte < 0x20 || byte == b'"' || byte == b'\\' || byte == 0x7f || (non_ascii && byte >= 0x80);
+    if !path.iter().copied().any(needs_escape) {
+        return Cow::Borrowed(std::str::from_utf8(path).expect("checked above"));
+    }
+    let mut out = vec![b'"'];
+    for &byte in path {
+        if !needs_escape(byte) {
+            out.push(byte);
+            continue;
+        }
+        let escape = match byte {
+            0x07 => b'a',
+            0x08 => b'b',
+            b'\t' => b't',
+            b'\n' => b'n',
+            0x0b => b'v',
+            0x0c => b'f',
+            b'\r' => b'r',
+            b'"' | b'\\' => byte,
+            _ => {
+                out.extend(format!("\\{byte:03o}").as_bytes());
+                continue;
+            }
+        };
+        out.extend([b'\\', escape]);
+    }
+    out.push(b'"');
+    // Whole characters outside ASCII are either kept or escaped byte by
+    // byte, and the rest is ASCII.
+    Cow::Owned(String::from_utf8(out).expect("quoting keeps UTF-8 valid"))
+}
+
+/// Reads the quoted path at the start of `text`, undoing `quote_path`, and
+/// returns it with what follows the closing quote, or `None` if `text`
+/// doesn't start with one.
+pub fn unquote(text: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let mut rest = text.strip_prefix(b"\"")?;
+    let mut out = vec![];
+    loop {
+        let (&byte, after) = rest.split_first()?;
+        rest = after;
+        match byte {
+            b'"' => return Some((out, rest)),
+            b'\\' => {
+                let (&escape, after) = rest.split_first()?;
+                rest = after;
+                out.push(match escape {
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'v' => 0x0b,
+                    b'f' => 0x0c,
+                    b'r' => b'\r',
+                    b'"' | b'\\' => escape,
+                    b'0'..=b'3' => {
+                        let (digits, after) = rest.split_at_checked(2)?;
+                        rest = after;
+                        digits.iter().try_fold(escape - b'0', |value, &digit| {
+                            matches!(digit, b'0'..=b'7').then(|| value * 8 + (digit - b'0'))
+                        })?
+                    }
+                    _ => return None,
+                });
+            }
+            _ => out.push(byte),
+        }
+    }
+}
+
+/// Whether `core.quotePath` asks for bytes outside ASCII to be escaped,
+/// which it does unless set to false.
+fn quote_non_ascii() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| Config::load().ok().and_then(|config| config.get_bool("core.quotePath").ok().flatten()).unwrap_or(true))
+}
@@ -0,0 +1,326 @@
+//! Taking pushes, as `got receive-pack` does for a client such as
+//! `git push --receive-pack='got receive-pack'`, and the checks applied to
+//! what is pushed before it is accepted.
+//!
+//! The exchange is git's: the repository's refs are advertised as
+//! `<hash> <name>` pkt-lines, the first followed by a NUL and the
+//! capabilities, then a flush. The client answers with an
+//! `<old> <new> <ref>` line for each update and a flush, followed by a pack
+//! unless it is only deleting refs, and is told `unpack ok` or why the pack
+//! was refused, then `ok <ref>` or `ng <ref> <why>` for each update. A ref
+//! is only updated if it still points where the client thought it did.
+//!
+//! A repository serving pushes can protect itself with:
+//!
+//! * `receive.maxInputSize`: the largest pack accepted, in bytes.
+//! * `receive.maxBlobSize`: the largest single blob accepted, in bytes.
+//! * `receive.denyNonFastForwards`: refuse history rewrites on every ref.
+//! * `receive.protectedBranch`: multi-valued patterns (`*` matches anything)
+//!   of refs on which history rewrites and deletion are refused, e.g.
+//!   `refs/heads/main` or `refs/heads/release/*`.
+//!
+//! Sizes accept the usual `k`/`m`/`g` suffixes and 0 means unlimited. A
+//! pack over either limit is refused whole and no ref is updated.
+
+use crate::config::Config;
+use crate::object::{Kind, Object};
+use crate::oid::Oid;
+use crate::refs::{self, NameRules};
+use crate::revwalk::Ancestry;
+use crate::transport::pkt_line;
+use crate::{pack, repository};
+use anyhow::Context;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// What `got receive-pack` tells clients it can do. Packs must hold the
+/// bases of their deltas, so that every object can be checked.
+const CAPABILITIES: &str = "report-status delete-refs ofs-delta no-thin";
+
+/// Serves a push to the repository at `git_dir`, reading the client's
+/// updates and pack from `input` and writing to `output`. Returns whether
+/// every update was made.
+pub fn receive_pack(git_dir: &Path, config: &Config, input: &mut impl Read, output: &mut impl Write) -> anyhow::Result<bool> {
+    let limits = ReceiveLimits::from_config(config)?;
+    let mut advertisement = vec![];
+    let mut capabilities = Some(format!("{CAPABILITIES} agent=got/{}", env!("CARGO_PKG_VERSION")));
+    for (name, _) in refs::list(git_dir)? {
+        if let Some(hash) = refs::resolve(git_dir, &name)? {
+            match capabilities.take() {
+                Some(capabilities) => pkt_line(&mut advertisement, &format!("{hash} {name}\0{capabilities}\n")),
+                None => pkt_line(&mut advertisement, &format!("{hash} {name}\n")),
+            }
+        }
+    }
+    if let Some(capabilities) = capabilities {
+        pkt_line(&mut advertisement, &format!("{} capabilities^{{}}\0{capabilities}\n", Oid::ZERO));
+    }
+    advertisement.extend(b"0000");
+    output.write_all(&advertisement).and_then(|()| output.flush()).context("write ref advertisement")?;
+
+    let mut commands = vec![];
+    while let Some(line) = read_pkt_line(input)? {
+        let line = String::from_utf8(line).context("invalid update line")?;
+        let line = line.split('\0').next().unwrap_or_default().trim_end_matches('\n');
+        let mut fields = line.splitn(3, ' ');
+        let (Some(old), Some(new), Some(name)) = (fields.next(), fields.next(), fields.next()) else {
+            anyhow::bail!("invalid update line '{line}'");
+        };
+        let (old, new): (Oid, Oid) = (old.parse()?, new.parse()?);
+        commands.push((name.to_string(), (!old.is_zero()).then_some(old), (!new.is_zero()).then_some(new)));
+    }
+    // A client with nothing to push hangs up after the advertisement.
+    if commands.is_empty() {
+        return Ok(true);
+    }
+
+    let unpacked = match commands.iter().any(|(_, _, new)| new.is_some()) {
+        true => {
+            let mut data = vec![];
+            input.read_to_end(&mut data).context("read pack")?;
+            limits.check_pack(&data).and_then(|()| {
+                pack::index_pack(&repository::objects_dir(git_dir).join("pack"), &data, None)?;
+                Ok(())
+            })
+        }
+        false => Ok(()),
+    };
+    let mut report = vec![];
+    let mut ok = true;
+    match &unpacked {
+        Ok(()) => pkt_line(&mut report, "unpack ok\n"),
+        Err(e) => pkt_line(&mut report, &format!("unpack {}\n", one_line(e))),
+    }
+    let ancestry = Ancestry::new(git_dir)?;
+    for (name, old, new) in &commands {
+        let updated = match unpacked {
+            Ok(()) => update(git_dir, &limits, &ancestry, name, *old, *new),
+            Err(_) => Err(anyhow::anyhow!("unpacker error")),
+        };
+        match updated {
+            Ok(()) => pkt_line(&mut report, &format!("ok {name}\n")),
+            Err(e) => {
+                pkt_line(&mut report, &format!("ng {name} {}\n", one_line(&e)));
+                ok = false;
+            }
+        }
+    }
+    report.extend(b"0000");
+    output.write_all(&report).and_then(|()| output.flush()).context("write push report")?;
+    Ok(ok)
+}
+
+/// Points ref `name` at `new` in place of `old`, or deletes it for `None`,
+/// if `limits` allow it and it still points at `old`.
+fn update(git_dir: &Path, limits: &ReceiveLimits, ancestry: &Ancestry, name: &str, old: Option<Oid>, new: Option<Oid>) -> anyhow::Result<()> {
+    anyhow::ensure!(name.starts_with("refs/") && refs::is_valid_name(name, NameRules::default()), "funny refname");
+    if let Some(new) = new {
+        anyhow::ensure!(Object::exists(&new), "missing necessary objects");
+    }
+    anyhow::ensure!(refs::resolve(git_dir, name)? == old, "failed to lock");
+    let fast_forward = match (old, new) {
+        (Some(old), Some(new)) => ancestry.is_ancestor(&old, &new).unwrap_or(false),
+        _ => true,
+    };
+    limits.check_update(&RefUpdate { name, old, new, fast_forward })?;
+    match new {
+        Some(new) => refs::write(git_dir, name, &new.to_string()),
+        None => fs::remove_file(git_dir.join(name)).with_context(|| format!("delete ref {name}")),
+    }
+}
+
+/// Reads the next pkt-line's data, or `None` for a flush.
+fn read_pkt_line(input: &mut impl Read) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    input.read_exact(&mut len).context("pkt-line is truncated")?;
+    let len = std::str::from_utf8(&len).ok().and_then(|len| usize::from_str_radix(len, 16).ok());
+    let len = len.context("invalid pkt-line length")?;
+    if len == 0 {
+        return Ok(None);
+    }
+    anyhow::ensure!(len >= 4, "invalid pkt-line length {len}");
+    let mut line = vec![0; len - 4];
+    input.read_exact(&mut line).context("pkt-line is truncated")?;
+    Ok(Some(line))
+}
+
+/// `e` and its causes on one line, for a report line.
+fn one_line(e: &anyhow::Error) -> String {
+    format!("{e:#}").replace('\n', " ")
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ReceiveLimits {
+    pub max_pack_size: Option<u64>,
+    pub max_blob_size: Option<u64>,
+    pub deny_non_fast_forwards: bool,
+    pub protected: Vec<String>,
+}
+
+/// A ref update requested by a push. `old` or `new` is `None` when the ref
+/// is being created or deleted respectively.
+pub struct RefUpdate<'a> {
+    pub name: &'a str,
+    pub old: Option<Oid>,
+    pub new: Option<Oid>,
+    /// Whether `new` descends from `old`, as determined by the caller.
+    pub fast_forward: bool,
+}
+
+impl ReceiveLimits {
+    pub fn from_config(config: &Config) -> anyhow::Result<ReceiveLimits> {
+        let size = |key: &str| -> anyhow::Result<Option<u64>> {
+            let size = config.get_int(key)?.unwrap_or(0);
+            anyhow::ensure!(size >= 0, "{key} must not be negative");
+            Ok((size > 0).then_some(size as u64))
+        };
+        Ok(ReceiveLimits {
+            max_pack_size: size("receive.maxInputSize")?,
+            max_blob_size: size("receive.maxBlobSize")?,
+            deny_non_fast_forwards: config
+                .get_bool("receive.denyNonFastForwards")?
+                .unwrap_or(false),
+            protected: config
+                .get_all("receive.protectedBranch")
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+
+    /// Checks a whole incoming pack: its size and every object in it.
+    pub fn check_pack(&self, data: &[u8]) -> anyhow::Result<()> {
+        self.check_pack_size(data.len() as u64)?;
+        if self.max_blob_size.is_some() {
+            pack::for_each_object(data, |kind, contents, hash| self.check_object(hash, kind, contents.len() as u64))?;
+        }
+        Ok(())
+    }
+
+    pub fn check_pack_size(&self, size: u64) -> anyhow::Result<()> {
+        if let Some(max) = self.max_pack_size {
+            anyhow::ensure!(size <= max, "pack exceeds maximum allowed size ({size} > {max} bytes)");
+        }
+        Ok(())
+    }
+
+    /// Checks one object of the incoming pack.
+    pub fn check_object(&self, hash: &Oid, kind: Kind, size: u64) -> anyhow::Result<()> {
+        if let (Kind::Blob, Some(max)) = (kind, self.max_blob_size) {
+            anyhow::ensure!(
+                size <= max,
+                "blob {hash} exceeds maximum allowed size ({size} > {max} bytes)"
+            );
+        }
+        Ok(())
+    }
+
+    pub fn check_update(&self, update: &RefUpdate) -> anyhow::Result<()> {
+        let protected = self.is_protected(update.name);
+        match (update.old, update.new) {
+            (Some(_), None) => {
+                anyhow::ensure!(!protected, "deletion of protected branch {} prohibited", update.name);
+            }
+            (Some(_), Some(_)) if !update.fast_forward => {
+                anyhow::ensure!(
+                    !(protected || self.deny_non_fast_forwards),
+                    "non-fast-forward update of {} prohibited",
+                    update.name
+                );
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn is_protected(&self, name: &str) -> bool {
+        self.protected.iter().any(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// Matches `name` against `pattern`, in which `*` stands for any run of
+/// characters.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A pack written by git holding a single blob of 144 bytes.
+    const BLOB_PACK: &str = "5041434b0000000200000001b009789c2b4a4d4ecd2c4b5528492d2e5148cac94fe22a1a501100f4d234615df84fb58f9ac203c70d1c7fed9d76fa8b4719e8";
+    const BLOB: &str = "dd864508bd94c94897ca600cdd7b597f078d26d8";
+
+    fn update(name: &str, old: Option<&str>, new: Option<&str>, fast_forward: bool) -> (String, Option<Oid>, Option<Oid>, bool) {
+        let parse = |hash: Option<&str>| hash.map(|hash| hash.parse().unwrap());
+        (name.to_string(), parse(old), parse(new), fast_forward)
+    }
+
+    fn check(limits: &ReceiveLimits, (name, old, new, fast_forward): (String, Option<Oid>, Option<Oid>, bool)) -> anyhow::Result<()> {
+        limits.check_update(&RefUpdate { name: &name, old, new, fast_forward })
+    }
+
+    const OLD: Option<&str> = Some("b52e59b6caf7cf35f0983db475d100412c301a01");
+    const NEW: Option<&str> = Some("4b86fd8946eb0eccc452def84b65873f5d90dfc9");
+
+    #[test]
+    fn oversized_packs_are_refused() {
+        let pack = hex::decode(BLOB_PACK).unwrap();
+        let limits = ReceiveLimits { max_pack_size: Some(62), ..ReceiveLimits::default() };
+        let e = limits.check_pack(&pack).unwrap_err();
+        assert_eq!(e.to_string(), "pack exceeds maximum allowed size (63 > 62 bytes)");
+        let limits = ReceiveLimits { max_pack_size: Some(63), ..ReceiveLimits::default() };
+        limits.check_pack(&pack).unwrap();
+    }
+
+    #[test]
+    fn oversized_blobs_are_refused() {
+        let pack = hex::decode(BLOB_PACK).unwrap();
+        let limits = ReceiveLimits { max_blob_size: Some(143), ..ReceiveLimits::default() };
+        let e = limits.check_pack(&pack).unwrap_err();
+        assert_eq!(e.to_string(), format!("blob {BLOB} exceeds maximum allowed size (144 > 143 bytes)"));
+        let limits = ReceiveLimits { max_blob_size: Some(144), ..ReceiveLimits::default() };
+        limits.check_pack(&pack).unwrap();
+    }
+
+    #[test]
+    fn protected_refs_refuse_rewrites_and_deletion() {
+        let limits = ReceiveLimits {
+            protected: vec!["refs/heads/release/*".to_string()],
+            ..ReceiveLimits::default()
+        };
+        let e = check(&limits, update("refs/heads/release/1.0", OLD, NEW, false)).unwrap_err();
+        assert_eq!(e.to_string(), "non-fast-forward update of refs/heads/release/1.0 prohibited");
+        let e = check(&limits, update("refs/heads/release/1.0", OLD, None, true)).unwrap_err();
+        assert_eq!(e.to_string(), "deletion of protected branch refs/heads/release/1.0 prohibited");
+        check(&limits, update("refs/heads/release/1.0", OLD, NEW, true)).unwrap();
+        check(&limits, update("refs/heads/release/2.0", None, NEW, false)).unwrap();
+        check(&limits, update("refs/heads/topic", OLD, NEW, false)).unwrap();
+        check(&limits, update("refs/heads/topic", OLD, None, true)).unwrap();
+    }
+
+    #[test]
+    fn non_fast_forwards_can_be_refused_everywhere() {
+        let limits = ReceiveLimits { deny_non_fast_forwards: true, ..ReceiveLimits::default() };
+        assert!(check(&limits, update("refs/heads/topic", OLD, NEW, false)).is_err());
+        check(&limits, update("refs/heads/topic", OLD, NEW, true)).unwrap();
+        check(&limits, update("refs/heads/topic", OLD, None, true)).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+//! Reflogs: the history of a ref's values under `logs/<ref>`, one line per
+//! update, `<old> <new> <committer> <time> <zone>\t<message>`, oldest first.
+//! `<ref>@{n}` names the value the ref had `n` updates ago.
+
+use crate::object::Signature;
+use crate::oid::Oid;
+use anyhow::Context;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub old: Oid,
+    pub new: Oid,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl Entry {
+    fn parse(line: &str) -> anyhow::Result<Entry> {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = head.splitn(3, ' ');
+        let (Some(old), Some(new), Some(committer)) = (fields.next(), fields.next(), fields.next()) else {
+            anyhow::bail!("malformed reflog entry '{line}'");
+        };
+        Ok(Entry {
+            old: old.parse()?,
+            new: new.parse()?,
+            committer: committer.parse()?,
+            message: message.to_string(),
+        })
+    }
+}
+
+/// The entries of `name`'s reflog, oldest first; empty if it has none.
+pub fn read(git_dir: &Path, name: &str) -> anyhow::Result<Vec<Entry>> {
+    let text = match fs::read_to_string(git_dir.join("logs").join(name)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("read reflog of {name}")),
+    };
+    text.lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| Entry::parse(line).with_context(|| format!("parse reflog of {name}")))
+        .collect()
+}
+
+/// Replaces `name`'s reflog with `entries`, removing it when there are none.
+pub fn write(git_dir: &Path, name: &str, entries: &[Entry]) -> anyhow::Result<()> {
+    let path = git_dir.join("logs").join(name);
+    if entries.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("remove reflog of {name}"))
+            }
+            _ => Ok(()),
+        };
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create directory for reflog of {name}"))?;
+    }
+    let mut text = String::new();
+    for entry in entries {
+        text.push_str(&format!("{} {} {}\t{}\n", entry.old, entry.new, entry.committer, entry.message));
+    }
+    fs::write(&path, text).with_context(|| format!("write reflog of {name}"))
+}
+
+/// Adds `entry` to the end of `name`'s reflog.
+pub fn append(git_dir: &Path, name: &str, entry: Entry) -> anyhow::Result<()> {
+    let mut entries = read(git_dir, name)?;
+    entries.push(entry);
+    write(git_dir, name, &entries)
+}
+
+/// Deletes the `n`th most recent entry, rewriting the `old` value of the
+/// entry after it so the log stays a continuous chain. Returns the deleted
+/// entry and the remaining entries.
+pub fn delete(git_dir: &Path, name: &str, n: usize) -> anyhow::Result<(Entry, Vec<Entry>)> {
+    let mut entries = read(git_dir, name)?;
+    let Some(i) = entries.len().checked_sub(n + 1) else {
+        anyhow::bail!("reflog of {name} has only {} entries", entries.len());
+    };
+    let deleted = entries.remove(i);
+    if let Some(next) = entries.get_mut(i) {
+        next.old = deleted.old;
+    }
+    write(git_dir, name, &entries)?;
+    Ok((deleted, entries))
+}
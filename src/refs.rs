@@ -0,0 +1,195 @@
+//! References: `HEAD` and the files under `refs/` naming commits (or, for
+//! symbolic refs, other refs via a `ref: <name>` line).
+//!
+//! Ref names are paths of `/`-separated components, with the rules of
+//! `git check-ref-format`: no component may be empty, start with `.` or end
+//! with `.lock`, and the name may not contain `..`, `@{`, control
+//! characters, spaces or any of `~^:?*[\`, nor end with `.` or be `@`.
+
+use crate::oid::Oid;
+use anyhow::Context;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How many `ref:` indirections `resolve` follows before assuming a loop.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The raw contents of ref `name` (e.g. `HEAD` or `refs/heads/main`) with
+/// the trailing newline removed, or `None` if it doesn't exist.
+pub fn read(git_dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    match fs::read_to_string(git_dir.join(name)) {
+        Ok(value) => Ok(Some(value.trim_end().to_string())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("read ref {name}")),
+    }
+}
+
+/// Writes ref `name`, which must be under `refs/` or be an all-caps name
+/// like `HEAD`.
+pub fn write(git_dir: &Path, name: &str, value: &str) -> anyhow::Result<()> {
+    let pseudo = !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_');
+    anyhow::ensure!(
+        pseudo || (name.starts_with("refs/") && is_valid_name(name, NameRules::default())),
+        "'{name}' is not a valid ref name"
+    );
+    let path = git_dir.join(name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create directory for ref {name}"))?;
+    }
+    fs::write(&path, format!("{value}\n")).with_context(|| format!("write ref {name}"))
+}
+
+/// Relaxations of the rules for ref names.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NameRules {
+    /// Allow names of a single component, like `main`.
+    pub allow_onelevel: bool,
+    /// Allow one `*`, as in a refspec pattern.
+    pub refspec_pattern: bool,
+}
+
+/// Whether `name` is a well-formed ref name.
+pub fn is_valid_name(name: &str, rules: NameRules) -> bool {
+    if name == "@" || name.ends_with('.') {
+        return false;
+    }
+    let mut stars = 0;
+    let mut count = 0;
+    for component in name.split('/') {
+        count += 1;
+        if component.is_empty() || component.starts_with('.') || component.ends_with(".lock") {
+            return false;
+        }
+        if component.contains("..") || component.contains("@{") {
+            return false;
+        }
+        for c in component.bytes() {
+            match c {
+                b'*' => stars += 1,
+                b' ' | b'~' | b'^' | b':' | b'?' | b'[' | b'\\' | 0x7f => return false,
+                c if c < b' ' => return false,
+                _ => {}
+            }
+        }
+    }
+    stars <= usize::from(rules.refspec_pattern) && (count > 1 || rules.allow_onelevel)
+}
+
+/// Whether `branch` can name a branch: `refs/heads/<branch>` is a valid ref
+/// name, and `branch` isn't `HEAD` or something that looks like an option.
+pub fn is_valid_branch_name(branch: &str) -> bool {
+    !branch.starts_with('-') && branch != "HEAD" && is_valid_name(&format!("refs/heads/{branch}"), NameRules::default())
+}
+
+/// Every ref under `refs/`, sorted by name, with its raw contents.
+pub fn list(git_dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let mut refs = vec![];
+    collect(git_dir, "refs", &mut refs)?;
+    refs.sort();
+    Ok(refs)
+}
+
+fn collect(git_dir: &Path, prefix: &str, refs: &mut Vec<(String, String)>) -> anyhow::Result<()> {
+    let dir = match fs::read_dir(git_dir.join(prefix)) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("list {prefix}")),
+    };
+    for entry in dir {
+        let entry = entry?;
+        let filename = entry.file_name();
+        let Some(filename) = filename.to_str() else {
+            continue;
+        };
+        if filename.starts_with('.') || filename.ends_with(".lock") {
+            continue;
+        }
+        let name = format!("{prefix}/{filename}");
+        if entry.file_type()?.is_dir() {
+            collect(git_dir, &name, refs)?;
+        } else if let Some(value) = read(git_dir, &name)? {
+            refs.push((name, value));
+        }
+    }
+    Ok(())
+}
+
+/// The object ref `name` ultimately points at, following symbolic refs.
+pub fn resolve(git_dir: &Path, name: &str) -> anyhow::Result<Option<Oid>> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read(git_dir, &name)? {
+            Some(value) => match value.strip_prefix("ref: ") {
+                Some(target) => name = target.to_string(),
+                None => {
+                    let oid = value.parse().with_context(|| format!("ref {name} does not contain an object ID"))?;
+                    return Ok(Some(oid));
+                }
+            },
+            None => return Ok(None),
+        }
+    }
+    anyhow::bail!("symbolic ref loop at '{name}'")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names with what `git check-ref-format` says of them: plainly, with
+    /// `--allow-onelevel` and with `--refspec-pattern`.
+    const NAMES: &[(&str, [bool; 3])] = &[
+        ("refs/heads/main", [true, true, true]),
+        ("refs/heads/a.b", [true, true, true]),
+        ("refs/heads/a..b", [false, false, false]),
+        ("refs/heads/a\tb", [false, false, false]),
+        ("refs/heads/a\x01b", [false, false, false]),
+        ("refs/heads/a\x7fb", [false, false, false]),
+        ("refs/heads/a b", [false, false, false]),
+        ("refs/heads/main.lock", [false, false, false]),
+        ("refs/heads/main.lock/x", [false, false, false]),
+        ("refs/heads/a.lockx", [true, true, true]),
+        ("refs/heads/a@{b", [false, false, false]),
+        ("refs/heads/a@b", [true, true, true]),
+        ("refs/heads/@", [true, true, true]),
+        ("@", [false, false, false]),
+        ("refs/heads/.hidden", [false, false, false]),
+        ("refs/.heads/x", [false, false, false]),
+        ("refs/heads/a/.b", [false, false, false]),
+        ("refs/heads/a.", [false, false, false]),
+        ("refs/heads//a", [false, false, false]),
+        ("refs/heads/a/", [false, false, false]),
+        ("refs/heads/a~1", [false, false, false]),
+        ("refs/heads/a^", [false, false, false]),
+        ("refs/heads/a:b", [false, false, false]),
+        ("refs/heads/a?", [false, false, false]),
+        ("refs/heads/a[", [false, false, false]),
+        ("refs/heads/a\\b", [false, false, false]),
+        ("main", [false, true, false]),
+        ("refs/heads/*", [false, false, true]),
+        ("refs/heads/a*b*", [false, false, false]),
+    ];
+
+    #[test]
+    fn names_are_checked_as_git_checks_them() {
+        let all_rules = [
+            NameRules::default(),
+            NameRules { allow_onelevel: true, ..NameRules::default() },
+            NameRules { refspec_pattern: true, ..NameRules::default() },
+        ];
+        for (name, valid) in NAMES {
+            for (rules, valid) in all_rules.iter().zip(valid) {
+                assert_eq!(is_valid_name(name, *rules), *valid, "{name:?} with {rules:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn branch_names_must_not_look_like_options_or_head() {
+        assert!(is_valid_branch_name("topic/x"));
+        assert!(!is_valid_branch_name("-x"));
+        assert!(!is_valid_branch_name("HEAD"));
+        assert!(!is_valid_branch_name("a..b"));
+    }
+}
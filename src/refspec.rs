@@ -0,0 +1,66 @@
+//! Refspecs such as `+refs/heads/*:refs/remotes/origin/*`, which map the
+//! names of refs on a remote to the names they are stored under locally.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    /// Whether non-fast-forward updates are allowed (a leading `+`).
+    pub force: bool,
+    pub src: String,
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    /// The destination `name` maps to, if it matches the source side.
+    pub fn map(&self, name: &str) -> Option<String> {
+        let dst = self.dst.as_ref()?;
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => {
+                let middle = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                Some(dst.replacen('*', middle, 1))
+            }
+            None => (name == self.src).then(|| dst.clone()),
+        }
+    }
+}
+
+impl FromStr for Refspec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Refspec> {
+        let (force, spec) = match s.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, s),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, Some(dst)),
+            None => (spec, None),
+        };
+        let globs = |side: &str| side.matches('*').count();
+        anyhow::ensure!(!src.is_empty() || dst.is_some(), "invalid refspec '{s}'");
+        anyhow::ensure!(globs(src) <= 1, "invalid refspec '{s}'");
+        if let Some(dst) = dst {
+            anyhow::ensure!(globs(src) == globs(dst), "invalid refspec '{s}': pattern mismatch");
+        }
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst: dst.map(str::to_string),
+        })
+    }
+}
+
+impl fmt::Display for Refspec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.force {
+            write!(f, "+")?;
+        }
+        write!(f, "{}", self.src)?;
+        if let Some(dst) = &self.dst {
+            write!(f, ":{dst}")?;
+        }
+        Ok(())
+    }
+}
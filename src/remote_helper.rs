@@ -0,0 +1,259 @@
+//! Remote helpers: transports that live outside got, in programs named
+//! `got-remote-<transport>`, so that support for other kinds of server can
+//! be added without changing got itself.
+//!
+//! A remote whose URL is `<transport>::<address>`, or whose URL scheme is
+//! one got has no transport of its own for, is reached by running
+//! `got-remote-<transport> <remote> <address>` with `GOT_DIR` set to the
+//! repository and talking to it over its standard input and output, one
+//! line per command, in the protocol of git's remote helpers:
+//!
+//! - `capabilities` is answered with the commands the helper supports, one
+//!   per line and ending with a blank line. A capability prefixed with `*`
+//!   is mandatory: got must understand it to use the helper.
+//! - `option <name> <value>` is answered with `ok`, `unsupported` or
+//!   `error <message>`.
+//! - `list` (or `list for-push`) is answered with the remote's refs, one
+//!   `<hash> <name>` per line, `@<target> <name>` for a symbolic ref or
+//!   `? <name>` if the value isn't known, and a blank line.
+//! - A batch of `fetch <hash> <name>` lines and a blank line asks for the
+//!   objects needed for those refs to be written into the repository's
+//!   object store; the helper answers with a blank line when it has,
+//!   preceded by `lock <file>` for each pack it protected with a `.keep`
+//!   file. got removes those files once it has updated the refs.
+//! - A batch of `push [+]<src>:<dst>` lines and a blank line asks for the
+//!   helper to update the remote's refs, reading objects from the
+//!   repository; it answers with `ok <dst>` or `error <dst> <why>` for
+//!   each and a blank line.
+
+use crate::oid::Oid;
+use crate::refspec::Refspec;
+use anyhow::Context;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// The URL schemes got handles itself.
+const BUILTIN_SCHEMES: &[&str] = &["file", "http", "https"];
+
+/// The capabilities got knows how to use.
+const KNOWN_CAPABILITIES: &[&str] = &["fetch", "push", "option", "refspec", "check-connectivity"];
+
+/// The transport and address to pass to a helper for `url`, if a helper is
+/// what reaches it.
+pub fn helper_for(url: &str) -> Option<(&str, &str)> {
+    if let Some((transport, address)) = url.split_once("::")
+        && !transport.is_empty()
+        && transport.bytes().all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b))
+    {
+        return Some((transport, address));
+    }
+    let (scheme, _) = url.split_once("://")?;
+    (!BUILTIN_SCHEMES.contains(&scheme)).then_some((scheme, url))
+}
+
+/// The `.keep` files of packs a fetch received, removed when dropped.
+#[derive(Debug, Default)]
+pub struct PackLocks(pub(crate) Vec<PathBuf>);
+
+impl Drop for PackLocks {
+    fn drop(&mut self) {
+        for file in &self.0 {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
+
+/// What a ref listed by a helper points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefValue {
+    Hash(Oid),
+    /// A symbolic ref, naming the ref it points to.
+    Symref(String),
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRef {
+    pub name: String,
+    pub value: RefValue,
+}
+
+/// How the helper reports a ref it was asked to push.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushStatus {
+    pub dst: String,
+    /// Why the remote refused the update, if it did.
+    pub error: Option<String>,
+}
+
+pub struct RemoteHelper {
+    transport: String,
+    child: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    capabilities: Vec<String>,
+}
+
+impl RemoteHelper {
+    /// Starts `got-remote-<transport>` for remote `remote` at `address` and
+    /// asks it what it can do.
+    pub fn spawn(transport: &str, remote: &str, address: &str, git_dir: &Path) -> anyhow::Result<RemoteHelper> {
+        let git_dir = std::path::absolute(git_dir).context("find repository")?;
+        let mut child = Command::new(format!("got-remote-{transport}"))
+            .arg(remote)
+            .arg(address)
+            .env("GOT_DIR", git_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("unable to find remote helper for '{transport}'"))?;
+        let input = child.stdin.take().expect("stdin is piped");
+        let output = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut helper = RemoteHelper {
+            transport: transport.to_string(),
+            child,
+            input,
+            output,
+            capabilities: vec![],
+        };
+        helper.send("capabilities\n")?;
+        for line in helper.read_block()? {
+            if let Some(mandatory) = line.strip_prefix('*') {
+                let name = mandatory.split(' ').next().unwrap_or_default();
+                anyhow::ensure!(
+                    KNOWN_CAPABILITIES.contains(&name),
+                    "unknown mandatory capability {name}; this remote helper probably needs a newer version of got"
+                );
+                helper.capabilities.push(mandatory.to_string());
+            } else {
+                helper.capabilities.push(line);
+            }
+        }
+        Ok(helper)
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|capability| capability == name)
+    }
+
+    /// Sets an option, returning whether the helper supports it.
+    pub fn set_option(&mut self, name: &str, value: &str) -> anyhow::Result<bool> {
+        if !self.has_capability("option") {
+            return Ok(false);
+        }
+        self.send(&format!("option {name} {value}\n"))?;
+        match self.read_line()?.as_str() {
+            "ok" => Ok(true),
+            "unsupported" => Ok(false),
+            reply => match reply.strip_prefix("error ") {
+                Some(message) => anyhow::bail!("remote helper could not set option {name}: {message}"),
+                None => anyhow::bail!("unexpected reply to option {name}: '{reply}'"),
+            },
+        }
+    }
+
+    /// The refs on the remote, as the helper reports them for fetching or,
+    /// with `for_push`, for pushing.
+    pub fn list(&mut self, for_push: bool) -> anyhow::Result<Vec<RemoteRef>> {
+        self.send(if for_push { "list for-push\n" } else { "list\n" })?;
+        self.read_block()?
+            .into_iter()
+            .map(|line| {
+                let mut fields = line.split(' ');
+                let (Some(value), Some(name)) = (fields.next(), fields.next()) else {
+                    anyhow::bail!("malformed ref from remote helper: '{line}'");
+                };
+                let value = match value {
+                    "?" => RefValue::Unknown,
+                    value => match value.strip_prefix('@') {
+                        Some(target) => RefValue::Symref(target.to_string()),
+                        None => RefValue::Hash(value.parse().with_context(|| format!("malformed ref '{line}'"))?),
+                    },
+                };
+                Ok(RemoteRef { name: name.to_string(), value })
+            })
+            .collect()
+    }
+
+    /// Has the helper fetch the objects needed for `refs` into the
+    /// repository. The packs it kept stay kept until the returned locks are
+    /// dropped, which should be once the refs point at their objects.
+    pub fn fetch(&mut self, refs: &[(Oid, String)]) -> anyhow::Result<PackLocks> {
+        anyhow::ensure!(self.has_capability("fetch"), "remote helper '{}' cannot fetch", self.transport);
+        let mut batch = String::new();
+        for (hash, name) in refs {
+            batch.push_str(&format!("fetch {hash} {name}\n"));
+        }
+        batch.push('\n');
+        self.send(&batch)?;
+        let mut locks = PackLocks::default();
+        for line in self.read_block()? {
+            match line.strip_prefix("lock ") {
+                Some(file) => locks.0.push(PathBuf::from(file)),
+                None if line == "connectivity-ok" => {}
+                None => eprintln!("warning: {} unexpectedly said: '{line}'", self.transport),
+            }
+        }
+        Ok(locks)
+    }
+
+    /// Has the helper update the remote's refs as `specs` say.
+    pub fn push(&mut self, specs: &[Refspec]) -> anyhow::Result<Vec<PushStatus>> {
+        anyhow::ensure!(self.has_capability("push"), "remote helper '{}' cannot push", self.transport);
+        let mut batch = String::new();
+        for spec in specs {
+            batch.push_str(&format!("push {spec}\n"));
+        }
+        batch.push('\n');
+        self.send(&batch)?;
+        self.read_block()?
+            .into_iter()
+            .map(|line| {
+                if let Some(dst) = line.strip_prefix("ok ") {
+                    return Ok(PushStatus { dst: dst.to_string(), error: None });
+                }
+                let Some((dst, why)) = line.strip_prefix("error ").map(|rest| rest.split_once(' ').unwrap_or((rest, ""))) else {
+                    anyhow::bail!("malformed push status from remote helper: '{line}'");
+                };
+                Ok(PushStatus {
+                    dst: dst.to_string(),
+                    error: Some(why.to_string()),
+                })
+            })
+            .collect()
+    }
+
+    /// Tells the helper there is nothing more to do and waits for it.
+    pub fn finish(self) -> anyhow::Result<()> {
+        let RemoteHelper { transport, mut child, input, .. } = self;
+        drop(input);
+        let status = child.wait().context("wait for remote helper")?;
+        anyhow::ensure!(status.success(), "remote helper '{transport}' failed with {status}");
+        Ok(())
+    }
+
+    fn send(&mut self, text: &str) -> anyhow::Result<()> {
+        self.input.write_all(text.as_bytes()).context("write to remote helper")?;
+        self.input.flush().context("write to remote helper")
+    }
+
+    fn read_line(&mut self) -> anyhow::Result<String> {
+        let mut line = String::new();
+        let read = self.output.read_line(&mut line).context("read from remote helper")?;
+        anyhow::ensure!(read > 0, "remote helper '{}' closed its output unexpectedly", self.transport);
+        Ok(line.trim_end_matches('\n').to_string())
+    }
+
+    /// Lines up to the blank line that ends a reply.
+    fn read_block(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut lines = vec![];
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(lines);
+            }
+            lines.push(line);
+        }
+    }
+}
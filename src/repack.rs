@@ -0,0 +1,611 @@
+//! Repacking: gathering objects into a new pack, with each stored as a
+//! delta against a similar one where that saves space.
+//!
+//! With `all`, every object reachable from the refs, `HEAD`, the reflogs or
+//! the index goes into one pack, and deleting then removes the old packs
+//! and the loose objects the new one holds. Otherwise only the reachable
+//! loose objects are packed.
+//!
+//! Finding deltas sorts the objects by type, by a hash weighted towards the
+//! end of their paths and by size, largest first, so that similar objects
+//! sit together. Each is then tried against the `window` objects before it
+//! (`pack.window`, 10 unless set), keeping chains of deltas at most `depth`
+//! long (`pack.depth`, 50). A bigger window finds more deltas at the cost
+//! of time, and deeper chains make the pack smaller and reading slower.
+//! The sorted objects are split between `threads` threads (`pack.threads`,
+//! one per CPU unless set), each searching its own share.
+//!
+//! An object already stored as a delta in a pack, against an object that is
+//! being packed too, keeps that delta rather than being searched for one
+//! again, which saves most of the time repacking or pushing takes unless
+//! `reuse_deltas` is off (`repack -f`). Chains made of reused deltas are cut
+//! where they would be deeper than `depth`.
+//!
+//! Delta islands keep a pack that serves more than one set of refs, such as
+//! the namespaces of repositories sharing their objects, from holding
+//! deltas one set's fetches can only get by sending objects of another.
+//! Each `pack.island` pattern, a wildcard like those of `.gitignore` where
+//! git takes a regular expression, is matched against the start of every
+//! ref name, and the refs matched by the same start of their names form an
+//! island: `refs/namespaces/*/` makes an island of each namespace. With
+//! `delta_islands` (`repack -i`, `repack.useDeltaIslands`), an object is
+//! only stored as a delta against one that every island reaching it reaches
+//! too. Reused deltas are held to the same rule.
+//!
+//! A reachability bitmap (`pack-<hash>.bitmap`, `repack.writeBitmaps`)
+//! records, for each commit a ref points at, which objects of the pack are
+//! reachable from it, so that serving a fetch needn't walk the history.
+//! Only a pack holding every reachable object can have one.
+//!
+//! A pack with a `.keep` file is left alone: deleting never removes it, and
+//! its objects aren't packed again unless `pack_kept_objects`
+//! (`repack.packKeptObjects`, by default whether a bitmap is wanted) says so.
+//! A fetch keeps the pack it is receiving until its refs are updated, so that
+//! a repack running meanwhile can't lose the objects nothing refers to yet.
+//!
+//! The objects of a deleted pack that nothing reaches are lost with it,
+//! unless `unpack_unreachable` gives a time: then those of packs modified
+//! since are written out loose first, with the pack's modification time,
+//! for pruning to remove once they are old enough.
+
+use crate::config::Config;
+use crate::ewah;
+use crate::ignore::wildmatch;
+use crate::index::Index;
+use crate::object::{self, Kind, Object, Tag};
+use crate::oid::Oid;
+use crate::pack::{self, DeltaIndex, Pack, PackIndex, PackWriter};
+use crate::progress::Progress;
+use crate::reflog;
+use crate::refs;
+use crate::repository;
+use crate::revision;
+use crate::revwalk::{self, RevWalk};
+use crate::tree::Mode;
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+const DEFAULT_WINDOW: usize = 10;
+const DEFAULT_DEPTH: usize = 50;
+/// The deepest chain git can read.
+const MAX_DEPTH: usize = 4095;
+/// Objects smaller than this aren't worth making deltas of.
+const MIN_DELTA_SIZE: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct RepackOptions {
+    pub all: bool,
+    pub delete: bool,
+    pub window: usize,
+    pub depth: usize,
+    /// 0 for one per CPU.
+    pub threads: usize,
+    pub write_bitmap: bool,
+    /// `None` to pack the objects of kept packs only with a bitmap.
+    pub pack_kept_objects: Option<bool>,
+    /// Write the unreachable objects of deleted packs modified after this
+    /// out loose.
+    pub unpack_unreachable: Option<SystemTime>,
+    /// Show progress meters on standard error.
+    pub progress: bool,
+    /// Keep the deltas objects are already stored as.
+    pub reuse_deltas: bool,
+    /// Keep deltas within the islands `islands` makes when repacking.
+    pub delta_islands: bool,
+    pub islands: Vec<String>,
+}
+
+impl RepackOptions {
+    /// The options as configured, for everything but `all`, `delete`,
+    /// `unpack_unreachable` and `progress`.
+    pub fn from_config(config: &Config) -> anyhow::Result<RepackOptions> {
+        let count = |key: &str, default: usize| -> anyhow::Result<usize> {
+            match config.get_int(key)? {
+                None => Ok(default),
+                Some(value) => usize::try_from(value).with_context(|| format!("{key} must not be negative")),
+            }
+        };
+        Ok(RepackOptions {
+            all: false,
+            delete: false,
+            window: count("pack.window", DEFAULT_WINDOW)?,
+            depth: count("pack.depth", DEFAULT_DEPTH)?,
+            threads: count("pack.threads", 0)?,
+            write_bitmap: config.get_bool("repack.writeBitmaps")?.unwrap_or(false),
+            pack_kept_objects: config.get_bool("repack.packKeptObjects")?,
+            unpack_unreachable: None,
+            progress: false,
+            reuse_deltas: true,
+            delta_islands: config.get_bool("repack.useDeltaIslands")?.unwrap_or(false),
+            islands: config.get_all("pack.island").into_iter().map(str::to_string).collect(),
+        })
+    }
+}
+
+/// An object to pack.
+struct Entry {
+    hash: Oid,
+    kind: Kind,
+    size: usize,
+    name_hash: u32,
+    /// The islands reaching the object, in order.
+    islands: Vec<u32>,
+}
+
+/// Objects, each with the path it was found at.
+type Named = Vec<(Oid, Vec<u8>)>;
+
+/// A delta: the entry it's against and the delta itself.
+type Delta = (usize, Vec<u8>);
+
+/// Packs the objects `options` ask for, returning the new pack's path, or
+/// `None` if there was nothing to pack.
+pub fn repack(git_dir: &Path, options: &RepackOptions) -> anyhow::Result<Option<PathBuf>> {
+    let objects_dir = repository::objects_dir(git_dir);
+    let packs = pack::packs(&objects_dir)?;
+    let pack_kept_objects = options.pack_kept_objects.unwrap_or(options.write_bitmap);
+    let skipped: Vec<&Pack> = packs.iter().filter(|pack| !options.all || (!pack_kept_objects && pack.is_kept())).collect();
+    let (mut objects, tips) = reachable(git_dir)?;
+    let reachable_count = objects.len();
+    let mut enumerating = Progress::new("Enumerating objects", None, options.progress);
+    enumerating.set(reachable_count as u64);
+    enumerating.done();
+    objects.retain(|(hash, _)| !skipped.iter().any(|pack| pack.contains(hash)));
+    if objects.is_empty() {
+        return Ok(None);
+    }
+    let islands = match options.delta_islands {
+        true => islands(git_dir, &options.islands)?,
+        false => HashMap::new(),
+    };
+    let (path, index, kinds) = write_pack(git_dir, &objects_dir.join("pack"), objects, &islands, options)?;
+
+    if options.write_bitmap && options.all && kinds.len() < reachable_count {
+        eprintln!("warning: disabling bitmap writing, as some objects are not being packed");
+    } else if options.write_bitmap && options.all {
+        write_bitmap(git_dir, &path, &index, &kinds, &tips)?;
+    }
+    if options.delete {
+        if options.all {
+            for old in packs.iter().filter(|old| !old.is_kept() && old.path.file_name() != path.file_name()) {
+                if let Some(since) = options.unpack_unreachable {
+                    unpack_unreachable(old, &packs, &index, since)?;
+                }
+                remove_pack(&old.path)?;
+            }
+        }
+        pack::prune_packed(&objects_dir, false)?;
+    }
+    Ok(Some(path))
+}
+
+/// Packs every object reachable from `tips` but not from the commits in
+/// `exclude` into a new pack in `dir`, which needn't be in the repository,
+/// returning the pack's path and index.
+pub fn pack_reachable_from(git_dir: &Path, tips: &[Oid], exclude: &[Oid], dir: &Path, options: &RepackOptions) -> anyhow::Result<(PathBuf, PackIndex)> {
+    let tips = tips.iter().map(|&hash| (hash, false)).collect();
+    let (objects, _) = reachable_from(git_dir, tips, exclude, vec![])?;
+    let (path, index, _) = write_pack(git_dir, dir, objects, &HashMap::new(), options)?;
+    Ok((path, index))
+}
+
+/// Packs `objects`, each with the path it was found at or an empty one,
+/// into a new pack in `dir`, returning the pack's path and index.
+pub fn pack_objects(git_dir: &Path, mut objects: Vec<(Oid, Vec<u8>)>, dir: &Path, options: &RepackOptions) -> anyhow::Result<(PathBuf, PackIndex)> {
+    let mut unique = HashSet::new();
+    objects.retain(|(hash, _)| unique.insert(*hash));
+    let (path, index, _) = write_pack(git_dir, dir, objects, &HashMap::new(), options)?;
+    Ok((path, index))
+}
+
+/// Writes `objects` into a new pack in `dir`, with deltas where `options`
+/// find them that `islands` allow, returning its path, its index and the
+/// kind of each object.
+fn write_pack(
+    git_dir: &Path,
+    dir: &Path,
+    objects: Named,
+    islands: &HashMap<Oid, Vec<u32>>,
+    options: &RepackOptions,
+) -> anyhow::Result<(PathBuf, PackIndex, HashMap<Oid, Kind>)> {
+    let mut counting = Progress::new("Counting objects", Some(objects.len() as u64), options.progress);
+    let mut entries = vec![];
+    for (hash, path) in objects {
+        let object = Object::read_original(&hash).with_context(|| format!("read object {hash}"))?;
+        entries.push(Entry {
+            hash,
+            kind: object.kind,
+            size: object.contents.len(),
+            name_hash: name_hash(&path),
+            islands: islands.get(&hash).cloned().unwrap_or_default(),
+        });
+        counting.tick();
+    }
+    counting.done();
+
+    let deltas = find_deltas(git_dir, &entries, options)?;
+    let mut writing = Progress::new("Writing objects", Some(entries.len() as u64), options.progress);
+    let mut writer = PackWriter::new(dir, entries.len() as u32)?;
+    for i in 0..entries.len() {
+        // Bases go before the deltas against them.
+        let mut chain = vec![i];
+        while let Some((base, _)) = deltas.get(chain.last().expect("chain is never empty")) {
+            chain.push(*base);
+        }
+        for &j in chain.iter().rev() {
+            let entry = &entries[j];
+            if writer.contains(&entry.hash) {
+                continue;
+            }
+            match deltas.get(&j) {
+                Some((base, delta)) => writer.add(&entry.hash, entry.kind, delta, Some(&entries[*base].hash))?,
+                None => {
+                    let object = Object::read_original(&entry.hash).with_context(|| format!("read object {}", entry.hash))?;
+                    writer.add(&entry.hash, entry.kind, &object.contents, None)?;
+                }
+            }
+            writing.tick();
+        }
+    }
+    let (path, index) = writer.finish()?;
+    writing.done();
+    let kinds = entries.iter().map(|entry| (entry.hash, entry.kind)).collect();
+    Ok((path, index, kinds))
+}
+
+/// Writes out loose the objects of `pack`, if it was modified after `since`,
+/// that aren't in the new pack with index `new` or in a kept pack among
+/// `packs`, giving them the pack's modification time.
+fn unpack_unreachable(pack: &Pack, packs: &[Pack], new: &PackIndex, since: SystemTime) -> anyhow::Result<()> {
+    let modified = fs::metadata(&pack.path)
+        .and_then(|meta| meta.modified())
+        .with_context(|| format!("stat {}", pack.path.display()))?;
+    if modified <= since {
+        return Ok(());
+    }
+    for hash in &pack.index.oids {
+        let kept = packs.iter().any(|other| other.is_kept() && other.contains(hash));
+        let path = object::object_path(hash);
+        if new.position(hash).is_some() || kept || path.exists() {
+            continue;
+        }
+        let (kind, contents) = pack.read(hash)?.with_context(|| format!("read object {hash}"))?;
+        Object::store_loose(kind, &contents)?;
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(modified))
+            .with_context(|| format!("touch {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Every object reachable from the refs, `HEAD`, the reflogs and the index,
+/// for pruning to keep.
+pub fn reachable_objects(git_dir: &Path) -> anyhow::Result<HashSet<Oid>> {
+    Ok(reachable(git_dir)?.0.into_iter().map(|(hash, _)| hash).collect())
+}
+
+/// Every object reachable from the refs, `HEAD`, the reflogs and the index,
+/// commits first and then tags, trees and blobs, with the path each was
+/// found at; and the commits refs point at.
+fn reachable(git_dir: &Path) -> anyhow::Result<(Named, Vec<Oid>)> {
+    let mut names: Vec<String> = refs::list(git_dir)?.into_iter().map(|(name, _)| name).collect();
+    names.push("HEAD".to_string());
+    let mut tips = vec![];
+    for name in &names {
+        if let Some(hash) = refs::resolve(git_dir, name)? {
+            tips.push((hash, true));
+        }
+        for entry in reflog::read(git_dir, name)? {
+            tips.extend([entry.old, entry.new].into_iter().filter(|hash| !hash.is_zero()).map(|hash| (hash, false)));
+        }
+    }
+    let staged = Index::read(git_dir)?
+        .entries()
+        .iter()
+        .filter(|entry| entry.mode != Mode::Submodule)
+        .map(|entry| (entry.hash, entry.path.clone()))
+        .collect();
+    reachable_from(git_dir, tips, &[], staged)
+}
+
+/// Every object reachable from `tips`, each marked with whether a ref
+/// points at it, but not from the commits in `exclude`, and the objects in
+/// `staged`, as `reachable` gives them.
+fn reachable_from(git_dir: &Path, tips: Vec<(Oid, bool)>, exclude: &[Oid], staged: Named) -> anyhow::Result<(Named, Vec<Oid>)> {
+    let mut ref_commits = vec![];
+    let mut seen: HashSet<Oid> = HashSet::new();
+    let mut commits = vec![];
+    let mut tags = vec![];
+    let mut others = vec![];
+    for (mut hash, from_ref) in tips {
+        // Objects only reflogs remember may be gone already.
+        while !seen.contains(&hash) && Object::exists(&hash) {
+            let object = Object::read_original(&hash).with_context(|| format!("read object {hash}"))?;
+            match object.kind {
+                Kind::Tag => {
+                    seen.insert(hash);
+                    tags.push((hash, vec![]));
+                    hash = Tag::parse(&object.contents).with_context(|| format!("parse tag {hash}"))?.object;
+                    continue;
+                }
+                Kind::Commit => {
+                    seen.insert(hash);
+                    commits.push(hash);
+                }
+                Kind::Tree => revwalk::walk_tree(&hash, &mut seen, &mut others)?,
+                Kind::Blob => {
+                    seen.insert(hash);
+                    others.push((hash, vec![]));
+                }
+            }
+            break;
+        }
+        if from_ref && let Ok(commit) = revision::peel(&hash, Some(Kind::Commit)) {
+            ref_commits.push(commit);
+        }
+    }
+    for (hash, path) in staged {
+        if Object::exists(&hash) && seen.insert(hash) {
+            others.push((hash, path));
+        }
+    }
+
+    let walk = RevWalk { include: commits, exclude: exclude.to_vec(), ..RevWalk::default() };
+    let commits = walk.commits(git_dir)?;
+    let mut objects: Named = commits.iter().map(|commit| (commit.hash, vec![])).collect();
+    objects.extend(tags);
+    objects.extend(walk.objects(git_dir, &commits)?);
+    objects.extend(others);
+    // Trees and blobs found from a tip or the index may also be in a commit.
+    let mut unique = HashSet::new();
+    objects.retain(|(hash, _)| unique.insert(*hash));
+    Ok((objects, ref_commits))
+}
+
+/// git's hash of a path for grouping similar objects: mostly its last
+/// characters, so that files of the same name and type sort together.
+fn name_hash(path: &[u8]) -> u32 {
+    path.iter()
+        .copied()
+        .filter(|c| !c.is_ascii_whitespace())
+        .fold(0u32, |hash, c| (hash >> 2).wrapping_add(u32::from(c) << 24))
+}
+
+/// The islands each object is in, numbered in the order of their names,
+/// for the refs `patterns` put in islands.
+fn islands(git_dir: &Path, patterns: &[String]) -> anyhow::Result<HashMap<Oid, Vec<u32>>> {
+    let mut tips: BTreeMap<String, Vec<(Oid, bool)>> = BTreeMap::new();
+    for (name, _) in refs::list(git_dir)? {
+        if let Some(island) = island_of(&name, patterns)
+            && let Some(hash) = refs::resolve(git_dir, &name)?
+        {
+            tips.entry(island).or_default().push((hash, false));
+        }
+    }
+    let mut islands: HashMap<Oid, Vec<u32>> = HashMap::new();
+    for (island, tips) in tips.into_values().enumerate() {
+        let (objects, _) = reachable_from(git_dir, tips, &[], vec![])?;
+        for (hash, _) in objects {
+            islands.entry(hash).or_default().push(island as u32);
+        }
+    }
+    Ok(islands)
+}
+
+/// The island ref `name` is in: the shortest start of its name that the
+/// first pattern matching one matches.
+fn island_of(name: &str, patterns: &[String]) -> Option<String> {
+    patterns.iter().find_map(|pattern| {
+        (1..=name.len())
+            .filter(|&end| name.is_char_boundary(end))
+            .map(|end| &name[..end])
+            .find(|start| wildmatch(pattern.as_bytes(), start.as_bytes()))
+            .map(str::to_string)
+    })
+}
+
+/// Whether `target` may be stored as a delta against `base`: every island
+/// reaching `target` must reach `base` too.
+fn allows_delta(base: &Entry, target: &Entry) -> bool {
+    target.islands.iter().all(|island| base.islands.binary_search(island).is_ok())
+}
+
+/// The deltas worth storing, by the entry they make: the entry they are
+/// against and the delta.
+fn find_deltas(git_dir: &Path, entries: &[Entry], options: &RepackOptions) -> anyhow::Result<HashMap<usize, Delta>> {
+    if options.window == 0 || options.depth == 0 {
+        return Ok(HashMap::new());
+    }
+    let depth = options.depth.min(MAX_DEPTH);
+    let mut deltas = match options.reuse_deltas {
+        true => reused_deltas(git_dir, entries)?,
+        false => HashMap::new(),
+    };
+    // Entries whose chains are too deep are searched like the rest.
+    limit_chains(&mut deltas, depth);
+    let mut order: Vec<usize> = (0..entries.len()).filter(|i| !deltas.contains_key(i)).collect();
+    order.sort_by_key(|&i| {
+        let entry = &entries[i];
+        (kind_rank(entry.kind), entry.name_hash, std::cmp::Reverse(entry.size))
+    });
+    let threads = match options.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let share = order.len().div_ceil(threads).max(1);
+    if options.progress {
+        eprintln!("Delta compression using up to {threads} threads");
+    }
+    let progress = Mutex::new(Progress::new("Compressing objects", Some(order.len() as u64), options.progress));
+    let found = thread::scope(|scope| {
+        let progress = &progress;
+        let handles: Vec<_> = order
+            .chunks(share)
+            .map(|chunk| scope.spawn(move || search(entries, chunk, options.window, depth, progress)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("delta search thread panicked"))
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+    progress.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()).done();
+    deltas.extend(found.into_iter().flatten());
+    limit_chains(&mut deltas, depth);
+    Ok(deltas)
+}
+
+/// The deltas entries are already stored as in the repository's packs,
+/// where the base is an entry too and the islands allow it.
+fn reused_deltas(git_dir: &Path, entries: &[Entry]) -> anyhow::Result<HashMap<usize, Delta>> {
+    let packs = pack::packs(&repository::objects_dir(git_dir))?;
+    let positions: HashMap<Oid, usize> = entries.iter().enumerate().map(|(i, entry)| (entry.hash, i)).collect();
+    let mut reused = HashMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let Some(pack) = packs.iter().find(|pack| pack.contains(&entry.hash)) else {
+            continue;
+        };
+        if let Some((base, delta)) = pack.read_delta(&entry.hash)?
+            && let Some(&base) = positions.get(&base)
+            && allows_delta(&entries[base], entry)
+        {
+            reused.insert(i, (base, delta));
+        }
+    }
+    Ok(reused)
+}
+
+/// Drops deltas, so that their entries are stored whole, until no chain is
+/// deeper than `max_depth` or goes round in a circle, as deltas reused from
+/// different packs can.
+fn limit_chains(deltas: &mut HashMap<usize, Delta>, max_depth: usize) {
+    let mut starts: Vec<usize> = deltas.keys().copied().collect();
+    starts.sort_unstable();
+    for start in starts {
+        let mut chain = HashSet::from([start]);
+        let mut at = start;
+        while let Some((base, _)) = deltas.get(&at) {
+            if chain.len() > max_depth || !chain.insert(*base) {
+                deltas.remove(&start);
+                break;
+            }
+            at = *base;
+        }
+    }
+}
+
+fn kind_rank(kind: Kind) -> u8 {
+    match kind {
+        Kind::Commit => 0,
+        Kind::Tree => 1,
+        Kind::Blob => 2,
+        Kind::Tag => 3,
+    }
+}
+
+/// Searches `order`, a share of the sorted entries, for deltas against
+/// the `window` entries before each, counting each entry on `progress`.
+fn search(
+    entries: &[Entry],
+    order: &[usize],
+    window: usize,
+    max_depth: usize,
+    progress: &Mutex<Progress>,
+) -> anyhow::Result<Vec<(usize, Delta)>> {
+    let mut recent: VecDeque<(usize, Vec<u8>, DeltaIndex)> = VecDeque::new();
+    let mut depths: HashMap<usize, usize> = HashMap::new();
+    let mut found = vec![];
+    for &i in order {
+        let entry = &entries[i];
+        let contents = Object::read_original(&entry.hash).with_context(|| format!("read object {}", entry.hash))?.contents;
+        let mut best: Option<Delta> = None;
+        if contents.len() >= MIN_DELTA_SIZE {
+            for (base, base_contents, index) in recent.iter().rev() {
+                let depth = depths.get(base).copied().unwrap_or(0);
+                if entries[*base].kind != entry.kind || depth >= max_depth || !allows_delta(&entries[*base], entry) {
+                    continue;
+                }
+                // A delta must at least halve the object to be worth it.
+                let max_size = best.as_ref().map_or(contents.len() / 2, |(_, delta)| delta.len() - 1);
+                if let Some(delta) = index.delta(base_contents, &contents, max_size) {
+                    best = Some((*base, delta));
+                }
+            }
+        }
+        if let Some((base, delta)) = best {
+            depths.insert(i, depths.get(&base).copied().unwrap_or(0) + 1);
+            found.push((i, (base, delta)));
+        }
+        let index = DeltaIndex::new(&contents);
+        recent.push_back((i, contents, index));
+        if recent.len() > window {
+            recent.pop_front();
+        }
+        progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).tick();
+    }
+    Ok(found)
+}
+
+/// Writes the reachability bitmap of the pack at `pack_path` for the
+/// commits in `tips`. Bits are numbered by the objects' order in the pack.
+fn write_bitmap(git_dir: &Path, pack_path: &Path, index: &PackIndex, kinds: &HashMap<Oid, Kind>, tips: &[Oid]) -> anyhow::Result<()> {
+    let mut by_offset: Vec<usize> = (0..index.oids.len()).collect();
+    by_offset.sort_by_key(|&i| index.offsets[i]);
+    let bit_of: HashMap<Oid, usize> = by_offset.iter().enumerate().map(|(bit, &i)| (index.oids[i], bit)).collect();
+
+    let mut out = b"BITM".to_vec();
+    out.extend(1u16.to_be_bytes());
+    // Every bitmap covers all of the history it reaches.
+    out.extend(1u16.to_be_bytes());
+    let mut selected: Vec<Oid> = tips.to_vec();
+    selected.sort();
+    selected.dedup();
+    out.extend((selected.len() as u32).to_be_bytes());
+    out.extend(index.pack_checksum.as_bytes());
+    for kind in [Kind::Commit, Kind::Tree, Kind::Blob, Kind::Tag] {
+        let bits: Vec<bool> = by_offset.iter().map(|&i| kinds.get(&index.oids[i]) == Some(&kind)).collect();
+        ewah::write(&mut out, &bits);
+    }
+    for commit in &selected {
+        let position = index.position(commit).with_context(|| format!("commit {commit} is not in the pack"))?;
+        let walk = RevWalk { include: vec![*commit], ..RevWalk::default() };
+        let commits = walk.commits(git_dir)?;
+        let mut bits = vec![false; by_offset.len()];
+        let reached = commits.iter().map(|commit| commit.hash).chain(walk.objects(git_dir, &commits)?.into_iter().map(|(hash, _)| hash));
+        for hash in reached {
+            let bit = bit_of.get(&hash).with_context(|| format!("object {hash} reachable from {commit} is not in the pack"))?;
+            bits[*bit] = true;
+        }
+        out.extend((position as u32).to_be_bytes());
+        // Neither XORed with an earlier bitmap nor flagged.
+        out.extend([0, 0]);
+        ewah::write(&mut out, &bits);
+    }
+    let checksum = Sha1::digest(&out);
+    out.extend(checksum);
+    let path = pack_path.with_extension("bitmap");
+    let temp = pack_path.with_extension("bitmap.lock");
+    fs::write(&temp, out).with_context(|| format!("write {}", path.display()))?;
+    fs::rename(&temp, &path).with_context(|| format!("write {}", path.display()))
+}
+
+/// Deletes the pack at `path` and the files that go with it.
+fn remove_pack(path: &Path) -> anyhow::Result<()> {
+    // The index goes first, so that the pack is never found without it.
+    for extension in ["idx", "pack", "bitmap"] {
+        let file = path.with_extension(extension);
+        match fs::remove_file(&file) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("remove {}", file.display())),
+        }
+    }
+    Ok(())
+}
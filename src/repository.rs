@@ -0,0 +1,148 @@
+//! Finding the repository a command runs in.
+//!
+//! A repository with a worktree keeps its metadata in `.got` below the
+//! current directory. A bare repository, as `clone --bare` makes, has no
+//! worktree: the current directory is the metadata directory itself. Which
+//! kind it is comes from `core.bare`, falling back to how it was found.
+//!
+//! Objects are kept in `objects` in the metadata directory, unless
+//! `GOT_OBJECT_DIRECTORY` or the repository's `core.objectDirectory`
+//! (relative to the metadata directory) puts them elsewhere: on another
+//! disk, say, or a network volume shared with other repositories.
+//!
+//! A read-only repository is one got must leave exactly as it is, such as
+//! a snapshot or a backup copy: commands that would change it refuse to
+//! run, and reading commands skip the caches they would otherwise update.
+//! It is one when `--read-only` is given, `GOT_READ_ONLY` or `core.readOnly`
+//! is true, or nobody may write to its metadata directory.
+
+use crate::config::Config;
+use anyhow::Context;
+use std::fs;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// The repository's metadata directory: `.got`, or `.` inside a bare
+/// repository.
+pub fn git_dir() -> &'static Path {
+    static GIT_DIR: OnceLock<PathBuf> = OnceLock::new();
+    GIT_DIR.get_or_init(|| {
+        let dotgot = Path::new(".got");
+        if !dotgot.is_dir() && looks_bare(Path::new(".")) {
+            PathBuf::from(".")
+        } else {
+            dotgot.to_path_buf()
+        }
+    })
+}
+
+/// Held by tests that change the current directory, which every thread of
+/// the process shares, so that they take turns.
+#[cfg(test)]
+pub static CURRENT_DIR_LOCK: Mutex<()> = Mutex::new(());
+
+/// An empty repository in a temporary directory, which is the current
+/// directory until it is dropped and removed.
+#[cfg(test)]
+pub struct ScratchRepository {
+    dir: PathBuf,
+    back: PathBuf,
+    _lock: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl ScratchRepository {
+    pub fn new(name: &str) -> ScratchRepository {
+        let lock = CURRENT_DIR_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let dir = std::env::temp_dir().join(format!("got-{name}-{}", std::process::id()));
+        fs::create_dir_all(dir.join(".got/objects")).unwrap();
+        let back = std::env::current_dir().unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+        ScratchRepository { dir, back, _lock: lock }
+    }
+}
+
+#[cfg(test)]
+impl Drop for ScratchRepository {
+    fn drop(&mut self) {
+        let _ = std::env::set_current_dir(&self.back);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Makes the repository read-only for the rest of the run, as `--read-only`
+/// does.
+pub fn set_read_only() {
+    READ_ONLY.store(true, Ordering::Relaxed);
+}
+
+/// Whether the repository must be left as it is.
+pub fn is_read_only(config: &Config) -> anyhow::Result<bool> {
+    if READ_ONLY.load(Ordering::Relaxed) {
+        return Ok(true);
+    }
+    if let Some(value) = std::env::var_os("GOT_READ_ONLY") {
+        let value = value.to_string_lossy();
+        if crate::config::parse_bool(&value).with_context(|| format!("bad boolean value '{value}' for GOT_READ_ONLY"))? {
+            return Ok(true);
+        }
+    }
+    if config.get_bool("core.readOnly")? == Some(true) {
+        return Ok(true);
+    }
+    Ok(fs::metadata(git_dir()).is_ok_and(|meta| meta.permissions().readonly()))
+}
+
+/// Fails for commands that would change the repository, in a read-only
+/// one.
+pub fn require_writable(config: &Config) -> anyhow::Result<()> {
+    anyhow::ensure!(!is_read_only(config)?, "the repository is read-only; this operation would modify it");
+    Ok(())
+}
+
+/// Where the repository whose metadata is in `git_dir` keeps its objects.
+pub fn objects_dir(git_dir: &Path) -> PathBuf {
+    if let Some(dir) = std::env::var_os("GOT_OBJECT_DIRECTORY") {
+        return PathBuf::from(dir);
+    }
+    // Asked for every object read, so the config is only read once per
+    // repository. Relative paths name other repositories once a command
+    // has changed directory, so the cache goes by absolute path and holds
+    // only the configured directory, which is relative to `git_dir`.
+    static CACHE: Mutex<Option<HashMap<PathBuf, Option<PathBuf>>>> = Mutex::new(None);
+    let key = std::path::absolute(git_dir).unwrap_or_else(|_| git_dir.to_path_buf());
+    let mut cache = CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let configured = cache.get_or_insert_with(HashMap::new).entry(key).or_insert_with(|| {
+        let mut config = Config::default();
+        // A config that can't be read is reported by whatever loads it next.
+        config
+            .read_file(&git_dir.join("config"))
+            .ok()
+            .and_then(|()| config.get("core.objectDirectory").map(PathBuf::from))
+    });
+    git_dir.join(configured.as_deref().unwrap_or(Path::new("objects")))
+}
+
+/// Whether `path` holds a repository's metadata directly.
+fn looks_bare(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+/// Whether the repository has no worktree.
+pub fn is_bare(config: &Config) -> anyhow::Result<bool> {
+    Ok(match config.get_bool("core.bare")? {
+        Some(bare) => bare,
+        None => git_dir() == Path::new("."),
+    })
+}
+
+/// Fails with git's message for commands that need a worktree, in a bare
+/// repository.
+pub fn require_worktree(config: &Config) -> anyhow::Result<()> {
+    anyhow::ensure!(!is_bare(config)?, "this operation must be run in a work tree");
+    Ok(())
+}
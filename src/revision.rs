@@ -0,0 +1,222 @@
+//! Turning revision names into object hashes.
+//!
+//! A revision is a ref name (`HEAD`, `main`, `v1.0`, `origin/main`), a full
+//! or abbreviated hash, optionally followed by `~<n>` (the n-th first-parent
+//! ancestor), `^<n>` (the n-th parent) and `^{<type>}` (peel tags until an
+//! object of that type is reached; `^{}` peels to any non-tag). A ref name
+//! may carry a reflog selector, `<ref>@{<n>}`, as in `stash@{2}`.
+//! `<revision>:<path>` names the blob or tree at `path` in the revision's
+//! tree.
+
+use crate::object::{Kind, Object, Tag};
+use crate::oid::Oid;
+use crate::pack;
+use crate::reflog;
+use crate::refs;
+use crate::repository;
+use crate::revwalk;
+use anyhow::Context;
+use std::fs;
+use std::path::Path;
+
+pub fn resolve(git_dir: &Path, spec: &str) -> anyhow::Result<Oid> {
+    if let Some((rev, path)) = spec.split_once(':').filter(|(rev, _)| !rev.is_empty()) {
+        let tree = peel(&resolve(git_dir, rev)?, Some(Kind::Tree))?;
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Ok(tree);
+        }
+        let entry = crate::tree::lookup(&tree, path.as_bytes())?.with_context(|| format!("path '{path}' does not exist in '{rev}'"))?;
+        return Ok(entry.hash);
+    }
+    let base_len = spec.find(['~', '^']).unwrap_or(spec.len());
+    let (base, mut suffix) = spec.split_at(base_len);
+    let mut hash = resolve_name(git_dir, base)?
+        .with_context(|| format!("unknown revision '{spec}'"))?;
+    while !suffix.is_empty() {
+        if let Some(rest) = suffix.strip_prefix("^{") {
+            let (kind, rest) = rest
+                .split_once('}')
+                .with_context(|| format!("invalid revision '{spec}'"))?;
+            hash = match kind {
+                "" => peel(&hash, None)?,
+                "commit" => peel(&hash, Some(Kind::Commit))?,
+                "tree" => peel(&hash, Some(Kind::Tree))?,
+                "blob" => peel(&hash, Some(Kind::Blob))?,
+                "tag" => {
+                    let object = Object::read(&hash)?;
+                    anyhow::ensure!(object.kind == Kind::Tag, "'{spec}' is not a tag");
+                    hash
+                }
+                _ => anyhow::bail!("invalid object type in '{spec}'"),
+            };
+            suffix = rest;
+            continue;
+        }
+        let op = suffix.as_bytes()[0];
+        let digits = suffix[1..].bytes().take_while(u8::is_ascii_digit).count();
+        let n: usize = if digits == 0 {
+            1
+        } else {
+            suffix[1..=digits].parse().with_context(|| format!("invalid revision '{spec}'"))?
+        };
+        suffix = &suffix[1 + digits..];
+        let commit = peel(&hash, Some(Kind::Commit))?;
+        hash = if op == b'~' {
+            let mut commit = commit;
+            for _ in 0..n {
+                commit = revwalk::read_commit(&commit)?
+                    .parents
+                    .into_iter()
+                    .next()
+                    .with_context(|| format!("revision '{spec}' goes past a root commit"))?;
+            }
+            commit
+        } else if n == 0 {
+            commit
+        } else {
+            revwalk::read_commit(&commit)?
+                .parents
+                .into_iter()
+                .nth(n - 1)
+                .with_context(|| format!("commit in '{spec}' has no parent {n}"))?
+        };
+    }
+    Ok(hash)
+}
+
+/// Resolves `spec` and peels it to the commit it names.
+pub fn resolve_commit(git_dir: &Path, spec: &str) -> anyhow::Result<Oid> {
+    let hash = resolve(git_dir, spec)?;
+    peel(&hash, Some(Kind::Commit)).with_context(|| format!("'{spec}' does not name a commit"))
+}
+
+/// Splits revision arguments into the tips to include and to exclude:
+/// `^rev` excludes `rev`, and `a..b` includes `b` but excludes `a` (either
+/// side defaulting to `HEAD`).
+pub fn parse_range(git_dir: &Path, args: &[String]) -> anyhow::Result<(Vec<Oid>, Vec<Oid>)> {
+    let mut include = vec![];
+    let mut exclude = vec![];
+    for arg in args {
+        if let Some(rev) = arg.strip_prefix('^') {
+            exclude.push(resolve_commit(git_dir, rev)?);
+        } else if let Some((from, to)) = arg.split_once("..") {
+            anyhow::ensure!(!to.starts_with('.'), "symmetric differences are not supported: '{arg}'");
+            let or_head = |rev: &str| if rev.is_empty() { "HEAD".to_string() } else { rev.to_string() };
+            exclude.push(resolve_commit(git_dir, &or_head(from))?);
+            include.push(resolve_commit(git_dir, &or_head(to))?);
+        } else {
+            include.push(resolve_commit(git_dir, arg)?);
+        }
+    }
+    Ok((include, exclude))
+}
+
+fn resolve_name(git_dir: &Path, name: &str) -> anyhow::Result<Option<Oid>> {
+    let name = if name == "@" { "HEAD" } else { name };
+    if let Some((name, rest)) = name.split_once("@{")
+        && let Some(n) = rest.strip_suffix('}')
+    {
+        return resolve_reflog(git_dir, name, n);
+    }
+    if let Some(full) = dwim_ref(git_dir, name)? {
+        return refs::resolve(git_dir, &full);
+    }
+    if name.len() >= 4 && name.len() <= 40 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return expand_hash(git_dir, &name.to_ascii_lowercase());
+    }
+    Ok(None)
+}
+
+/// The full name of the ref that `name` abbreviates, trying `refs/<name>`,
+/// `refs/tags/<name>`, `refs/heads/<name>` and the remotes in turn.
+pub fn dwim_ref(git_dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    if name.is_empty() || name.contains("..") {
+        return Ok(None);
+    }
+    let candidates = [
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ];
+    for candidate in candidates {
+        if candidate != "HEAD" && !candidate.starts_with("refs/") {
+            continue;
+        }
+        if refs::read(git_dir, &candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// `<name>@{<n>}`: the value ref `name` had `n` updates ago. An empty name
+/// means the current branch.
+fn resolve_reflog(git_dir: &Path, name: &str, n: &str) -> anyhow::Result<Option<Oid>> {
+    let n: usize = n
+        .parse()
+        .with_context(|| format!("only numbered reflog entries are supported: '{name}@{{{n}}}'"))?;
+    let full = if name.is_empty() {
+        match refs::read(git_dir, "HEAD")? {
+            Some(head) => head.strip_prefix("ref: ").unwrap_or("HEAD").to_string(),
+            None => return Ok(None),
+        }
+    } else {
+        match dwim_ref(git_dir, name)? {
+            Some(full) => full,
+            None => return Ok(None),
+        }
+    };
+    let entries = reflog::read(git_dir, &full)?;
+    let Some(i) = entries.len().checked_sub(n + 1) else {
+        anyhow::bail!("log for '{full}' only has {} entries", entries.len());
+    };
+    Ok(Some(entries[i].new))
+}
+
+/// The ID of the one object whose hash starts with `prefix`.
+fn expand_hash(git_dir: &Path, prefix: &str) -> anyhow::Result<Option<Oid>> {
+    let mut found: Option<Oid> = None;
+    let mut candidates = vec![];
+    for pack in pack::packs(&repository::objects_dir(git_dir))?.iter() {
+        candidates.extend(pack.index.with_prefix(prefix).copied());
+    }
+    if let Ok(entries) = fs::read_dir(repository::objects_dir(git_dir).join(&prefix[..2])) {
+        for entry in entries {
+            let entry = entry?;
+            let rest = entry.file_name().to_string_lossy().to_string();
+            let hash = format!("{}{rest}", &prefix[..2]);
+            if hash.len() == 40 && hash.starts_with(prefix) {
+                candidates.push(hash.parse()?);
+            }
+        }
+    }
+    // An object both packed and loose is still only one object.
+    for hash in candidates {
+        anyhow::ensure!(found.is_none_or(|found| found == hash), "short object ID {prefix} is ambiguous");
+        found = Some(hash);
+    }
+    Ok(found)
+}
+
+/// Follows annotated tags from `hash` until reaching an object of `kind` (or
+/// any non-tag when `kind` is `None`). Commits peel to their tree.
+pub fn peel(hash: &Oid, kind: Option<Kind>) -> anyhow::Result<Oid> {
+    let mut hash = *hash;
+    loop {
+        let object = Object::read(&hash)?;
+        if Some(object.kind) == kind || (kind.is_none() && object.kind != Kind::Tag) {
+            return Ok(hash);
+        }
+        match object.kind {
+            Kind::Tag => {
+                hash = Tag::parse(&object.contents).with_context(|| format!("parse tag {hash}"))?.object;
+            }
+            Kind::Commit if kind == Some(Kind::Tree) => return Ok(revwalk::read_commit(&hash)?.tree),
+            _ => anyhow::bail!("object {hash} is a {}, not a {}", object.kind, kind.unwrap_or(Kind::Commit)),
+        }
+    }
+}
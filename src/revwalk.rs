@@ -0,0 +1,644 @@
+//! Walking the commit graph, as `got rev-list` (and push, gc and bundle
+//! creation, which all need to enumerate history) does.
+//!
+//! Commits are visited newest first by committer date, starting from a set
+//! of tips and stopping at anything reachable from an excluded tip. Commits
+//! on the shallow boundary are treated as having no parents.
+//!
+//! Visiting by date can show a parent before one of its children when
+//! clocks were skewed. The other orders walk everything first and then sort
+//! it so that no parent comes before its children: by committer or author
+//! date, or keeping each line of history together (`--topo-order`).
+//!
+//! Bisection looks for the commit in a range that comes closest to having
+//! half of the range behind it, so that testing it halves what is left to
+//! search whichever way the test goes.
+
+use crate::commit_graph::CommitGraph;
+use crate::object::{Commit, Kind, Object, Signature};
+use crate::oid::Oid;
+use crate::shallow;
+use crate::tree::TreeWalk;
+use anyhow::Context;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+
+/// The parts of a commit that history walks need.
+#[derive(Debug, Clone)]
+pub struct CommitInfo {
+    pub hash: Oid,
+    pub tree: Oid,
+    pub parents: Vec<Oid>,
+    /// Committer timestamp in seconds since the epoch.
+    pub time: i64,
+    pub author: Signature,
+    pub message: String,
+}
+
+pub fn read_commit(hash: &Oid) -> anyhow::Result<CommitInfo> {
+    let object = Object::read(hash)?;
+    anyhow::ensure!(object.kind == Kind::Commit, "object {hash} is a {}, not a commit", object.kind);
+    let commit = Commit::parse(&object.contents).with_context(|| format!("parse commit {hash}"))?;
+    Ok(CommitInfo {
+        hash: *hash,
+        tree: commit.tree,
+        parents: commit.parents,
+        time: commit.committer.time.timestamp(),
+        author: commit.author,
+        message: commit.message,
+    })
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RevWalk {
+    pub include: Vec<Oid>,
+    pub exclude: Vec<Oid>,
+    /// Only list commits with at most this many parents (0 lists roots).
+    pub max_parents: Option<usize>,
+    pub min_parents: usize,
+    pub max_count: Option<usize>,
+    /// Only follow the first parent of merges, for the mainline view of a
+    /// branch. Merges still count all their parents for `max_parents` and
+    /// `min_parents`.
+    pub first_parent: bool,
+    pub order: Order,
+    /// List oldest first, after `max_count` has picked the newest.
+    pub reverse: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// In the order commits are visited, newest committer date first.
+    #[default]
+    Visit,
+    /// Children before parents, otherwise by committer date.
+    Date,
+    /// Children before parents, otherwise by author date.
+    AuthorDate,
+    /// Children before parents, with each line of history together.
+    Topo,
+}
+
+impl RevWalk {
+    /// The selected commits, newest first.
+    pub fn commits(&self, git_dir: &Path) -> anyhow::Result<Vec<CommitInfo>> {
+        let boundary = shallow::read(git_dir)?;
+        let parents_of = |commit: &CommitInfo| -> Vec<Oid> {
+            if boundary.contains(&commit.hash) {
+                vec![]
+            } else {
+                commit.parents.clone()
+            }
+        };
+        let excluded = self.excluded(git_dir)?;
+
+        // Commits are read when first queued and kept until they're popped.
+        // Ties on date go to whichever commit was queued first.
+        let mut queue = BinaryHeap::new();
+        let mut sequence = 0;
+        let mut queued: HashMap<Oid, CommitInfo> = HashMap::new();
+        let mut seen: HashSet<Oid> = HashSet::new();
+        for hash in &self.include {
+            if !excluded.contains(hash) && seen.insert(*hash) {
+                let commit = read_commit(hash)?;
+                sequence += 1;
+                queue.push((commit.time, Reverse(sequence), commit.hash));
+                queued.insert(commit.hash, commit);
+            }
+        }
+        // Sorting needs every commit the walk reaches, wanted or not.
+        let sorting = self.order != Order::Visit;
+        let wanted = |parents: usize| parents >= self.min_parents && self.max_parents.is_none_or(|max| parents <= max);
+        let mut commits = vec![];
+        while let Some((_, _, hash)) = queue.pop() {
+            if !sorting && self.max_count.is_some_and(|max| commits.len() >= max) {
+                break;
+            }
+            let commit = queued.remove(&hash).expect("queued commits are loaded");
+            let parents = parents_of(&commit);
+            let followed = if self.first_parent { &parents[..parents.len().min(1)] } else { &parents[..] };
+            for parent in followed {
+                if !excluded.contains(parent) && seen.insert(*parent) {
+                    let parent = read_commit(parent)?;
+                    sequence += 1;
+                    queue.push((parent.time, Reverse(sequence), parent.hash));
+                    queued.insert(parent.hash, parent);
+                }
+            }
+            if sorting || wanted(parents.len()) {
+                commits.push(commit);
+            }
+        }
+        if sorting {
+            let parents: Vec<Vec<Oid>> = commits.iter().map(parents_of).collect();
+            commits = sort_topologically(commits, &parents, self.order);
+            commits.retain(|commit| wanted(parents_of(commit).len()));
+            if let Some(max) = self.max_count {
+                commits.truncate(max);
+            }
+        }
+        if self.reverse {
+            commits.reverse();
+        }
+        Ok(commits)
+    }
+
+    /// The trees and blobs reachable from `commits`, with the path each was
+    /// first found at, leaving out anything reachable from excluded commits
+    /// that border the walk.
+    pub fn objects(&self, git_dir: &Path, commits: &[CommitInfo]) -> anyhow::Result<Vec<(Oid, Vec<u8>)>> {
+        let mut seen: HashSet<Oid> = HashSet::new();
+        let excluded = self.excluded(git_dir)?;
+        let mut uninteresting: Vec<Oid> = self.exclude.clone();
+        for commit in commits {
+            uninteresting.extend(commit.parents.iter().filter(|p| excluded.contains(*p)));
+        }
+        let mut ignored = vec![];
+        for hash in uninteresting {
+            let tree = read_commit(&hash)?.tree;
+            walk_tree(&tree, &mut seen, &mut ignored)?;
+        }
+
+        let mut objects = vec![];
+        for commit in commits {
+            walk_tree(&commit.tree, &mut seen, &mut objects)?;
+        }
+        Ok(objects)
+    }
+
+    /// Every commit reachable from the excluded tips.
+    fn excluded(&self, git_dir: &Path) -> anyhow::Result<HashSet<Oid>> {
+        let boundary = shallow::read(git_dir)?;
+        let mut excluded: HashSet<Oid> = HashSet::new();
+        let mut pending = self.exclude.clone();
+        while let Some(hash) = pending.pop() {
+            if excluded.insert(hash) && !boundary.contains(&hash) {
+                pending.extend(read_commit(&hash)?.parents);
+            }
+        }
+        Ok(excluded)
+    }
+}
+
+/// The newest common ancestor of `a` and `b`, if they have any.
+pub fn merge_base(git_dir: &Path, a: &Oid, b: &Oid) -> anyhow::Result<Option<Oid>> {
+    Ancestry::new(git_dir)?.merge_base(a, b)
+}
+
+/// Whether `ancestor` is `commit` or one of its ancestors.
+pub fn is_ancestor(git_dir: &Path, ancestor: &Oid, commit: &Oid) -> anyhow::Result<bool> {
+    Ancestry::new(git_dir)?.is_ancestor(ancestor, commit)
+}
+
+/// How evenly testing each of `commits` would split them, for bisection:
+/// the number of them it reaches, itself included, or the number it
+/// doesn't, whichever is smaller. Parents outside `commits` are ignored, as
+/// are all but the first with `first_parent`.
+pub fn bisection_distances(commits: &[CommitInfo], first_parent: bool) -> Vec<usize> {
+    let weights = bisection_weights(&parents_within(commits, first_parent));
+    weights.iter().map(|&weight| weight.min(commits.len() - weight)).collect()
+}
+
+/// The commit of `commits`, listed newest first, that bisection tests next
+/// because it splits them most evenly. Ties are broken as git breaks them,
+/// so that both pick the same commit: counting from the oldest, the first
+/// found to reach half of the commits (merges are counted before the rest),
+/// or failing that the oldest of those that come closest.
+pub fn bisection_midpoint(commits: &[CommitInfo], first_parent: bool) -> Option<&CommitInfo> {
+    let parents = parents_within(commits, first_parent);
+    let weights = bisection_weights(&parents);
+    let halfway = |i: usize| (2 * weights[i]).abs_diff(commits.len()) <= 1;
+    if let Some(merge) = (0..commits.len()).rev().find(|&i| parents[i].len() > 1 && halfway(i)) {
+        return Some(&commits[merge]);
+    }
+    // The rest are counted a pass at a time, each once its parent has been.
+    let mut counted: Vec<bool> = parents.iter().map(|parents| parents.len() != 1).collect();
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for i in (0..commits.len()).rev() {
+            if counted[i] || !counted[parents[i][0]] {
+                continue;
+            }
+            if halfway(i) {
+                return Some(&commits[i]);
+            }
+            counted[i] = true;
+            progress = true;
+        }
+    }
+    let distances = bisection_distances(commits, first_parent);
+    let best = (0..commits.len()).rev().reduce(|best, i| if distances[i] > distances[best] { i } else { best })?;
+    Some(&commits[best])
+}
+
+/// The positions in `commits` of each one's parents that are among them,
+/// only the first parent with `first_parent`.
+fn parents_within(commits: &[CommitInfo], first_parent: bool) -> Vec<Vec<usize>> {
+    let positions: HashMap<Oid, usize> = commits.iter().enumerate().map(|(i, commit)| (commit.hash, i)).collect();
+    commits
+        .iter()
+        .map(|commit| {
+            let parents = if first_parent { &commit.parents[..commit.parents.len().min(1)] } else { &commit.parents[..] };
+            parents.iter().filter_map(|parent| positions.get(parent).copied()).collect()
+        })
+        .collect()
+}
+
+/// How many of the commits whose parents are `parents` each one reaches,
+/// itself included.
+fn bisection_weights(parents: &[Vec<usize>]) -> Vec<usize> {
+    // Which commits each one reaches, one bit per commit, worked out for
+    // its parents first.
+    let words = parents.len().div_ceil(64);
+    let mut reached: Vec<Option<Vec<u64>>> = vec![None; parents.len()];
+    for start in 0..parents.len() {
+        let mut stack = vec![(start, false)];
+        while let Some((i, parents_done)) = stack.pop() {
+            if reached[i].is_some() {
+                continue;
+            }
+            if !parents_done {
+                stack.push((i, true));
+                stack.extend(parents[i].iter().filter(|&&parent| reached[parent].is_none()).map(|&parent| (parent, false)));
+                continue;
+            }
+            let mut bits = vec![0u64; words];
+            bits[i / 64] |= 1 << (i % 64);
+            for &parent in &parents[i] {
+                let theirs = reached[parent].as_ref().expect("parents are done first");
+                bits.iter_mut().zip(theirs).for_each(|(bits, theirs)| *bits |= theirs);
+            }
+            reached[i] = Some(bits);
+        }
+    }
+    reached
+        .into_iter()
+        .map(|bits| bits.expect("every commit is done").iter().map(|bits| bits.count_ones() as usize).sum())
+        .collect()
+}
+
+/// Answers ancestry questions about a repository's commits, using its
+/// commit-graph where there is one: parents then come from the graph
+/// instead of commit objects, and searches skip commits whose generation
+/// is too low for them to lead to what is being looked for. Make one to
+/// ask many questions, such as which of a list of branches contain a
+/// commit.
+pub struct Ancestry {
+    graph: Option<CommitGraph>,
+    boundary: BTreeSet<Oid>,
+}
+
+/// A commit as the searches see it.
+struct Node {
+    generation: u64,
+    time: i64,
+    parents: Vec<Oid>,
+}
+
+impl Ancestry {
+    pub fn new(git_dir: &Path) -> anyhow::Result<Ancestry> {
+        Ok(Ancestry {
+            graph: CommitGraph::read(git_dir)?,
+            boundary: shallow::read(git_dir)?,
+        })
+    }
+
+    fn node(&self, hash: &Oid) -> anyhow::Result<Node> {
+        if let Some(commit) = self.graph.as_ref().and_then(|graph| graph.get(hash)) {
+            return Ok(Node {
+                generation: commit.generation,
+                time: commit.time,
+                parents: commit.parents.clone(),
+            });
+        }
+        let commit = read_commit(hash)?;
+        Ok(Node {
+            generation: u64::MAX,
+            time: commit.time,
+            parents: if self.boundary.contains(hash) { vec![] } else { commit.parents },
+        })
+    }
+
+    fn generation(&self, hash: &Oid) -> u64 {
+        self.graph.as_ref().map_or(u64::MAX, |graph| graph.generation(hash))
+    }
+
+    /// The newest common ancestor of `a` and `b`, if they have any. Commits
+    /// are visited highest generation first (then newest first), marked
+    /// with which of the two reach them, and the first reached from both is
+    /// the answer.
+    pub fn merge_base(&self, a: &Oid, b: &Oid) -> anyhow::Result<Option<Oid>> {
+        const FROM_A: u8 = 1;
+        const FROM_B: u8 = 2;
+        let mut flags: HashMap<Oid, u8> = HashMap::new();
+        let mut nodes: HashMap<Oid, Node> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let mut sequence = 0;
+        let mut push = |queue: &mut BinaryHeap<_>, hash: Oid| -> anyhow::Result<()> {
+            let node = match nodes.entry(hash) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.node(&hash)?),
+            };
+            sequence += 1;
+            queue.push((node.generation, node.time, Reverse(sequence), hash, node.parents.clone()));
+            Ok(())
+        };
+        for (hash, flag) in [(a, FROM_A), (b, FROM_B)] {
+            *flags.entry(*hash).or_default() |= flag;
+            push(&mut queue, *hash)?;
+        }
+        while let Some((_, _, _, hash, parents)) = queue.pop() {
+            let flag = flags[&hash];
+            if flag == FROM_A | FROM_B {
+                return Ok(Some(hash));
+            }
+            for parent in parents {
+                let old = flags.get(&parent).copied().unwrap_or(0);
+                if old & flag == flag {
+                    continue;
+                }
+                flags.insert(parent, old | flag);
+                push(&mut queue, parent)?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether `ancestor` is `commit` or one of its ancestors. Since every
+    /// commit's generation is above its parents', nothing below the
+    /// ancestor's generation needs to be searched.
+    pub fn is_ancestor(&self, ancestor: &Oid, commit: &Oid) -> anyhow::Result<bool> {
+        let floor = self.generation(ancestor);
+        let mut seen: HashSet<Oid> = HashSet::new();
+        let mut pending = vec![*commit];
+        while let Some(hash) = pending.pop() {
+            if hash == *ancestor {
+                return Ok(true);
+            }
+            if !seen.insert(hash) || self.generation(&hash) < floor {
+                continue;
+            }
+            pending.extend(self.node(&hash)?.parents);
+        }
+        Ok(false)
+    }
+}
+
+/// Sorts `commits`, in the order they were visited and with `parents`
+/// their parents, so that each comes before its parents, as `order` says.
+fn sort_topologically(commits: Vec<CommitInfo>, parents: &[Vec<Oid>], order: Order) -> Vec<CommitInfo> {
+    let position: HashMap<Oid, usize> = commits.iter().enumerate().map(|(i, commit)| (commit.hash, i)).collect();
+    let in_walk = |i: usize| parents[i].iter().filter_map(|parent| position.get(parent).copied()).collect::<Vec<_>>();
+    // How many children of each commit are still to be listed.
+    let mut children = vec![0usize; commits.len()];
+    for i in 0..commits.len() {
+        for parent in in_walk(i) {
+            children[parent] += 1;
+        }
+    }
+    let time = |i: usize| match order {
+        Order::AuthorDate => commits[i].author.time.timestamp(),
+        _ => commits[i].time,
+    };
+    // Topological order takes the most recently freed commit, so a line of
+    // history is followed to its end; the others take the newest, then the
+    // first freed.
+    let mut queue = BinaryHeap::new();
+    let mut sequence = 0;
+    let mut push = |queue: &mut BinaryHeap<((i64, i64), usize)>, i: usize| {
+        sequence += 1;
+        let key = match order {
+            Order::Topo => (0, sequence),
+            _ => (time(i), -sequence),
+        };
+        queue.push((key, i));
+    };
+    let mut tips: Vec<usize> = (0..commits.len()).filter(|&i| children[i] == 0).collect();
+    if order == Order::Topo {
+        tips.reverse();
+    }
+    for i in tips {
+        push(&mut queue, i);
+    }
+    let mut sorted = vec![];
+    while let Some((_, i)) = queue.pop() {
+        for parent in in_walk(i) {
+            children[parent] -= 1;
+            if children[parent] == 0 {
+                push(&mut queue, parent);
+            }
+        }
+        sorted.push(i);
+    }
+    let mut commits: Vec<Option<CommitInfo>> = commits.into_iter().map(Some).collect();
+    sorted.into_iter().map(|i| commits[i].take().expect("each commit is listed once")).collect()
+}
+
+/// Adds tree `hash` and the trees and blobs below it to `objects`, with
+/// their paths, skipping those in `seen` and adding the rest to it.
+pub fn walk_tree(hash: &Oid, seen: &mut HashSet<Oid>, objects: &mut Vec<(Oid, Vec<u8>)>) -> anyhow::Result<()> {
+    if !seen.insert(*hash) {
+        return Ok(());
+    }
+    objects.push((*hash, vec![]));
+    let seen = RefCell::new(seen);
+    // Subtrees listed before are yielded but neither listed nor descended
+    // into again.
+    let walk = TreeWalk::new(hash)?.prune(|entry| seen.borrow().contains(&entry.hash));
+    for entry in walk {
+        let entry = entry?;
+        // Submodule commits live in another repository.
+        if entry.kind() == Kind::Commit {
+            continue;
+        }
+        if seen.borrow_mut().insert(entry.hash) {
+            objects.push((entry.hash, entry.path));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::ScratchRepository;
+
+    /// Commits made by git, oldest first, each with its message as its
+    /// name: `A`, then `B1` to `B3` on one branch and `S1` to `S3` on
+    /// another, merged by `M`, and `N` after it. The dates are skewed so
+    /// that each order comes out differently: `S2` was committed before
+    /// `A`, and the `B`s were authored after the `S`s but committed
+    /// before `S3`.
+    const HISTORY: &[(&str, &str)] = &[
+        (
+            "a4ecabefb5d2531fd3c711ec9578a69697843200",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+author A U Thor <author@example.com> 1700000000 +0000
+committer A U Thor <author@example.com> 1700000000 +0000
+
+A
+",
+        ),
+        (
+            "9404f509aab0980bd10835d1090064348d2a3e39",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent a4ecabefb5d2531fd3c711ec9578a69697843200
+author A U Thor <author@example.com> 1700000400 +0000
+committer A U Thor <author@example.com> 1700000200 +0000
+
+B1
+",
+        ),
+        (
+            "34b8c1573c25941efa2f38f3bc40e10d15efb4da",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent 9404f509aab0980bd10835d1090064348d2a3e39
+author A U Thor <author@example.com> 1700000500 +0000
+committer A U Thor <author@example.com> 1700000500 +0000
+
+B2
+",
+        ),
+        (
+            "f4e2ffef47210b3a938772ef8e65991e2ddfd7cc",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent 34b8c1573c25941efa2f38f3bc40e10d15efb4da
+author A U Thor <author@example.com> 1700000600 +0000
+committer A U Thor <author@example.com> 1700000600 +0000
+
+B3
+",
+        ),
+        (
+            "9314a8eed85df14477ce4899895edb58f6b0ea39",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent a4ecabefb5d2531fd3c711ec9578a69697843200
+author A U Thor <author@example.com> 1700000200 +0000
+committer A U Thor <author@example.com> 1700000300 +0000
+
+S1
+",
+        ),
+        (
+            "206a757e23741bc8410566dba4a9539caf95784e",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent 9314a8eed85df14477ce4899895edb58f6b0ea39
+author A U Thor <author@example.com> 1700000300 +0000
+committer A U Thor <author@example.com> 1699999900 +0000
+
+S2
+",
+        ),
+        (
+            "3c8591ecd1b4a87040081b02995df58ce9fe3a52",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent 206a757e23741bc8410566dba4a9539caf95784e
+author A U Thor <author@example.com> 1700000350 +0000
+committer A U Thor <author@example.com> 1700000700 +0000
+
+S3
+",
+        ),
+        (
+            "0a7827f3f590de17e00c94eab181adcd253ed36f",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent f4e2ffef47210b3a938772ef8e65991e2ddfd7cc
+parent 3c8591ecd1b4a87040081b02995df58ce9fe3a52
+author A U Thor <author@example.com> 1700000800 +0000
+committer A U Thor <author@example.com> 1700000800 +0000
+
+M
+",
+        ),
+        (
+            "10d15535697df5b3f20f491f08035046d9280651",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent 0a7827f3f590de17e00c94eab181adcd253ed36f
+author A U Thor <author@example.com> 1700000900 +0000
+committer A U Thor <author@example.com> 1700000900 +0000
+
+N
+",
+        ),
+    ];
+
+    /// The scratch repository with `HISTORY` stored in it, and the hash
+    /// of each commit by name.
+    fn history(name: &str) -> (ScratchRepository, HashMap<String, Oid>) {
+        let repository = ScratchRepository::new(name);
+        let mut names = HashMap::new();
+        for (hash, raw) in HISTORY {
+            let stored = Object::store(Kind::Commit, raw.as_bytes()).unwrap();
+            assert_eq!(stored.to_string(), *hash);
+            names.insert(Commit::parse(raw.as_bytes()).unwrap().message.trim_end().to_string(), stored);
+        }
+        (repository, names)
+    }
+
+    fn names(commits: &[CommitInfo]) -> Vec<&str> {
+        commits.iter().map(|commit| commit.message.trim_end()).collect()
+    }
+
+    #[test]
+    fn commits_are_listed_in_each_order_as_git_lists_them() {
+        let (_repository, hashes) = history("revwalk-order-test");
+        let git_dir = Path::new(".got");
+        let walk = RevWalk { include: vec![hashes["N"]], ..RevWalk::default() };
+        for (order, listed) in [
+            (Order::Visit, ["N", "M", "S3", "B3", "B2", "B1", "A", "S2", "S1"]),
+            (Order::Date, ["N", "M", "S3", "B3", "B2", "B1", "S2", "S1", "A"]),
+            (Order::AuthorDate, ["N", "M", "B3", "B2", "B1", "S3", "S2", "S1", "A"]),
+            (Order::Topo, ["N", "M", "S3", "S2", "S1", "B3", "B2", "B1", "A"]),
+        ] {
+            let walk = RevWalk { order, ..walk.clone() };
+            assert_eq!(names(&walk.commits(git_dir).unwrap()), listed, "{order:?}");
+        }
+    }
+
+    #[test]
+    fn reverse_lists_oldest_first_after_the_count_is_taken() {
+        let (_repository, hashes) = history("revwalk-reverse-test");
+        let git_dir = Path::new(".got");
+        let walk = RevWalk { include: vec![hashes["N"]], reverse: true, ..RevWalk::default() };
+        let topo = RevWalk { order: Order::Topo, ..walk.clone() };
+        assert_eq!(names(&topo.commits(git_dir).unwrap()), ["A", "B1", "B2", "B3", "S1", "S2", "S3", "M", "N"]);
+        let walk = RevWalk { max_count: Some(3), ..walk };
+        assert_eq!(names(&walk.commits(git_dir).unwrap()), ["S3", "M", "N"]);
+        let topo = RevWalk { max_count: Some(4), ..topo };
+        assert_eq!(names(&topo.commits(git_dir).unwrap()), ["S2", "S3", "M", "N"]);
+    }
+
+    #[test]
+    fn bisection_picks_the_midpoint_git_picks() {
+        let (_repository, hashes) = history("revwalk-bisect-test");
+        let git_dir = Path::new(".got");
+        let range = |good: &str, first_parent: bool| {
+            let walk = RevWalk { include: vec![hashes["N"]], exclude: vec![hashes[good]], first_parent, ..RevWalk::default() };
+            walk.commits(git_dir).unwrap()
+        };
+        // `B3` and `S3` split the range alike; git takes `B3`.
+        for (good, first_parent, midpoint) in [
+            ("A", false, "B3"),
+            ("A", true, "B2"),
+            ("B1", false, "S3"),
+            ("B2", false, "S3"),
+            ("B3", false, "S2"),
+            ("S1", false, "B3"),
+            ("S2", false, "B3"),
+            ("S3", false, "B2"),
+        ] {
+            let commits = range(good, first_parent);
+            let found = bisection_midpoint(&commits, first_parent).map(|commit| commit.message.trim_end());
+            assert_eq!(found, Some(midpoint), "{good} first_parent={first_parent}");
+        }
+        // As `rev-list --bisect-all` gives them.
+        let commits = range("A", false);
+        let distances: HashMap<&str, usize> = names(&commits).into_iter().zip(bisection_distances(&commits, false)).collect();
+        let expected = [("S3", 3), ("B3", 3), ("S2", 2), ("B2", 2), ("M", 1), ("S1", 1), ("B1", 1), ("N", 0)];
+        assert_eq!(distances, HashMap::from(expected));
+    }
+}
@@ -0,0 +1,419 @@
+//! Scanning the worktree against the index, which `status` and `add` need
+//! to find what changed.
+//!
+//! The walk is spread over several threads (`index.threads`, by default
+//! one per CPU) sharing a queue of directories: each takes a directory,
+//! lists it, queues the subdirectories it finds and deals with its files,
+//! checking ignore rules for untracked ones and rehashing tracked ones
+//! whose stat data no longer matches the index. Slow `stat` calls and
+//! hashing on one thread overlap with the others' work, which is what
+//! makes large worktrees quick to scan.
+//!
+//! Directories that hold nothing tracked are only walked when untracked
+//! files are wanted, and ignored ones not at all; a directory that is a
+//! repository of its own is reported as a single untracked `dir/`.
+//!
+//! When listing untracked files with an untracked cache in the index (see
+//! [`crate::untracked_cache`]), a directory whose stat data hasn't changed
+//! isn't listed again: its untracked files come from the cache and only
+//! its tracked files are looked at. The cache is then refreshed from what
+//! the walk found.
+
+use crate::config::Config;
+use crate::ignore::Ignore;
+use crate::index::{self, Entry, Index, Stat};
+use crate::object::{Kind, Object};
+use crate::oid::Oid;
+use crate::path;
+use crate::repository;
+use crate::tree::Mode;
+use crate::untracked_cache::{self, Dir, UntrackedCache};
+use anyhow::Context;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Rules for leaving out untracked files, or `None` not to look for
+    /// untracked files at all.
+    pub untracked: Option<Ignore>,
+    /// Write the blobs of changed files to the object store, as staging
+    /// them needs.
+    pub store: bool,
+    /// How many threads to walk with; 0 for one per CPU.
+    pub threads: usize,
+    /// Whether to keep an untracked cache in the index when listing
+    /// untracked files: `true` adds one, `false` drops it, and `None` uses
+    /// one only if the index has it.
+    pub untracked_cache: Option<bool>,
+}
+
+impl ScanOptions {
+    /// Options with the thread count from `index.threads`: `true` or 0
+    /// for one per CPU, `false` or 1 for no threading, or a number; and
+    /// the use of the untracked cache from `core.untrackedCache`, where
+    /// `keep` is the same as leaving it unset.
+    pub fn from_config(config: &Config) -> anyhow::Result<ScanOptions> {
+        let threads = match config.get("index.threads") {
+            None => 0,
+            Some(value) => match crate::config::parse_bool(value) {
+                Ok(true) => 0,
+                Ok(false) => 1,
+                Err(_) => usize::try_from(crate::config::parse_int(value)?).context("index.threads must not be negative")?,
+            },
+        };
+        let untracked_cache = match config.get("core.untrackedCache") {
+            None | Some("keep") => None,
+            Some(value) => Some(crate::config::parse_bool(value)?),
+        };
+        Ok(ScanOptions {
+            threads,
+            untracked_cache,
+            ..ScanOptions::default()
+        })
+    }
+}
+
+/// What a scan found, each list sorted by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scan {
+    /// Tracked files whose contents or mode differ from the index, as the
+    /// entries staging them would add.
+    pub modified: Vec<Entry>,
+    /// Tracked files whose contents are unchanged but whose stat data
+    /// isn't, with fresh stat data to cache in the index.
+    pub stale: Vec<Entry>,
+    /// Tracked paths that are gone from the worktree.
+    pub deleted: Vec<Vec<u8>>,
+    /// Untracked files that aren't ignored, and `dir/` for nested
+    /// repositories.
+    pub untracked: Vec<Vec<u8>>,
+}
+
+/// The directories waiting to be listed, and how many are being listed.
+struct Queue {
+    dirs: Vec<Vec<u8>>,
+    busy: usize,
+}
+
+/// A directory as the untracked cache is to record it, with the paths of
+/// the subdirectories walked from it.
+struct Listing {
+    dir: Dir,
+    subdirs: Vec<Vec<u8>>,
+}
+
+struct Walk<'a> {
+    tracked: HashMap<&'a [u8], &'a Entry>,
+    /// Every directory with something tracked below it.
+    tracked_dirs: HashSet<&'a [u8]>,
+    options: &'a ScanOptions,
+    queue: Mutex<Queue>,
+    ready: Condvar,
+    /// Set when an untracked cache is being kept: the directories in the
+    /// index's cache by path, the tracked files directly in each directory,
+    /// and the directories listed or reused from the cache.
+    caching: bool,
+    cached: HashMap<Vec<u8>, &'a Dir>,
+    tracked_in: HashMap<&'a [u8], Vec<&'a Entry>>,
+    listings: Mutex<HashMap<Vec<u8>, Listing>>,
+}
+
+/// Scans the worktree (the current directory) against `index`, updating
+/// its untracked cache if it has one or `options` asks for one.
+pub fn scan(index: &mut Index, options: &ScanOptions) -> anyhow::Result<Scan> {
+    if options.untracked_cache == Some(false) {
+        index.untracked_cache = None;
+    }
+    let caching = options.untracked.is_some() && (options.untracked_cache == Some(true) || index.untracked_cache.is_some());
+    let mut fresh = None;
+    let mut old = None;
+    if caching {
+        let new = UntrackedCache::new(&Config::load()?, repository::git_dir());
+        // A cache made under other ignore rules is no use.
+        old = index.untracked_cache.take().filter(|cache| cache.is_current(&new));
+        fresh = Some(new);
+    }
+    let mut cached = HashMap::new();
+    if let Some(root) = old.as_ref().and_then(|cache| cache.root.as_ref()) {
+        collect_cached(root, vec![], &mut cached);
+    }
+
+    let mut tracked: HashMap<&[u8], &Entry> = HashMap::new();
+    let mut tracked_dirs = HashSet::new();
+    for entry in index.entries() {
+        // Unmerged paths are neither modified nor deleted until resolved.
+        if entry.stage == 0 {
+            tracked.insert(&entry.path[..], entry);
+        }
+        for parent in path::parents(&entry.path) {
+            if !tracked_dirs.insert(parent) {
+                break;
+            }
+        }
+    }
+    let unmerged: HashSet<&[u8]> = index.entries().iter().filter(|entry| entry.stage > 0).map(|entry| &entry.path[..]).collect();
+    let mut walk = Walk {
+        tracked,
+        tracked_dirs,
+        options,
+        queue: Mutex::new(Queue {
+            dirs: vec![vec![]],
+            busy: 0,
+        }),
+        ready: Condvar::new(),
+        caching,
+        cached,
+        tracked_in: HashMap::new(),
+        listings: Mutex::new(HashMap::new()),
+    };
+    if caching {
+        for entry in walk.tracked.values() {
+            let parent = path::split_parent(&entry.path).map_or(&b""[..], |(parent, _)| parent);
+            walk.tracked_in.entry(parent).or_default().push(entry);
+        }
+    }
+    let threads = match options.threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+
+    let results = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| walk.work())).collect();
+        workers.into_iter().map(|worker| worker.join().expect("scan thread panicked")).collect::<Vec<_>>()
+    });
+    let mut scan = Scan::default();
+    let mut seen: HashSet<Vec<u8>> = HashSet::new();
+    for result in results {
+        let (part, part_seen) = result?;
+        scan.modified.extend(part.modified);
+        scan.stale.extend(part.stale);
+        scan.untracked.extend(part.untracked);
+        seen.extend(part_seen);
+    }
+    scan.deleted = walk
+        .tracked
+        .keys()
+        .filter(|path| !seen.contains(**path) && !unmerged.contains(**path))
+        .map(|path| path.to_vec())
+        .collect();
+    scan.modified.sort_by(|a, b| a.path.cmp(&b.path));
+    scan.stale.sort_by(|a, b| a.path.cmp(&b.path));
+    scan.deleted.sort();
+    scan.untracked.sort();
+    let mut listings = walk.listings.into_inner().expect("scan listings lock poisoned");
+    if let Some(mut cache) = fresh {
+        cache.root = assemble(&mut listings, b"", b"");
+        index.untracked_cache = Some(cache);
+    }
+    Ok(scan)
+}
+
+/// Adds `dir`, at `path`, and the directories below it to `cached`.
+fn collect_cached<'a>(dir: &'a Dir, path: Vec<u8>, cached: &mut HashMap<Vec<u8>, &'a Dir>) {
+    for sub in &dir.dirs {
+        collect_cached(sub, path::join(&path, &sub.name), cached);
+    }
+    cached.insert(path, dir);
+}
+
+/// Builds the cache's directory at `path`, named `name`, from the walk's
+/// listings.
+fn assemble(listings: &mut HashMap<Vec<u8>, Listing>, path: &[u8], name: &[u8]) -> Option<Dir> {
+    let Listing { mut dir, mut subdirs } = listings.remove(path)?;
+    subdirs.sort();
+    dir.name = name.to_vec();
+    dir.dirs = subdirs.iter().filter_map(|sub| assemble(listings, sub, path::file_name(sub))).collect();
+    Some(dir)
+}
+
+impl Walk<'_> {
+    /// Takes directories from the queue until every one has been listed,
+    /// returning what was found and the tracked paths seen in the worktree.
+    fn work(&self) -> anyhow::Result<(Scan, Vec<Vec<u8>>)> {
+        let mut scan = Scan::default();
+        let mut seen = vec![];
+        loop {
+            let dir = {
+                let mut queue = self.queue.lock().expect("scan queue lock poisoned");
+                loop {
+                    if let Some(dir) = queue.dirs.pop() {
+                        queue.busy += 1;
+                        break Some(dir);
+                    }
+                    if queue.busy == 0 {
+                        break None;
+                    }
+                    queue = self.ready.wait(queue).expect("scan queue lock poisoned");
+                }
+            };
+            let Some(dir) = dir else {
+                // Wake the others so they see there's nothing left.
+                self.ready.notify_all();
+                return Ok((scan, seen));
+            };
+            let result = self.list(&dir, &mut scan, &mut seen);
+            let mut queue = self.queue.lock().expect("scan queue lock poisoned");
+            queue.busy -= 1;
+            if let Err(e) = result {
+                // Stop everyone; the first error is the one reported.
+                queue.dirs.clear();
+                drop(queue);
+                self.ready.notify_all();
+                return Err(e);
+            }
+            drop(queue);
+            self.ready.notify_all();
+        }
+    }
+
+    fn list(&self, dir: &[u8], scan: &mut Scan, seen: &mut Vec<Vec<u8>>) -> anyhow::Result<()> {
+        let dir_path = if dir.is_empty() { Path::new(".") } else { path::to_path(dir) };
+        let mut stat = None;
+        if self.caching {
+            // Taken before listing, so changes made meanwhile show later.
+            let current = Stat::from_metadata(&fs::symlink_metadata(dir_path).with_context(|| format!("stat {}", path::display(dir)))?);
+            if let Some(cached) = self.cached.get(dir)
+                && cached.valid
+                && cached.stat == Some(current)
+            {
+                return self.reuse(dir, cached, scan, seen);
+            }
+            stat = Some(current);
+        }
+        let read = fs::read_dir(dir_path).with_context(|| format!("list {}", path::display(dir)))?;
+        let mut subdirs = vec![];
+        let mut untracked = vec![];
+        for entry in read {
+            let entry = entry.with_context(|| format!("list {}", path::display(dir)))?;
+            let name = entry.file_name();
+            let name = path::from_os(&name);
+            if name == b".got" {
+                continue;
+            }
+            let path = path::join(dir, name);
+            let meta = entry.metadata().with_context(|| format!("stat {}", path::display(&path)))?;
+            if let Some(tracked) = self.tracked.get(&path[..])
+                && self.visit_tracked(tracked, &meta, scan, seen)?
+            {
+                continue;
+            }
+            if meta.is_dir() {
+                let has_tracked = self.tracked_dirs.contains(&path[..]);
+                if !has_tracked && !self.wants_untracked(&path, true) {
+                    continue;
+                }
+                if !has_tracked && path::to_path(&path).join(".got").exists() {
+                    untracked.push([name, b"/"].concat());
+                    scan.untracked.push([&path[..], b"/"].concat());
+                } else {
+                    subdirs.push(path);
+                }
+            } else if index::worktree_mode(&meta).is_some() && self.wants_untracked(&path, false) {
+                untracked.push(name.to_vec());
+                scan.untracked.push(path);
+            }
+        }
+        if let Some(stat) = stat {
+            // Only the top-level ignore file is read, so only its hash
+            // matters.
+            let exclude_hash = if dir.is_empty() { untracked_cache::exclude_hash(b"") } else { Oid::ZERO };
+            let dir_entry = Dir {
+                untracked,
+                valid: true,
+                stat: Some(stat),
+                exclude_hash,
+                ..Dir::default()
+            };
+            self.record(dir, dir_entry, subdirs.clone());
+        }
+        self.queue_dirs(subdirs);
+        Ok(())
+    }
+
+    /// Deals with a directory the untracked cache says is unchanged: the
+    /// untracked files are the cached ones, and only the tracked files in
+    /// it need looking at.
+    fn reuse(&self, dir: &[u8], cached: &Dir, scan: &mut Scan, seen: &mut Vec<Vec<u8>>) -> anyhow::Result<()> {
+        scan.untracked.extend(cached.untracked.iter().map(|name| path::join(dir, name)));
+        for entry in self.tracked_in.get(dir).into_iter().flatten() {
+            let meta = match fs::symlink_metadata(path::to_path(&entry.path)) {
+                Ok(meta) => meta,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).with_context(|| format!("stat {}", path::display(&entry.path))),
+            };
+            self.visit_tracked(entry, &meta, scan, seen)?;
+        }
+        let subdirs: Vec<Vec<u8>> = cached.dirs.iter().map(|sub| path::join(dir, &sub.name)).collect();
+        let dir_entry = Dir {
+            untracked: cached.untracked.clone(),
+            valid: true,
+            stat: cached.stat,
+            exclude_hash: cached.exclude_hash,
+            ..Dir::default()
+        };
+        self.record(dir, dir_entry, subdirs.clone());
+        self.queue_dirs(subdirs);
+        Ok(())
+    }
+
+    /// Deals with a tracked path found in the worktree, returning whether
+    /// it is still something that can be tracked there. A directory where
+    /// a file was tracked is walked as untracked.
+    fn visit_tracked(&self, tracked: &Entry, meta: &fs::Metadata, scan: &mut Scan, seen: &mut Vec<Vec<u8>>) -> anyhow::Result<bool> {
+        if tracked.mode == Mode::Submodule {
+            if meta.is_dir() {
+                seen.push(tracked.path.clone());
+            }
+            return Ok(true);
+        }
+        if meta.is_dir() || index::worktree_mode(meta).is_none() {
+            return Ok(false);
+        }
+        seen.push(tracked.path.clone());
+        self.check(tracked, &tracked.path, meta, scan)?;
+        Ok(true)
+    }
+
+    fn record(&self, dir: &[u8], entry: Dir, subdirs: Vec<Vec<u8>>) {
+        let listing = Listing { dir: entry, subdirs };
+        self.listings.lock().expect("scan listings lock poisoned").insert(dir.to_vec(), listing);
+    }
+
+    fn queue_dirs(&self, subdirs: Vec<Vec<u8>>) {
+        if !subdirs.is_empty() {
+            self.queue.lock().expect("scan queue lock poisoned").dirs.extend(subdirs);
+            self.ready.notify_all();
+        }
+    }
+
+    /// Whether an untracked `path` is to be reported or, for a directory,
+    /// walked.
+    fn wants_untracked(&self, path: &[u8], is_dir: bool) -> bool {
+        self.options.untracked.as_ref().is_some_and(|ignore| !ignore.is_ignored(path, is_dir))
+    }
+
+    /// Compares tracked file `path` with its index entry.
+    fn check(&self, entry: &Entry, path: &[u8], meta: &fs::Metadata, scan: &mut Scan) -> anyhow::Result<()> {
+        if entry.ignores_worktree() || !entry.intent_to_add && entry.stat_matches(meta) {
+            return Ok(());
+        }
+        let mode = index::worktree_mode(meta).expect("only files are checked");
+        let contents = index::read_worktree_file(path::to_path(path), meta)?;
+        let hash = match self.options.store {
+            true => Object::store(Kind::Blob, &contents)?,
+            false => Object::hash_of(Kind::Blob, &contents),
+        };
+        let fresh = Entry::new(path, mode, hash, Stat::from_metadata(meta));
+        if hash == entry.hash && mode == entry.mode && !entry.intent_to_add {
+            scan.stale.push(fresh);
+        } else {
+            scan.modified.push(fresh);
+        }
+        Ok(())
+    }
+}
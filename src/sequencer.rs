@@ -0,0 +1,146 @@
+//! The state `got cherry-pick` and `got revert` keep while working through
+//! several commits, so that a run stopped by a conflict can be taken up
+//! again with `--continue`, go past the commit with `--skip` or be undone
+//! with `--abort`.
+//!
+//! It lives in `.got/sequencer`: `todo` lists the steps left, one
+//! `pick <hash> <subject>` or `revert <hash> <subject>` per line, the first
+//! being the one a stopped run stopped at; `head` holds the commit HEAD was
+//! at before the first, for `--abort` to go back to; and `opts` has a
+//! `no-commit` line when the changes are only to be left in the index and
+//! an `edit` line when each message goes through the editor first.
+
+use crate::oid::Oid;
+use anyhow::Context;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Pick,
+    Revert,
+}
+
+impl Action {
+    /// The command that runs it, as messages name it.
+    pub fn command(self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        })
+    }
+}
+
+/// One commit to pick or revert.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub action: Action,
+    pub commit: Oid,
+    /// The first line of the commit's message, for people reading `todo`.
+    pub subject: String,
+}
+
+impl Step {
+    fn parse(line: &str) -> anyhow::Result<Step> {
+        let mut fields = line.splitn(3, ' ');
+        let action = match fields.next() {
+            Some("pick") => Action::Pick,
+            Some("revert") => Action::Revert,
+            _ => anyhow::bail!("invalid line in .got/sequencer/todo: '{line}'"),
+        };
+        let commit = fields.next().unwrap_or_default().parse().with_context(|| format!("invalid line in .got/sequencer/todo: '{line}'"))?;
+        Ok(Step {
+            action,
+            commit,
+            subject: fields.next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/// A run of picks or reverts in progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequencer {
+    dir: PathBuf,
+    pub todo: Vec<Step>,
+    pub head: Oid,
+    pub no_commit: bool,
+    pub edit: bool,
+}
+
+impl Sequencer {
+    fn dir(git_dir: &Path) -> PathBuf {
+        git_dir.join("sequencer")
+    }
+
+    /// Starts a run of `todo` from `head`, failing if one is already in
+    /// progress.
+    pub fn start(git_dir: &Path, todo: Vec<Step>, head: &Oid, no_commit: bool, edit: bool) -> anyhow::Result<Sequencer> {
+        let dir = Sequencer::dir(git_dir);
+        match fs::create_dir(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                anyhow::bail!("a cherry-pick or revert is already in progress\nhint: try \"got cherry-pick (--continue | --skip | --abort)\"")
+            }
+            result => result.context("create .got/sequencer")?,
+        }
+        fs::write(dir.join("head"), format!("{head}\n")).context("write .got/sequencer/head")?;
+        let opts = [(no_commit, "no-commit\n"), (edit, "edit\n")];
+        let opts: String = opts.iter().filter(|(set, _)| *set).map(|(_, line)| *line).collect();
+        fs::write(dir.join("opts"), opts).context("write .got/sequencer/opts")?;
+        let sequencer = Sequencer {
+            dir,
+            todo,
+            head: *head,
+            no_commit,
+            edit,
+        };
+        sequencer.save()?;
+        Ok(sequencer)
+    }
+
+    /// The run in progress, if there is one.
+    pub fn load(git_dir: &Path) -> anyhow::Result<Option<Sequencer>> {
+        let dir = Sequencer::dir(git_dir);
+        let read = |name: &str| fs::read_to_string(dir.join(name)).with_context(|| format!("read .got/sequencer/{name}"));
+        let todo = match fs::read_to_string(dir.join("todo")) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            result => result.context("read .got/sequencer/todo")?,
+        };
+        let opts = read("opts")?;
+        Ok(Some(Sequencer {
+            todo: todo.lines().filter(|line| !line.is_empty()).map(Step::parse).collect::<anyhow::Result<_>>()?,
+            head: read("head")?.trim().parse().context("corrupt .got/sequencer/head")?,
+            no_commit: opts.lines().any(|line| line == "no-commit"),
+            edit: opts.lines().any(|line| line == "edit"),
+            dir,
+        }))
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let todo: String = self.todo.iter().map(|step| format!("{} {} {}\n", step.action, step.commit, step.subject)).collect();
+        fs::write(self.dir.join("todo"), todo).context("write .got/sequencer/todo")
+    }
+
+    /// Moves on past the first step.
+    pub fn advance(&mut self) -> anyhow::Result<()> {
+        if !self.todo.is_empty() {
+            self.todo.remove(0);
+        }
+        self.save()
+    }
+
+    /// Forgets the run.
+    pub fn remove(self) -> anyhow::Result<()> {
+        fs::remove_dir_all(&self.dir).context("remove .got/sequencer")
+    }
+}
@@ -0,0 +1,47 @@
+//! The shallow boundary of a repository cloned or fetched with limited depth.
+//!
+//! `.got/shallow` lists, one hex hash per line, the commits whose parents
+//! were never fetched. History walks treat them as root commits, and
+//! `fetch --unshallow` deepens the history until the file can be removed.
+
+use crate::oid::Oid;
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The commits at the shallow boundary; empty for a complete repository.
+pub fn read(git_dir: &Path) -> anyhow::Result<BTreeSet<Oid>> {
+    match fs::read_to_string(git_dir.join("shallow")) {
+        Ok(text) => text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| line.parse().context("parse .got/shallow"))
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(e) => Err(e).context("read .got/shallow"),
+    }
+}
+
+pub fn is_shallow(git_dir: &Path) -> anyhow::Result<bool> {
+    Ok(!read(git_dir)?.is_empty())
+}
+
+/// Records `commits` as the shallow boundary, removing the file entirely once
+/// the boundary is empty and the repository is complete again.
+pub fn write(git_dir: &Path, commits: &BTreeSet<Oid>) -> anyhow::Result<()> {
+    let path = git_dir.join("shallow");
+    if commits.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e).context("remove .got/shallow"),
+            _ => Ok(()),
+        };
+    }
+    let mut text = String::new();
+    for commit in commits {
+        text.push_str(&format!("{commit}\n"));
+    }
+    fs::write(&path, text).context("write .got/shallow")
+}
@@ -0,0 +1,122 @@
+//! Split indexes: the entries kept in a shared index file,
+//! `.got/sharedindex.<hash>`, with `.got/index` holding only what changed
+//! since. Writing the index then costs in proportion to the changes
+//! rather than to the whole worktree, which matters once there are
+//! hundreds of thousands of entries.
+//!
+//! `.got/index` carries a `link` extension naming the shared index by its
+//! checksum, with two bitmaps over the shared entries: those deleted, and
+//! those replaced by the first entries of `.got/index` (which are stored
+//! without names, taking them from the entries they replace). The rest of
+//! `.got/index`'s entries are added. Once more than
+//! `splitIndex.maxPercentChange` percent of the entries (20 unless set)
+//! aren't in the shared index, a new one is written with all of them, and
+//! shared indexes unused for `splitIndex.sharedIndexExpire` (two weeks
+//! unless set) are deleted.
+
+use crate::config::{self, Config};
+use crate::ewah;
+use crate::oid::Oid;
+use anyhow::Context;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const SIGNATURE: &[u8; 4] = b"link";
+const DEFAULT_MAX_PERCENT_CHANGE: i64 = 20;
+const DEFAULT_EXPIRE: &str = "2.weeks.ago";
+const PREFIX: &str = "sharedindex.";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Link {
+    /// The checksum of the shared index, or zero if there is none.
+    pub base: Oid,
+    pub delete: Vec<bool>,
+    pub replace: Vec<bool>,
+}
+
+impl Link {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Link> {
+        let base = Oid::from_bytes(data.get(..20).context("truncated link extension")?)?;
+        let mut link = Link { base, ..Link::default() };
+        // An index needing no shared index may leave out the bitmaps.
+        if data.len() > 20 {
+            let (delete, len) = ewah::read(&data[20..]).context("corrupt delete bitmap in link extension")?;
+            let (replace, rest) = ewah::read(&data[20 + len..]).context("corrupt replace bitmap in link extension")?;
+            anyhow::ensure!(20 + len + rest == data.len(), "link extension has trailing data");
+            link.delete = delete;
+            link.replace = replace;
+        }
+        Ok(link)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = self.base.as_bytes().to_vec();
+        ewah::write(&mut out, &self.delete);
+        ewah::write(&mut out, &self.replace);
+        out
+    }
+}
+
+pub fn shared_path(git_dir: &Path, hash: &Oid) -> PathBuf {
+    git_dir.join(format!("{PREFIX}{hash}"))
+}
+
+/// The percentage of entries that may be missing from the shared index
+/// before a new one is written: `splitIndex.maxPercentChange`.
+pub fn max_percent_change(config: &Config) -> anyhow::Result<i64> {
+    match config.get_int("splitIndex.maxPercentChange")? {
+        None => Ok(DEFAULT_MAX_PERCENT_CHANGE),
+        Some(percent) if (0..=100).contains(&percent) => Ok(percent),
+        Some(percent) => {
+            eprintln!("warning: splitIndex.maxPercentChange value '{percent}' should be between 0 and 100");
+            Ok(DEFAULT_MAX_PERCENT_CHANGE)
+        }
+    }
+}
+
+/// Writes shared index `data`, named by its checksum, unless it already
+/// exists, and deletes the expired ones.
+pub fn write_shared(config: &Config, git_dir: &Path, data: &[u8]) -> anyhow::Result<Oid> {
+    let hash = Oid::from_bytes(&data[data.len() - 20..])?;
+    let path = shared_path(git_dir, &hash);
+    if !path.exists() {
+        let temp = git_dir.join(format!("{PREFIX}{hash}.lock"));
+        fs::write(&temp, data).with_context(|| format!("write {}", temp.display()))?;
+        fs::rename(&temp, &path).with_context(|| format!("write {}", path.display()))?;
+    }
+    expire(config, git_dir, &hash)?;
+    Ok(hash)
+}
+
+/// Marks shared index `hash` as still in use, so it doesn't expire.
+pub fn freshen(git_dir: &Path, hash: &Oid) -> anyhow::Result<()> {
+    let path = shared_path(git_dir, hash);
+    let file = fs::File::options().write(true).open(&path).with_context(|| format!("open {}", path.display()))?;
+    file.set_modified(SystemTime::now()).with_context(|| format!("touch {}", path.display()))
+}
+
+/// Deletes the shared indexes other than `keep` last used before
+/// `splitIndex.sharedIndexExpire`.
+fn expire(config: &Config, git_dir: &Path, keep: &Oid) -> anyhow::Result<()> {
+    let value = config.get("splitIndex.sharedIndexExpire").unwrap_or(DEFAULT_EXPIRE);
+    let Some(age) = config::parse_age(value).with_context(|| format!("invalid splitIndex.sharedIndexExpire '{value}'"))? else {
+        return Ok(());
+    };
+    let cutoff = SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH);
+    for entry in fs::read_dir(git_dir).context("list .got")? {
+        let entry = entry.context("list .got")?;
+        let name = entry.file_name();
+        let Some(hex) = name.to_str().and_then(|name| name.strip_prefix(PREFIX)) else {
+            continue;
+        };
+        if hex.parse::<Oid>().is_err() || hex == keep.to_string() {
+            continue;
+        }
+        let modified = entry.metadata().and_then(|meta| meta.modified());
+        if modified.is_ok_and(|modified| modified <= cutoff) {
+            fs::remove_file(entry.path()).with_context(|| format!("remove {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
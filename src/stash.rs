@@ -0,0 +1,71 @@
+//! Stashes, kept the way git keeps them: `refs/stash` points at the newest
+//! stash commit and its reflog holds every stash, so `stash@{0}` is the
+//! newest and `stash@{n}` the one stashed `n` stashes earlier.
+
+use crate::object::Signature;
+use crate::oid::Oid;
+use crate::reflog::{self, Entry};
+use crate::refs;
+use anyhow::Context;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const STASH_REF: &str = "refs/stash";
+
+/// Every stash, newest (`stash@{0}`) first.
+pub fn list(git_dir: &Path) -> anyhow::Result<Vec<Entry>> {
+    let mut entries = reflog::read(git_dir, STASH_REF)?;
+    entries.reverse();
+    Ok(entries)
+}
+
+/// The index named by a stash argument: `stash@{<n>}` or a bare `<n>`.
+pub fn parse_selector(spec: &str) -> anyhow::Result<usize> {
+    let n = spec
+        .strip_prefix("stash@{")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .unwrap_or(spec);
+    n.parse().with_context(|| format!("'{spec}' is not a stash reference"))
+}
+
+/// Stash `n`, or an error naming it if there is no such stash.
+pub fn get(git_dir: &Path, n: usize) -> anyhow::Result<Entry> {
+    let entries = list(git_dir)?;
+    anyhow::ensure!(!entries.is_empty(), "no stash entries found");
+    entries
+        .into_iter()
+        .nth(n)
+        .with_context(|| format!("stash@{{{n}}} does not exist"))
+}
+
+/// Records `commit` as the new `stash@{0}`, pushing the others down.
+pub fn store(git_dir: &Path, commit: &Oid, committer: Signature, message: &str) -> anyhow::Result<()> {
+    let mut entries = reflog::read(git_dir, STASH_REF)?;
+    let old = refs::resolve(git_dir, STASH_REF)?.unwrap_or(Oid::ZERO);
+    entries.push(Entry {
+        old,
+        new: *commit,
+        committer,
+        message: message.to_string(),
+    });
+    reflog::write(git_dir, STASH_REF, &entries)?;
+    refs::write(git_dir, STASH_REF, &commit.to_string())
+}
+
+/// Removes stash `n` and returns it. `refs/stash` moves to the new newest
+/// stash, and goes away along with its log when none are left.
+pub fn drop(git_dir: &Path, n: usize) -> anyhow::Result<Entry> {
+    get(git_dir, n)?;
+    let (dropped, remaining) = reflog::delete(git_dir, STASH_REF, n)?;
+    match remaining.last() {
+        Some(newest) => refs::write(git_dir, STASH_REF, &newest.new.to_string())?,
+        None => match fs::remove_file(git_dir.join(STASH_REF)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(e).context("remove refs/stash");
+            }
+            _ => {}
+        },
+    }
+    Ok(dropped)
+}
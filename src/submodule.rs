@@ -0,0 +1,90 @@
+//! Submodules: other repositories checked out inside the worktree, at the
+//! commit a gitlink entry of the superproject's tree records.
+//!
+//! `.gitmodules`, in config syntax, names each submodule and says where it
+//! lives and where it is cloned from:
+//!
+//! ```text
+//! [submodule "lib"]
+//!     path = vendor/lib
+//!     url = ../lib
+//! ```
+//!
+//! A URL starting with `./` or `../` is relative to the superproject's
+//! `origin`. A submodule is initialized once its URL has been copied into
+//! the superproject's config as `submodule.<name>.url`; only initialized
+//! submodules are cloned and updated.
+
+use crate::config::Config;
+use crate::path;
+use anyhow::Context;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submodule {
+    pub name: String,
+    /// Where it is checked out, `/`-separated from the top of the worktree.
+    pub path: String,
+    pub url: String,
+}
+
+/// Parses the contents of a `.gitmodules` file.
+pub fn parse_gitmodules(text: &str) -> anyhow::Result<Vec<Submodule>> {
+    let mut config = Config::default();
+    config.parse(text).context("parse .gitmodules")?;
+    config
+        .subsections("submodule")
+        .into_iter()
+        .map(|name| {
+            let get = |key: &str| {
+                config
+                    .get(&format!("submodule.{name}.{key}"))
+                    .map(str::to_string)
+                    .with_context(|| format!("submodule '{name}' has no {key} in .gitmodules"))
+            };
+            Ok(Submodule {
+                name: name.to_string(),
+                path: get("path")?.trim_end_matches('/').to_string(),
+                url: get("url")?,
+            })
+        })
+        .collect()
+}
+
+/// The submodules listed in the worktree's `.gitmodules`, if there is one.
+pub fn read_gitmodules() -> anyhow::Result<Vec<Submodule>> {
+    match std::fs::read_to_string(".gitmodules") {
+        Ok(text) => parse_gitmodules(&text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e).context("read .gitmodules"),
+    }
+}
+
+/// Resolves a submodule `url` relative to `base`, the superproject's
+/// `origin` URL (or, without one, the superproject itself). Each `../`
+/// drops one component of `base`.
+pub fn resolve_url(url: &str, base: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_string();
+    }
+    let mut base = base.trim_end_matches('/').to_string();
+    let mut rest = url;
+    loop {
+        if let Some(after) = rest.strip_prefix("./") {
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("../") {
+            rest = after;
+            match base.rfind('/') {
+                Some(at) => base.truncate(at),
+                None => base.clear(),
+            }
+        } else {
+            break;
+        }
+    }
+    if base.is_empty() { rest.to_string() } else { format!("{base}/{rest}") }
+}
+
+/// Whether the submodule at `path` has been cloned.
+pub fn is_populated(path: &[u8]) -> bool {
+    path::to_path(path).join(".got").is_dir()
+}
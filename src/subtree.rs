@@ -0,0 +1,227 @@
+//! Subtrees: another project's history merged in under a prefix directory,
+//! as `git subtree` does it, an alternative to submodules that keeps
+//! everything in one repository.
+//!
+//! Adding a subtree makes a merge commit whose second parent is the other
+//! project's commit and whose tree has that commit's tree at the prefix;
+//! its message records the prefix and the commit in `git-subtree-dir:` and
+//! `git-subtree-split:` trailers. Pulling merges a newer commit of the
+//! other project into the prefix the same way. Splitting goes back the
+//! other way, rewriting the history of the prefix into commits of its own
+//! that reuse the other project's commits wherever they came from there, so
+//! that splitting is repeatable and the result can be pushed upstream.
+
+use crate::merge;
+use crate::object::{Commit, Kind, Object};
+use crate::oid::Oid;
+use crate::path;
+use crate::revwalk::Ancestry;
+use crate::tree::{self, Mode, Tree};
+use crate::tree_diff;
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+/// The tree at `prefix` in tree `root`, if there is a directory there.
+pub fn subtree_of(root: &Oid, prefix: &[u8]) -> anyhow::Result<Option<Oid>> {
+    Ok(tree::lookup(root, prefix)?
+        .filter(|entry| entry.mode == Mode::Tree)
+        .map(|entry| entry.hash))
+}
+
+/// Tree `root` (or an empty tree) with the entry at `path` set to `entry`,
+/// or removed for `None`, storing whatever new trees that takes. Trees
+/// left empty are removed as well.
+pub fn with_entry(root: Option<&Oid>, path: &[u8], entry: Option<(Mode, Oid)>) -> anyhow::Result<Oid> {
+    let mut tree = match root {
+        Some(root) => Tree::read(root)?,
+        None => Tree::new(),
+    };
+    let (name, rest) = match path::split_first(path) {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (path, None),
+    };
+    let entry = match rest {
+        None => entry,
+        Some(rest) => {
+            let child = tree.find(name).filter(|child| child.mode == Mode::Tree).map(|child| child.hash);
+            let child = with_entry(child.as_ref(), rest, entry)?;
+            (!Tree::read(&child)?.is_empty()).then_some((Mode::Tree, child))
+        }
+    };
+    match entry {
+        Some((mode, hash)) => tree.insert(mode, name, hash)?,
+        None => {
+            tree.remove(name);
+        }
+    }
+    Object::store(Kind::Tree, &tree.serialize())
+}
+
+/// Merges the changes from tree `base` (`None` if there is no common
+/// ancestor) to tree `theirs` into tree `ours`, file by file. Files changed
+/// differently on both sides are conflicts, which this can't resolve.
+pub fn merge_trees(base: Option<&Oid>, ours: &Oid, theirs: &Oid) -> anyhow::Result<Oid> {
+    let changes = |tree: &Oid| -> anyhow::Result<BTreeMap<Vec<u8>, merge::Side>> {
+        tree_diff::diff_trees(base, Some(tree))?
+            .into_iter()
+            .map(|change| {
+                let entry = if change.new_hash.is_zero() {
+                    None
+                } else {
+                    Some((Mode::from_bits(change.new_mode)?, change.new_hash))
+                };
+                Ok((change.path, entry))
+            })
+            .collect()
+    };
+    let ours_changes = changes(ours)?;
+    let mut merged = *ours;
+    let mut conflicts = vec![];
+    for (path, entry) in changes(theirs)? {
+        match ours_changes.get(&path) {
+            Some(ours_entry) if *ours_entry == entry => {}
+            Some(_) => conflicts.push(path::display(&path).into_owned()),
+            None => merged = with_entry(Some(&merged), &path, entry)?,
+        }
+    }
+    anyhow::ensure!(
+        conflicts.is_empty(),
+        "both sides changed the following files; merge them by hand:\n\t{}",
+        conflicts.join("\n\t")
+    );
+    Ok(merged)
+}
+
+/// Splits the history of `prefix` out of the commits reachable from `head`
+/// and returns the commit standing for `head`, or `None` if `prefix` never
+/// had anything in it.
+pub fn split(git_dir: &Path, head: &Oid, prefix: &str) -> anyhow::Result<Option<Oid>> {
+    let commits = parents_first(head)?;
+    let ancestry = Ancestry::new(git_dir)?;
+    // What each commit becomes in the split history; commits without the
+    // prefix that don't descend from one with it have no entry.
+    let mut mapped: HashMap<Oid, Oid> = HashMap::new();
+    // Commits the other project contributed through `add` stand for
+    // themselves.
+    for (_, commit) in &commits {
+        if let Some(split) = added_split(commit, prefix) {
+            mapped.insert(split, split);
+        }
+    }
+    for (hash, commit) in &commits {
+        if mapped.contains_key(hash) {
+            continue;
+        }
+        let mut parents: Vec<Oid> = vec![];
+        for parent in &commit.parents {
+            if let Some(parent) = mapped.get(parent)
+                && !parents.contains(parent)
+            {
+                parents.push(*parent);
+            }
+        }
+        let Some(tree) = subtree_of(&commit.tree, prefix.as_bytes())? else {
+            // A commit of the other project after the last one added.
+            if !parents.is_empty() {
+                mapped.insert(*hash, *hash);
+            }
+            continue;
+        };
+        let result = match reusable_parent(&ancestry, &parents, &tree)? {
+            Some(parent) => parent,
+            None => {
+                let copy = Commit {
+                    tree,
+                    parents,
+                    extra_headers: vec![],
+                    gpgsig: None,
+                    ..commit.clone()
+                };
+                Object::store(Kind::Commit, &copy.serialize())?
+            }
+        };
+        mapped.insert(*hash, result);
+    }
+    Ok(mapped.get(head).copied())
+}
+
+/// The split parent that a commit with subtree `tree` can be replaced by:
+/// one with the same tree, when the other parents bring in no history of
+/// their own.
+fn reusable_parent(ancestry: &Ancestry, parents: &[Oid], tree: &Oid) -> anyhow::Result<Option<Oid>> {
+    let mut identical: Option<Oid> = None;
+    let mut different = vec![];
+    for parent in parents {
+        if read_commit(parent)?.tree != *tree {
+            different.push(*parent);
+            continue;
+        }
+        match identical {
+            None => identical = Some(*parent),
+            // Of two with the same tree, one must contain the other.
+            Some(other) if ancestry.is_ancestor(&other, parent)? => identical = Some(*parent),
+            Some(other) if ancestry.is_ancestor(parent, &other)? => {}
+            Some(_) => return Ok(None),
+        }
+    }
+    let Some(identical) = identical else {
+        return Ok(None);
+    };
+    for parent in different {
+        if !ancestry.is_ancestor(&parent, &identical)? {
+            return Ok(None);
+        }
+    }
+    Ok(Some(identical))
+}
+
+/// The other project's commit that `commit` added or rejoined at `prefix`,
+/// from its `git-subtree-dir:` and `git-subtree-split:` trailers.
+fn added_split(commit: &Commit, prefix: &str) -> Option<Oid> {
+    let trailer = |name: &str| {
+        commit
+            .message
+            .lines()
+            .find_map(|line| line.strip_prefix(name).map(str::trim))
+    };
+    if trailer("git-subtree-dir:")?.trim_end_matches('/') != prefix {
+        return None;
+    }
+    trailer("git-subtree-split:")?.parse().ok()
+}
+
+/// Every commit reachable from `head`, each after all of its parents.
+fn parents_first(head: &Oid) -> anyhow::Result<Vec<(Oid, Commit)>> {
+    let mut order = vec![];
+    let mut seen: HashSet<Oid> = HashSet::new();
+    // Each commit is pushed once to expand its parents, then once more to
+    // be emitted after them.
+    let mut stack = vec![(*head, false)];
+    let mut loaded: HashMap<Oid, Commit> = HashMap::new();
+    while let Some((hash, expanded)) = stack.pop() {
+        if expanded {
+            let commit = loaded.remove(&hash).expect("expanded commits are loaded");
+            order.push((hash, commit));
+            continue;
+        }
+        if !seen.insert(hash) {
+            continue;
+        }
+        let commit = read_commit(&hash)?;
+        stack.push((hash, true));
+        for parent in commit.parents.iter().rev() {
+            if !seen.contains(parent) {
+                stack.push((*parent, false));
+            }
+        }
+        loaded.insert(hash, commit);
+    }
+    Ok(order)
+}
+
+fn read_commit(hash: &Oid) -> anyhow::Result<Commit> {
+    let object = Object::read(hash)?;
+    anyhow::ensure!(object.kind == Kind::Commit, "object {hash} is a {}, not a commit", object.kind);
+    Commit::parse(&object.contents).with_context(|| format!("parse commit {hash}"))
+}
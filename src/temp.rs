@@ -0,0 +1,64 @@
+//! Temporary files and directories, for handing contents to programs that
+//! only understand paths: external diffs, difftools, textconv commands and
+//! `got unpack-file`'s callers.
+
+use crate::object::{Kind, Object};
+use crate::oid::Oid;
+use anyhow::Context;
+use std::fs;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A directory under the system's temporary directory, removed with
+/// everything in it when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(purpose: &str) -> anyhow::Result<TempDir> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("got-{purpose}-{}-{n}", std::process::id()));
+        fs::create_dir_all(&path).with_context(|| format!("create {}", path.display()))?;
+        Ok(TempDir { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Writes the contents of blob `hash` to a new file in `dir`, named
+/// `.merge_file_XXXXXX` as git names them, and returns its path. The file
+/// is the caller's to remove.
+pub fn unpack_blob(hash: &Oid, dir: &Path) -> anyhow::Result<PathBuf> {
+    let object = Object::read(hash)?;
+    anyhow::ensure!(object.kind == Kind::Blob, "unable to read blob object {hash}: it is a {}", object.kind);
+    const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+    loop {
+        let mut seed = RandomState::new().hash_one(std::process::id());
+        let suffix: String = (0..6)
+            .map(|_| {
+                let c = CHARS[(seed % CHARS.len() as u64) as usize] as char;
+                seed /= CHARS.len() as u64;
+                c
+            })
+            .collect();
+        let path = dir.join(format!(".merge_file_{suffix}"));
+        let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => result.with_context(|| format!("create {}", path.display()))?,
+        };
+        file.write_all(&object.contents).with_context(|| format!("write {}", path.display()))?;
+        return Ok(path);
+    }
+}
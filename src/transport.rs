@@ -0,0 +1,710 @@
+//! Git's smart HTTP protocol, which got speaks itself to fetch from and
+//! push to `http://` and `https://` remotes.
+//!
+//! Everything is sent in pkt-lines: four hex digits giving the length of the
+//! line, themselves included, and then its data, with `0000` (a flush)
+//! ending each section. A fetch takes two requests:
+//!
+//! - `GET <url>/info/refs?service=git-upload-pack` is answered with a
+//!   `# service=git-upload-pack` line and a flush, then the remote's refs as
+//!   `<hash> <name>` lines and a flush. The first ref is followed by a NUL
+//!   and the server's capabilities separated by spaces, among them
+//!   `symref=HEAD:<ref>` saying which branch HEAD points to.
+//! - `POST <url>/git-upload-pack` sends a `want <hash>` line for each tip
+//!   wanted, the first followed by the capabilities got asks for, then a
+//!   `shallow <hash>` line for each commit on the repository's shallow
+//!   boundary, `deepen <depth>` to fetch history past it, and a flush. Then
+//!   comes a `have <hash>` line for each of the repository's most recent
+//!   commits, so that the server can leave out what they already hold, and
+//!   `done`. The answer is, when deepening, the `shallow <hash>` and
+//!   `unshallow <hash>` lines that move the boundary and a flush, then
+//!   `ACK <hash>` for a commit in common or `NAK`, and then the pack. With
+//!   `side-band-64k` the pack comes in lines whose first byte says whether
+//!   the rest is pack data (1), progress to show (2) or an error (3).
+//!
+//! A push asks for `info/refs?service=git-receive-pack` the same way, then
+//! posts to `<url>/git-receive-pack` an `<old> <new> <ref>` line for each
+//! ref to update, with the zero hash for a ref being created or deleted and
+//! the capabilities after a NUL on the first line, a flush and a pack of
+//! the objects the remote doesn't have yet. The remote answers with
+//! `unpack ok` (or why it couldn't take the pack), then `ok <ref>` or
+//! `ng <ref> <why>` for each update and a flush, in side-band 1 if asked to.
+//!
+//! A fetched pack is stored as it is, with an index and a `.keep` file
+//! that stays until the refs point into it, or if it is small, taken apart
+//! into loose objects. A server that answers 401 is asked again with a
+//! username and password from the credential helpers or the user, and the
+//! helpers are told whether it accepted them.
+
+use crate::config::Config;
+use crate::credential::{self, Credential};
+use crate::http::HttpOptions;
+use crate::object::Kind;
+use crate::oid::Oid;
+use crate::remote_helper::{PackLocks, PushStatus, RefValue, RemoteRef};
+use crate::repack::{self, RepackOptions};
+use crate::revwalk::RevWalk;
+use crate::temp::TempDir;
+use crate::{pack, refs, repository, revision, shallow};
+use anyhow::Context;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use ureq::Agent;
+use ureq::http::Request;
+
+const FLUSH: &[u8] = b"0000";
+const UPLOAD_PACK: &str = "git-upload-pack";
+const RECEIVE_PACK: &str = "git-receive-pack";
+
+/// The capabilities got asks for when the server offers them.
+const WANTED_CAPABILITIES: &[&str] = &["side-band-64k", "ofs-delta", "shallow"];
+
+/// How many of the repository's most recent commits are offered as haves.
+const MAX_HAVES: usize = 256;
+
+/// How many objects a fetched pack may hold and still be unpacked into
+/// loose objects, unless `fetch.unpackLimit` or `transfer.unpackLimit`
+/// says otherwise.
+const DEFAULT_UNPACK_LIMIT: i64 = 100;
+
+/// The depth that asks for all of history.
+const INFINITE_DEPTH: u32 = 0x7fff_ffff;
+
+/// A remote reached over smart HTTP.
+pub struct SmartHttp {
+    /// The repository's URL without a username, password or trailing slash.
+    url: String,
+    options: HttpOptions,
+    agent: Agent,
+    credential: Credential,
+    /// Whether `credential` is complete and sent with each request.
+    authenticating: bool,
+    capabilities: Vec<String>,
+    /// What the refs listed last point at.
+    advertised: Vec<Oid>,
+}
+
+impl SmartHttp {
+    /// Prepares to talk to the repository at `url`. Nothing is sent until
+    /// its refs are listed.
+    pub fn new(config: &Config, url: &str) -> anyhow::Result<SmartHttp> {
+        let credential = Credential::from_url(url)?;
+        let (scheme, rest) = url.split_once("://").with_context(|| format!("invalid URL '{url}'"))?;
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+        let url = format!("{scheme}://{host}/{path}").trim_end_matches('/').to_string();
+        let options = HttpOptions::from_config(config, &url)?;
+        Ok(SmartHttp {
+            agent: options.agent()?,
+            options,
+            authenticating: credential.is_complete(),
+            credential,
+            url,
+            capabilities: vec![],
+            advertised: vec![],
+        })
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|capability| capability == name)
+    }
+
+    /// The remote's refs, as it advertises them to fetches or, with
+    /// `for_push`, to pushes. HEAD is listed as a symbolic ref if the server
+    /// says which branch it points to.
+    pub fn list(&mut self, config: &Config, for_push: bool) -> anyhow::Result<Vec<RemoteRef>> {
+        let service = if for_push { RECEIVE_PACK } else { UPLOAD_PACK };
+        let (content_type, body) = self.request(config, service, None)?;
+        anyhow::ensure!(
+            content_type == format!("application/x-{service}-advertisement"),
+            "{}/ doesn't speak the smart HTTP protocol",
+            self.url
+        );
+        self.read_advertisement(service, &body)
+    }
+
+    /// Reads the refs and capabilities `service` advertised in `body`.
+    fn read_advertisement(&mut self, service: &str, body: &[u8]) -> anyhow::Result<Vec<RemoteRef>> {
+        let mut lines = PktLines(body);
+        anyhow::ensure!(
+            lines.next()? == Some(format!("# service={service}\n").as_bytes()) && lines.next()?.is_none(),
+            "invalid advertisement from {}/",
+            self.url
+        );
+        let mut listed = vec![];
+        while let Some(line) = lines.next()? {
+            let line = std::str::from_utf8(line).context("invalid ref advertisement")?.trim_end_matches('\n');
+            let line = match line.split_once('\0') {
+                Some((line, capabilities)) => {
+                    self.capabilities = capabilities.split(' ').map(str::to_string).collect();
+                    line
+                }
+                None => line,
+            };
+            let (hash, name) = line.split_once(' ').with_context(|| format!("invalid ref advertisement line '{line}'"))?;
+            // An empty repository advertises only its capabilities, and
+            // peeled tags are of no use to a fetch.
+            if name == "capabilities^{}" || name.ends_with("^{}") {
+                continue;
+            }
+            listed.push(RemoteRef {
+                name: name.to_string(),
+                value: RefValue::Hash(hash.parse()?),
+            });
+        }
+        self.advertised = listed
+            .iter()
+            .filter_map(|listed| match listed.value {
+                RefValue::Hash(hash) => Some(hash),
+                _ => None,
+            })
+            .collect();
+        for symref in self.capabilities.iter().filter_map(|capability| capability.strip_prefix("symref=")) {
+            if let Some((name, target)) = symref.split_once(':')
+                && let Some(listed) = listed.iter_mut().find(|listed| listed.name == name)
+            {
+                listed.value = RefValue::Symref(target.to_string());
+            }
+        }
+        Ok(listed)
+    }
+
+    /// Fetches the objects needed for `wants` into the repository at
+    /// `git_dir`, or with `deepen` all the history behind them, moving its
+    /// shallow boundary back as far as it then goes. A pack of fewer objects
+    /// than the unpack limit is unpacked into loose objects, unless `keep`
+    /// asks for it to be stored whole as a clone does, which also allows
+    /// `git_dir` to be another repository than the current one. A stored
+    /// pack stays kept until the returned locks are dropped, which should be
+    /// once the refs point at its objects.
+    pub fn fetch(&mut self, config: &Config, git_dir: &Path, wants: &[Oid], deepen: bool, keep: bool) -> anyhow::Result<PackLocks> {
+        let mut boundary = shallow::read(git_dir)?;
+        anyhow::ensure!(
+            (boundary.is_empty() && !deepen) || self.has_capability("shallow"),
+            "the remote doesn't support fetching into shallow repositories"
+        );
+        let mut capabilities: Vec<&str> = WANTED_CAPABILITIES.iter().copied().filter(|name| self.has_capability(name)).collect();
+        if !std::io::stderr().is_terminal() && self.has_capability("no-progress") {
+            capabilities.push("no-progress");
+        }
+        capabilities.push(concat!("agent=got/", env!("CARGO_PKG_VERSION")));
+        let sideband = capabilities.contains(&"side-band-64k");
+
+        let request = upload_request(wants, &capabilities, &boundary, deepen, &haves(git_dir)?);
+        let body = self.request_result(config, UPLOAD_PACK, &request)?;
+        let data = self.read_upload_result(&body, deepen, sideband, &mut boundary)?;
+
+        let limit = match config.get_int("fetch.unpackLimit")? {
+            Some(limit) => limit,
+            None => config.get_int("transfer.unpackLimit")?.unwrap_or(DEFAULT_UNPACK_LIMIT),
+        };
+        let locks = if !keep && (pack::object_count(&data)? as i64) < limit {
+            pack::unpack_objects(&data, false).with_context(|| format!("unpack objects from {}", self.url))?;
+            PackLocks::default()
+        } else {
+            let reason = format!("fetch-pack {} from {}", std::process::id(), self.url);
+            let (path, _) = pack::index_pack(&repository::objects_dir(git_dir).join("pack"), &data, Some(&reason))
+                .with_context(|| format!("index pack from {}", self.url))?;
+            PackLocks(vec![path.with_extension("keep")])
+        };
+        if deepen {
+            shallow::write(git_dir, &boundary)?;
+        }
+        Ok(locks)
+    }
+
+    /// Reads upload-pack's answer in `body`: with `deepen`, the commits to
+    /// add to and take off the shallow `boundary`, then its acknowledgements
+    /// and the pack, which comes in side-band 1 with `sideband`.
+    fn read_upload_result(&self, body: &[u8], deepen: bool, sideband: bool, boundary: &mut BTreeSet<Oid>) -> anyhow::Result<Vec<u8>> {
+        let mut lines = PktLines(body);
+        if deepen {
+            while let Some(line) = lines.next()? {
+                let line = String::from_utf8_lossy(line);
+                match line.trim_end().split_once(' ') {
+                    Some(("shallow", hash)) => boundary.insert(hash.parse()?),
+                    Some(("unshallow", hash)) => boundary.remove(&hash.parse()?),
+                    _ => anyhow::bail!("expected shallow list, got '{}'", line.trim_end()),
+                };
+            }
+        }
+        // Some servers acknowledge more than one commit in common.
+        let mut acknowledged = false;
+        loop {
+            let mut peek = PktLines(lines.0);
+            match peek.next() {
+                Ok(Some(line)) if line.starts_with(b"ACK ") || line.trim_ascii_end() == b"NAK" => {
+                    lines = peek;
+                    acknowledged = true;
+                }
+                _ if acknowledged => break,
+                _ => anyhow::bail!("expected ACK/NAK from {}/{UPLOAD_PACK}", self.url),
+            }
+        }
+        match sideband {
+            true => demultiplex(lines),
+            false => Ok(lines.0.to_vec()),
+        }
+    }
+
+    /// Asks the remote to point each ref of `updates` at the new value in
+    /// place of the old one it has now, either `None` where the ref is being
+    /// created or deleted, and sends it a pack of the objects it lacks from
+    /// the repository at `git_dir`. Returns how each update went.
+    pub fn push(&mut self, config: &Config, git_dir: &Path, updates: &[(String, Option<Oid>, Option<Oid>)]) -> anyhow::Result<Vec<PushStatus>> {
+        anyhow::ensure!(self.has_capability("report-status"), "the remote doesn't report how pushes went");
+        anyhow::ensure!(
+            updates.iter().all(|(_, _, new)| new.is_some()) || self.has_capability("delete-refs"),
+            "the remote doesn't support deleting refs"
+        );
+        let mut capabilities: Vec<&str> = ["report-status", "side-band-64k"].into_iter().filter(|name| self.has_capability(name)).collect();
+        capabilities.push(concat!("agent=got/", env!("CARGO_PKG_VERSION")));
+        let sideband = capabilities.contains(&"side-band-64k");
+
+        let mut request = vec![];
+        for (i, (name, old, new)) in updates.iter().enumerate() {
+            let (old, new) = (old.unwrap_or(Oid::ZERO), new.unwrap_or(Oid::ZERO));
+            match i {
+                0 => pkt_line(&mut request, &format!("{old} {new} {name}\0{}\n", capabilities.join(" "))),
+                _ => pkt_line(&mut request, &format!("{old} {new} {name}\n")),
+            }
+        }
+        request.extend(FLUSH);
+        // Only deletions need no pack. The remote is taken to have the
+        // history of every commit it advertised that is here too.
+        let tips: Vec<Oid> = updates.iter().filter_map(|(_, _, new)| *new).collect();
+        if !tips.is_empty() {
+            let known: Vec<Oid> = self.advertised.iter().filter_map(|hash| revision::peel(hash, Some(Kind::Commit)).ok()).collect();
+            let mut options = RepackOptions::from_config(config)?;
+            options.progress = std::io::stderr().is_terminal();
+            if !self.has_capability("ofs-delta") {
+                options.window = 0;
+            }
+            let temp = TempDir::new("push")?;
+            let (path, _) = repack::pack_reachable_from(git_dir, &tips, &known, temp.path(), &options)?;
+            request.extend(fs::read(&path).with_context(|| format!("read {}", path.display()))?);
+        }
+
+        let body = self.request_result(config, RECEIVE_PACK, &request)?;
+        let report = match sideband {
+            true => demultiplex(PktLines(&body))?,
+            false => body,
+        };
+        let mut lines = PktLines(&report);
+        let unpack = lines.next()?.context("the remote didn't say how the push went")?;
+        let unpack = String::from_utf8_lossy(unpack);
+        match unpack.trim_end().strip_prefix("unpack ") {
+            Some("ok") => {}
+            Some(why) => anyhow::bail!("remote unpack failed: {why}"),
+            None => anyhow::bail!("invalid push status '{}'", unpack.trim_end()),
+        }
+        let mut statuses = vec![];
+        while let Some(line) = lines.next()? {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end();
+            statuses.push(match line.split_once(' ') {
+                Some(("ok", dst)) => PushStatus { dst: dst.to_string(), error: None },
+                Some(("ng", rest)) => {
+                    let (dst, why) = rest.split_once(' ').unwrap_or((rest, ""));
+                    PushStatus {
+                        dst: dst.to_string(),
+                        error: Some(why.to_string()),
+                    }
+                }
+                _ => anyhow::bail!("invalid push status '{line}'"),
+            });
+        }
+        Ok(statuses)
+    }
+
+    /// Posts `request` to `service` and returns the body of the response.
+    fn request_result(&mut self, config: &Config, service: &str, request: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let (content_type, body) = self.request(config, service, Some(request))?;
+        anyhow::ensure!(
+            content_type == format!("application/x-{service}-result"),
+            "invalid content-type '{content_type}' from {}/{service}",
+            self.url
+        );
+        Ok(body)
+    }
+
+    /// Asks for the refs `service` advertises or, with `body`, posts it to
+    /// `service`, returning the content type and body of the response. When
+    /// the server asks for credentials they are filled in and the request
+    /// made again. Transient failures are retried, except for a push: the
+    /// remote may have updated its refs before failing to answer.
+    fn request(&mut self, config: &Config, service: &str, body: Option<&[u8]>) -> anyhow::Result<(String, Vec<u8>)> {
+        let url = match body {
+            Some(_) => format!("{}/{service}", self.url),
+            None => format!("{}/info/refs?service={service}", self.url),
+        };
+        let mut asked = false;
+        loop {
+            let authorization = self.authenticating.then(|| basic_authorization(&self.credential));
+            let attempt = || {
+                let mut request = Request::builder().uri(&url);
+                if let Some(authorization) = &authorization {
+                    request = request.header("Authorization", authorization);
+                }
+                let mut response = match body {
+                    Some(body) => self.agent.run(
+                        request
+                            .method("POST")
+                            .header("Content-Type", format!("application/x-{service}-request"))
+                            .header("Accept", format!("application/x-{service}-result"))
+                            .body(body)?,
+                    )?,
+                    None => self.agent.run(request.method("GET").body(())?)?,
+                };
+                let content_type = response
+                    .headers()
+                    .get("content-type")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                let data = response.body_mut().with_config().limit(u64::MAX).read_to_vec()?;
+                Ok((content_type, data))
+            };
+            let result = match (service, body) {
+                (RECEIVE_PACK, Some(_)) => attempt().map_err(anyhow::Error::from),
+                _ => self.options.retry(attempt),
+            };
+            let status = match &result {
+                Err(e) => match e.downcast_ref::<ureq::Error>() {
+                    Some(ureq::Error::StatusCode(status)) => Some(*status),
+                    _ => None,
+                },
+                Ok(_) => None,
+            };
+            match (result, status) {
+                (Ok(response), _) => {
+                    if asked {
+                        credential::approve(config, &self.credential)?;
+                    }
+                    return Ok(response);
+                }
+                (Err(_), Some(401)) if !self.authenticating => {
+                    credential::fill(config, &mut self.credential)?;
+                    self.authenticating = true;
+                    asked = true;
+                }
+                (Err(_), Some(401)) => {
+                    credential::reject(config, &self.credential)?;
+                    anyhow::bail!("Authentication failed for '{}/'", self.url);
+                }
+                (Err(_), Some(404)) => anyhow::bail!("repository '{}/' not found", self.url),
+                (Err(e), _) => return Err(e.context(format!("unable to access '{}/'", self.url))),
+            }
+        }
+    }
+}
+
+/// Pkt-lines being read from a response.
+struct PktLines<'a>(&'a [u8]);
+
+impl<'a> PktLines<'a> {
+    /// The next line's data, or `None` for a flush.
+    fn next(&mut self) -> anyhow::Result<Option<&'a [u8]>> {
+        let len = self.0.get(..4).context("pkt-line is truncated")?;
+        let len = std::str::from_utf8(len).ok().and_then(|len| usize::from_str_radix(len, 16).ok());
+        let len = len.context("invalid pkt-line length")?;
+        if len == 0 {
+            self.0 = &self.0[4..];
+            return Ok(None);
+        }
+        anyhow::ensure!(len >= 4, "invalid pkt-line length {len}");
+        let line = self.0.get(4..len).context("pkt-line is truncated")?;
+        self.0 = &self.0[len..];
+        if let Some(message) = line.strip_prefix(b"ERR ") {
+            anyhow::bail!("remote error: {}", String::from_utf8_lossy(message).trim_end());
+        }
+        Ok(Some(line))
+    }
+}
+
+/// An upload-pack request for `wants`, asking for `capabilities`, from a
+/// repository whose shallow boundary is `boundary` and which has `haves`.
+/// With `deepen`, all the history behind `wants` is asked for.
+fn upload_request(wants: &[Oid], capabilities: &[&str], boundary: &BTreeSet<Oid>, deepen: bool, haves: &[Oid]) -> Vec<u8> {
+    let mut request = vec![];
+    for (i, want) in wants.iter().enumerate() {
+        match i {
+            0 => pkt_line(&mut request, &format!("want {want} {}\n", capabilities.join(" "))),
+            _ => pkt_line(&mut request, &format!("want {want}\n")),
+        }
+    }
+    for commit in boundary {
+        pkt_line(&mut request, &format!("shallow {commit}\n"));
+    }
+    if deepen {
+        pkt_line(&mut request, &format!("deepen {INFINITE_DEPTH}\n"));
+    }
+    request.extend(FLUSH);
+    for have in haves {
+        pkt_line(&mut request, &format!("have {have}\n"));
+    }
+    pkt_line(&mut request, "done\n");
+    request
+}
+
+pub(crate) fn pkt_line(out: &mut Vec<u8>, line: &str) {
+    out.extend(format!("{:04x}{line}", line.len() + 4).as_bytes());
+}
+
+/// Collects the pack data sent on side-band 1 up to the next flush, showing
+/// the remote's progress messages on standard error as they come.
+fn demultiplex(mut lines: PktLines) -> anyhow::Result<Vec<u8>> {
+    let mut data = vec![];
+    let mut line_start = true;
+    let mut stderr = std::io::stderr();
+    while let Some(line) = lines.next()? {
+        match line.split_first() {
+            Some((1, pack)) => data.extend(pack),
+            Some((2, progress)) => {
+                for part in progress.split_inclusive(|&byte| byte == b'\n' || byte == b'\r') {
+                    if line_start {
+                        stderr.write_all(b"remote: ")?;
+                    }
+                    stderr.write_all(part)?;
+                    line_start = part.ends_with(b"\n") || part.ends_with(b"\r");
+                }
+            }
+            Some((3, message)) => anyhow::bail!("remote error: {}", String::from_utf8_lossy(message).trim_end()),
+            _ => anyhow::bail!("invalid side-band packet"),
+        }
+    }
+    Ok(data)
+}
+
+/// The commits offered to the remote as ones the repository has: the most
+/// recent of those reachable from its refs.
+fn haves(git_dir: &Path) -> anyhow::Result<Vec<Oid>> {
+    let mut tips = vec![];
+    for (name, _) in refs::list(git_dir)? {
+        let commit = refs::resolve(git_dir, &name)?.and_then(|hash| revision::peel(&hash, Some(Kind::Commit)).ok());
+        if let Some(commit) = commit
+            && !tips.contains(&commit)
+        {
+            tips.push(commit);
+        }
+    }
+    let walk = RevWalk {
+        include: tips,
+        max_count: Some(MAX_HAVES),
+        ..RevWalk::default()
+    };
+    Ok(walk.commits(git_dir)?.into_iter().map(|commit| commit.hash).collect())
+}
+
+/// The `Authorization` header sending `credential` by HTTP basic
+/// authentication.
+fn basic_authorization(credential: &Credential) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let username = credential.username.as_deref().unwrap_or_default();
+    let password = credential.password.as_deref().unwrap_or_default();
+    let mut encoded = String::from("Basic ");
+    for chunk in format!("{username}:{password}").as_bytes().chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | u32::from(byte) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Exchanges with `git http-backend` serving a repository whose history
+    // is `master` at N, merging B3 and S3 (`side`), with `A` tagged
+    // lightly and `v1` an annotated tag of M:
+    //
+    //     A - B1 - B2 - B3 - M - N
+    //      \                /
+    //       S1 - S2 - S3 --
+
+    const N: &str = "10d15535697df5b3f20f491f08035046d9280651";
+    const M: &str = "0a7827f3f590de17e00c94eab181adcd253ed36f";
+    const B3: &str = "f4e2ffef47210b3a938772ef8e65991e2ddfd7cc";
+    const S1: &str = "9314a8eed85df14477ce4899895edb58f6b0ea39";
+    const S2: &str = "206a757e23741bc8410566dba4a9539caf95784e";
+    const S3: &str = "3c8591ecd1b4a87040081b02995df58ce9fe3a52";
+    const A: &str = "a4ecabefb5d2531fd3c711ec9578a69697843200";
+    const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    /// The answer to `GET info/refs?service=git-upload-pack`.
+    const ADVERTISEMENT: &str = concat!(
+        "001e# service=git-upload-pack\n0000",
+        "010d10d15535697df5b3f20f491f08035046d9280651 HEAD\0multi_ack thin-pack side-band side-band-64k ofs-delta shallow ",
+        "deepen-since deepen-not deepen-relative no-progress include-tag multi_ack_detailed no-done symref=HEAD:refs/heads/master ",
+        "object-format=sha1 agent=git/2.39.5\n",
+        "003f10d15535697df5b3f20f491f08035046d9280651 refs/heads/master\n",
+        "003d3c8591ecd1b4a87040081b02995df58ce9fe3a52 refs/heads/side\n",
+        "0039a4ecabefb5d2531fd3c711ec9578a69697843200 refs/tags/A\n",
+        "003a47292e1ca0f1a689888d9c9116df9f07d5810c43 refs/tags/v1\n",
+        "003d0a7827f3f590de17e00c94eab181adcd253ed36f refs/tags/v1^{}\n",
+        "0000",
+    );
+
+    const CAPABILITIES: &[&str] = &["side-band-64k", "ofs-delta", "shallow", "no-progress", "agent=got/test"];
+
+    /// Each request posted to `git-upload-pack`, and the answer to it in
+    /// hex: wanting N from a repository that has B3, which is acknowledged;
+    /// wanting S3 with nothing in common, which isn't; and deepening a
+    /// repository whose shallow boundary is S2 to all the history of S3.
+    const ACK_REQUEST: &str = concat!(
+        "006dwant 10d15535697df5b3f20f491f08035046d9280651 side-band-64k ofs-delta shallow no-progress agent=got/test\n0000",
+        "0032have f4e2ffef47210b3a938772ef8e65991e2ddfd7cc\n",
+        "0009done\n",
+    );
+    const ACK_RESULT: &str = concat!(
+        "3030333141434b20663465326666656634373231306233613933383737326566386536353939316532646466643763630a30323031015041434b000000020000",
+        "00059010789c958b4d6a03310c46f73e85f781226b645b8250da0374971ec03f12096432c3e0428e9f0ca107c8b77a3cde3736554f9531f696085b4d5aa54002",
+        "8d548d7b1264b6aa4a02e4d6b2e96d7823453335ca18a04e4526ce19d5585314098abd5bcfadfdf753e3f8d4ad874a85331000870a2812bb456e2aa65389e8ca",
+        "df382f9bfff6bffeb4c3f125bef45ee6f5aa1f6d993f7dc8b08f01fc6107f7b4f3650c7dfbe87edc03f5d54a99e504802b789c6b60bac038c154c320d1dcc2c8",
+        "3ccd38cdd4d22025d5d03cd5c020d9d2243531c9d0c230312539c5c8d43835c5d82c6d62af2ea3e5c4dd16bc9606060ada0640c0c5e5c70500b73f12b3e70480",
+        "7b789c6b60bac838c154c3c8c02cd1dcd43cd5c8d8dcc43029d9c2c4d0c0d4cc2c2529d124d1d2d4d8323931cdd2d4dcc2247562af2e93b1e9c43de67ce60606",
+        "0ada0640c0c5156ccc0500b606123aec04814d789c6b60bac838c154c3d2d8d024d1223535c5c23425cdd0c4c4dc3c39d5c4c2d2d2c2d2343525c9d422cd2cc9",
+        "2035d1d87262af2ea3f1c4dd46226696606060a0a06d00045c5cc1465c003fdf1407e6048220789c6b60bac838c15423d12435393129352dc934c5c8d4d8302d",
+        "c538d9dcd03035d9d2d4dc22d1ccd2ccd2dcc2c4d8c8c06062af2ea3d1c4dd167cc606060ada0640c0c5156cc80500d92f12303031390188a0a46c0fc05b96d3",
+        "d3be4a0f9d9031067a100e30303036016730303030",
+    );
+    const NAK_REQUEST: &str = concat!(
+        "006dwant 3c8591ecd1b4a87040081b02995df58ce9fe3a52 side-band-64k ofs-delta shallow no-progress agent=got/test\n0000",
+        "0009done\n",
+    );
+    const NAK_RESULT: &str = concat!(
+        "303030384e414b0a30313739015041434b0000000200000005910d789c8d8b5b0ac2400c45ff6715f917249d49e60122ba067501c93445c1da524670f95a7401",
+        "de8fcbe1c0698b199066cf7d8de4ab46d32218d19874c87d2c3ee741cda820b959167b34f0182571321f12755a3375c831f62a248543a932144e99ccc9b35da7",
+        "058e7081f30abbaf38d84bc6f96edb3a8d7be812ae0b8cb059c17dec786bcdfe0c3fff0bdd29b8370db63c26ec048013789cbbc878917182a986a5b1a149a245",
+        "6a6a8a85694a9aa18989b97972aa8985a5a585a5696a4a92a9459a5992416aa2b1e5c4583d4683893d86226696606060a0a06d00045c5cc1465c004de113f5e7",
+        "048066789cbbc878917182a946a2496a7262526a5a92698a91a9b1615a8a71b2b9a1616ab2a5a9b945a299a599a5b98589b19181c1c4585d262383893de67cc6",
+        "06060ada0640c0c5156cc80500fa3c12a9ed018138789cbbc8b8807182dec4381d260383893de6bc0606060ada40c2808bcb910b008094070a20789c03000000",
+        "0030303139010131f3cb15f4648bb87813d0c3f66f044eb80cc430303036010230303030",
+    );
+    const DEEPEN_REQUEST: &str = concat!(
+        "006dwant 3c8591ecd1b4a87040081b02995df58ce9fe3a52 side-band-64k ofs-delta shallow no-progress agent=got/test\n",
+        "0035shallow 206a757e23741bc8410566dba4a9539caf95784e\n",
+        "0016deepen 2147483647\n0000",
+        "0032have 206a757e23741bc8410566dba4a9539caf95784e\n",
+        "0009done\n",
+    );
+    const DEEPEN_RESULT: &str = concat!(
+        "30303336756e7368616c6c6f77203230366137353765323337343162633834313035363664626134613935333963616639353738346530303030303033314143",
+        "4b20323036613735376532333734316263383431303536366462613461393533396361663935373834650a30313164015041434b0000000200000003910d789c",
+        "8d8b5b0ac2400c45ff6715f917249d49e60122ba067501c93445c1da524670f95a7401de8fcbe1c0698b199066cf7d8de4ab46d32218d19874c87d2c3ee741cd",
+        "a820b959167b34f0182571321f12755a3375c831f62a248543a932144e99ccc9b35da7058e7081f30abbaf38d84bc6f96edb3a8d7be812ae0b8cb059c17dec78",
+        "6bcdfe0c3fff0bdd29b8370db63c26e7048013789cbbc878917182a946a2496a7262526a5a92698a91a9b1615a8a71b2b9a1616ab2a5a9b945a299a599a5b985",
+        "89b19181c1c4585d262383893de67cc606060ada0640c0c5156cc80500fa3c12a9ed018065789cbbc8b8807182dec4381d260383893de6bc0606060ada40c280",
+        "8bcb910b0080940730303139010af0d8f9727e9c302a46faafe319d54bcf1af7ec3030303601f330303030",
+    );
+
+    fn oid(hash: &str) -> Oid {
+        hash.parse().unwrap()
+    }
+
+    fn remote() -> SmartHttp {
+        SmartHttp::new(&Config::default(), "https://example.com/repo.git").unwrap()
+    }
+
+    /// The objects of `pack`, by hash, sorted.
+    fn objects(pack: &[u8]) -> Vec<String> {
+        let mut objects = vec![];
+        pack::for_each_object(pack, |_, _, hash| {
+            objects.push(hash.to_string());
+            Ok(())
+        })
+        .unwrap();
+        objects.sort();
+        objects
+    }
+
+    fn sorted(hashes: &[&str]) -> Vec<String> {
+        let mut hashes: Vec<String> = hashes.iter().map(|hash| hash.to_string()).collect();
+        hashes.sort();
+        hashes
+    }
+
+    #[test]
+    fn pkt_lines_are_framed_by_their_length() {
+        let mut out = vec![];
+        pkt_line(&mut out, "done\n");
+        pkt_line(&mut out, "");
+        out.extend(FLUSH);
+        assert_eq!(out, b"0009done\n00040000");
+        let mut lines = PktLines(&out);
+        assert_eq!(lines.next().unwrap(), Some(&b"done\n"[..]));
+        assert_eq!(lines.next().unwrap(), Some(&b""[..]));
+        assert_eq!(lines.next().unwrap(), None);
+        assert!(lines.0.is_empty());
+    }
+
+    #[test]
+    fn bad_pkt_lines_are_refused() {
+        for (data, error) in [
+            (&b"000"[..], "pkt-line is truncated"),
+            (b"0009don", "pkt-line is truncated"),
+            (b"00x9done\n", "invalid pkt-line length"),
+            (b"0003", "invalid pkt-line length 3"),
+            (b"0016ERR access denied\n", "remote error: access denied"),
+        ] {
+            assert_eq!(PktLines(data).next().unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn advertised_refs_are_listed_with_head_as_a_symref() {
+        let mut remote = remote();
+        let listed = remote.read_advertisement(UPLOAD_PACK, ADVERTISEMENT.as_bytes()).unwrap();
+        let hash = |name: &str, hash: &str| RemoteRef { name: name.to_string(), value: RefValue::Hash(oid(hash)) };
+        assert_eq!(
+            listed,
+            [
+                RemoteRef { name: "HEAD".to_string(), value: RefValue::Symref("refs/heads/master".to_string()) },
+                hash("refs/heads/master", N),
+                hash("refs/heads/side", S3),
+                hash("refs/tags/A", A),
+                hash("refs/tags/v1", "47292e1ca0f1a689888d9c9116df9f07d5810c43"),
+            ]
+        );
+        assert!(remote.has_capability("side-band-64k") && remote.has_capability("shallow"));
+        assert!(!remote.has_capability("filter"));
+        assert!(remote.read_advertisement(RECEIVE_PACK, ADVERTISEMENT.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn wants_and_haves_are_sent_as_git_answers_them() {
+        let remote = remote();
+        let none = BTreeSet::new();
+        let request = upload_request(&[oid(N)], CAPABILITIES, &none, false, &[oid(B3)]);
+        assert_eq!(String::from_utf8(request).unwrap(), ACK_REQUEST);
+        let mut boundary = BTreeSet::new();
+        let pack = remote.read_upload_result(&hex::decode(ACK_RESULT).unwrap(), false, true, &mut boundary).unwrap();
+        // Everything N has that B3 doesn't.
+        assert_eq!(objects(&pack), sorted(&[N, M, S3, S2, S1]));
+
+        let request = upload_request(&[oid(S3)], CAPABILITIES, &none, false, &[]);
+        assert_eq!(String::from_utf8(request).unwrap(), NAK_REQUEST);
+        let pack = remote.read_upload_result(&hex::decode(NAK_RESULT).unwrap(), false, true, &mut boundary).unwrap();
+        assert_eq!(objects(&pack), sorted(&[S3, S2, S1, A, EMPTY_TREE]));
+        assert!(boundary.is_empty());
+    }
+
+    #[test]
+    fn deepening_moves_the_shallow_boundary() {
+        let remote = remote();
+        let mut boundary = BTreeSet::from([oid(S2)]);
+        let request = upload_request(&[oid(S3)], CAPABILITIES, &boundary, true, &[oid(S2)]);
+        assert_eq!(String::from_utf8(request).unwrap(), DEEPEN_REQUEST);
+        let pack = remote.read_upload_result(&hex::decode(DEEPEN_RESULT).unwrap(), true, true, &mut boundary).unwrap();
+        assert!(boundary.is_empty());
+        // S2's tree was there already; S3 comes again.
+        assert_eq!(objects(&pack), sorted(&[S3, S1, A]));
+    }
+}
@@ -0,0 +1,347 @@
+//! Tree objects: `Tree` parses and builds them, and `TreeWalk` walks them
+//! recursively.
+//!
+//! `TreeWalk` is the one traversal shared by everything that visits the
+//! contents of a tree (ls-tree, rev-list --objects, checkout, archive): it
+//! yields every entry below a root tree with its full path, mode and hash,
+//! either before (pre-order, the default) or after (post-order) the entries
+//! of a subtree, and a pruning callback can stop it descending into subtrees.
+
+use crate::object::{Kind, Object};
+use crate::oid::Oid;
+use crate::path;
+use anyhow::Context;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+pub const MODE_TREE: u32 = 0o040000;
+pub const MODE_FILE: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+pub const MODE_SUBMODULE: u32 = 0o160000;
+
+/// The kinds of entry a tree can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Tree,
+    File,
+    Executable,
+    Symlink,
+    Submodule,
+}
+
+impl Mode {
+    /// Parses an octal mode as found in a tree. Regular files with unusual
+    /// permission bits, as some old git versions wrote, count as files or
+    /// executables the way git treats them.
+    pub fn parse(mode: &str) -> anyhow::Result<Mode> {
+        let bits = u32::from_str_radix(mode, 8).with_context(|| format!("invalid mode '{mode}'"))?;
+        Mode::from_bits(bits)
+    }
+
+    pub fn from_bits(bits: u32) -> anyhow::Result<Mode> {
+        Ok(match bits & 0o170000 {
+            MODE_TREE => Mode::Tree,
+            MODE_SYMLINK => Mode::Symlink,
+            MODE_SUBMODULE => Mode::Submodule,
+            0o100000 if bits & 0o100 != 0 => Mode::Executable,
+            0o100000 => Mode::File,
+            _ => anyhow::bail!("invalid mode '{bits:o}'"),
+        })
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            Mode::Tree => MODE_TREE,
+            Mode::File => MODE_FILE,
+            Mode::Executable => MODE_EXECUTABLE,
+            Mode::Symlink => MODE_SYMLINK,
+            Mode::Submodule => MODE_SUBMODULE,
+        }
+    }
+
+    /// The mode as written in a tree object: octal, without leading zeros.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Tree => "40000",
+            Mode::File => "100644",
+            Mode::Executable => "100755",
+            Mode::Symlink => "120000",
+            Mode::Submodule => "160000",
+        }
+    }
+
+    /// The kind of object an entry with this mode points to.
+    pub fn kind(self) -> Kind {
+        match self {
+            Mode::Tree => Kind::Tree,
+            Mode::Submodule => Kind::Commit,
+            _ => Kind::Blob,
+        }
+    }
+}
+
+/// One entry of a tree object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub mode: Mode,
+    pub name: Vec<u8>,
+    pub hash: Oid,
+}
+
+/// The contents of a tree object. Entries are kept in git's order, which
+/// sorts names bytewise as if every subtree's name ended in `/`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tree {
+    entries: Vec<Entry>,
+}
+
+impl Tree {
+    pub fn new() -> Tree {
+        Tree::default()
+    }
+
+    /// Reads tree `hash` from the object store.
+    pub fn read(hash: &Oid) -> anyhow::Result<Tree> {
+        let object = Object::read(hash)?;
+        anyhow::ensure!(object.kind == Kind::Tree, "object {hash} is a {}, not a tree", object.kind);
+        Tree::parse(&object.contents).with_context(|| format!("parse tree {hash}"))
+    }
+
+    /// Parses tree contents, rejecting malformed, unsorted and duplicate
+    /// entries.
+    pub fn parse(contents: &[u8]) -> anyhow::Result<Tree> {
+        let mut entries: Vec<Entry> = vec![];
+        let mut rest = contents;
+        while !rest.is_empty() {
+            let nul = rest.iter().position(|&b| b == 0).context("truncated tree entry")?;
+            let item = &rest[..nul];
+            let Some(space) = item.iter().position(|&b| b == b' ') else {
+                anyhow::bail!("malformed tree entry '{}'", path::display(item));
+            };
+            let mode = std::str::from_utf8(&item[..space]).with_context(|| format!("malformed tree entry '{}'", path::display(item)))?;
+            let hash = rest.get(nul + 1..nul + 21).context("truncated tree entry")?;
+            let entry = Entry {
+                mode: Mode::parse(mode)?,
+                name: item[space + 1..].to_vec(),
+                hash: Oid::from_bytes(hash)?,
+            };
+            check_name(&entry.name)?;
+            if let Some(last) = entries.last() {
+                anyhow::ensure!(last.name != entry.name, "duplicate tree entry '{}'", path::display(&entry.name));
+                anyhow::ensure!(
+                    entry_order(last, &entry) == Ordering::Less,
+                    "tree entry '{}' is out of order",
+                    path::display(&entry.name)
+                );
+            }
+            entries.push(entry);
+            rest = &rest[nul + 21..];
+        }
+        Ok(Tree { entries })
+    }
+
+    /// The canonical tree object contents.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = vec![];
+        for entry in &self.entries {
+            out.extend(entry.mode.as_str().as_bytes());
+            out.push(b' ');
+            out.extend(&entry.name);
+            out.push(0);
+            out.extend(entry.hash.as_bytes());
+        }
+        out
+    }
+
+    /// Adds an entry in sorted position, replacing any entry of the same
+    /// name.
+    pub fn insert(&mut self, mode: Mode, name: &[u8], hash: Oid) -> anyhow::Result<()> {
+        check_name(name)?;
+        self.entries.retain(|entry| entry.name != name);
+        let entry = Entry {
+            mode,
+            name: name.to_vec(),
+            hash,
+        };
+        let at = self
+            .entries
+            .binary_search_by(|probe| entry_order(probe, &entry))
+            .unwrap_or_else(|at| at);
+        self.entries.insert(at, entry);
+        Ok(())
+    }
+
+    /// Removes the entry named `name`, returning whether there was one.
+    pub fn remove(&mut self, name: &[u8]) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|entry| entry.name != name);
+        self.entries.len() != len
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The entry named `name`.
+    pub fn find(&self, name: &[u8]) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// The entry at `path`, a `/`-separated path below tree `root`.
+pub fn lookup(root: &Oid, path: &[u8]) -> anyhow::Result<Option<Entry>> {
+    let mut tree = Tree::read(root)?;
+    let mut components = path.split(|&b| b == b'/').peekable();
+    while let Some(name) = components.next() {
+        let Some(entry) = tree.find(name) else {
+            return Ok(None);
+        };
+        if components.peek().is_none() {
+            return Ok(Some(entry.clone()));
+        }
+        if entry.mode != Mode::Tree {
+            return Ok(None);
+        }
+        tree = Tree::read(&entry.hash)?;
+    }
+    Ok(None)
+}
+
+fn check_name(name: &[u8]) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !name.is_empty() && name != b"." && name != b".." && !name.iter().any(|&b| b == b'/' || b == 0),
+        "invalid tree entry name '{}'",
+        path::display(name)
+    );
+    Ok(())
+}
+
+/// Git's tree order: bytewise by name, with subtrees compared as if their
+/// names ended in `/`.
+fn entry_order(a: &Entry, b: &Entry) -> Ordering {
+    let key = |entry: &Entry| {
+        let suffix: &[u8] = if entry.mode == Mode::Tree { b"/" } else { b"" };
+        entry.name.iter().chain(suffix).copied().collect::<Vec<u8>>()
+    };
+    key(a).cmp(&key(b))
+}
+
+/// An entry found by `TreeWalk`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    /// Path from the root of the walk, `/`-separated.
+    pub path: Vec<u8>,
+    pub name: Vec<u8>,
+    pub mode: Mode,
+    pub hash: Oid,
+    /// 0 for entries of the root tree, 1 for their children, and so on.
+    pub depth: usize,
+}
+
+impl TreeEntry {
+    pub fn is_tree(&self) -> bool {
+        self.mode == Mode::Tree
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.mode.kind()
+    }
+}
+
+struct Frame {
+    entries: VecDeque<Entry>,
+    prefix: Vec<u8>,
+    depth: usize,
+    /// In post-order, the subtree entry to yield once its frame is done.
+    dir: Option<TreeEntry>,
+}
+
+type Prune<'a> = Box<dyn FnMut(&TreeEntry) -> bool + 'a>;
+
+pub struct TreeWalk<'a> {
+    stack: Vec<Frame>,
+    post_order: bool,
+    prune: Option<Prune<'a>>,
+}
+
+impl<'a> TreeWalk<'a> {
+    pub fn new(tree: &Oid) -> anyhow::Result<TreeWalk<'a>> {
+        let entries = Tree::read(tree)?.entries.into();
+        Ok(TreeWalk {
+            stack: vec![Frame {
+                entries,
+                prefix: vec![],
+                depth: 0,
+                dir: None,
+            }],
+            post_order: false,
+            prune: None,
+        })
+    }
+
+    /// Yields each subtree after its contents rather than before.
+    pub fn post_order(mut self) -> Self {
+        self.post_order = true;
+        self
+    }
+
+    /// Skips the contents of every subtree for which `prune` returns true;
+    /// the subtree entry itself is still yielded.
+    pub fn prune(mut self, prune: impl FnMut(&TreeEntry) -> bool + 'a) -> Self {
+        self.prune = Some(Box::new(prune));
+        self
+    }
+
+    /// Only yields the entries of the root tree.
+    pub fn shallow(self) -> Self {
+        self.prune(|_| true)
+    }
+
+    fn next_entry(&mut self) -> anyhow::Result<Option<TreeEntry>> {
+        loop {
+            let Some(frame) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+            let Some(Entry { mode, name, hash }) = frame.entries.pop_front() else {
+                let frame = self.stack.pop().expect("checked above");
+                if let Some(dir) = frame.dir {
+                    return Ok(Some(dir));
+                }
+                continue;
+            };
+            let entry = TreeEntry {
+                path: [&frame.prefix[..], &name].concat(),
+                name,
+                mode,
+                hash,
+                depth: frame.depth,
+            };
+            if !entry.is_tree() || self.prune.as_mut().is_some_and(|prune| prune(&entry)) {
+                return Ok(Some(entry));
+            }
+            let depth = frame.depth + 1;
+            self.stack.push(Frame {
+                entries: Tree::read(&entry.hash)?.entries.into(),
+                prefix: [&entry.path[..], b"/"].concat(),
+                depth,
+                dir: self.post_order.then(|| entry.clone()),
+            });
+            if !self.post_order {
+                return Ok(Some(entry));
+            }
+        }
+    }
+}
+
+impl Iterator for TreeWalk<'_> {
+    type Item = anyhow::Result<TreeEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
@@ -0,0 +1,504 @@
+//! Comparing two trees file by file.
+//!
+//! Subtrees with the same hash on both sides are skipped without being read,
+//! so the cost is proportional to what changed. Each difference is reported
+//! as a `Change`, which renders in the `--raw` (`:100644 100644 <old> <new>
+//! M<tab>path`) and `--name-status` (`M<tab>path`) formats.
+
+use crate::diff;
+use crate::object::Object;
+use crate::oid::Oid;
+use crate::path;
+use crate::quote::quote_path;
+use crate::tree::{self, Tree, TreeWalk};
+use anyhow::Context;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Added,
+    Deleted,
+    Modified,
+    /// The entry changed between file, symlink and submodule.
+    TypeChanged,
+    /// Moved from `old_path`, with the similarity as a percentage.
+    Renamed(u8),
+    /// Copied from `old_path`, which is still there, with the similarity
+    /// as a percentage.
+    Copied(u8),
+    /// The path has a conflict in the index waiting to be resolved.
+    Unmerged,
+}
+
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub status: Status,
+    pub old_mode: u32,
+    pub new_mode: u32,
+    pub old_hash: Oid,
+    pub new_hash: Oid,
+    pub old_path: Vec<u8>,
+    pub path: Vec<u8>,
+}
+
+impl Change {
+    pub fn letter(&self) -> String {
+        match self.status {
+            Status::Added => "A".to_string(),
+            Status::Deleted => "D".to_string(),
+            Status::Modified => "M".to_string(),
+            Status::TypeChanged => "T".to_string(),
+            Status::Renamed(score) => format!("R{score:03}"),
+            Status::Copied(score) => format!("C{score:03}"),
+            Status::Unmerged => "U".to_string(),
+        }
+    }
+
+    fn paths(&self) -> String {
+        match self.status {
+            Status::Renamed(_) | Status::Copied(_) => format!("{}\t{}", quote_path(&self.old_path), quote_path(&self.path)),
+            _ => quote_path(&self.path).into_owned(),
+        }
+    }
+
+    /// The `--raw` line, with hashes abbreviated to `abbrev` digits if given.
+    pub fn format_raw(&self, abbrev: Option<usize>) -> String {
+        let short = |hash: &Oid| hash.abbrev(abbrev.unwrap_or(40));
+        format!(
+            ":{:06o} {:06o} {} {} {}\t{}",
+            self.old_mode,
+            self.new_mode,
+            short(&self.old_hash),
+            short(&self.new_hash),
+            self.letter(),
+            self.paths()
+        )
+    }
+
+    pub fn format_name_status(&self) -> String {
+        format!("{}\t{}", self.letter(), self.paths())
+    }
+}
+
+/// The file-level differences between trees `old` and `new`, where `None`
+/// stands for an empty tree, sorted by path.
+pub fn diff_trees(old: Option<&Oid>, new: Option<&Oid>) -> anyhow::Result<Vec<Change>> {
+    let mut changes = vec![];
+    diff_into(old, new, b"", &mut changes)?;
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(changes)
+}
+
+fn diff_into(old: Option<&Oid>, new: Option<&Oid>, prefix: &[u8], changes: &mut Vec<Change>) -> anyhow::Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let entries = |tree: Option<&Oid>| -> anyhow::Result<BTreeMap<Vec<u8>, (u32, Oid)>> {
+        let Some(tree) = tree else {
+            return Ok(BTreeMap::new());
+        };
+        Ok(Tree::read(tree)?
+            .entries()
+            .iter()
+            .map(|entry| (entry.name.clone(), (entry.mode.bits(), entry.hash)))
+            .collect())
+    };
+    let old_entries = entries(old)?;
+    let mut new_entries = entries(new)?;
+    for (name, (old_mode, old_hash)) in old_entries {
+        let path = [prefix, &name].concat();
+        match new_entries.remove(&name) {
+            Some((new_mode, new_hash)) => {
+                if old_mode == new_mode && old_hash == new_hash {
+                    continue;
+                }
+                match (is_tree(old_mode), is_tree(new_mode)) {
+                    (true, true) => diff_into(Some(&old_hash), Some(&new_hash), &[&path[..], b"/"].concat(), changes)?,
+                    (false, false) => {
+                        let status = if old_mode & 0o170000 == new_mode & 0o170000 {
+                            Status::Modified
+                        } else {
+                            Status::TypeChanged
+                        };
+                        changes.push(Change {
+                            status,
+                            old_mode,
+                            new_mode,
+                            old_hash,
+                            new_hash,
+                            old_path: path.clone(),
+                            path,
+                        });
+                    }
+                    _ => {
+                        removed(old_mode, old_hash, &path, changes)?;
+                        added(new_mode, new_hash, &path, changes)?;
+                    }
+                }
+            }
+            None => removed(old_mode, old_hash, &path, changes)?,
+        }
+    }
+    for (name, (new_mode, new_hash)) in new_entries {
+        added(new_mode, new_hash, &[prefix, &name].concat(), changes)?;
+    }
+    Ok(())
+}
+
+fn added(mode: u32, hash: Oid, path: &[u8], changes: &mut Vec<Change>) -> anyhow::Result<()> {
+    if is_tree(mode) {
+        return diff_into(None, Some(&hash), &[path, b"/"].concat(), changes);
+    }
+    changes.push(Change {
+        status: Status::Added,
+        old_mode: 0,
+        new_mode: mode,
+        old_hash: Oid::ZERO,
+        new_hash: hash,
+        old_path: path.to_vec(),
+        path: path.to_vec(),
+    });
+    Ok(())
+}
+
+fn removed(mode: u32, hash: Oid, path: &[u8], changes: &mut Vec<Change>) -> anyhow::Result<()> {
+    if is_tree(mode) {
+        return diff_into(Some(&hash), None, &[path, b"/"].concat(), changes);
+    }
+    changes.push(Change {
+        status: Status::Deleted,
+        old_mode: mode,
+        new_mode: 0,
+        old_hash: hash,
+        new_hash: Oid::ZERO,
+        old_path: path.to_vec(),
+        path: path.to_vec(),
+    });
+    Ok(())
+}
+
+fn is_tree(mode: u32) -> bool {
+    mode & 0o170000 == tree::MODE_TREE
+}
+
+/// How alike two files must be to be paired up as a rename or a copy, out
+/// of `MAX_SCORE`: half, as with git's `-M` and `-C`.
+const MIN_SCORE: u64 = 30_000;
+const MAX_SCORE: u64 = 60_000;
+
+/// A file's contents cut into chunks the way git cuts them to compare
+/// files: lines, with long lines cut every 64 bytes. Each chunk is known by
+/// a hash, and identical chunks are counted together by their size.
+struct Chunks {
+    len: u64,
+    sizes: HashMap<u32, u64>,
+}
+
+impl Chunks {
+    fn new(data: &[u8]) -> Chunks {
+        const HASHBASE: u32 = 107_927;
+        // In text, the CR of a CRLF is ignored.
+        let is_text = !diff::is_binary(data);
+        let mut sizes: HashMap<u32, u64> = HashMap::new();
+        let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0u64);
+        for (i, &c) in data.iter().enumerate() {
+            if is_text && c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            let old = accum1;
+            accum1 = (accum1 << 7) ^ (accum2 >> 25);
+            accum2 = (accum2 << 7) ^ (old >> 25);
+            accum1 = accum1.wrapping_add(u32::from(c));
+            n += 1;
+            if n < 64 && c != b'\n' {
+                continue;
+            }
+            *sizes.entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE).or_default() += n;
+            (accum1, accum2, n) = (0, 0, 0);
+        }
+        if n > 0 {
+            *sizes.entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASHBASE).or_default() += n;
+        }
+        Chunks { len: data.len() as u64, sizes }
+    }
+
+    /// How much of `dst` could have come from `self`, out of `MAX_SCORE`, if
+    /// it is at least `MIN_SCORE`: the bytes of the chunks both have, over
+    /// the size of the larger file.
+    fn score(&self, dst: &Chunks) -> Option<u64> {
+        let (max, min) = (self.len.max(dst.len), self.len.min(dst.len));
+        // Files too different in size can't be similar enough.
+        if dst.len == 0 || max * (MAX_SCORE - MIN_SCORE) < (max - min) * MAX_SCORE {
+            return None;
+        }
+        let copied: u64 = self.sizes.iter().map(|(hash, &size)| size.min(dst.sizes.get(hash).copied().unwrap_or(0))).sum();
+        Some(copied * MAX_SCORE / max).filter(|&score| score >= MIN_SCORE)
+    }
+}
+
+/// A score as the percentage git shows.
+fn percentage(score: u64) -> u8 {
+    (score * 100 / MAX_SCORE) as u8
+}
+
+/// The chunks of the blob of a side of a change, if it can be compared:
+/// submodules have no blob here.
+fn chunks(hash: &Oid, mode: u32) -> anyhow::Result<Option<Chunks>> {
+    if mode & 0o170000 == tree::MODE_SUBMODULE {
+        return Ok(None);
+    }
+    let object = Object::read(hash).with_context(|| format!("read blob {hash}"))?;
+    Ok(Some(Chunks::new(&object.contents)))
+}
+
+fn same_type(a: u32, b: u32) -> bool {
+    a & 0o170000 == b & 0o170000
+}
+
+/// Pairs deleted files with added files as renames: first those with
+/// identical contents, then those at least half alike, the most alike
+/// first and those keeping their file name before others as git does.
+pub fn detect_renames(changes: Vec<Change>) -> anyhow::Result<Vec<Change>> {
+    let mut result = vec![];
+    let (mut added, mut deleted) = (vec![], vec![]);
+    for change in changes {
+        match change.status {
+            Status::Added => added.push(change),
+            Status::Deleted => deleted.push(change),
+            _ => result.push(change),
+        }
+    }
+    let mut pairs = vec![];
+    let mut added_used = vec![false; added.len()];
+    let mut deleted_used = vec![false; deleted.len()];
+    for (i, source) in deleted.iter().enumerate() {
+        let found = (0..added.len())
+            .find(|&j| !added_used[j] && added[j].new_hash == source.old_hash && same_type(added[j].new_mode, source.old_mode));
+        if let Some(j) = found {
+            (deleted_used[i], added_used[j]) = (true, true);
+            pairs.push((i, j, MAX_SCORE));
+        }
+    }
+    if added_used.contains(&false) && deleted_used.contains(&false) {
+        let mut sources = vec![];
+        for (i, source) in deleted.iter().enumerate().filter(|&(i, _)| !deleted_used[i]) {
+            if let Some(chunks) = chunks(&source.old_hash, source.old_mode)? {
+                sources.push((i, chunks));
+            }
+        }
+        // Each candidate pair, by score and then by whether the file name
+        // stays the same.
+        let mut scored = vec![];
+        for (j, target) in added.iter().enumerate().filter(|&(j, _)| !added_used[j]) {
+            if sources.is_empty() {
+                break;
+            }
+            let Some(target_chunks) = chunks(&target.new_hash, target.new_mode)? else {
+                continue;
+            };
+            for (i, source_chunks) in &sources {
+                let source = &deleted[*i];
+                if !same_type(source.old_mode, target.new_mode) {
+                    continue;
+                }
+                if let Some(score) = source_chunks.score(&target_chunks) {
+                    scored.push((score, path::file_name(&source.path) == path::file_name(&target.path), *i, j));
+                }
+            }
+        }
+        scored.sort_by_key(|&(score, same_name, _, _)| Reverse((score, same_name)));
+        for (score, _, i, j) in scored {
+            if !deleted_used[i] && !added_used[j] {
+                (deleted_used[i], added_used[j]) = (true, true);
+                pairs.push((i, j, score));
+            }
+        }
+    }
+    for &(i, j, score) in &pairs {
+        let (source, target) = (&deleted[i], &added[j]);
+        result.push(Change {
+            status: Status::Renamed(percentage(score)),
+            old_mode: source.old_mode,
+            new_mode: target.new_mode,
+            old_hash: source.old_hash,
+            new_hash: target.new_hash,
+            old_path: source.path.clone(),
+            path: target.path.clone(),
+        });
+    }
+    result.extend(deleted.into_iter().zip(deleted_used).filter(|(_, used)| !used).map(|(change, _)| change));
+    result.extend(added.into_iter().zip(added_used).filter(|(_, used)| !used).map(|(change, _)| change));
+    result.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(result)
+}
+
+/// The changes to the files `wanted` picks, with renames detected as for
+/// `log --follow`: only those files can be rename destinations, while any
+/// deleted file can be a source. Otherwise a copy of the followed file
+/// elsewhere could claim its old name first.
+pub fn detect_renames_to(changes: Vec<Change>, wanted: impl Fn(&[u8]) -> bool) -> anyhow::Result<Vec<Change>> {
+    let changes = changes.into_iter().filter(|change| change.status == Status::Deleted || wanted(&change.path)).collect();
+    Ok(detect_renames(changes)?.into_iter().filter(|change| wanted(&change.path)).collect())
+}
+
+/// A file copies may be made from: its path, mode and blob.
+type Source = (Vec<u8>, u32, Oid);
+
+/// Turns each added file into a copy of the file of the old tree it is
+/// most like, if that is at least half alike. The candidates are the files
+/// `changes` modifies or renames away or, with `harder`, every file of
+/// `old`, the old tree. Between candidates alike to the same degree, one
+/// with the same file name is preferred. When a renamed file was also
+/// copied, the last of its destinations is the rename, as in git.
+pub fn detect_copies(changes: Vec<Change>, old: Option<&Oid>, harder: bool) -> anyhow::Result<Vec<Change>> {
+    let mut sources: Vec<Source> = vec![];
+    if harder && let Some(old) = old {
+        for entry in TreeWalk::new(old)? {
+            let entry = entry?;
+            if !entry.is_tree() {
+                sources.push((entry.path, entry.mode.bits(), entry.hash));
+            }
+        }
+    } else {
+        for change in &changes {
+            if matches!(change.status, Status::Modified | Status::TypeChanged | Status::Renamed(_)) {
+                sources.push((change.old_path.clone(), change.old_mode, change.old_hash));
+            }
+        }
+        sources.sort();
+    }
+    // Read only once something needs comparing.
+    let mut source_chunks: Option<Vec<Option<Chunks>>> = None;
+    let mut result = vec![];
+    for change in changes {
+        if change.status != Status::Added {
+            result.push(change);
+            continue;
+        }
+        let same_name = |path: &[u8]| path::file_name(path) == path::file_name(&change.path);
+        let identical = |(_, mode, hash): &&(Vec<u8>, u32, Oid)| *hash == change.new_hash && same_type(*mode, change.new_mode);
+        let exact = sources
+            .iter()
+            .filter(identical)
+            .find(|(path, _, _)| same_name(path))
+            .or_else(|| sources.iter().find(identical))
+            .map(|source| (source, MAX_SCORE));
+        let found = match exact {
+            Some(found) => Some(found),
+            None if sources.is_empty() => None,
+            None => {
+                let source_chunks = match &mut source_chunks {
+                    Some(chunks) => chunks,
+                    None => source_chunks.insert(sources.iter().map(|(_, mode, hash)| chunks(hash, *mode)).collect::<anyhow::Result<_>>()?),
+                };
+                match chunks(&change.new_hash, change.new_mode)? {
+                    Some(target) => sources
+                        .iter()
+                        .zip(source_chunks.iter())
+                        .filter(|((_, mode, _), _)| same_type(*mode, change.new_mode))
+                        .filter_map(|(source, chunks)| Some((source, chunks.as_ref()?.score(&target)?)))
+                        // The first of the best, preferring the same name.
+                        .fold(None, |best: Option<(&Source, u64)>, (source, score)| match best {
+                            Some((best_source, best_score))
+                                if (best_score, same_name(&best_source.0)) >= (score, same_name(&source.0)) =>
+                            {
+                                Some((best_source, best_score))
+                            }
+                            _ => Some((source, score)),
+                        }),
+                    None => None,
+                }
+            }
+        };
+        match found {
+            Some(((path, mode, hash), score)) => result.push(Change {
+                status: Status::Copied(percentage(score)),
+                old_mode: *mode,
+                old_hash: *hash,
+                old_path: path.clone(),
+                ..change
+            }),
+            None => result.push(change),
+        }
+    }
+    // `result` is sorted by path, so the last copy from a renamed file
+    // comes last.
+    for i in 0..result.len() {
+        let Status::Renamed(score) = result[i].status else {
+            continue;
+        };
+        let last = (i + 1..result.len())
+            .rev()
+            .find(|&j| matches!(result[j].status, Status::Copied(_)) && result[j].old_path == result[i].old_path);
+        if let Some(j) = last {
+            let Status::Copied(copy_score) = result[j].status else {
+                unreachable!("only copies are looked for");
+            };
+            result[i].status = Status::Copied(score);
+            result[j].status = Status::Renamed(copy_score);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(range: std::ops::RangeInclusive<u32>, format: fn(u32) -> String) -> String {
+        range.map(|i| format(i) + "\n").collect()
+    }
+
+    // Each with the similarity git gives it.
+
+    #[test]
+    fn edited_files_are_scored_as_git_scores_them() {
+        let old = lines(1..=30, |i| format!("line {i}"));
+        let score = Chunks::new(old.as_bytes()).score(&Chunks::new(format!("{old}extra\n").as_bytes()));
+        assert_eq!(score.map(percentage), Some(97));
+        let old = lines(1..=20, |i| i.to_string());
+        let score = Chunks::new(old.as_bytes()).score(&Chunks::new(format!("{old}more\n").as_bytes()));
+        assert_eq!(score.map(percentage), Some(91));
+    }
+
+    #[test]
+    fn carriage_returns_of_text_are_left_out() {
+        let score = Chunks::new(b"x\r\ny\r\nz\r\n").score(&Chunks::new(b"x\ny\nz\n"));
+        assert_eq!(score.map(percentage), Some(66));
+    }
+
+    fn change(status: Status, path: &[u8], hash: &str) -> Change {
+        let hash: Oid = hash.parse().unwrap();
+        let (old_hash, new_hash) = match status {
+            Status::Deleted => (hash, Oid::ZERO),
+            _ => (Oid::ZERO, hash),
+        };
+        let (old_mode, new_mode) = match status {
+            Status::Deleted => (0o100644, 0),
+            _ => (0, 0o100644),
+        };
+        Change { status, old_mode, new_mode, old_hash, new_hash, old_path: path.to_vec(), path: path.to_vec() }
+    }
+
+    #[test]
+    fn a_followed_file_keeps_its_old_name_over_a_copy_of_it() {
+        // `mv src.txt z.txt && cp z.txt a.txt`, which git log --follow --
+        // z.txt takes back to src.txt.
+        let hash = "587be6b4c3f93f93c489c0111bba5596147a26cb";
+        let changes = vec![change(Status::Added, b"a.txt", hash), change(Status::Deleted, b"src.txt", hash), change(Status::Added, b"z.txt", hash)];
+        let followed = detect_renames_to(changes.clone(), |path| path == b"z.txt").unwrap();
+        assert_eq!(followed.len(), 1);
+        assert_eq!((followed[0].status, &*followed[0].old_path, &*followed[0].path), (Status::Renamed(100), &b"src.txt"[..], &b"z.txt"[..]));
+        // Over the whole tree the copy, first by path, takes the old name.
+        let renamed = detect_renames(changes).unwrap();
+        assert!(renamed.iter().any(|change| change.status == Status::Renamed(100) && change.path == b"a.txt"));
+    }
+
+    #[test]
+    fn files_less_than_half_alike_are_not_paired() {
+        let old = lines(1..=20, |i| i.to_string());
+        assert_eq!(Chunks::new(old.as_bytes()).score(&Chunks::new(lines(15..=30, |i| i.to_string()).as_bytes())), None);
+        assert_eq!(Chunks::new(old.as_bytes()).score(&Chunks::new(b"")), None);
+    }
+}
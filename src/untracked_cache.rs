@@ -0,0 +1,362 @@
+//! The untracked cache, an index extension (`UNTR`) that remembers which
+//! untracked files each directory held, so a scan can skip listing the
+//! directories that haven't changed since.
+//!
+//! For each directory walked the cache keeps the untracked names directly
+//! in it, its subdirectories and its stat data; while the directory's
+//! stat data still matches, nothing has been added to or removed from it
+//! and the names can be reused. Changes to the ignore rules throw the whole
+//! cache away: it records the stat data and hash of `info/exclude` and the
+//! user's excludes file, and the hash of the top-level `.gotignore`.
+//! Staging or unstaging a path marks its directory as needing a fresh
+//! listing, since that changes what is untracked without touching the
+//! directory.
+//!
+//! The layout is git's: an environment string, the stat data and hashes
+//! of the two exclude files, the directory flags, the name of per-directory
+//! ignore files, then the directories depth first, each with its untracked
+//! names, followed by EWAH bitmaps saying which directories are valid,
+//! which were only checked for untracked files and which have an ignore
+//! file hash, then the stat data of the valid ones and those hashes.
+
+use crate::config::Config;
+use crate::ewah;
+use crate::ignore;
+use crate::index::Stat;
+use crate::object::{Kind, Object};
+use crate::oid::Oid;
+use crate::path;
+use anyhow::Context;
+use std::fs;
+use std::path::Path;
+
+pub const SIGNATURE: &[u8; 4] = b"UNTR";
+/// The per-directory ignore file name recorded in the cache.
+const PER_DIR_EXCLUDE: &str = ".gotignore";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UntrackedCache {
+    /// Where the cache was made: the worktree and the system.
+    pub ident: String,
+    pub exclude: (Stat, Oid),
+    pub excludes_file: (Stat, Oid),
+    pub dir_flags: u32,
+    /// The name of per-directory ignore files the cache was made with.
+    pub exclude_per_dir: String,
+    pub root: Option<Dir>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dir {
+    pub name: Vec<u8>,
+    /// Untracked files directly in the directory, and `name/` for nested
+    /// repositories.
+    pub untracked: Vec<Vec<u8>>,
+    pub dirs: Vec<Dir>,
+    /// Whether `untracked` can be trusted (while the stat data matches).
+    pub valid: bool,
+    /// Whether git only looked for whether the directory held anything
+    /// untracked.
+    pub check_only: bool,
+    /// The directory's stat data, kept while it is valid.
+    pub stat: Option<Stat>,
+    /// The hash of the directory's ignore file, or the zero hash.
+    pub exclude_hash: Oid,
+}
+
+impl UntrackedCache {
+    /// An empty cache for the current environment.
+    pub fn new(config: &Config, git_dir: &Path) -> UntrackedCache {
+        UntrackedCache {
+            ident: ident(),
+            exclude: file_state(&git_dir.join("info/exclude")),
+            excludes_file: ignore::excludes_file(config).map_or((Stat::default(), Oid::ZERO), |path| file_state(&path)),
+            dir_flags: 0,
+            exclude_per_dir: PER_DIR_EXCLUDE.to_string(),
+            root: None,
+        }
+    }
+
+    /// Whether the cache was made under the same ignore rules as `fresh`,
+    /// a new cache for the current environment.
+    pub fn is_current(&self, fresh: &UntrackedCache) -> bool {
+        self.ident == fresh.ident
+            && self.exclude == fresh.exclude
+            && self.excludes_file == fresh.excludes_file
+            && self.dir_flags == fresh.dir_flags
+            && self.exclude_per_dir == fresh.exclude_per_dir
+            && self.root.as_ref().is_some_and(|root| root.valid && root.exclude_hash == exclude_hash(b""))
+    }
+
+    /// Marks the directory holding `path` as needing a fresh listing, or
+    /// the deepest cached directory above it if it isn't cached.
+    pub fn invalidate(&mut self, path: &[u8]) {
+        let Some(mut dir) = self.root.as_mut() else {
+            return;
+        };
+        let mut components: Vec<&[u8]> = path.split(|&b| b == b'/').collect();
+        components.pop();
+        for component in components {
+            match dir.dirs.iter().position(|sub| sub.name == component) {
+                Some(i) => dir = &mut dir.dirs[i],
+                None => break,
+            }
+        }
+        dir.valid = false;
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<UntrackedCache> {
+        let mut reader = Reader { data, at: 0 };
+        let ident_len = reader.varint()? as usize;
+        // Only the first of the environment strings is used.
+        let ident = reader.take(ident_len)?.split(|&b| b == 0).next().unwrap_or_default();
+        let ident = String::from_utf8_lossy(ident).into_owned();
+        let exclude_stat = reader.stat()?;
+        let excludes_file_stat = reader.stat()?;
+        let dir_flags = reader.u32()?;
+        let exclude = (exclude_stat, Oid::from_bytes(reader.take(20)?)?);
+        let excludes_file = (excludes_file_stat, Oid::from_bytes(reader.take(20)?)?);
+        let exclude_per_dir = String::from_utf8_lossy(&reader.name()?).into_owned();
+        let mut cache = UntrackedCache {
+            ident,
+            exclude,
+            excludes_file,
+            dir_flags,
+            exclude_per_dir,
+            root: None,
+        };
+        let count = match reader.at < data.len() {
+            true => reader.varint()? as usize,
+            false => 0,
+        };
+        if count == 0 {
+            return Ok(cache);
+        }
+        let mut root = reader.dir()?;
+        let total = root.count();
+        anyhow::ensure!(total == count, "untracked cache lists {total} directories, not {count}");
+        let valid = reader.ewah()?;
+        let check_only = reader.ewah()?;
+        let hashed = reader.ewah()?;
+        let count = |bits: &[bool]| (0..total).filter(|&i| bits.get(i) == Some(&true)).count();
+        let stats = (0..count(&valid)).map(|_| reader.stat()).collect::<anyhow::Result<Vec<_>>>()?;
+        let hashes = (0..count(&hashed))
+            .map(|_| Oid::from_bytes(reader.take(20)?))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (mut stats, mut hashes) = (stats.into_iter(), hashes.into_iter());
+        let mut i = 0;
+        root.each_mut(&mut |dir| {
+            dir.valid = valid.get(i) == Some(&true);
+            dir.check_only = check_only.get(i) == Some(&true);
+            if dir.valid {
+                dir.stat = stats.next();
+            }
+            if hashed.get(i) == Some(&true) {
+                dir.exclude_hash = hashes.next().unwrap_or(Oid::ZERO);
+            }
+            i += 1;
+        });
+        cache.root = Some(root);
+        Ok(cache)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = vec![];
+        let ident = format!("{}\0", self.ident);
+        write_varint(&mut out, ident.len() as u64);
+        out.extend(ident.as_bytes());
+        write_stat(&mut out, &self.exclude.0);
+        write_stat(&mut out, &self.excludes_file.0);
+        out.extend(self.dir_flags.to_be_bytes());
+        out.extend(self.exclude.1.as_bytes());
+        out.extend(self.excludes_file.1.as_bytes());
+        out.extend(self.exclude_per_dir.as_bytes());
+        out.push(0);
+        let Some(root) = &self.root else {
+            write_varint(&mut out, 0);
+            return out;
+        };
+        let mut dirs = vec![];
+        root.each(&mut |dir| dirs.push(dir));
+        write_varint(&mut out, dirs.len() as u64);
+        root.write_blocks(&mut out);
+        // A directory is only valid with stat data to check it against.
+        let valid: Vec<Option<Stat>> = dirs.iter().map(|dir| dir.stat.filter(|_| dir.valid)).collect();
+        write_bitmap(&mut out, valid.iter().map(Option::is_some));
+        write_bitmap(&mut out, dirs.iter().map(|dir| dir.check_only));
+        write_bitmap(&mut out, dirs.iter().map(|dir| !dir.exclude_hash.is_zero()));
+        for stat in valid.iter().flatten() {
+            write_stat(&mut out, stat);
+        }
+        for dir in dirs.iter().filter(|dir| !dir.exclude_hash.is_zero()) {
+            out.extend(dir.exclude_hash.as_bytes());
+        }
+        out.push(0);
+        out
+    }
+}
+
+impl Dir {
+    fn count(&self) -> usize {
+        1 + self.dirs.iter().map(Dir::count).sum::<usize>()
+    }
+
+    /// Calls `f` on the directory and those below it, depth first.
+    fn each<'a>(&'a self, f: &mut impl FnMut(&'a Dir)) {
+        f(self);
+        for dir in &self.dirs {
+            dir.each(f);
+        }
+    }
+
+    fn each_mut(&mut self, f: &mut impl FnMut(&mut Dir)) {
+        f(self);
+        for dir in &mut self.dirs {
+            dir.each_mut(f);
+        }
+    }
+
+    fn write_blocks(&self, out: &mut Vec<u8>) {
+        write_varint(out, self.untracked.len() as u64);
+        write_varint(out, self.dirs.len() as u64);
+        out.extend(&self.name);
+        out.push(0);
+        for name in &self.untracked {
+            out.extend(name);
+            out.push(0);
+        }
+        for dir in &self.dirs {
+            dir.write_blocks(out);
+        }
+    }
+}
+
+/// The environment a cache is made in, which it is only good for.
+fn ident() -> String {
+    let worktree = std::env::current_dir().unwrap_or_default();
+    format!("Location {}, system {}", worktree.display(), std::env::consts::OS)
+}
+
+/// The stat data and blob hash of `path`, or nothing if it doesn't exist.
+fn file_state(path: &Path) -> (Stat, Oid) {
+    match (fs::metadata(path), fs::read(path)) {
+        (Ok(meta), Ok(data)) => (Stat::from_metadata(&meta), Object::hash_of(Kind::Blob, &data)),
+        _ => (Stat::default(), Oid::ZERO),
+    }
+}
+
+/// The hash of the ignore file in worktree directory `dir`, or the zero
+/// hash if there is none.
+pub fn exclude_hash(dir: &[u8]) -> Oid {
+    match fs::read(path::to_path(&path::join(dir, PER_DIR_EXCLUDE.as_bytes()))) {
+        Ok(data) => Object::hash_of(Kind::Blob, &data),
+        Err(_) => Oid::ZERO,
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self.data.get(self.at..self.at + len).context("truncated untracked cache")?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().expect("slice is 4 bytes")))
+    }
+
+    /// A number in git's variable-length encoding: seven bits a byte, most
+    /// significant first, with each continued byte standing for one more
+    /// than its bits.
+    fn varint(&mut self) -> anyhow::Result<u64> {
+        let mut byte = self.take(1)?[0];
+        let mut value = u64::from(byte & 0x7f);
+        while byte & 0x80 != 0 {
+            byte = self.take(1)?[0];
+            value = ((value + 1) << 7) | u64::from(byte & 0x7f);
+        }
+        Ok(value)
+    }
+
+    /// A NUL-terminated name.
+    fn name(&mut self) -> anyhow::Result<Vec<u8>> {
+        let len = self.data[self.at..].iter().position(|&b| b == 0).context("truncated untracked cache")?;
+        let name = self.take(len)?.to_vec();
+        self.at += 1;
+        Ok(name)
+    }
+
+    fn stat(&mut self) -> anyhow::Result<Stat> {
+        let mut field = || self.u32();
+        Ok(Stat {
+            ctime: field()?,
+            ctime_nsec: field()?,
+            mtime: field()?,
+            mtime_nsec: field()?,
+            dev: field()?,
+            ino: field()?,
+            uid: field()?,
+            gid: field()?,
+            size: field()?,
+        })
+    }
+
+    /// A directory block and, depth first, those of its subdirectories.
+    fn dir(&mut self) -> anyhow::Result<Dir> {
+        let untracked = self.varint()? as usize;
+        let dirs = self.varint()? as usize;
+        let name = self.name()?;
+        let untracked = (0..untracked).map(|_| self.name()).collect::<anyhow::Result<Vec<_>>>()?;
+        let dirs = (0..dirs).map(|_| self.dir()).collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Dir {
+            name,
+            untracked,
+            dirs,
+            ..Dir::default()
+        })
+    }
+
+    fn ewah(&mut self) -> anyhow::Result<Vec<bool>> {
+        let (bits, len) = ewah::read(&self.data[self.at..]).context("corrupt bitmap in untracked cache")?;
+        self.at += len;
+        Ok(bits)
+    }
+}
+
+/// Writes `bits` as git does, up to the last bit set.
+fn write_bitmap(out: &mut Vec<u8>, bits: impl Iterator<Item = bool>) {
+    let mut bits: Vec<bool> = bits.collect();
+    let len = bits.iter().rposition(|&bit| bit).map_or(0, |last| last + 1);
+    bits.truncate(len);
+    ewah::write(out, &bits);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    while value >= 0x80 {
+        value = (value >> 7) - 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn write_stat(out: &mut Vec<u8>, stat: &Stat) {
+    for field in [
+        stat.ctime,
+        stat.ctime_nsec,
+        stat.mtime,
+        stat.mtime_nsec,
+        stat.dev,
+        stat.ino,
+        stat.uid,
+        stat.gid,
+        stat.size,
+    ] {
+        out.extend(field.to_be_bytes());
+    }
+}
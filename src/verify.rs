@@ -0,0 +1,263 @@
+//! Checking that a commit's tree is intact: every object below it is
+//! present, of the right kind and hashes to its name, and every tree is in
+//! canonical form, so that rebuilding it from its entries gives back the
+//! same hash.
+//!
+//! Also checking connectivity, that everything reachable from some new ref
+//! tips is present, as fetch does before updating refs so that a truncated
+//! transfer can't leave them pointing at missing objects. Objects named in
+//! an `fsck.skipList` file are known to be missing or broken and are
+//! neither reported nor followed.
+//!
+//! Objects are read as stored, ignoring replace refs, since it is the
+//! stored objects whose integrity is in question.
+
+use crate::config::Config;
+use crate::object::{Commit, Kind, Object, Tag};
+use crate::oid::Oid;
+use crate::path;
+use crate::shallow;
+use crate::tree::{Mode, Tree};
+use anyhow::Context;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Something wrong with one object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub hash: Oid,
+    /// Where the object was found, `/`-separated from the root tree; empty
+    /// for the commit and its tree.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}: {}", self.hash, self.message)
+        } else {
+            write!(f, "{} ({}): {}", self.hash, self.path, self.message)
+        }
+    }
+}
+
+/// Checks commit `hash` and everything in its tree, returning the problems
+/// found. Objects shared by several paths are checked once. Submodule
+/// commits live in another repository and are not checked.
+pub fn verify_commit(hash: &Oid) -> anyhow::Result<Vec<Problem>> {
+    let mut verifier = Verifier::default();
+    let Some(contents) = verifier.object(hash, "", Kind::Commit) else {
+        return Ok(verifier.problems);
+    };
+    let commit = Commit::parse(&contents).with_context(|| format!("parse commit {hash}"))?;
+    verifier.tree(&commit.tree, "");
+    Ok(verifier.problems)
+}
+
+/// Checks that every object reachable from `tips` is present and of the
+/// kind it is referred to as, returning the problems found. The walk stops
+/// at commits reachable from `known`, tips whose history the repository
+/// already has, at the shallow boundary and at objects in `skip`.
+pub fn check_connectivity(git_dir: &Path, tips: &[Oid], known: &[Oid], skip: &HashSet<Oid>) -> anyhow::Result<Vec<Problem>> {
+    let boundary = shallow::read(git_dir)?;
+    let known = known_commits(known, &boundary);
+    let mut problems = vec![];
+    let mut report = |hash: &Oid, message: String| {
+        problems.push(Problem {
+            hash: *hash,
+            path: String::new(),
+            message,
+        })
+    };
+    let mut seen: HashSet<Oid> = HashSet::new();
+    let mut pending: Vec<(Oid, Option<Kind>)> = tips.iter().map(|tip| (*tip, None)).collect();
+    while let Some((hash, kind)) = pending.pop() {
+        if skip.contains(&hash) || known.contains(&hash) || !seen.insert(hash) {
+            continue;
+        }
+        let name = kind.map_or("object".to_string(), |kind| kind.to_string());
+        // Blobs lead nowhere, so they needn't be read.
+        if kind == Some(Kind::Blob) {
+            if !Object::exists(&hash) {
+                report(&hash, format!("missing {name}"));
+            }
+            continue;
+        }
+        let Ok(object) = Object::read_original(&hash) else {
+            report(&hash, format!("missing {name}"));
+            continue;
+        };
+        if let Some(kind) = kind
+            && object.kind != kind
+        {
+            report(&hash, format!("expected a {kind}, found a {}", object.kind));
+            continue;
+        }
+        match object.kind {
+            Kind::Commit => match Commit::parse(&object.contents) {
+                Ok(commit) => {
+                    pending.push((commit.tree, Some(Kind::Tree)));
+                    if !boundary.contains(&hash) {
+                        pending.extend(commit.parents.into_iter().map(|parent| (parent, Some(Kind::Commit))));
+                    }
+                }
+                Err(err) => report(&hash, format!("bad commit: {err:#}")),
+            },
+            Kind::Tree => match Tree::parse(&object.contents) {
+                Ok(tree) => {
+                    for entry in tree.entries() {
+                        match entry.mode {
+                            Mode::Tree => pending.push((entry.hash, Some(Kind::Tree))),
+                            Mode::Submodule => {}
+                            Mode::File | Mode::Executable | Mode::Symlink => pending.push((entry.hash, Some(Kind::Blob))),
+                        }
+                    }
+                }
+                Err(err) => report(&hash, format!("bad tree: {err:#}")),
+            },
+            Kind::Tag => match Tag::parse(&object.contents) {
+                Ok(tag) => pending.push((tag.object, Some(tag.kind))),
+                Err(err) => report(&hash, format!("bad tag: {err:#}")),
+            },
+            Kind::Blob => {}
+        }
+    }
+    Ok(problems)
+}
+
+/// The commits reachable from `tips`, and the tags on the way, as far as
+/// they can be read.
+fn known_commits(tips: &[Oid], boundary: &BTreeSet<Oid>) -> HashSet<Oid> {
+    let mut known = HashSet::new();
+    let mut pending = tips.to_vec();
+    while let Some(hash) = pending.pop() {
+        if !known.insert(hash) {
+            continue;
+        }
+        let Ok(object) = Object::read_original(&hash) else {
+            continue;
+        };
+        match object.kind {
+            Kind::Tag => pending.extend(Tag::parse(&object.contents).map(|tag| tag.object)),
+            Kind::Commit if !boundary.contains(&hash) => pending.extend(Commit::parse(&object.contents).map(|commit| commit.parents).unwrap_or_default()),
+            _ => {}
+        }
+    }
+    known
+}
+
+/// The objects listed in the first of the skip list files `keys` name, such
+/// as `fetch.fsck.skipList` and then `fsck.skipList`: one object name per
+/// line, with `#` starting a comment.
+pub fn skip_list(config: &Config, keys: &[&str]) -> anyhow::Result<HashSet<Oid>> {
+    let Some(path) = keys.iter().find_map(|key| config.get(key)) else {
+        return Ok(HashSet::new());
+    };
+    let text = fs::read_to_string(path).with_context(|| format!("could not open skip list: {path}"))?;
+    let mut skip = HashSet::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if !line.is_empty() {
+            skip.insert(line.parse().with_context(|| format!("invalid object name in skip list {path}: {line}"))?);
+        }
+    }
+    Ok(skip)
+}
+
+#[derive(Default)]
+struct Verifier {
+    seen: HashSet<Oid>,
+    problems: Vec<Problem>,
+}
+
+impl Verifier {
+    fn report(&mut self, hash: &Oid, path: &str, message: String) {
+        self.problems.push(Problem {
+            hash: *hash,
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    /// Reads object `hash`, expected to be a `kind`, and checks that its
+    /// contents hash to its name. Returns the contents if they are usable.
+    fn object(&mut self, hash: &Oid, path: &str, kind: Kind) -> Option<Vec<u8>> {
+        let object = match Object::read_original(hash) {
+            Ok(object) => object,
+            Err(err) => {
+                self.report(hash, path, format!("cannot read {kind}: {err:#}"));
+                return None;
+            }
+        };
+        if object.kind != kind {
+            self.report(hash, path, format!("expected a {kind}, found a {}", object.kind));
+            return None;
+        }
+        let actual = Object::hash_of(kind, &object.contents);
+        if actual != *hash {
+            self.report(hash, path, format!("hash mismatch: contents hash to {actual}"));
+        }
+        Some(object.contents)
+    }
+
+    fn tree(&mut self, hash: &Oid, path: &str) {
+        if !self.seen.insert(*hash) {
+            return;
+        }
+        let Some(contents) = self.object(hash, path, Kind::Tree) else {
+            return;
+        };
+        let tree = match Tree::parse(&contents) {
+            Ok(tree) => tree,
+            Err(err) => {
+                self.report(hash, path, format!("bad tree: {err:#}"));
+                return;
+            }
+        };
+        for (entry, mode) in tree.entries().iter().zip(recorded_modes(&contents)) {
+            if mode != entry.mode.as_str() {
+                let entry_path = join(path, &entry.name);
+                self.report(hash, path, format!("non-canonical mode {mode} for '{entry_path}'"));
+            }
+        }
+        let rebuilt = tree.serialize();
+        if rebuilt != contents {
+            let rebuilt = Object::hash_of(Kind::Tree, &rebuilt);
+            self.report(hash, path, format!("tree rebuilt from its entries hashes to {rebuilt}"));
+        }
+
+        for entry in tree.entries() {
+            let entry_path = join(path, &entry.name);
+            match entry.mode {
+                Mode::Tree => self.tree(&entry.hash, &entry_path),
+                Mode::Submodule => {}
+                Mode::File | Mode::Executable | Mode::Symlink => {
+                    if self.seen.insert(entry.hash) {
+                        self.object(&entry.hash, &entry_path, Kind::Blob);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The modes of a tree's entries exactly as written, which `Tree::parse`
+/// normalizes. The contents must already have parsed.
+fn recorded_modes(contents: &[u8]) -> Vec<String> {
+    let mut modes = vec![];
+    let mut rest = contents;
+    while let Some(nul) = rest.iter().position(|&b| b == 0) {
+        let space = rest.iter().position(|&b| b == b' ').unwrap_or(0);
+        modes.push(String::from_utf8_lossy(&rest[..space]).into_owned());
+        rest = &rest[(nul + 21).min(rest.len())..];
+    }
+    modes
+}
+
+fn join(dir: &str, name: &[u8]) -> String {
+    let name = path::display(name);
+    if dir.is_empty() { name.into_owned() } else { format!("{dir}/{name}") }
+}
@@ -0,0 +1,123 @@
+//! Comparing names as versions, so that `v1.10` sorts after `v1.9`.
+//!
+//! Runs of digits compare by their value and everything else byte by byte,
+//! as `git tag --sort=version:refname` does, following `strverscmp(3)`: a
+//! run with leading zeros is a fraction, sorting before the integers.
+//! Suffixes from `versionsort.suffix` mark prereleases: where two names
+//! differ at one of them, the name with the suffix comes first, so
+//! `v1.0-rc1` sorts before `v1.0`, and names with different suffixes sort in
+//! the order the suffixes are configured.
+
+use crate::config::Config;
+use std::cmp::Ordering;
+
+/// The prerelease suffixes configured, from `versionsort.suffix` or the
+/// older `versionsort.prereleaseSuffix`.
+pub fn suffixes(config: &Config) -> Vec<String> {
+    let mut suffixes = config.get_all("versionsort.suffix");
+    let old = config.get_all("versionsort.prereleaseSuffix");
+    if suffixes.is_empty() {
+        suffixes = old;
+    } else if !old.is_empty() {
+        eprintln!("warning: ignoring versionsort.prereleasesuffix because versionsort.suffix is set");
+    }
+    suffixes.into_iter().map(str::to_string).collect()
+}
+
+// The states of the comparison as it scans the common prefix: in no
+// number, in an integer, in a fraction (digits after leading zeros) or in
+// leading zeros. Adding the kind of a character (0 for a non-digit, 1 for a
+// digit other than zero, 2 for zero) to a state gives its entry in a table.
+const NONE: usize = 0;
+const INTEGER: usize = 3;
+const FRACTION: usize = 6;
+const ZEROS: usize = 9;
+
+const NEXT_STATE: [usize; 12] = [
+    NONE, INTEGER, ZEROS, //
+    NONE, INTEGER, INTEGER, //
+    NONE, FRACTION, FRACTION, //
+    NONE, FRACTION, ZEROS,
+];
+
+/// Compare the differing characters.
+const CMP: i8 = 2;
+/// Compare the lengths of the numbers, then the differing characters.
+const LEN: i8 = 3;
+
+/// How to decide, by state and the kinds of the two differing characters.
+const RESULT: [i8; 36] = [
+    CMP, CMP, CMP, CMP, LEN, CMP, CMP, CMP, CMP, //
+    CMP, -1, -1, 1, LEN, LEN, 1, LEN, LEN, //
+    CMP, CMP, CMP, CMP, CMP, CMP, CMP, CMP, CMP, //
+    CMP, 1, 1, -1, CMP, CMP, -1, CMP, CMP,
+];
+
+/// Compares `a` and `b` as versions, with `suffixes` marking prereleases.
+pub fn compare(a: &str, b: &str, suffixes: &[String]) -> Ordering {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let at = |text: &[u8], i: usize| text.get(i).copied().unwrap_or(0);
+    let kind = |c: u8| usize::from(c == b'0') + usize::from(c.is_ascii_digit());
+    let mut off = 0;
+    let mut state = NONE + kind(at(a, 0));
+    while at(a, off) == at(b, off) {
+        if at(a, off) == 0 {
+            return Ordering::Equal;
+        }
+        off += 1;
+        state = NEXT_STATE[state] + kind(at(a, off));
+    }
+    if let Some(order) = compare_prereleases(a, b, off, suffixes) {
+        return order;
+    }
+    let diff = at(a, off).cmp(&at(b, off));
+    match RESULT[state * 3 + kind(at(b, off))] {
+        CMP => diff,
+        LEN => {
+            // The longer number is the bigger one.
+            let mut i = off + 1;
+            while at(a, i).is_ascii_digit() {
+                if !at(b, i).is_ascii_digit() {
+                    return Ordering::Greater;
+                }
+                i += 1;
+            }
+            if at(b, i).is_ascii_digit() { Ordering::Less } else { diff }
+        }
+        result => result.cmp(&0),
+    }
+}
+
+/// Orders `a` and `b`, which first differ at `off`, by the prerelease
+/// suffixes there, or `None` if that doesn't decide it.
+fn compare_prereleases(a: &[u8], b: &[u8], off: usize, suffixes: &[String]) -> Option<Ordering> {
+    let find = |name: &[u8]| {
+        // The suffix that contains or ends at `off`, preferring the one
+        // starting earliest and then the longest.
+        let mut best: Option<(usize, usize, usize)> = None;
+        for (position, suffix) in suffixes.iter().enumerate() {
+            let suffix = suffix.as_bytes();
+            for start in off.saturating_sub(suffix.len())..=off {
+                if !name.get(start..).is_some_and(|rest| rest.starts_with(suffix)) {
+                    continue;
+                }
+                let better = match best {
+                    None => true,
+                    Some((_, best_start, best_len)) => start < best_start || (start == best_start && suffix.len() > best_len),
+                };
+                if better {
+                    best = Some((position, start, suffix.len()));
+                }
+                break;
+            }
+        }
+        best.map(|(position, _, _)| position)
+    };
+    match (find(a), find(b)) {
+        (None, None) => None,
+        (x, y) if x == y => None,
+        (Some(x), Some(y)) => Some(x.cmp(&y)),
+        (Some(_), None) => Some(Ordering::Less),
+        (None, Some(_)) => Some(Ordering::Greater),
+    }
+}
@@ -0,0 +1,129 @@
+//! Moving the worktree and index from one tree to another, as a checkout
+//! does.
+//!
+//! Only the paths that differ between the two trees are touched. Before
+//! anything is written, every one of them is checked: what is staged must
+//! be what `from` has (or already what `to` has), a tracked file must still
+//! match the index and an untracked file must not be in the way, so local
+//! work is never lost. Submodules are left as (empty) directories;
+//! filling them in is up to `submodule`.
+
+use crate::index::{self, Entry, Index, Stat};
+use crate::object::Object;
+use crate::oid::Oid;
+use crate::path;
+use crate::tree::Mode;
+use crate::tree_diff::{self, Change, Status};
+use anyhow::Context;
+use std::fs;
+use std::io;
+
+/// Changes the worktree and `index` from tree `from` (`None` for an empty
+/// worktree) to tree `to`.
+pub fn checkout(index: &mut Index, from: Option<&Oid>, to: &Oid) -> anyhow::Result<()> {
+    let changes = tree_diff::diff_trees(from, Some(to))?;
+    let blocked: Vec<_> = changes
+        .iter()
+        .filter(|change| is_staged(index, change) || is_blocked(index, &change.path, change.status == Status::Deleted))
+        .map(|change| path::display(&change.path))
+        .collect();
+    anyhow::ensure!(
+        blocked.is_empty(),
+        "your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+         Please commit your changes or stash them before you switch branches.",
+        blocked.join("\n\t")
+    );
+
+    // Removals first, so a file can make way for a directory of the same
+    // name and the other way round.
+    for change in &changes {
+        if change.status == Status::Deleted {
+            remove(&change.path)?;
+            index.remove(&change.path);
+        }
+    }
+    for change in &changes {
+        if change.status == Status::Deleted {
+            continue;
+        }
+        let mode = Mode::from_bits(change.new_mode)?;
+        let file = path::to_path(&change.path);
+        if mode == Mode::Submodule {
+            if fs::symlink_metadata(file).is_ok_and(|meta| !meta.is_dir()) {
+                remove(&change.path)?;
+            }
+            fs::create_dir_all(file).with_context(|| format!("create {}", path::display(&change.path)))?;
+            index.insert(Entry::new(&change.path, mode, change.new_hash, Stat::default()));
+            continue;
+        }
+        if fs::symlink_metadata(file).is_ok_and(|meta| meta.is_dir()) {
+            // What was a submodule (now initialized or not) is replaced.
+            fs::remove_dir_all(file).with_context(|| format!("remove {}", path::display(&change.path)))?;
+        }
+        let contents = Object::read(&change.new_hash)?.contents;
+        index::write_worktree_file(&change.path, mode, &contents)?;
+        let meta = fs::symlink_metadata(file).with_context(|| format!("stat {}", path::display(&change.path)))?;
+        index.insert(Entry::new(&change.path, mode, change.new_hash, Stat::from_metadata(&meta)));
+    }
+    Ok(())
+}
+
+/// Whether the index has something staged for `change`'s path that is in
+/// neither tree, which checking out `change` would throw away.
+fn is_staged(index: &Index, change: &Change) -> bool {
+    let staged = index.get(&change.path).map(|entry| (entry.mode.bits(), entry.hash));
+    let side = |mode: u32, hash: Oid| (mode != 0).then_some((mode, hash));
+    staged != side(change.old_mode, change.old_hash) && staged != side(change.new_mode, change.new_hash)
+}
+
+/// Whether writing `path` (or removing it) would lose something in the
+/// worktree: changes to a tracked file not in the index, or an untracked
+/// file in the way.
+pub fn is_blocked(index: &Index, path: &[u8], removing: bool) -> bool {
+    let file = path::to_path(path);
+    let Ok(meta) = fs::symlink_metadata(file) else {
+        return false;
+    };
+    match index.get(path) {
+        Some(entry) if entry.mode == Mode::Submodule => false,
+        Some(entry) => {
+            if entry.stat_matches(&meta) {
+                return false;
+            }
+            let Some(mode) = index::worktree_mode(&meta) else {
+                return true;
+            };
+            let Ok(contents) = index::read_worktree_file(file, &meta) else {
+                return true;
+            };
+            mode != entry.mode || Object::hash_of(entry.mode.kind(), &contents) != entry.hash
+        }
+        // An empty directory, such as an uninitialized submodule, can go.
+        None if meta.is_dir() => fs::read_dir(file).is_ok_and(|mut dir| dir.next().is_some()),
+        None => !removing,
+    }
+}
+
+/// Removes the file (or empty submodule directory) at `path`, then any
+/// directories that leaves empty.
+pub fn remove(path: &[u8]) -> anyhow::Result<()> {
+    let file = path::to_path(path);
+    let result = match fs::symlink_metadata(file) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir(file),
+        _ => fs::remove_file(file),
+    };
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound && e.kind() != io::ErrorKind::DirectoryNotEmpty => {
+            return Err(e).with_context(|| format!("remove {}", path::display(path)));
+        }
+        _ => {}
+    }
+    let mut dir = file.parent();
+    while let Some(parent) = dir.filter(|parent| !parent.as_os_str().is_empty()) {
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}